@@ -0,0 +1,18 @@
+/* STM32G431CB bootloader layout, keep in sync with ../memory-bootloader.x */
+MEMORY
+{
+  FLASH            : ORIGIN = 0x08000000, LENGTH = 24K
+  BOOTLOADER_STATE : ORIGIN = 0x08006000, LENGTH = 2K
+  ACTIVE           : ORIGIN = 0x08006800, LENGTH = 48K
+  DFU              : ORIGIN = 0x08012800, LENGTH = 50K
+  RAM              : ORIGIN = 0x20000000, LENGTH = 32K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(FLASH);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(FLASH);
+
+__bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(FLASH);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(FLASH);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(FLASH);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(FLASH);
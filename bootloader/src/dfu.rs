@@ -0,0 +1,346 @@
+// bootloader/src/dfu.rs
+// DFU 1.1 download mode
+//
+// The host downloads an `fw-image` update file: the header is parsed from the
+// first bytes, the payload is written page by page into the DFU partition
+// while CRC-32 / SHA-256 (and optionally the Ed25519 signature) are checked
+// on the fly. Only a fully verified image is marked for the swap. Images are
+// checked against the size of ACTIVE, not DFU: the DFU partition is a page
+// larger for the swap, and what doesn't fit ACTIVE would be cut short.
+
+use core::cell::RefCell;
+
+use defmt::*;
+use embassy_boot_stm32::{AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterConfig};
+use embassy_futures::select::select;
+use embassy_stm32::flash::{Bank1Region, Blocking, WRITE_SIZE};
+use embassy_stm32::usb::{Driver, InterruptHandler};
+use embassy_stm32::{Peri, bind_interrupts, peripherals};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use embedded_storage::nor_flash::NorFlash;
+use fw_image::{HEADER_LEN, ImageHeader, PUBLIC_KEY_LEN, Verifier};
+
+bind_interrupts!(
+    struct Irqs {
+        USB_LP => InterruptHandler<peripherals::USB>;
+    }
+);
+
+// Same VID/PID as the application so host tools find the device again
+const USB_VID: u16 = 0x1209;
+const USB_PID: u16 = 0x0001;
+
+const CLASS_APPLICATION_SPECIFIC: u8 = 0xFE;
+const SUBCLASS_DFU: u8 = 0x01;
+const PROTOCOL_DFU_MODE: u8 = 0x02;
+
+const DESCRIPTOR_DFU_FUNCTIONAL: u8 = 0x21;
+// bitCanDnload, not manifestation tolerant: the device resets after download
+const DFU_ATTRIBUTES: u8 = 0x01;
+const DETACH_TIMEOUT_MS: u16 = 1000;
+const TRANSFER_SIZE: u16 = 1024;
+const DFU_VERSION: u16 = 0x0110;
+
+const REQ_DETACH: u8 = 0x00;
+const REQ_DNLOAD: u8 = 0x01;
+const REQ_GETSTATUS: u8 = 0x03;
+const REQ_CLRSTATUS: u8 = 0x04;
+const REQ_GETSTATE: u8 = 0x05;
+const REQ_ABORT: u8 = 0x06;
+
+const PAGE_SIZE: usize = 2048;
+
+#[cfg(feature = "signed-images")]
+const PUBLIC_KEY: Option<&[u8; PUBLIC_KEY_LEN]> = Some(include_bytes!("../keys/ed25519.pub"));
+#[cfg(not(feature = "signed-images"))]
+const PUBLIC_KEY: Option<&[u8; PUBLIC_KEY_LEN]> = None;
+
+static RESET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+enum DfuState {
+    DfuIdle = 2,
+    DnloadSync = 3,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    Error = 10,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+enum DfuStatus {
+    Ok = 0x00,
+    ErrTarget = 0x01,
+    ErrFile = 0x02,
+    ErrWrite = 0x03,
+    ErrVerify = 0x07,
+    ErrAddress = 0x08,
+    ErrNotDone = 0x09,
+    ErrStalledPkt = 0x0F,
+}
+
+struct DfuMode<'d, DFU: NorFlash, STATE: NorFlash> {
+    updater: BlockingFirmwareUpdater<'d, DFU, STATE>,
+    interface: InterfaceNumber,
+    capacity: u32,
+    state: DfuState,
+    status: DfuStatus,
+    header: [u8; HEADER_LEN],
+    header_len: usize,
+    verifier: Option<Verifier>,
+    page: [u8; PAGE_SIZE],
+    page_len: usize,
+    written: usize,
+}
+
+impl<'d, DFU: NorFlash, STATE: NorFlash> DfuMode<'d, DFU, STATE> {
+    fn restart(&mut self) {
+        self.state = DfuState::DfuIdle;
+        self.status = DfuStatus::Ok;
+        self.header_len = 0;
+        self.verifier = None;
+        self.page_len = 0;
+        self.written = 0;
+    }
+
+    fn fail(&mut self, status: DfuStatus) {
+        warn!("DFU error {:?}", status);
+        self.state = DfuState::Error;
+        self.status = status;
+    }
+
+    fn download(&mut self, mut data: &[u8]) -> Result<(), DfuStatus> {
+        if self.verifier.is_none() {
+            let take = (HEADER_LEN - self.header_len).min(data.len());
+            self.header[self.header_len..][..take].copy_from_slice(&data[..take]);
+            self.header_len += take;
+            data = &data[take..];
+            if self.header_len < HEADER_LEN {
+                return Ok(());
+            }
+
+            let header = ImageHeader::parse(&self.header).map_err(|_| DfuStatus::ErrFile)?;
+            info!(
+                "Receiving firmware {}.{}.{}, {} bytes",
+                header.version.major,
+                header.version.minor,
+                header.version.patch,
+                header.payload_len
+            );
+            let verifier =
+                Verifier::new(header, self.capacity).map_err(|_| DfuStatus::ErrAddress)?;
+            self.verifier = Some(verifier);
+        }
+
+        if let Some(verifier) = self.verifier.as_mut() {
+            verifier.update(data).map_err(|_| DfuStatus::ErrAddress)?;
+        }
+
+        while !data.is_empty() {
+            let take = (PAGE_SIZE - self.page_len).min(data.len());
+            self.page[self.page_len..][..take].copy_from_slice(&data[..take]);
+            self.page_len += take;
+            data = &data[take..];
+            if self.page_len == PAGE_SIZE {
+                self.flush_page()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_page(&mut self) -> Result<(), DfuStatus> {
+        if self.page_len == 0 {
+            return Ok(());
+        }
+        self.page[self.page_len..].fill(0xFF);
+        self.updater
+            .write_firmware(self.written, &self.page)
+            .map_err(|_| DfuStatus::ErrWrite)?;
+        self.written += PAGE_SIZE;
+        self.page_len = 0;
+        Ok(())
+    }
+
+    fn manifest(&mut self) -> Result<(), DfuStatus> {
+        self.flush_page()?;
+        let verifier = self.verifier.take().ok_or(DfuStatus::ErrNotDone)?;
+        verifier.finish(PUBLIC_KEY).map_err(|e| {
+            warn!("Image rejected: {}", defmt::Debug2Format(&e));
+            DfuStatus::ErrVerify
+        })?;
+        self.updater
+            .mark_updated()
+            .map_err(|_| DfuStatus::ErrTarget)?;
+        info!("Image verified, swapping on reset");
+        Ok(())
+    }
+
+    fn accepts(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == self.interface.0 as u16
+    }
+}
+
+impl<'d, DFU: NorFlash, STATE: NorFlash> Handler for DfuMode<'d, DFU, STATE> {
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        match req.request {
+            REQ_DNLOAD => {
+                let result = match (self.state, req.length) {
+                    // Zero length download ends the transfer
+                    (DfuState::DnloadIdle, 0) => self.manifest().map(|()| DfuState::ManifestSync),
+                    (DfuState::DfuIdle, 0) => Err(DfuStatus::ErrNotDone),
+                    (DfuState::DfuIdle | DfuState::DnloadIdle, _) => {
+                        self.download(data).map(|()| DfuState::DnloadSync)
+                    }
+                    _ => Err(DfuStatus::ErrStalledPkt),
+                };
+                match result {
+                    Ok(state) => {
+                        self.state = state;
+                        Some(OutResponse::Accepted)
+                    }
+                    Err(status) => {
+                        self.fail(status);
+                        Some(OutResponse::Rejected)
+                    }
+                }
+            }
+            REQ_CLRSTATUS | REQ_ABORT => {
+                self.restart();
+                Some(OutResponse::Accepted)
+            }
+            // Already in DFU mode
+            REQ_DETACH => Some(OutResponse::Accepted),
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        match req.request {
+            REQ_GETSTATUS => {
+                // Blocks are written synchronously in control_out, so the
+                // busy states are never reported and no poll timeout is needed
+                let reported = match self.state {
+                    DfuState::DnloadSync => {
+                        self.state = DfuState::DnloadIdle;
+                        DfuState::DnloadIdle
+                    }
+                    DfuState::ManifestSync => {
+                        self.state = DfuState::ManifestWaitReset;
+                        RESET.signal(());
+                        DfuState::Manifest
+                    }
+                    state => state,
+                };
+                buf[..6].copy_from_slice(&[self.status as u8, 0, 0, 0, reported as u8, 0]);
+                Some(InResponse::Accepted(&buf[..6]))
+            }
+            REQ_GETSTATE => {
+                buf[0] = self.state as u8;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => Some(InResponse::Rejected),
+        }
+    }
+}
+
+pub fn run(
+    usb: Peri<'static, peripherals::USB>,
+    dp: Peri<'static, peripherals::PA12>,
+    dm: Peri<'static, peripherals::PA11>,
+    flash: &Mutex<NoopRawMutex, RefCell<Bank1Region<'static, Blocking>>>,
+    capacity: u32,
+) -> ! {
+    let driver = Driver::new(usb, Irqs, dp, dm);
+
+    let mut config = embassy_usb::Config::new(USB_VID, USB_PID);
+    config.manufacturer = Some("Ivan Li");
+    config.product = Some("Isolated USB Hub (DFU)");
+    config.serial_number = Some(embassy_stm32::uid::uid_hex());
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    let mut config_descriptor = [0; 128];
+    let mut bos_descriptor = [0; 64];
+    let mut msos_descriptor = [0; 0];
+    let mut control_buf = [0; TRANSFER_SIZE as usize];
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut msos_descriptor,
+        &mut control_buf,
+    );
+
+    let fw_config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash, flash);
+    let mut magic = AlignedBuffer([0; WRITE_SIZE]);
+    let updater = BlockingFirmwareUpdater::new(fw_config, &mut magic.0);
+
+    let interface = {
+        let mut function =
+            builder.function(CLASS_APPLICATION_SPECIFIC, SUBCLASS_DFU, PROTOCOL_DFU_MODE);
+        let mut interface = function.interface();
+        let number = interface.interface_number();
+        let mut alt = interface.alt_setting(
+            CLASS_APPLICATION_SPECIFIC,
+            SUBCLASS_DFU,
+            PROTOCOL_DFU_MODE,
+            None,
+        );
+        let timeout = DETACH_TIMEOUT_MS.to_le_bytes();
+        let transfer = TRANSFER_SIZE.to_le_bytes();
+        let version = DFU_VERSION.to_le_bytes();
+        alt.descriptor(
+            DESCRIPTOR_DFU_FUNCTIONAL,
+            &[
+                DFU_ATTRIBUTES,
+                timeout[0],
+                timeout[1],
+                transfer[0],
+                transfer[1],
+                version[0],
+                version[1],
+            ],
+        );
+        number
+    };
+
+    let mut handler = DfuMode {
+        updater,
+        interface,
+        capacity,
+        state: DfuState::DfuIdle,
+        status: DfuStatus::Ok,
+        header: [0; HEADER_LEN],
+        header_len: 0,
+        verifier: None,
+        page: [0; PAGE_SIZE],
+        page_len: 0,
+        written: 0,
+    };
+    builder.handler(&mut handler);
+
+    let mut device = builder.build();
+    embassy_futures::block_on(select(device.run(), RESET.wait()));
+
+    // Give the host time to read the final status before dropping off the bus
+    cortex_m::asm::delay(16_000_000 / 10);
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,69 @@
+// bootloader/src/main.rs
+// embassy-boot based bootloader for the hub
+//
+// On every reset it finishes (or reverts) a pending A/B swap and jumps into
+// the ACTIVE partition. When the application requested DFU mode via its DFU
+// runtime interface, it enumerates as a DFU device instead and accepts a
+// verified update image into the DFU partition.
+#![no_std]
+#![no_main]
+
+mod dfu;
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use defmt::*;
+use defmt_rtt as _;
+use embassy_boot_stm32::{BootLoader, BootLoaderConfig, State};
+use embassy_stm32::flash::{BANK1_REGION, Flash};
+use embassy_sync::blocking_mutex::Mutex;
+
+#[entry]
+fn main() -> ! {
+    let mut config = embassy_stm32::Config::default();
+    {
+        use embassy_stm32::rcc::*;
+        // USB runs from the HSI48 trimmed by the CRS, like in the application
+        config.rcc.hsi48 = Some(Hsi48Config {
+            sync_from_usb: true,
+        });
+        config.rcc.mux.clk48sel = mux::Clk48sel::HSI48;
+    }
+    let p = embassy_stm32::init(config);
+
+    let layout = Flash::new_blocking(p.FLASH).into_blocking_regions();
+    let flash = Mutex::new(RefCell::new(layout.bank1_region));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let active_size = config.active.size();
+    let bl = BootLoader::prepare::<_, _, _, 2048>(config);
+
+    if bl.state == State::DfuDetach {
+        info!("Entering DFU mode");
+        // Resets the chip once a verified image has been downloaded
+        dfu::run(p.USB, p.PA12, p.PA11, &flash, active_size);
+    }
+
+    unsafe { bl.load(BANK1_REGION.base + active_offset) }
+}
+
+#[unsafe(no_mangle)]
+#[cfg_attr(target_os = "none", unsafe(link_section = ".HardFault.user"))]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    const SCB_ICSR: *const u32 = 0xE000_ED04 as *const u32;
+    let irqn = unsafe { core::ptr::read_volatile(SCB_ICSR) } as u8 as i16 - 16;
+
+    defmt::panic!("DefaultHandler #{:?}", irqn);
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::asm::udf();
+}
@@ -0,0 +1,18 @@
+//! Selects the linker memory layout: the whole flash for standalone firmware,
+//! or the ACTIVE partition when running behind the bootloader.
+
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let layout = if env::var_os("CARGO_FEATURE_BOOTLOADER").is_some() {
+        "memory-bootloader.x"
+    } else {
+        "memory-standalone.x"
+    };
+    fs::copy(layout, out.join("memory.x")).unwrap();
+
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory-standalone.x");
+    println!("cargo:rerun-if-changed=memory-bootloader.x");
+}
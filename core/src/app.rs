@@ -0,0 +1,432 @@
+// core/src/app.rs
+// Everything the main loop does between the sensors and the panel
+//
+// Fed once per loop with the raw port readings, plus button presses, PD
+// status and trace records as they arrive. Corrects the currents with the
+// stored calibration and runs new calibrations, keeps the usage counters,
+// sessions and trend history, logs notable events, shows notices and draws
+// the current page or the settings menu, arms and stops burst captures, or
+// puts the panel to sleep when nobody is looking. The firmware and the
+// simulator both run it; the board is only reached through the `hal` traits.
+
+use embassy_time::Instant;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use crate::attach::AttachEvent;
+use crate::calibration::{self, Calibration};
+use crate::display::Page;
+use crate::display::dashboard::{Dashboard, Error};
+use crate::display::event_log::EventLogPage;
+use crate::display::notice::Notice;
+use crate::display::orientation::Oriented;
+use crate::display::pd_contract::{self, PdContractPage};
+use crate::display::pd_trace::PdTracePage;
+use crate::display::port::PortPage;
+use crate::display::scope::{self, ScopePage};
+use crate::display::screensaver::ScreenSaver;
+use crate::display::settings_menu::{Outcome, SettingsMenu};
+use crate::display::trends::TrendsPage;
+use crate::event_log::ring::Event;
+use crate::hal::{Button, Panel, Platform, Press, Sound};
+use crate::history::PortHistory;
+use crate::pd::policy::{Contract, PdStatus};
+use crate::pd::trace::TraceRecord;
+use crate::shared::{PORT_COUNT, PortReadings};
+use crate::storage::counters::Counters;
+use crate::storage::settings::{CurrentCorrection, Settings};
+use crate::usage::{Session, UsageEvent, UsageTracker};
+
+fn milliamps(amps: f32) -> u32 {
+    libm::roundf(libm::fabsf(amps) * 1000.0) as u32
+}
+
+fn sleep_timeout_ms(settings: &Settings) -> u64 {
+    settings.sleep_minutes as u64 * 60_000
+}
+
+/// Backlight level of a brightness in percent
+fn backlight_level(percent: u8) -> u8 {
+    (percent.min(100) as u32 * 255 / 100) as u8
+}
+
+pub struct App {
+    settings: Settings,
+    readings: PortReadings,
+    last_sample: Instant,
+    sensor_failed: [bool; PORT_COUNT],
+    last_contract: Option<Contract>,
+    usage: UsageTracker,
+    histories: [PortHistory; PORT_COUNT],
+    page: Page,
+    // The page comes back from scratch, after a switch or a notice
+    redraw: bool,
+    screensaver: ScreenSaver,
+    // What the panel was last told
+    panel_asleep: bool,
+    shift: (u16, u16),
+    brightness: Option<u8>,
+    notice: Notice,
+    menu: SettingsMenu,
+    calibration: Option<Calibration>,
+    dashboard: Dashboard,
+    trends_page: TrendsPage,
+    port_page: PortPage,
+    contract_page: PdContractPage,
+    pd_page: PdTracePage,
+    event_page: EventLogPage,
+    scope_page: ScopePage,
+}
+
+impl App {
+    pub fn new(settings: Settings, counters: Counters) -> Self {
+        let now = Instant::now();
+        let screensaver = ScreenSaver::new(sleep_timeout_ms(&settings), now.as_millis());
+        Self {
+            settings,
+            readings: [(0.0, 0.0, 0.0); PORT_COUNT],
+            last_sample: now,
+            sensor_failed: [false; PORT_COUNT],
+            last_contract: None,
+            usage: UsageTracker::new(counters, now.as_millis()),
+            histories: [PortHistory::new(); PORT_COUNT],
+            page: Page::Dashboard,
+            redraw: true,
+            screensaver,
+            panel_asleep: false,
+            shift: (0, 0),
+            brightness: None,
+            notice: Notice::new(),
+            menu: SettingsMenu::new(),
+            calibration: None,
+            dashboard: Dashboard::new(),
+            trends_page: TrendsPage::new(),
+            port_page: PortPage::new(),
+            contract_page: PdContractPage::new(),
+            pd_page: PdTracePage::new(),
+            event_page: EventLogPage::new(),
+            scope_page: ScopePage::new(),
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        if settings.rotated != self.settings.rotated || settings.theme != self.settings.theme {
+            self.redraw = true;
+        }
+        if settings.sleep_minutes != self.settings.sleep_minutes {
+            self.screensaver
+                .set_timeout(sleep_timeout_ms(&settings), Instant::now().as_millis());
+        }
+        self.settings = settings;
+    }
+
+    pub fn page(&self) -> Page {
+        self.page
+    }
+
+    /// Latest corrected readings, failed sensors read as zero
+    pub fn readings(&self) -> &PortReadings {
+        &self.readings
+    }
+
+    pub fn counters(&self) -> &Counters {
+        self.usage.counters()
+    }
+
+    pub fn sessions(&self) -> &[Session; PORT_COUNT] {
+        self.usage.sessions()
+    }
+
+    /// Start a calibration run on the next `SAMPLES` updates, replacing one
+    /// still running
+    pub fn calibrate(&mut self, request: calibration::Request) {
+        self.calibration = Some(Calibration::new(request));
+        self.notice.show("Calibrating...", Rgb565::WHITE);
+    }
+
+    /// Feed one sample of every port, as read from the sensors; `sensor_ok`
+    /// is false where the INA226 didn't answer
+    pub fn update(
+        &mut self,
+        platform: &mut impl Platform,
+        mut readings: PortReadings,
+        sensor_ok: [bool; PORT_COUNT],
+    ) {
+        // Log a sensor error once per outage
+        for (port, ok) in sensor_ok.into_iter().enumerate() {
+            if !ok && !self.sensor_failed[port] {
+                platform.record(Event::SensorError { port: port as u8 });
+            }
+            self.sensor_failed[port] = !ok;
+        }
+
+        if let Some(result) = self.calibration.as_mut().and_then(|calibration| {
+            calibration.feed(&readings, sensor_ok, &self.settings.corrections)
+        }) {
+            self.calibration = None;
+            self.calibrated(platform, result);
+        }
+        for ((reading, correction), ok) in readings
+            .iter_mut()
+            .zip(&self.settings.corrections)
+            .zip(sensor_ok)
+        {
+            if ok {
+                let (volts, amps, _) = *reading;
+                let amps = correction.apply(amps);
+                *reading = (volts, amps, volts * amps);
+            }
+        }
+        self.readings = readings;
+
+        let now = Instant::now();
+        let dt_ms = (now - self.last_sample).as_millis() as u32;
+        self.last_sample = now;
+        for event in self
+            .usage
+            .update(&readings, &self.settings.thresholds, dt_ms)
+        {
+            // Anything happening on a port wakes the display
+            self.screensaver.activity(now.as_millis());
+            match event {
+                UsageEvent::Attach { port, event } => {
+                    let mut text = String::<26>::new();
+                    let _ =
+                        core::fmt::write(&mut text, format_args!("P{} {}", port + 1, event.name()));
+                    self.notice.show(&text, Rgb565::WHITE);
+                    match event {
+                        AttachEvent::Attached => {
+                            platform.record(Event::Plugged { port: port as u8 });
+                            platform.play(Sound::Attach);
+                        }
+                        AttachEvent::Detached => {
+                            platform.record(Event::Unplugged { port: port as u8 });
+                            platform.play(Sound::Detach);
+                        }
+                        AttachEvent::Charging | AttachEvent::Idle => {}
+                    }
+                }
+                UsageEvent::Trip { port, kind } => platform.record(Event::Trip {
+                    port: port as u8,
+                    kind,
+                }),
+            }
+        }
+
+        // Trend graphs, one point per second
+        for (history, &(_, amps, watts)) in self.histories.iter_mut().zip(&readings) {
+            let power_mw = libm::roundf(watts.max(0.0) * 1000.0) as u32;
+            history.update(milliamps(amps), power_mw, dt_ms);
+        }
+
+        let save_interval_ms = self.settings.counter_save_minutes as u64 * 60_000;
+        if self.usage.save_due(now.as_millis(), save_interval_ms) {
+            // Even a failed save waits for the next interval, no retry storm
+            platform.save_counters(self.usage.counters());
+            self.usage.mark_saved(now.as_millis());
+        }
+
+        self.contract_page
+            .update_input_voltage(readings[pd_contract::INPUT_SENSOR].0);
+        self.dashboard.update_data(readings);
+    }
+
+    fn calibrated(
+        &mut self,
+        platform: &mut impl Platform,
+        result: Result<[CurrentCorrection; PORT_COUNT], calibration::Error>,
+    ) {
+        match result {
+            Ok(corrections) => {
+                let settings = Settings {
+                    corrections,
+                    ..self.settings
+                };
+                platform.save_settings(&settings);
+                self.set_settings(settings);
+                self.menu.set_corrections(corrections);
+                self.notice.show("Calibration saved", Rgb565::WHITE);
+            }
+            Err(e) => {
+                let mut text = String::<26>::new();
+                let _ = core::fmt::write(&mut text, format_args!("Cal failed: {}", e));
+                self.notice.show(&text, Rgb565::RED);
+            }
+        }
+    }
+
+    pub fn pd_status(&mut self, platform: &mut impl Platform, status: PdStatus) {
+        if status.contract != self.last_contract {
+            self.last_contract = status.contract;
+            platform.record(match status.contract {
+                Some(contract) => Event::PdContract {
+                    voltage_mv: contract.voltage_mv as u16,
+                    current_ma: contract.current_ma as u16,
+                },
+                None => Event::PdNoContract,
+            });
+        }
+        self.contract_page.update_status(status);
+    }
+
+    /// Follow the PD trace even while another page is shown
+    pub fn pd_trace(&mut self, record: &TraceRecord) {
+        self.pd_page.feed(record);
+    }
+
+    /// A press of `button` as reported by the board; settings saved from
+    /// the menu go to `platform`
+    pub fn press(&mut self, platform: &mut impl Platform, button: Button, press: Press) {
+        // A press that wakes the display does only that
+        if self.screensaver.activity(Instant::now().as_millis()) {
+            return;
+        }
+        // A press while a notice is up only dismisses it
+        if self.notice.is_active() {
+            self.notice.dismiss();
+            return;
+        }
+        // Upside down, BTN3 is on the left
+        let button = if self.settings.rotated {
+            button.mirrored()
+        } else {
+            button
+        };
+
+        if self.menu.is_open() {
+            match self.menu.press(button, press) {
+                Outcome::Open => {}
+                Outcome::Closed { discarded } => {
+                    if discarded {
+                        self.notice.show("Changes dropped", Rgb565::WHITE);
+                    }
+                    self.redraw = true;
+                }
+                Outcome::Save(settings) => {
+                    platform.save_settings(&settings);
+                    self.set_settings(settings);
+                    self.notice.show("Settings saved", Rgb565::WHITE);
+                    self.redraw = true;
+                }
+                Outcome::Calibrate(request) => self.calibrate(request),
+            }
+            return;
+        }
+        if self.page == Page::Scope {
+            match self.scope_page.press(button, press) {
+                scope::Outcome::Ignored => {}
+                scope::Outcome::Handled => return,
+                scope::Outcome::Capture { run } => {
+                    platform.arm_capture(run.then_some(self.settings.capture_trigger));
+                    return;
+                }
+            }
+        }
+        match press {
+            Press::Short if button == Button::Right => self.page = self.page.next(),
+            Press::Short => self.page = self.page.previous(),
+            Press::Long => self.menu.open(self.settings),
+            // Holding a button doesn't race through the pages
+            Press::Repeat(_) => return,
+        }
+        self.redraw = true;
+    }
+
+    /// Draw the notice, the menu or the current page, only what changed
+    pub async fn draw(
+        &mut self,
+        panel: &mut impl Panel,
+        platform: &impl Platform,
+    ) -> Result<(), Error> {
+        let now_ms = Instant::now().as_millis();
+        self.screensaver.update(now_ms);
+        if self.screensaver.is_asleep() != self.panel_asleep {
+            self.panel_asleep = self.screensaver.is_asleep();
+            panel.sleep(self.panel_asleep).await?;
+        }
+        if self.panel_asleep {
+            return Ok(());
+        }
+        // The menu shows the brightness being edited
+        let brightness = self
+            .menu
+            .draft()
+            .map_or(self.settings.brightness, |draft| draft.brightness);
+        if self.brightness != Some(brightness) {
+            self.brightness = Some(brightness);
+            panel.set_brightness(backlight_level(brightness)).await?;
+        }
+        let shift = self.screensaver.shift(now_ms);
+        if shift != self.shift {
+            self.shift = shift;
+            self.redraw = true;
+        }
+
+        let panel = &mut Oriented::new(
+            panel,
+            self.settings.rotated,
+            self.shift,
+            self.settings.theme,
+        );
+        if self.notice.take_ended() {
+            self.redraw = true;
+        }
+        if self.redraw {
+            self.redraw = false;
+            panel.fill_color(Rgb565::BLACK).await?;
+            self.contract_page.invalidate();
+            self.pd_page.invalidate();
+            self.event_page.invalidate();
+            self.port_page.invalidate();
+            self.trends_page.invalidate();
+            self.scope_page.invalidate();
+            self.menu.invalidate();
+        }
+
+        if self.notice.is_active() {
+            return self.notice.draw(panel).await;
+        }
+        if self.menu.is_open() {
+            return self.menu.draw(panel).await;
+        }
+        match self.page {
+            Page::Dashboard => self.dashboard.draw(panel).await,
+            Page::Trends => {
+                self.trends_page
+                    .draw(panel, &self.histories, self.settings.trend)
+                    .await
+            }
+            Page::Port(port) => {
+                self.port_page
+                    .draw(
+                        panel,
+                        port,
+                        milliamps(self.readings[port].1),
+                        &self.usage.sessions()[port],
+                        &self.histories[port],
+                        self.settings.trend,
+                    )
+                    .await
+            }
+            Page::PdContract => self.contract_page.draw(panel).await,
+            Page::PdTrace => self.pd_page.draw(panel).await,
+            Page::EventLog => {
+                self.event_page
+                    .draw(panel, platform.next_event_seq(), || platform.events())
+                    .await
+            }
+            Page::Scope => {
+                self.scope_page
+                    .draw(
+                        panel,
+                        &self.histories,
+                        self.settings.capture_trigger,
+                        platform.capture_status(),
+                        || platform.capture(),
+                    )
+                    .await
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// core/src/attach/mod.rs
+// Device attach detection for a downstream port
+//
+// A pure state machine fed with every sample of the port's INA226. A device
+// counts as attached once it draws current for a while, as charging while
+// it draws clearly more, and as gone once the current stays near zero or
+// VBUS disappears. Every transition needs its condition to hold for a
+// minimum dwell time, and the enter/leave thresholds are apart, so inrush
+// spikes, cable wiggles and noisy trickle currents don't produce events.
+//
+//            >= ATTACH_MA, 0.5 s          >= CHARGING_MA, 2 s
+//   Detached ------------------> Idle ------------------------> Charging
+//            <------------------      <------------------------
+//             < DETACH_MA, 3 s              < IDLE_MA, 5 s
+//
+// Charging also goes straight to Detached; losing VBUS detaches at once.
+// Host tests replay the sample traces in `traces/`.
+
+// Current thresholds with hysteresis, mA
+const ATTACH_MA: u32 = 30;
+const DETACH_MA: u32 = 10;
+const CHARGING_MA: u32 = 150;
+const IDLE_MA: u32 = 100;
+// VBUS counts as present above PRESENT and as lost below LOST, mV
+const VBUS_PRESENT_MV: u32 = 4400;
+const VBUS_LOST_MV: u32 = 4000;
+
+// Minimum dwell times, ms
+const ATTACH_DWELL_MS: u32 = 500;
+const DETACH_DWELL_MS: u32 = 3000;
+const CHARGING_DWELL_MS: u32 = 2000;
+const IDLE_DWELL_MS: u32 = 5000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AttachState {
+    #[default]
+    Detached,
+    /// Attached, drawing little or no charge current
+    Idle,
+    Charging,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachEvent {
+    Attached,
+    Detached,
+    Charging,
+    Idle,
+}
+
+impl AttachEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            AttachEvent::Attached => "attached",
+            AttachEvent::Detached => "detached",
+            AttachEvent::Charging => "charging",
+            AttachEvent::Idle => "idle",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AttachDetector {
+    state: AttachState,
+    // State the samples point to and for how long they did
+    pending: Option<AttachState>,
+    pending_ms: u32,
+}
+
+impl AttachDetector {
+    pub const fn new() -> Self {
+        Self {
+            state: AttachState::Detached,
+            pending: None,
+            pending_ms: 0,
+        }
+    }
+
+    pub fn state(&self) -> AttachState {
+        self.state
+    }
+
+    /// Feed one sample that stood for the last `dt_ms` milliseconds
+    pub fn update(&mut self, voltage_mv: u32, current_ma: u32, dt_ms: u32) -> Option<AttachEvent> {
+        let vbus_lost = voltage_mv < VBUS_LOST_MV;
+        let target = match self.state {
+            AttachState::Detached => {
+                if voltage_mv >= VBUS_PRESENT_MV && current_ma >= ATTACH_MA {
+                    AttachState::Idle
+                } else {
+                    AttachState::Detached
+                }
+            }
+            _ if vbus_lost || current_ma < DETACH_MA => AttachState::Detached,
+            AttachState::Idle if current_ma >= CHARGING_MA => AttachState::Charging,
+            AttachState::Charging if current_ma < IDLE_MA => AttachState::Idle,
+            state => state,
+        };
+
+        if target == self.state {
+            self.pending = None;
+            return None;
+        }
+        if self.pending != Some(target) {
+            self.pending = Some(target);
+            self.pending_ms = 0;
+        }
+        self.pending_ms = self.pending_ms.saturating_add(dt_ms);
+
+        let dwell_ms = match (self.state, target) {
+            (_, AttachState::Detached) if vbus_lost => 0,
+            (_, AttachState::Detached) => DETACH_DWELL_MS,
+            (AttachState::Detached, _) => ATTACH_DWELL_MS,
+            (_, AttachState::Charging) => CHARGING_DWELL_MS,
+            (_, AttachState::Idle) => IDLE_DWELL_MS,
+        };
+        if self.pending_ms < dwell_ms {
+            return None;
+        }
+
+        let event = match (self.state, target) {
+            (_, AttachState::Detached) => AttachEvent::Detached,
+            (AttachState::Detached, _) => AttachEvent::Attached,
+            (_, AttachState::Charging) => AttachEvent::Charging,
+            (_, AttachState::Idle) => AttachEvent::Idle,
+        };
+        self.state = target;
+        self.pending = None;
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    /// Events with the trace time they were emitted at
+    fn replay(trace: &str) -> Vec<(u32, AttachEvent)> {
+        let mut detector = AttachDetector::new();
+        let mut events = Vec::new();
+        let mut last_ms = 0;
+        for line in trace.lines().filter(|l| !l.starts_with('#')) {
+            let fields: Vec<u32> = line.split(',').map(|f| f.parse().unwrap()).collect();
+            let [t_ms, mv, ma] = fields[..] else {
+                panic!("bad trace line {line}");
+            };
+            let dt_ms = if t_ms == 0 { 100 } else { t_ms - last_ms };
+            last_ms = t_ms;
+            if let Some(event) = detector.update(mv, ma, dt_ms) {
+                events.push((t_ms, event));
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn phone_charge_session() {
+        assert_eq!(
+            replay(include_str!("traces/phone.csv")),
+            [
+                (2400, AttachEvent::Attached),
+                (5200, AttachEvent::Charging),
+                (31600, AttachEvent::Idle),
+                (37600, AttachEvent::Detached),
+            ]
+        );
+    }
+
+    #[test]
+    fn trickle_near_threshold_stays_attached() {
+        assert_eq!(
+            replay(include_str!("traces/earbuds.csv")),
+            [
+                (1400, AttachEvent::Attached),
+                (3400, AttachEvent::Charging),
+                (11900, AttachEvent::Idle),
+                (23900, AttachEvent::Detached),
+            ]
+        );
+    }
+
+    #[test]
+    fn glitches_on_an_empty_port_are_ignored() {
+        assert_eq!(replay(include_str!("traces/glitches.csv")), []);
+    }
+
+    #[test]
+    fn vbus_loss_detaches_at_once() {
+        let events = replay(include_str!("traces/port_off.csv"));
+        assert_eq!(events.last(), Some(&(9000, AttachEvent::Detached)));
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn dwell_restarts_when_the_condition_breaks() {
+        let mut detector = AttachDetector::new();
+        for _ in 0..4 {
+            assert_eq!(detector.update(5000, 50, 100), None);
+        }
+        assert_eq!(detector.update(5000, 5, 100), None);
+        for _ in 0..4 {
+            assert_eq!(detector.update(5000, 50, 100), None);
+        }
+        assert_eq!(detector.update(5000, 50, 100), Some(AttachEvent::Attached));
+        assert_eq!(detector.state(), AttachState::Idle);
+    }
+}
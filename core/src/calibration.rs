@@ -0,0 +1,228 @@
+// core/src/calibration.rs
+// Offset and gain calibration of the current readings
+//
+// On an idle 10 mΩ shunt the INA226 reads a few mA either way, as much as a
+// phone draws when it is full. A zero run averages the downstream ports
+// with nothing plugged in and stores what they read as their offsets; a
+// gain run averages one port carrying a known current, set on a bench
+// supply or an electronic load, and stores the ratio of the reference to
+// the reading without the offset. `App` feeds a run the raw readings, one
+// sample per loop, and applies the stored corrections to every sample.
+
+use core::fmt;
+
+use crate::shared::{INPUT_PORT, PORT_COUNT, PortReadings};
+use crate::storage::settings::CurrentCorrection;
+
+/// Samples averaged by a run, 2 s of the main loop
+pub const SAMPLES: u32 = 20;
+/// Largest idle reading taken for an offset; more means a device is drawing
+/// power
+pub const MAX_OFFSET_UA: i32 = 50_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Nothing plugged into the downstream ports. The input has no zero
+    /// point, its shunt always carries the hub's own supply current
+    Zero,
+    /// `reference_ma` flowing through `port`
+    Gain { port: usize, reference_ma: u32 },
+}
+
+impl Request {
+    /// Whether the run averages `port`
+    pub fn measures(&self, port: usize) -> bool {
+        match *self {
+            Request::Zero => port != INPUT_PORT,
+            Request::Gain { port: p, .. } => port == p,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The sensor failed during the run
+    NoReading { port: usize },
+    /// More than `MAX_OFFSET_UA` on a port that should be idle
+    Loaded { port: usize },
+    /// The gain is outside `CurrentCorrection::GAIN_RANGE_PPM`
+    OutOfRange { port: usize },
+}
+
+/// At most 20 characters, for "Cal failed: ..."
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::NoReading { port } => write!(f, "P{} no reading", port + 1),
+            Error::Loaded { port } => write!(f, "P{} not idle", port + 1),
+            Error::OutOfRange { port } => write!(f, "P{} off range", port + 1),
+        }
+    }
+}
+
+pub struct Calibration {
+    request: Request,
+    // Raw currents in A, summed over the samples so far
+    sums: [f32; PORT_COUNT],
+    samples: u32,
+    failed: Option<usize>,
+}
+
+impl Calibration {
+    pub fn new(request: Request) -> Self {
+        Self {
+            request,
+            sums: [0.0; PORT_COUNT],
+            samples: 0,
+            failed: None,
+        }
+    }
+
+    pub fn request(&self) -> Request {
+        self.request
+    }
+
+    /// Add one sample of raw readings. Once `SAMPLES` are in, returns
+    /// `corrections` with the result of the run applied
+    pub fn feed(
+        &mut self,
+        readings: &PortReadings,
+        sensor_ok: [bool; PORT_COUNT],
+        corrections: &[CurrentCorrection; PORT_COUNT],
+    ) -> Option<Result<[CurrentCorrection; PORT_COUNT], Error>> {
+        for port in (0..PORT_COUNT).filter(|&port| self.request.measures(port)) {
+            if !sensor_ok[port] {
+                self.failed.get_or_insert(port);
+            }
+            self.sums[port] += readings[port].1;
+        }
+        self.samples += 1;
+        if self.samples < SAMPLES {
+            return None;
+        }
+        Some(match self.failed {
+            Some(port) => Err(Error::NoReading { port }),
+            None => self.result(*corrections),
+        })
+    }
+
+    fn mean_ua(&self, port: usize) -> i32 {
+        libm::roundf(self.sums[port] / self.samples as f32 * 1_000_000.0) as i32
+    }
+
+    fn result(
+        &self,
+        mut corrections: [CurrentCorrection; PORT_COUNT],
+    ) -> Result<[CurrentCorrection; PORT_COUNT], Error> {
+        match self.request {
+            Request::Zero => {
+                for port in (0..PORT_COUNT).filter(|&port| self.request.measures(port)) {
+                    let offset_ua = self.mean_ua(port);
+                    if offset_ua.abs() > MAX_OFFSET_UA {
+                        return Err(Error::Loaded { port });
+                    }
+                    corrections[port].offset_ua = offset_ua;
+                }
+            }
+            Request::Gain { port, reference_ma } => {
+                // The second point, the first is the stored offset
+                let measured_ua = self.mean_ua(port) as i64 - corrections[port].offset_ua as i64;
+                let gain_ppm = (measured_ua > 0)
+                    .then(|| reference_ma as i64 * 1_000_000_000 / measured_ua)
+                    .and_then(|gain| u32::try_from(gain).ok())
+                    .filter(|gain| CurrentCorrection::GAIN_RANGE_PPM.contains(gain))
+                    .ok_or(Error::OutOfRange { port })?;
+                corrections[port].gain_ppm = gain_ppm;
+            }
+        }
+        Ok(corrections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::ToString;
+
+    use super::*;
+
+    fn run(
+        calibration: &mut Calibration,
+        amps: [f32; PORT_COUNT],
+        corrections: &[CurrentCorrection; PORT_COUNT],
+    ) -> Result<[CurrentCorrection; PORT_COUNT], Error> {
+        let readings = amps.map(|a| (5.0, a, 5.0 * a));
+        for _ in 1..SAMPLES {
+            assert_eq!(
+                calibration.feed(&readings, [true; PORT_COUNT], corrections),
+                None
+            );
+        }
+        calibration
+            .feed(&readings, [true; PORT_COUNT], corrections)
+            .unwrap()
+    }
+
+    #[test]
+    fn zero_then_gain_corrects_both_points() {
+        let none = [CurrentCorrection::NONE; PORT_COUNT];
+        // The input carries the hub's own current and is left alone
+        let zeroed = run(
+            &mut Calibration::new(Request::Zero),
+            [0.08, 0.0037, -0.0021],
+            &none,
+        )
+        .unwrap();
+        assert_eq!(zeroed[INPUT_PORT], CurrentCorrection::NONE);
+        assert_eq!(zeroed[1].offset_ua, 3700);
+        assert_eq!(zeroed[2].offset_ua, -2100);
+
+        // 1 A through a shunt 2% above nominal reads 1.02 A plus the offset
+        let request = Request::Gain {
+            port: 1,
+            reference_ma: 1000,
+        };
+        let gained = run(&mut Calibration::new(request), [0.5, 1.0237, 0.0], &zeroed).unwrap();
+        assert_eq!(gained[1].offset_ua, 3700);
+        assert!((gained[1].gain() - 1.0 / 1.02).abs() < 1e-4);
+        assert_eq!(gained[2], zeroed[2]);
+
+        assert!(gained[1].apply(0.0037).abs() < 1e-6);
+        assert!((gained[1].apply(2.0437) - 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rejects_loads_wrong_references_and_failed_sensors() {
+        let none = [CurrentCorrection::NONE; PORT_COUNT];
+        assert_eq!(
+            run(&mut Calibration::new(Request::Zero), [0.0, 0.0, 0.3], &none),
+            Err(Error::Loaded { port: 2 })
+        );
+
+        let gain = |reference_ma| {
+            Calibration::new(Request::Gain {
+                port: 2,
+                reference_ma,
+            })
+        };
+        // 2 A set on the load but 1 A flowing, and a load connected backwards
+        assert_eq!(
+            run(&mut gain(2000), [0.0, 0.0, 1.0], &none),
+            Err(Error::OutOfRange { port: 2 })
+        );
+        assert_eq!(
+            run(&mut gain(1000), [0.0, 0.0, -1.0], &none),
+            Err(Error::OutOfRange { port: 2 })
+        );
+
+        // One failed sample spoils the run, other ports don't matter
+        let mut calibration = gain(1000);
+        let readings = [(5.0, 1.0, 5.0); PORT_COUNT];
+        calibration.feed(&readings, [true, true, false], &none);
+        calibration.feed(&readings, [false, true, true], &none);
+        let result = (2..SAMPLES)
+            .filter_map(|_| calibration.feed(&readings, [true; PORT_COUNT], &none))
+            .next();
+        assert_eq!(result, Some(Err(Error::NoReading { port: 2 })));
+        assert_eq!(Error::OutOfRange { port: 2 }.to_string(), "P3 off range");
+    }
+}
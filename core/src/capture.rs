@@ -0,0 +1,443 @@
+// core/src/capture.rs
+// Triggered burst capture of one port's current
+//
+// The main loop reads every sensor each 100 ms and never sees the inrush of
+// a device being plugged in. A capture switches one INA226 to shunt voltage
+// only, 140 us conversions without averaging, and reads it every
+// `SAMPLE_PERIOD_US`. While armed, `Capture` keeps the latest samples in a
+// ring; the first sample at or above the trigger level after one below it
+// triggers, and the window fills up with `PRE_TRIGGER` samples before the
+// trigger and the rest after it. A trigger that doesn't come within
+// `ARM_TIMEOUT_US` stops the capture. The register access is here as well,
+// generic over the async I2C traits so it runs against the mock INA226; the
+// firmware provides the sampling task and the clock.
+
+use embedded_hal_async::i2c::I2c;
+
+use crate::shared::PORT_COUNT;
+use crate::storage::settings::{CurrentCorrection, SensorProfile};
+
+/// Samples in a window, about 0.3 s
+pub const CAPTURE_SAMPLES: usize = 512;
+/// Samples kept from before the trigger
+pub const PRE_TRIGGER: usize = CAPTURE_SAMPLES / 4;
+/// One register read takes about 480 us at 100 kHz I2C, the rest of the
+/// period leaves the bus to the main loop's sensor reads
+pub const SAMPLE_PERIOD_US: u64 = 600;
+/// Longest wait for the trigger
+pub const ARM_TIMEOUT_US: u64 = 60_000_000;
+
+const REG_CONFIG: u8 = 0x00;
+const REG_SHUNT_VOLTAGE: u8 = 0x01;
+/// Shunt voltage only, continuous, 140 us conversions, no averaging
+pub const CONFIG_FAST: u16 = 0x4005;
+// Shunt voltage LSB is 2.5 uV
+const SHUNT_LSB_NV: i64 = 2500;
+
+/// What starts a capture: the current of `port` rising through `level_ma`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trigger {
+    pub port: usize,
+    pub level_ma: u32,
+}
+
+impl Default for Trigger {
+    /// First downstream port, above what an idle device draws
+    fn default() -> Self {
+        Self {
+            port: 1,
+            level_ma: 500,
+        }
+    }
+}
+
+impl Trigger {
+    pub fn is_valid(&self) -> bool {
+        self.port < PORT_COUNT && self.level_ma > 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Nothing captured since boot
+    Empty,
+    /// Sampling into the ring, waiting for the trigger
+    Armed,
+    /// Triggered, filling the rest of the window
+    Filling,
+    /// A full window, kept until the next arm
+    Done,
+    /// Stopped before the window was full, by the user, a sensor error or
+    /// the arm timeout
+    Stopped,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Empty => "empty",
+            State::Armed => "armed",
+            State::Filling => "triggered",
+            State::Done => "done",
+            State::Stopped => "stopped",
+        }
+    }
+}
+
+/// What a capture is doing, without its samples
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Status {
+    /// Changes with every change of state, to notice new windows
+    pub seq: u32,
+    pub state: State,
+    pub trigger: Option<Trigger>,
+}
+
+impl Status {
+    /// Waiting for the trigger or filling the window
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Armed | State::Filling)
+    }
+}
+
+#[derive(Clone)]
+pub struct Capture {
+    trigger: Option<Trigger>,
+    state: State,
+    seq: u32,
+    // Current in uA; a ring until the window is complete, then in time order
+    samples: [i32; CAPTURE_SAMPLES],
+    head: usize,
+    filled: usize,
+    // The last sample was below the level, so the next may trigger
+    below: bool,
+    pre: usize,
+    remaining: usize,
+    // Time of the first sample after the arm
+    armed_us: Option<u64>,
+    trigger_us: u64,
+    period_us: u32,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Capture {
+    pub const fn new() -> Self {
+        Self {
+            trigger: None,
+            state: State::Empty,
+            seq: 0,
+            samples: [0; CAPTURE_SAMPLES],
+            head: 0,
+            filled: 0,
+            below: false,
+            pre: 0,
+            remaining: 0,
+            armed_us: None,
+            trigger_us: 0,
+            period_us: 0,
+        }
+    }
+
+    /// Drop the last window and wait for `trigger`
+    pub fn arm(&mut self, trigger: Trigger) {
+        *self = Self {
+            trigger: Some(trigger),
+            state: State::Armed,
+            seq: self.seq.wrapping_add(1),
+            ..Self::new()
+        };
+    }
+
+    /// Give up waiting or filling; a finished window is kept
+    pub fn stop(&mut self) {
+        if self.status().is_running() {
+            self.state = State::Stopped;
+            self.seq = self.seq.wrapping_add(1);
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            seq: self.seq,
+            state: self.state,
+            trigger: self.trigger,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Add a sample taken at `at_us`; true once the window is complete.
+    /// Stops the capture when the trigger takes longer than `ARM_TIMEOUT_US`.
+    pub fn feed(&mut self, current_ua: i32, at_us: u64) -> bool {
+        let Some(trigger) = self.trigger else {
+            return false;
+        };
+        match self.state {
+            State::Armed => {
+                let armed_us = *self.armed_us.get_or_insert(at_us);
+                if at_us.saturating_sub(armed_us) >= ARM_TIMEOUT_US {
+                    self.stop();
+                    return false;
+                }
+                let above = current_ua as i64 >= trigger.level_ma as i64 * 1000;
+                if above && self.below {
+                    self.pre = self.filled.min(PRE_TRIGGER);
+                    self.remaining = CAPTURE_SAMPLES - self.pre;
+                    self.trigger_us = at_us;
+                    self.state = State::Filling;
+                    self.seq = self.seq.wrapping_add(1);
+                }
+                self.below = !above;
+            }
+            State::Filling => {}
+            State::Empty | State::Done | State::Stopped => return false,
+        }
+
+        self.samples[self.head] = current_ua;
+        self.head = (self.head + 1) % CAPTURE_SAMPLES;
+        self.filled = (self.filled + 1).min(CAPTURE_SAMPLES);
+        if self.state == State::Filling {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.finish(at_us);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn finish(&mut self, last_us: u64) {
+        // The ring is full, its oldest sample is the first of the window
+        self.samples.rotate_left(self.head);
+        self.head = 0;
+        let intervals = (CAPTURE_SAMPLES - self.pre - 1).max(1) as u64;
+        self.period_us = (last_us.saturating_sub(self.trigger_us) / intervals) as u32;
+        self.state = State::Done;
+        self.seq = self.seq.wrapping_add(1);
+    }
+
+    /// The window in time order, empty unless `Done`
+    pub fn samples(&self) -> &[i32] {
+        match self.state {
+            State::Done => &self.samples,
+            _ => &[],
+        }
+    }
+
+    /// Position of the trigger sample in `samples`
+    pub fn trigger_index(&self) -> usize {
+        self.pre
+    }
+
+    /// Mean time between samples of the window
+    pub fn period_us(&self) -> u32 {
+        self.period_us
+    }
+
+    /// Largest current of the window, 0 unless `Done`
+    pub fn peak_ua(&self) -> i32 {
+        self.samples().iter().copied().max().unwrap_or(0)
+    }
+}
+
+/// Switch the INA226 at `address` to `CONFIG_FAST`; returns the
+/// configuration to put back with `restore`
+pub async fn start_fast<I: I2c>(i2c: &mut I, address: u8) -> Result<u16, I::Error> {
+    let mut config = [0u8; 2];
+    i2c.write_read(address, &[REG_CONFIG], &mut config).await?;
+    let [msb, lsb] = CONFIG_FAST.to_be_bytes();
+    i2c.write(address, &[REG_CONFIG, msb, lsb]).await?;
+    Ok(u16::from_be_bytes(config))
+}
+
+pub async fn restore<I: I2c>(i2c: &mut I, address: u8, config: u16) -> Result<(), I::Error> {
+    let [msb, lsb] = config.to_be_bytes();
+    i2c.write(address, &[REG_CONFIG, msb, lsb]).await
+}
+
+/// One sample in uA from the shunt voltage register, which works without
+/// the calibration register and whatever the bus voltage does; corrected
+/// like the regular readings
+pub async fn read_current_ua<I: I2c>(
+    i2c: &mut I,
+    address: u8,
+    sensor: &SensorProfile,
+    correction: &CurrentCorrection,
+) -> Result<i32, I::Error> {
+    let mut raw = [0u8; 2];
+    // The pointer is set every time, the main loop reads the same chip
+    i2c.write_read(address, &[REG_SHUNT_VOLTAGE], &mut raw)
+        .await?;
+    let shunt_nv = i16::from_be_bytes(raw) as i64 * SHUNT_LSB_NV;
+    let raw_ua = shunt_nv * 1000 / sensor.shunt_micro_ohms.max(1) as i64;
+    let amps = correction.apply(raw_ua as f32 / 1_000_000.0);
+    Ok(libm::roundf(amps * 1_000_000.0) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::mock::ina226::{Ina226, Ina226Bus, Register};
+    use crate::mock::waveform::Waveform;
+
+    const LEVEL: Trigger = Trigger {
+        port: 1,
+        level_ma: 500,
+    };
+
+    /// Feed `count` samples of `ua(i)`, 500 us apart from `first`
+    fn feed(capture: &mut Capture, first: usize, count: usize, ua: impl Fn(usize) -> i32) -> bool {
+        (first..first + count).any(|i| capture.feed(ua(i), i as u64 * 500))
+    }
+
+    #[test]
+    fn window_holds_samples_around_the_trigger() {
+        let mut capture = Capture::new();
+        capture.arm(LEVEL);
+        // Long idle, then a step at sample 1000
+        let inrush = |i: usize| if i < 1000 { i as i32 } else { 1_200_000 };
+        assert!(!feed(&mut capture, 0, 1000, inrush));
+        assert_eq!(capture.state(), State::Armed);
+        assert!(capture.samples().is_empty());
+        assert!(!feed(
+            &mut capture,
+            1000,
+            CAPTURE_SAMPLES - PRE_TRIGGER - 1,
+            inrush
+        ));
+        assert_eq!(capture.state(), State::Filling);
+        assert!(capture.feed(1_200_000, 0x1234_5678));
+        assert_eq!(capture.state(), State::Done);
+
+        let samples = capture.samples();
+        assert_eq!(capture.trigger_index(), PRE_TRIGGER);
+        assert_eq!(samples[0], (1000 - PRE_TRIGGER) as i32);
+        assert_eq!(samples[PRE_TRIGGER - 1], 999);
+        assert!(samples[PRE_TRIGGER..].iter().all(|&ua| ua == 1_200_000));
+        assert_eq!(capture.peak_ua(), 1_200_000);
+        // Nothing more goes in until it is armed again
+        assert!(!capture.feed(0, 0));
+    }
+
+    #[test]
+    fn triggers_on_a_rising_crossing_only() {
+        let mut capture = Capture::new();
+        // A device already drawing doesn't trigger until it dips below
+        capture.arm(LEVEL);
+        assert!(!feed(&mut capture, 0, 10, |_| 800_000));
+        assert_eq!(capture.state(), State::Armed);
+        feed(&mut capture, 10, 1, |_| 499_999);
+        feed(&mut capture, 11, 1, |_| 500_000);
+        assert_eq!(capture.state(), State::Filling);
+
+        // Early trigger: fewer samples before it, more after
+        capture.arm(LEVEL);
+        feed(&mut capture, 0, 3, |_| 0);
+        assert!(feed(&mut capture, 3, CAPTURE_SAMPLES - 3, |_| 600_000));
+        assert_eq!(capture.trigger_index(), 3);
+        assert_eq!(capture.samples().len(), CAPTURE_SAMPLES);
+        assert_eq!(capture.period_us(), 500);
+    }
+
+    #[test]
+    fn stop_and_rearm_change_the_sequence() {
+        let mut capture = Capture::new();
+        assert_eq!(capture.state(), State::Empty);
+        capture.stop();
+        assert_eq!(capture.status().seq, 0);
+        capture.arm(LEVEL);
+        let armed = capture.status();
+        assert!(armed.is_running());
+        capture.stop();
+        assert_eq!(capture.state(), State::Stopped);
+        assert_ne!(capture.status().seq, armed.seq);
+        assert!(!capture.feed(600_000, 0));
+
+        capture.arm(Trigger { port: 2, ..LEVEL });
+        assert_eq!(capture.status().trigger.map(|t| t.port), Some(2));
+        assert!(!Trigger { port: 3, ..LEVEL }.is_valid());
+    }
+
+    #[test]
+    fn stops_when_the_trigger_does_not_come() {
+        let mut capture = Capture::new();
+        capture.arm(LEVEL);
+        // The timeout counts from the first sample, not from boot
+        let first_us = 5_000_000;
+        assert!(!capture.feed(0, first_us));
+        assert!(!capture.feed(0, first_us + ARM_TIMEOUT_US - 1));
+        assert_eq!(capture.state(), State::Armed);
+        assert!(!capture.feed(600_000, first_us + ARM_TIMEOUT_US));
+        assert_eq!(capture.state(), State::Stopped);
+
+        // Once triggered, the window fills however long that takes
+        capture.arm(LEVEL);
+        feed(&mut capture, 0, 2, |i| i as i32 * 600_000);
+        assert_eq!(capture.state(), State::Filling);
+        assert!((0..CAPTURE_SAMPLES as u64).any(|i| capture.feed(600_000, ARM_TIMEOUT_US + i)));
+        assert_eq!(capture.state(), State::Done);
+    }
+
+    #[test]
+    fn samples_the_shunt_of_a_mock_sensor() {
+        const SENSOR: u8 = 0x41;
+        let mut bus = Ina226Bus::new();
+        bus.attach(
+            SENSOR,
+            Ina226::new(
+                0.01,
+                Waveform::Constant(5.0),
+                Waveform::Step {
+                    before: 0.004,
+                    after: 2.0,
+                    at_ms: 300,
+                },
+            ),
+        );
+        let profile = SensorProfile {
+            shunt_micro_ohms: 10_000,
+            max_current_ma: 4000,
+        };
+        let correction = CurrentCorrection {
+            offset_ua: 4000,
+            gain_ppm: 1_000_000,
+        };
+
+        let config = block_on(start_fast(&mut bus, SENSOR)).unwrap();
+        assert_eq!(
+            bus.device(SENSOR).unwrap().register(Register::Config),
+            CONFIG_FAST
+        );
+        let mut capture = Capture::new();
+        capture.arm(LEVEL);
+        let mut t_ms = 0;
+        loop {
+            bus.advance(1);
+            t_ms += 1;
+            let ua = block_on(read_current_ua(&mut bus, SENSOR, &profile, &correction)).unwrap();
+            if capture.feed(ua, t_ms * 1000) {
+                break;
+            }
+        }
+        block_on(restore(&mut bus, SENSOR, config)).unwrap();
+        assert_eq!(
+            bus.device(SENSOR).unwrap().register(Register::Config),
+            config
+        );
+
+        // The idle offset is corrected away
+        let samples = capture.samples();
+        assert_eq!(samples[0], 0);
+        assert_eq!(samples[capture.trigger_index()], 1_996_000);
+        assert_eq!(capture.period_us(), 1000);
+    }
+}
@@ -0,0 +1,277 @@
+// core/src/charge.rs
+// Charge phase of a battery device on a downstream port
+//
+// Lithium chargers run constant current (CC) until the cell reaches its
+// voltage limit, then hold the voltage while the current tapers off about
+// exponentially, drop to a small trickle and finally stop. Only VBUS is
+// visible from the hub, so the phase is inferred from the drawn power
+// relative to the session's plateau; power rather than current keeps a
+// fast-charge voltage step from looking like a taper. Each phase change has
+// to hold for `PHASE_DWELL_MS`, because phones in use draw bursts.
+//
+// Time to full fits an exponential to the last minutes of power while
+// tapering and extrapolates to where the charge would count as complete.
+
+use libm::{expf, logf};
+
+// Share of the plateau power below which each phase starts
+const TAPER_PERCENT: f32 = 85.0;
+const TRICKLE_PERCENT: f32 = 15.0;
+const COMPLETE_PERCENT: f32 = 4.0;
+// Below this the device is done whatever its plateau was, about 20 mA
+const COMPLETE_MW: f32 = 100.0;
+
+const SMOOTHING_MS: f32 = 10_000.0;
+// No phase before the plateau has been seen for a while
+const WARMUP_MS: u32 = 30_000;
+const PHASE_DWELL_MS: u32 = 30_000;
+
+// Smoothed power every 10 s over the last 5+ minutes, for the fit
+const HISTORY_INTERVAL_MS: u32 = 10_000;
+const HISTORY_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChargePhase {
+    /// Not enough samples yet
+    #[default]
+    Unknown,
+    ConstantCurrent,
+    /// Constant voltage, the current falls off
+    Taper,
+    Trickle,
+    Complete,
+}
+
+impl ChargePhase {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChargePhase::Unknown => "--",
+            ChargePhase::ConstantCurrent => "CC",
+            ChargePhase::Taper => "taper",
+            ChargePhase::Trickle => "trickle",
+            ChargePhase::Complete => "full",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChargeClassifier {
+    elapsed_ms: u32,
+    smoothed_mw: f32,
+    plateau_mw: f32,
+    phase: ChargePhase,
+    // Phase the samples point to and for how long they did
+    pending: ChargePhase,
+    pending_ms: u32,
+    history: [f32; HISTORY_LEN],
+    history_len: usize,
+    history_ms: u32,
+}
+
+impl Default for ChargeClassifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChargeClassifier {
+    pub const fn new() -> Self {
+        Self {
+            elapsed_ms: 0,
+            smoothed_mw: 0.0,
+            plateau_mw: 0.0,
+            phase: ChargePhase::Unknown,
+            pending: ChargePhase::Unknown,
+            pending_ms: 0,
+            history: [0.0; HISTORY_LEN],
+            history_len: 0,
+            history_ms: 0,
+        }
+    }
+
+    pub fn phase(&self) -> ChargePhase {
+        self.phase
+    }
+
+    /// Feed one sample that stood for the last `dt_ms` milliseconds
+    pub fn update(&mut self, voltage_mv: u32, current_ma: u32, dt_ms: u32) -> ChargePhase {
+        let power_mw = voltage_mv as f32 * current_ma as f32 / 1000.0;
+        if self.elapsed_ms == 0 {
+            self.smoothed_mw = power_mw;
+        } else {
+            let alpha = dt_ms as f32 / (SMOOTHING_MS + dt_ms as f32);
+            self.smoothed_mw += alpha * (power_mw - self.smoothed_mw);
+        }
+        self.elapsed_ms = self.elapsed_ms.saturating_add(dt_ms);
+        self.plateau_mw = self.plateau_mw.max(self.smoothed_mw);
+
+        self.history_ms += dt_ms;
+        if self.history_ms >= HISTORY_INTERVAL_MS {
+            self.history_ms -= HISTORY_INTERVAL_MS;
+            if self.history_len == HISTORY_LEN {
+                self.history.copy_within(1.., 0);
+                self.history_len -= 1;
+            }
+            self.history[self.history_len] = self.smoothed_mw;
+            self.history_len += 1;
+        }
+
+        if self.elapsed_ms < WARMUP_MS {
+            return self.phase;
+        }
+        let target = self.target();
+        if target == self.phase {
+            self.pending_ms = 0;
+            return self.phase;
+        }
+        if target != self.pending {
+            self.pending = target;
+            self.pending_ms = 0;
+        }
+        self.pending_ms = self.pending_ms.saturating_add(dt_ms);
+        // The first phase needs no dwell, the warm-up already was one
+        if self.phase == ChargePhase::Unknown || self.pending_ms >= PHASE_DWELL_MS {
+            self.phase = target;
+            self.pending_ms = 0;
+        }
+        self.phase
+    }
+
+    fn complete_mw(&self) -> f32 {
+        (self.plateau_mw * COMPLETE_PERCENT / 100.0).max(COMPLETE_MW)
+    }
+
+    fn target(&self) -> ChargePhase {
+        let percent = self.smoothed_mw * 100.0 / self.plateau_mw.max(1.0);
+        if self.smoothed_mw < self.complete_mw() {
+            ChargePhase::Complete
+        } else if percent < TRICKLE_PERCENT {
+            ChargePhase::Trickle
+        } else if percent < TAPER_PERCENT {
+            ChargePhase::Taper
+        } else {
+            ChargePhase::ConstantCurrent
+        }
+    }
+
+    /// Seconds until the charge completes, once the taper can be fitted
+    pub fn time_to_full_s(&self) -> Option<u32> {
+        match self.phase {
+            ChargePhase::Complete => return Some(0),
+            ChargePhase::Taper | ChargePhase::Trickle => {}
+            _ => return None,
+        }
+        // Least squares fit of ln(P) = a + k * t over the history, t in s
+        let points = &self.history[..self.history_len];
+        if points.len() < HISTORY_LEN / 2 || points.iter().any(|&p| p <= 0.0) {
+            return None;
+        }
+        let step_s = (HISTORY_INTERVAL_MS / 1000) as f32;
+        let n = points.len() as f32;
+        let mean_t = (n - 1.0) * step_s / 2.0;
+        let mean_ln = points.iter().map(|&p| logf(p)).sum::<f32>() / n;
+        let (mut cov, mut var) = (0.0, 0.0);
+        for (i, &p) in points.iter().enumerate() {
+            let dt = i as f32 * step_s - mean_t;
+            cov += dt * (logf(p) - mean_ln);
+            var += dt * dt;
+        }
+        let k = cov / var;
+        // Flat or rising power, e.g. the phone is in use
+        if k >= -1e-5 {
+            return None;
+        }
+        let now_mw = expf(mean_ln + k * ((n - 1.0) * step_s - mean_t));
+        let remaining_s = logf(self.complete_mw() / now_mw) / k;
+        Some(remaining_s.max(0.0) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VBUS_MV: u32 = 5000;
+
+    /// Phone on a 10 W charger: 2 A CC for 40 minutes, taper with a
+    /// 20 minute time constant, then a 30 mA trickle that stops after 2.5 h
+    fn phone_current_ma(t_s: u32) -> u32 {
+        let taper_start_s = 40 * 60;
+        let noise = (t_s * 7919 % 41) as i32 - 20;
+        let ma = if t_s < taper_start_s {
+            2000.0
+        } else {
+            let taper = 2000.0 * expf(-((t_s - taper_start_s) as f32) / 1200.0);
+            if t_s < 150 * 60 { taper.max(30.0) } else { 0.0 }
+        };
+        (ma as i32 + noise).max(0) as u32
+    }
+
+    /// Phase and time to full at every minute of a phone charge
+    fn replay() -> std::vec::Vec<(ChargePhase, Option<u32>)> {
+        let mut classifier = ChargeClassifier::new();
+        let mut minutes = std::vec::Vec::new();
+        for t_s in 0..160 * 60 {
+            classifier.update(VBUS_MV, phone_current_ma(t_s), 1000);
+            if (t_s + 1) % 60 == 0 {
+                minutes.push((classifier.phase(), classifier.time_to_full_s()));
+            }
+        }
+        minutes
+    }
+
+    #[test]
+    fn phases_follow_the_charge_curve() {
+        let minutes = replay();
+        assert_eq!(minutes[0].0, ChargePhase::ConstantCurrent);
+        assert_eq!(minutes[30], (ChargePhase::ConstantCurrent, None));
+        // 85 % of the plateau is reached 3.3 minutes into the taper
+        assert_eq!(minutes[50].0, ChargePhase::Taper);
+        // 15 % after 38 minutes, plus smoothing and dwell
+        assert_eq!(minutes[70].0, ChargePhase::Taper);
+        assert_eq!(minutes[85].0, ChargePhase::Trickle);
+        // 4 % (400 mW) after 64 minutes
+        assert_eq!(minutes[110], (ChargePhase::Complete, Some(0)));
+    }
+
+    #[test]
+    fn time_to_full_tracks_the_taper() {
+        let minutes = replay();
+        // Complete at 400 mW, 40 + 64.4 minutes into the charge
+        for minute in [60, 70, 80, 90] {
+            let expected_s = (104.4 - (minute + 1) as f32) * 60.0;
+            let estimate = minutes[minute].1.expect("taper fitted") as f32;
+            assert!(
+                (estimate - expected_s).abs() < 0.1 * expected_s + 60.0,
+                "minute {minute}: {estimate} s, expected {expected_s} s"
+            );
+        }
+    }
+
+    #[test]
+    fn bursts_during_cc_do_not_change_phase() {
+        let mut classifier = ChargeClassifier::new();
+        for t_s in 0..20 * 60 {
+            // 10 s dips to half the current every minute
+            let ma = if t_s % 60 < 10 { 1000 } else { 2000 };
+            classifier.update(VBUS_MV, ma, 1000);
+            if t_s >= 60 {
+                assert_eq!(classifier.phase(), ChargePhase::ConstantCurrent);
+            }
+        }
+    }
+
+    #[test]
+    fn voltage_step_is_not_a_taper() {
+        let mut classifier = ChargeClassifier::new();
+        for _ in 0..300 {
+            classifier.update(5000, 2000, 1000);
+        }
+        // Fast charge: same power at 9 V
+        for _ in 0..600 {
+            classifier.update(9000, 1111, 1000);
+        }
+        assert_eq!(classifier.phase(), ChargePhase::ConstantCurrent);
+        assert_eq!(classifier.time_to_full_s(), None);
+    }
+}
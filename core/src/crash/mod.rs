@@ -0,0 +1,4 @@
+// core/src/crash/mod.rs
+// Crash record format; the panic handler that fills it is in the firmware
+
+pub mod record;
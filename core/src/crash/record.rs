@@ -0,0 +1,253 @@
+// core/src/crash/record.rs
+// Crash record kept in RAM across the reset that follows a panic
+//
+// Fixed size and plain integers only, so the record can sit in `.uninit`
+// memory and be checked at boot: a magic plus a CRC over every field tell a
+// real record from the random RAM content after power-on.
+
+use core::fmt;
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+pub const FILE_LEN: usize = 32;
+pub const MESSAGE_LEN: usize = 96;
+pub const STACK_WORDS: usize = 8;
+
+const MAGIC: u32 = 0xC4A5_4ED0;
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct CrashRecord {
+    magic: u32,
+    checksum: u32,
+    pub line: u32,
+    pub column: u32,
+    pub uptime_ms: u32,
+    /// Where `stack` was read, the frame that called the panic handler
+    pub sp: u32,
+    /// Words at `sp` and up, unused ones are 0
+    pub stack: [u32; STACK_WORDS],
+    file_len: u32,
+    message_len: u32,
+    file: [u8; FILE_LEN],
+    message: [u8; MESSAGE_LEN],
+}
+
+/// File name without the directories, which don't help on a 160 px screen
+fn basename(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// Longest prefix of `s` that fits `max` bytes without splitting a char
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Words of the panicking code's stack, skipping the panic handler's own
+/// frame: read from `frame` (an address in the caller's frame) when it lies
+/// between `sp` and `top`, else from `sp`. Returns where the words start.
+///
+/// # Safety
+///
+/// `sp..top` must be readable, word aligned memory.
+pub unsafe fn read_stack(
+    sp: *const u32,
+    frame: *const u32,
+    top: *const u32,
+) -> (*const u32, [u32; STACK_WORDS]) {
+    let from = if sp <= frame && frame < top {
+        frame
+    } else {
+        sp
+    };
+    let mut stack = [0; STACK_WORDS];
+    let available = if from < top {
+        unsafe { top.offset_from(from) as usize }
+    } else {
+        0
+    };
+    for (i, word) in stack.iter_mut().take(available).enumerate() {
+        *word = unsafe { from.add(i).read_volatile() };
+    }
+    (from, stack)
+}
+
+impl CrashRecord {
+    /// Empty message, not valid until `seal`
+    pub fn new(file: &str, line: u32, column: u32, uptime_ms: u32, sp: u32, stack: &[u32]) -> Self {
+        let mut record = Self {
+            magic: 0,
+            checksum: 0,
+            line,
+            column,
+            uptime_ms,
+            sp,
+            stack: [0; STACK_WORDS],
+            file_len: 0,
+            message_len: 0,
+            file: [0; FILE_LEN],
+            message: [0; MESSAGE_LEN],
+        };
+        let n = stack.len().min(STACK_WORDS);
+        record.stack[..n].copy_from_slice(&stack[..n]);
+        let file = truncate(basename(file), FILE_LEN);
+        record.file[..file.len()].copy_from_slice(file.as_bytes());
+        record.file_len = file.len() as u32;
+        record
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let mut digest = CRC.digest();
+        for word in [
+            self.line,
+            self.column,
+            self.uptime_ms,
+            self.sp,
+            self.file_len,
+            self.message_len,
+        ]
+        .iter()
+        .chain(&self.stack)
+        {
+            digest.update(&word.to_le_bytes());
+        }
+        digest.update(&self.file);
+        digest.update(&self.message);
+        digest.finalize()
+    }
+
+    pub fn seal(&mut self) {
+        self.magic = MAGIC;
+        self.checksum = self.compute_checksum();
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+            && self.file_len as usize <= FILE_LEN
+            && self.message_len as usize <= MESSAGE_LEN
+            && self.checksum == self.compute_checksum()
+    }
+
+    /// Make `is_valid` fail, so the record is reported only once
+    pub fn invalidate(&mut self) {
+        self.magic = 0;
+    }
+
+    pub fn file(&self) -> &str {
+        let bytes = &self.file[..(self.file_len as usize).min(FILE_LEN)];
+        core::str::from_utf8(bytes).unwrap_or("?")
+    }
+
+    pub fn message(&self) -> &str {
+        let bytes = &self.message[..(self.message_len as usize).min(MESSAGE_LEN)];
+        core::str::from_utf8(bytes).unwrap_or("?")
+    }
+}
+
+/// Appends to the message, cutting it off when full
+impl fmt::Write for CrashRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let used = self.message_len as usize;
+        let s = truncate(s, MESSAGE_LEN - used);
+        self.message[used..used + s.len()].copy_from_slice(s.as_bytes());
+        self.message_len += s.len() as u32;
+        Ok(())
+    }
+}
+
+/// `crashed at dashboard.rs:123`
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "crashed at {}:{}", self.file(), self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use super::*;
+
+    fn sample() -> CrashRecord {
+        let mut record = CrashRecord::new(
+            "src/display/dashboard.rs",
+            123,
+            45,
+            61_000,
+            0x2000_7F00,
+            &[1, 2, 3],
+        );
+        let _ = write!(record, "index out of bounds: {} >= {}", 7, 3);
+        record.seal();
+        record
+    }
+
+    #[test]
+    fn sealed_record_reads_back() {
+        let record = sample();
+        assert!(record.is_valid());
+        assert_eq!(record.file(), "dashboard.rs");
+        assert_eq!(record.message(), "index out of bounds: 7 >= 3");
+        assert_eq!(record.stack, [1, 2, 3, 0, 0, 0, 0, 0]);
+        assert_eq!(std::format!("{}", record), "crashed at dashboard.rs:123");
+    }
+
+    #[test]
+    fn unsealed_changed_or_taken_records_are_invalid() {
+        let mut record = CrashRecord::new("main.rs", 1, 1, 0, 0, &[]);
+        assert!(!record.is_valid());
+
+        let mut changed = sample();
+        changed.line = 124;
+        assert!(!changed.is_valid());
+
+        record = sample();
+        record.invalidate();
+        assert!(!record.is_valid());
+    }
+
+    #[test]
+    fn stack_is_read_above_the_handler_frame() {
+        // The handler's zeroed locals at `sp`, the caller's frame above them
+        let ram: [u32; 12] = [0, 0, 0, 0, 0x2000_7F40, 0x0800_1235, 7, 3, 9, 10, 11, 12];
+        let sp = ram.as_ptr();
+        let top = ram.as_ptr_range().end;
+        let frame = ram[4..].as_ptr();
+
+        let (from, stack) = unsafe { read_stack(sp, frame, top) };
+        assert_eq!(from, frame);
+        assert_eq!(stack, [0x2000_7F40, 0x0800_1235, 7, 3, 9, 10, 11, 12]);
+
+        // Close to the top, the words past it stay 0
+        let (_, stack) = unsafe { read_stack(sp, ram[9..].as_ptr(), top) };
+        assert_eq!(stack, [10, 11, 12, 0, 0, 0, 0, 0]);
+
+        // A frame address outside the stack falls back to `sp`
+        let (from, stack) = unsafe { read_stack(sp, top, top) };
+        assert_eq!(from, sp);
+        assert_eq!(stack, [0, 0, 0, 0, 0x2000_7F40, 0x0800_1235, 7, 3]);
+    }
+
+    #[test]
+    fn long_text_is_cut_at_char_boundaries() {
+        let long_path = std::format!("C:\\src\\{}é.rs", "x".repeat(FILE_LEN - 1));
+        let mut record = CrashRecord::new(&long_path, 1, 1, 0, 0, &[0; 20]);
+        assert_eq!(record.file(), "x".repeat(FILE_LEN - 1));
+
+        for _ in 0..MESSAGE_LEN {
+            let _ = record.write_str("ü");
+        }
+        record.seal();
+        assert!(record.is_valid());
+        assert_eq!(record.message().len(), MESSAGE_LEN);
+        assert!(record.message().chars().all(|c| c == 'ü'));
+    }
+}
@@ -0,0 +1,128 @@
+// core/src/display/canvas.rs
+// One-colour text line rendered with the embedded-graphics mono fonts
+//
+// The 8x12 font only covers digits and units. Pages that need text draw it
+// into a 1-bit `TextLine` and blit the line to the panel in one colour, so
+// no full RGB565 frame buffer is needed.
+
+use core::convert::Infallible;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::pixelcolor::{BinaryColor, Rgb565};
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+
+use super::dashboard::Error;
+use crate::hal::Panel;
+
+pub const LINE_WIDTH: usize = 160;
+/// One FONT_6X10 row plus spacing, three lines fill the 40 px panel
+pub const LINE_HEIGHT: usize = 13;
+
+// Columns sent per write_area call
+const BLIT_COLUMNS: usize = 16;
+
+pub struct TextLine {
+    // Row-major, one bit per pixel, MSB first
+    bits: [u8; LINE_WIDTH * LINE_HEIGHT / 8],
+}
+
+impl Default for TextLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextLine {
+    pub fn new() -> Self {
+        Self {
+            bits: [0; LINE_WIDTH * LINE_HEIGHT / 8],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    /// Replace the line content with `text`, left aligned at `x`
+    pub fn set_text(&mut self, text: &str, x: i32) {
+        self.clear();
+        let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        let _ = Text::with_baseline(text, Point::new(x, 1), style, Baseline::Top).draw(self);
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> bool {
+        let index = y * LINE_WIDTH + x;
+        self.bits[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    /// Draw the line at row `y` of the panel, cut off at its right edge
+    pub async fn blit<P: Panel>(
+        &self,
+        display: &mut P,
+        y: u16,
+        fg_color: Rgb565,
+        bg_color: Rgb565,
+    ) -> Result<(), Error> {
+        let width = LINE_WIDTH.min(display.size().0 as usize);
+        let mut pixels = [bg_color; BLIT_COLUMNS * LINE_HEIGHT];
+        for x0 in (0..width).step_by(BLIT_COLUMNS) {
+            let columns = BLIT_COLUMNS.min(width - x0);
+            // Column-major, like the font bitmaps
+            for col in 0..columns {
+                for row in 0..LINE_HEIGHT {
+                    pixels[col * LINE_HEIGHT + row] = if self.pixel(x0 + col, row) {
+                        fg_color
+                    } else {
+                        bg_color
+                    };
+                }
+            }
+            display
+                .write_area(
+                    x0 as u16,
+                    y,
+                    columns as u16,
+                    LINE_HEIGHT as u16,
+                    &pixels[..columns * LINE_HEIGHT],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl OriginDimensions for TextLine {
+    fn size(&self) -> Size {
+        Size::new(LINE_WIDTH as u32, LINE_HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for TextLine {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x < 0
+                || point.y < 0
+                || point.x >= LINE_WIDTH as i32
+                || point.y >= LINE_HEIGHT as i32
+            {
+                continue;
+            }
+            let index = point.y as usize * LINE_WIDTH + point.x as usize;
+            let mask = 0x80 >> (index % 8);
+            if color.is_on() {
+                self.bits[index / 8] |= mask;
+            } else {
+                self.bits[index / 8] &= !mask;
+            }
+        }
+        Ok(())
+    }
+}
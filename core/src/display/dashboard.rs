@@ -1,4 +1,4 @@
-// src/display/dashboard.rs
+// core/src/display/dashboard.rs
 // Dashboard 页面模块
 
 // Keep Rgb565 for colors
@@ -13,24 +13,20 @@ use embedded_graphics::prelude::RgbColor;
 //     geometry::Point,
 // };
 
-use gc9d01::GC9D01; // Import GC9D01
-use crate::display::font::{char_to_mono_bitmap, mono_bitmap_to_rgb565, FONT_8X12_WIDTH, FONT_8X12_HEIGHT}; // Updated constant names
-
-// Import necessary traits for GC9D01 (these are bounds on the GC9D01 struct itself)
-use embedded_hal::digital::OutputPin;
-use embedded_hal_async::spi::SpiDevice;
-use gc9d01::Timer as Gc9d01Timer; // Moved this import up
-use core::convert::TryInto; // Added import for try_into
-use alloc::format; // Added import for alloc::format!
+use crate::display::font::{
+    FONT_8X12_HEIGHT, FONT_8X12_WIDTH, char_to_mono_bitmap, mono_bitmap_to_rgb565,
+}; // Updated constant names
+use crate::hal::Panel;
+use alloc::format;
+use core::convert::TryInto; // Added import for try_into // Added import for alloc::format!
 
 #[derive(Debug)]
 pub enum Error {
     // Add specific error types later if needed
     DriverError, // Placeholder for errors from the GC9D01 driver
-    // Add other errors like FontError, LayoutError, etc. as needed
+                 // Add other errors like FontError, LayoutError, etc. as needed
 }
 
-
 // Define colors
 const COLOR_VOLTAGE: Rgb565 = Rgb565::YELLOW;
 const COLOR_CURRENT: Rgb565 = Rgb565::RED;
@@ -47,6 +43,12 @@ pub struct Dashboard {
     draw_count: u32,
 }
 
+impl Default for Dashboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Dashboard {
     // Create new Dashboard instance
     pub fn new() -> Self {
@@ -61,25 +63,18 @@ impl Dashboard {
         self.port_data = data;
     }
 
-    // Draw Dashboard directly to the display driver using write_area
-    // Accept GC9D01 directly
-    pub async fn draw<'a, BUS, DC, RST, TIMER>(&mut self, display: &mut GC9D01<'a, BUS, DC, RST, TIMER>) -> Result<(), Error>
-    where
-        BUS: SpiDevice,
-        DC: OutputPin<Error = core::convert::Infallible>, // Specify Infallible error type
-        RST: OutputPin<Error = core::convert::Infallible>, // Specify Infallible error type
-        TIMER: Gc9d01Timer,
-    {
+    // Draw Dashboard directly to the panel using write_area
+    pub async fn draw<P: Panel>(&mut self, display: &mut P) -> Result<(), Error> {
         // Clear screen manually by writing black pixels to the whole area
-        let screen_width = 160; // Assuming landscape 160x40
-        let screen_height = 40;
+        let (screen_width, screen_height) = display.size();
+        let (screen_width, screen_height) = (screen_width as usize, screen_height as usize);
         let _black_pixel = Rgb565::BLUE;
         // Create a buffer for a 20x20 block of black pixels
         // const BLOCK_SIZE: u16 = 20; // Removed unused constant
 
         // Clear screen manually by writing black pixels to the whole area
         // Only clear every 1000 draws to save resources
-        if self.draw_count % 1000 == 0 {
+        if self.draw_count.is_multiple_of(1000) {
             let _ = display.fill_color(Rgb565::BLACK).await;
             // Handle potential remaining rows/columns if screen dimensions are not multiples of BLOCK_SIZE
             // (Assuming 160x40 is a multiple of 20x20, so no extra handling needed for this specific case)
@@ -87,36 +82,49 @@ impl Dashboard {
         self.draw_count += 1;
         // (Assuming 160x40 is a multiple of 20x20, so no extra handling needed for this specific case)
 
-
         // Layout: 3 columns, 2 rows
-        let col_width = screen_width / 3; // Approx 53
-        let _row_height = screen_height / 3; // Approx 13 // Mark as unused
+        let col_width = screen_width / 3; // Approx 53 on the 160 px panel
+        let row_height = screen_height / 3; // Approx 13 on the 40 px panel
         let row_spacing = 1; // Additional spacing between rows
-        let actual_row_height = FONT_8X12_HEIGHT + row_spacing; // 12 + 1 = 13
+        let actual_row_height = row_height.max(FONT_8X12_HEIGHT + row_spacing); // At least 12 + 1 = 13
 
         // Buffer for character pixels (8x12)
         let mut char_pixel_buffer = [Rgb565::BLACK; FONT_8X12_WIDTH * FONT_8X12_HEIGHT]; // Updated constant names
 
         // Helper function to draw a string
         // Helper function to draw a string with right alignment
-        async fn draw_string<'a, BUS, DC, RST, TIMER>(
-            display: &mut GC9D01<'a, BUS, DC, RST, TIMER>,
+        #[allow(clippy::too_many_arguments)]
+        async fn draw_string<P: Panel>(
+            display: &mut P,
             s: &str,
+            left_edge_x: usize,  // Left edge of the drawing area
             right_edge_x: usize, // Right edge of the drawing area
             start_y: usize,
             fg_color: Rgb565,
             bg_color: Rgb565,
             char_pixel_buffer: &mut [Rgb565], // Pass buffer as argument
-        ) -> Result<(), Error>
-        where
-            BUS: SpiDevice,
-            DC: OutputPin<Error = core::convert::Infallible>, // Specify Infallible error type
-            RST: OutputPin<Error = core::convert::Infallible>, // Specify Infallible error type
-            TIMER: Gc9d01Timer,
-        {
+        ) -> Result<(), Error> {
             let string_pixel_width = s.chars().count() * FONT_8X12_WIDTH;
             let start_x = right_edge_x.saturating_sub(string_pixel_width); // Calculate start_x for right alignment, handle potential underflow
 
+            // Blank the area left of the text, or a value shorter than the
+            // last one leaves its leading characters behind
+            char_pixel_buffer.fill(bg_color);
+            let mut blank_x = left_edge_x;
+            while blank_x < start_x {
+                let width = (start_x - blank_x).min(FONT_8X12_WIDTH);
+                display
+                    .write_area(
+                        blank_x as u16,
+                        start_y as u16,
+                        width as u16,
+                        FONT_8X12_HEIGHT as u16,
+                        char_pixel_buffer,
+                    )
+                    .await?;
+                blank_x += width;
+            }
+
             let mut current_x = start_x;
             for c in s.chars() {
                 if let Some(bitmap) = char_to_mono_bitmap(c) {
@@ -127,13 +135,15 @@ impl Dashboard {
                     let _x1 = x0 + FONT_8X12_WIDTH - 1; // Updated constant name
                     let _y1 = y0 + FONT_8X12_HEIGHT - 1; // Updated constant name
 
-                    display.write_area(
-                        x0.try_into().unwrap(),
-                        y0.try_into().unwrap(),
-                        FONT_8X12_WIDTH.try_into().unwrap(), // Updated constant name
-                        FONT_8X12_HEIGHT.try_into().unwrap(), // Updated constant name
-                        char_pixel_buffer,
-                    ).await.map_err(|_| Error::DriverError)?;
+                    display
+                        .write_area(
+                            x0.try_into().unwrap(),
+                            y0.try_into().unwrap(),
+                            FONT_8X12_WIDTH.try_into().unwrap(), // Updated constant name
+                            FONT_8X12_HEIGHT.try_into().unwrap(), // Updated constant name
+                            char_pixel_buffer,
+                        )
+                        .await?;
 
                     current_x += FONT_8X12_WIDTH; // Updated constant name
                 } else {
@@ -149,7 +159,7 @@ impl Dashboard {
 
         // Draw data for each port (column)
         for i in 0..3 {
-            let _col_start_x = i * col_width; // Mark as unused
+            let col_start_x = i * col_width;
             let col_right_edge_x = (i + 1) * col_width;
 
             let port_voltage = self.port_data[i].0;
@@ -177,25 +187,55 @@ impl Dashboard {
                 (COLOR_CURRENT, p_color) // Red for current
             };
 
-
             // Draw Voltage (Row 1)
             let voltage_str = self.float_to_string(&mut buffer, port_voltage);
-            draw_string(display, &format!("{}V", voltage_str), col_right_edge_x as usize, 0, voltage_color, Rgb565::BLACK, &mut char_pixel_buffer).await?;
+            draw_string(
+                display,
+                &format!("{}V", voltage_str),
+                col_start_x,
+                col_right_edge_x,
+                0,
+                voltage_color,
+                Rgb565::BLACK,
+                &mut char_pixel_buffer,
+            )
+            .await?;
 
             // Draw Current (Row 2)
             let current_str = self.float_to_string(&mut buffer, port_current);
-            draw_string(display, &format!("{}A", current_str), col_right_edge_x as usize, actual_row_height as usize, current_color, Rgb565::BLACK, &mut char_pixel_buffer).await?;
+            draw_string(
+                display,
+                &format!("{}A", current_str),
+                col_start_x,
+                col_right_edge_x,
+                actual_row_height,
+                current_color,
+                Rgb565::BLACK,
+                &mut char_pixel_buffer,
+            )
+            .await?;
 
             // Draw Power (Row 3)
             let power_str = self.float_to_string(&mut buffer, port_power);
-            draw_string(display, &format!("{}W", power_str), col_right_edge_x as usize, (actual_row_height * 2) as usize, power_color, Rgb565::BLACK, &mut char_pixel_buffer).await?;
+            draw_string(
+                display,
+                &format!("{}W", power_str),
+                col_start_x,
+                col_right_edge_x,
+                actual_row_height * 2,
+                power_color,
+                Rgb565::BLACK,
+                &mut char_pixel_buffer,
+            )
+            .await?;
         }
 
         Ok(())
     }
 
     // Simplified float to string function (moved from inside draw)
-    fn float_to_string<'a>(&self, buffer: &'a mut [u8], value: f32) -> &'a str { // Added &self and value parameter and lifetime
+    fn float_to_string<'a>(&self, buffer: &'a mut [u8], value: f32) -> &'a str {
+        // Added &self and value parameter and lifetime
         // This is a very simplified implementation for demonstration only
         // Does not handle negative numbers, large numbers, or specific precision well
         let integer_part = value as i32;
@@ -217,15 +257,16 @@ impl Dashboard {
             temp %= divisor;
             divisor /= 10;
         }
-         if integer_part == 0 && value.abs() < 1.0 && value >= 0.0 { // Handle 0.x case
-             buffer[cursor] = b'0';
-             cursor += 1;
-        } else if integer_part == 0 && value.abs() < 1.0 && value < 0.0 && cursor == 1 { // Handle -0.x case
-             buffer[cursor] = b'0';
-             cursor += 1;
+        if integer_part == 0 && value.abs() < 1.0 && value >= 0.0 {
+            // Handle 0.x case
+            buffer[cursor] = b'0';
+            cursor += 1;
+        } else if integer_part == 0 && value.abs() < 1.0 && value < 0.0 && cursor == 1 {
+            // Handle -0.x case
+            buffer[cursor] = b'0';
+            cursor += 1;
         }
 
-
         buffer[cursor] = b'.';
         cursor += 1;
 
@@ -0,0 +1,84 @@
+// core/src/display/event_log.rs
+// Event log page: the three most recent events, newest at the bottom
+//
+//   b4 0:00:00 Boot
+//   b4 0:00:02 PD 20.00V 3.00A
+//   b4 0:12:41 P2 plugged
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, TextLine};
+use super::dashboard::Error;
+use crate::event_log::ring::{Event, EventRing, ResetCause};
+use crate::hal::Panel;
+
+const COLOR_EVENT: Rgb565 = Rgb565::WHITE;
+const COLOR_WARNING: Rgb565 = Rgb565::new(31, 40, 0);
+
+type Line = String<32>;
+
+pub struct EventLogPage {
+    // `Platform::next_event_seq` at the last draw
+    shown_seq: Option<u32>,
+    line: TextLine,
+}
+
+impl Default for EventLogPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLogPage {
+    pub fn new() -> Self {
+        Self {
+            shown_seq: None,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Redraw everything on the next `draw`, e.g. after a page switch
+    pub fn invalidate(&mut self) {
+        self.shown_seq = None;
+    }
+
+    /// `snapshot` is only taken when `next_seq` shows new events
+    pub async fn draw<P: Panel>(
+        &mut self,
+        display: &mut P,
+        next_seq: u32,
+        snapshot: impl FnOnce() -> EventRing,
+    ) -> Result<(), Error> {
+        if self.shown_seq == Some(next_seq) {
+            return Ok(());
+        }
+        self.shown_seq = Some(next_seq);
+
+        let ring = snapshot();
+        let mut lines = [
+            (Line::new(), COLOR_EVENT),
+            (Line::new(), COLOR_EVENT),
+            (Line::new(), COLOR_EVENT),
+        ];
+        for (line, record) in lines.iter_mut().rev().zip(ring.iter().rev()) {
+            let _ = write!(line.0, "{}", record);
+            line.1 = match record.event {
+                Event::SensorError { .. }
+                | Event::Trip { .. }
+                | Event::Boot(ResetCause::Watchdog | ResetCause::BrownOut) => COLOR_WARNING,
+                _ => COLOR_EVENT,
+            };
+        }
+
+        for (i, (text, color)) in lines.iter().enumerate() {
+            self.line.set_text(text, 0);
+            let y = (LINE_HEIGHT * i) as u16;
+            self.line.blit(display, y, *color, Rgb565::BLACK).await?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-// src/display/font.rs
+// core/src/display/font.rs
 // Manually generated font data and rendering functions
 use embedded_graphics::pixelcolor::Rgb565;
 
@@ -291,9 +291,8 @@ pub fn mono_bitmap_to_rgb565(
     let mut buffer_idx = 0;
     for col_idx in 0..output_width {
         // Iterate over the 8 columns
-        for row_idx in 0..output_height {
+        for &row_bitmap in bitmap.iter().take(output_height) {
             // Iterate over the 12 rows
-            let row_bitmap = bitmap[row_idx];
             // Check if the pixel is set in the monochrome bitmap (using 8 bits)
             if (row_bitmap >> (output_width - 1 - col_idx)) & 1 == 1 {
                 // Check bit from MSB side
@@ -0,0 +1,59 @@
+// core/src/display/mod.rs
+pub mod canvas;
+pub mod dashboard;
+pub mod event_log;
+pub mod font;
+pub mod notice;
+pub mod orientation;
+pub mod pd_contract;
+pub mod pd_trace;
+pub mod port;
+pub mod scope;
+pub mod screensaver;
+pub mod selftest;
+pub mod settings_menu;
+#[cfg(test)]
+mod snapshots;
+pub mod sparkline;
+pub mod trends;
+
+// 其他显示相关的模块可以在这里声明
+
+/// Pages cycled with the front panel buttons
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Page {
+    Dashboard,
+    Trends,
+    /// Details of a downstream port, numbered from 0 like the sensors
+    Port(usize),
+    PdContract,
+    PdTrace,
+    EventLog,
+    /// Current of one port against time, from the trends or a capture
+    Scope,
+}
+
+impl Page {
+    const ALL: [Page; 8] = [
+        Page::Dashboard,
+        Page::Trends,
+        Page::Port(1),
+        Page::Port(2),
+        Page::Scope,
+        Page::PdContract,
+        Page::PdTrace,
+        Page::EventLog,
+    ];
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|page| *page == self).unwrap_or(0)
+    }
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self.index() + 1) % Self::ALL.len()]
+    }
+
+    pub fn previous(self) -> Self {
+        Self::ALL[(self.index() + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
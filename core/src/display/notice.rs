@@ -0,0 +1,97 @@
+// core/src/display/notice.rs
+// Short notification shown over the whole panel, e.g. "P2 attached"
+//
+// While a notice is up the main loop skips the current page; afterwards, or
+// when a button dismisses it, the page is redrawn from scratch like after a
+// page switch.
+
+use embassy_time::{Duration, Instant};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, LINE_WIDTH, TextLine};
+use super::dashboard::Error;
+use crate::hal::Panel;
+
+const DURATION: Duration = Duration::from_millis(1500);
+// FONT_6X10 columns on the panel
+const MAX_CHARS: usize = LINE_WIDTH / 6;
+
+pub struct Notice {
+    text: String<MAX_CHARS>,
+    color: Rgb565,
+    until: Option<Instant>,
+    drawn: bool,
+    line: TextLine,
+}
+
+impl Default for Notice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notice {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            color: Rgb565::WHITE,
+            until: None,
+            drawn: false,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Replace any notice still shown
+    pub fn show(&mut self, text: &str, color: Rgb565) {
+        self.text.clear();
+        for c in text.chars() {
+            if self.text.push(c).is_err() {
+                break;
+            }
+        }
+        self.color = color;
+        self.until = Some(Instant::now() + DURATION);
+        self.drawn = false;
+    }
+
+    pub fn dismiss(&mut self) {
+        if self.until.is_some() {
+            self.until = Some(Instant::MIN);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.until.is_some()
+    }
+
+    /// True once when the notice has run out and the page needs a redraw
+    pub fn take_ended(&mut self) -> bool {
+        match self.until {
+            Some(until) if Instant::now() >= until => {
+                self.until = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn draw<P: Panel>(&mut self, display: &mut P) -> Result<(), Error> {
+        if self.drawn {
+            return Ok(());
+        }
+        self.drawn = true;
+
+        display.fill_color(Rgb565::BLACK).await?;
+        // Centred on the panel, as far as the line reaches
+        let (width, height) = display.size();
+        let width = LINE_WIDTH.min(width as usize);
+        let x = width.saturating_sub(self.text.chars().count() * 6) / 2;
+        self.line.set_text(&self.text, x as i32);
+        let y = (height as usize).saturating_sub(LINE_HEIGHT) / 2;
+        self.line
+            .blit(display, y as u16, self.color, Rgb565::BLACK)
+            .await
+    }
+}
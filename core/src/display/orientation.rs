@@ -0,0 +1,265 @@
+// core/src/display/orientation.rs
+// Where and how the pages land on the panel: turned by 180° for enclosures
+// mounted upside down, moved by the screen saver's pixel shift, and in the
+// colours of the theme
+//
+// Wraps the panel for the duration of a draw. The shift moves every block
+// right and down, whatever is pushed off the panel is cut. Turned, a block
+// at (x, y) lands at (width - x - w, height - y - h) with its pixels in
+// reverse order, which for a column-major block is the rotation. Blocks are
+// re-sent through a small buffer, split into whole columns where they don't
+// fit.
+//
+// The pages draw in dark theme colours. The light theme maps every colour
+// on its way to the panel: the lightness is turned over, so the black
+// background becomes white and white text black, and colours are darkened
+// to stand out on white while keeping their hue.
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+
+use super::dashboard::Error;
+use crate::hal::Panel;
+use crate::storage::settings::Theme;
+
+// Pixels moved per write_area call
+const BUFFER_PIXELS: usize = 256;
+
+pub struct Oriented<'a, P> {
+    panel: &'a mut P,
+    rotated: bool,
+    shift: (u16, u16),
+    theme: Theme,
+    // (x, y, width, height) from the last `set_window`
+    window: (u16, u16, u16, u16),
+}
+
+impl<'a, P: Panel> Oriented<'a, P> {
+    /// `shift` is (dx, dy) before the rotation
+    pub fn new(panel: &'a mut P, rotated: bool, shift: (u16, u16), theme: Theme) -> Self {
+        Self {
+            panel,
+            rotated,
+            shift,
+            theme,
+            window: (0, 0, 0, 0),
+        }
+    }
+}
+
+/// `color` as the theme shows it
+pub fn themed(theme: Theme, color: Rgb565) -> Rgb565 {
+    if theme == Theme::Dark {
+        return color;
+    }
+    // Channels on the 6-bit green scale
+    let channels = [
+        color.r() as i32 * 63 / 31,
+        color.g() as i32,
+        color.b() as i32 * 63 / 31,
+    ];
+    let max = channels.into_iter().max().unwrap_or(0);
+    let min = channels.into_iter().min().unwrap_or(0);
+    // Moving every channel by the same amount keeps the hue and the
+    // saturation, this one puts the lightness at 1 - lightness
+    let turn = 63 - max - min;
+    let [r, g, b] = channels.map(|c| {
+        let c = c + turn;
+        // Grey stays as turned, colours at 60%
+        if max > min { c * 3 / 5 } else { c }
+    });
+    Rgb565::new((r * 31 / 63) as u8, g as u8, (b * 31 / 63) as u8)
+}
+
+impl<P: Panel> Panel for Oriented<'_, P> {
+    fn size(&self) -> (u16, u16) {
+        self.panel.size()
+    }
+
+    async fn init(&mut self) -> Result<(), Error> {
+        self.panel.init().await
+    }
+
+    async fn set_window(&mut self, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error> {
+        self.window = (x, y, width, height);
+        Ok(())
+    }
+
+    async fn write_pixels(&mut self, pixels: &[Rgb565]) -> Result<(), Error> {
+        let (x, y, width, height) = self.window;
+        self.write_area(x, y, width, height, pixels).await
+    }
+
+    async fn write_area(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        pixels: &[Rgb565],
+    ) -> Result<(), Error> {
+        if !self.rotated && self.shift == (0, 0) && self.theme == Theme::Dark {
+            return self.panel.write_area(x, y, width, height, pixels).await;
+        }
+        let (panel_width, panel_height) = self.panel.size();
+        let h = height as usize;
+        if x + width > panel_width || y + height > panel_height || pixels.len() < width as usize * h
+        {
+            return Err(Error::DriverError);
+        }
+
+        // The part still on the panel after the shift
+        let (x, y) = (x + self.shift.0, y + self.shift.1);
+        let visible_w = width.min(panel_width.saturating_sub(x)) as usize;
+        let visible_h = height.min(panel_height.saturating_sub(y)) as usize;
+
+        let mut buf = [Rgb565::BLACK; BUFFER_PIXELS];
+        let rows_per_block = visible_h.clamp(1, BUFFER_PIXELS);
+        let cols_per_block = (BUFFER_PIXELS / rows_per_block).max(1);
+        for c0 in (0..visible_w).step_by(cols_per_block) {
+            let cols = cols_per_block.min(visible_w - c0);
+            for r0 in (0..visible_h).step_by(rows_per_block) {
+                let rows = rows_per_block.min(visible_h - r0);
+                for col in 0..cols {
+                    for row in 0..rows {
+                        let index = if self.rotated {
+                            (cols - 1 - col) * rows + (rows - 1 - row)
+                        } else {
+                            col * rows + row
+                        };
+                        buf[index] = themed(self.theme, pixels[(c0 + col) * h + r0 + row]);
+                    }
+                }
+                let (block_x, block_y) = if self.rotated {
+                    (
+                        panel_width - x - (c0 + cols) as u16,
+                        panel_height - y - (r0 + rows) as u16,
+                    )
+                } else {
+                    (x + c0 as u16, y + r0 as u16)
+                };
+                self.panel
+                    .write_area(
+                        block_x,
+                        block_y,
+                        cols as u16,
+                        rows as u16,
+                        &buf[..cols * rows],
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn fill_color(&mut self, color: Rgb565) -> Result<(), Error> {
+        self.panel.fill_color(themed(self.theme, color)).await
+    }
+
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Error> {
+        self.panel.set_brightness(level).await
+    }
+
+    async fn sleep(&mut self, asleep: bool) -> Result<(), Error> {
+        self.panel.sleep(asleep).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::display::canvas::TextLine;
+    use crate::mock::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+
+    fn dark(
+        panel: &mut Framebuffer,
+        rotated: bool,
+        shift: (u16, u16),
+    ) -> Oriented<'_, Framebuffer> {
+        Oriented::new(panel, rotated, shift, Theme::Dark)
+    }
+
+    async fn draw(panel: &mut impl Panel) {
+        let mut line = TextLine::new();
+        line.set_text("P2 plugged 4.93V", 7);
+        line.blit(panel, 20, Rgb565::GREEN, Rgb565::BLUE)
+            .await
+            .unwrap();
+        // Taller than the rotation buffer holds in one piece
+        let column = [Rgb565::RED; 40 * 7];
+        panel.write_area(3, 0, 7, 40, &column).await.unwrap();
+    }
+
+    #[test]
+    fn rotated_is_the_upright_picture_turned() {
+        let mut upright = Framebuffer::new();
+        block_on(draw(&mut dark(&mut upright, false, (0, 0))));
+        let mut rotated = Framebuffer::new();
+        block_on(draw(&mut dark(&mut rotated, true, (0, 0))));
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                assert_eq!(
+                    rotated.pixel(WIDTH - 1 - x, HEIGHT - 1 - y),
+                    upright.pixel(x, y),
+                    "at ({x}, {y})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_writes_stay_on_the_panel() {
+        let mut panel = Framebuffer::new();
+        let mut rotated = dark(&mut panel, true, (0, 0));
+        assert!(block_on(rotated.write_area(155, 0, 6, 1, &[Rgb565::RED; 6])).is_err());
+        assert!(block_on(rotated.write_area(0, 0, 2, 2, &[Rgb565::RED; 3])).is_err());
+    }
+
+    #[test]
+    fn shift_moves_the_picture_and_cuts_the_edges() {
+        for rotated in [false, true] {
+            let mut plain = Framebuffer::new();
+            block_on(draw(&mut dark(&mut plain, rotated, (0, 0))));
+            let mut shifted = Framebuffer::new();
+            block_on(draw(&mut dark(&mut shifted, rotated, (1, 1))));
+
+            // Turned, the shift goes left and up on the glass
+            let (dx, dy): (isize, isize) = if rotated { (-1, -1) } else { (1, 1) };
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let (sx, sy) = (x as isize + dx, y as isize + dy);
+                    if (0..WIDTH as isize).contains(&sx) && (0..HEIGHT as isize).contains(&sy) {
+                        assert_eq!(
+                            shifted.pixel(sx as usize, sy as usize),
+                            plain.pixel(x, y),
+                            "rotated {rotated} at ({x}, {y})"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn light_theme_turns_the_lightness_over() {
+        assert_eq!(themed(Theme::Dark, Rgb565::RED), Rgb565::RED);
+        assert_eq!(themed(Theme::Light, Rgb565::BLACK), Rgb565::WHITE);
+        assert_eq!(themed(Theme::Light, Rgb565::WHITE), Rgb565::BLACK);
+        // Colours keep their hue, darker
+        assert_eq!(themed(Theme::Light, Rgb565::RED), Rgb565::new(18, 0, 0));
+        assert_eq!(themed(Theme::Light, Rgb565::YELLOW), Rgb565::new(18, 37, 0));
+        // A dim grey comes out a light grey
+        assert_eq!(
+            themed(Theme::Light, Rgb565::new(8, 16, 8)),
+            Rgb565::new(23, 47, 23)
+        );
+
+        let mut panel = Framebuffer::new();
+        let mut light = Oriented::new(&mut panel, false, (0, 0), Theme::Light);
+        block_on(draw(&mut light));
+        assert_eq!(panel.pixel(3, 0), themed(Theme::Light, Rgb565::RED));
+    }
+}
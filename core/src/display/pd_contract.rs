@@ -0,0 +1,317 @@
+// core/src/display/pd_contract.rs
+// PD contract page: negotiated supply, measured input voltage, PDO list
+//
+//   20V 3A          19.87V       <- contract and INA226 input voltage
+//   PDO 5 9 12 15 [20] V         <- fixed PDOs, selected one in brackets
+//   PPS 3.3-11 3.3-21 V          <- PPS ranges
+//
+// The first line turns red when the measured voltage does not match the
+// contract, e.g. 15 V requested but 12.1 V measured.
+
+use core::fmt::Write;
+
+use embassy_time::{Duration, Instant};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, TextLine};
+use super::dashboard::Error;
+use crate::hal::Panel;
+use crate::pd::decode::{Amps, Volts};
+use crate::pd::message::Pdo;
+use crate::pd::policy::{Contract, PdStatus, State};
+
+pub const INPUT_SENSOR: usize = crate::shared::INPUT_PORT;
+
+// vSrcNew is ±5 %; the rest covers cable and connector drop under load
+const TOLERANCE_PERCENT: u32 = 5;
+const TOLERANCE_OFFSET_MV: u32 = 250;
+// The source has tSrcReady (285 ms) after PS_RDY, plus the sensor averaging
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+const COLOR_OK: Rgb565 = Rgb565::GREEN;
+const COLOR_MISMATCH: Rgb565 = Rgb565::RED;
+const COLOR_TEXT: Rgb565 = Rgb565::WHITE;
+const COLOR_IDLE: Rgb565 = Rgb565::new(15, 30, 15);
+
+type Line = String<32>;
+
+/// Measured voltage is outside the tolerance around the contract voltage
+fn voltage_mismatch(contract_mv: u32, measured_mv: u32) -> bool {
+    let tolerance = contract_mv * TOLERANCE_PERCENT / 100 + TOLERANCE_OFFSET_MV;
+    measured_mv.abs_diff(contract_mv) > tolerance
+}
+
+pub struct PdContractPage {
+    status: Option<PdStatus>,
+    contract_since: Instant,
+    input_voltage_mv: u32,
+    // Text currently on screen, only changed lines are redrawn
+    shown: [(Line, Rgb565); 3],
+    dirty: bool,
+    line: TextLine,
+}
+
+impl Default for PdContractPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PdContractPage {
+    pub fn new() -> Self {
+        Self {
+            status: None,
+            contract_since: Instant::now(),
+            input_voltage_mv: 0,
+            shown: [
+                (Line::new(), COLOR_TEXT),
+                (Line::new(), COLOR_TEXT),
+                (Line::new(), COLOR_TEXT),
+            ],
+            dirty: true,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Redraw everything on the next `draw`, e.g. after a page switch
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn update_status(&mut self, status: PdStatus) {
+        let old_contract = self.status.as_ref().and_then(|s| s.contract);
+        if status.contract != old_contract {
+            self.contract_since = Instant::now();
+        }
+        self.status = Some(status);
+    }
+
+    pub fn update_input_voltage(&mut self, volts: f32) {
+        self.input_voltage_mv = libm::roundf(volts * 1000.0).max(0.0) as u32;
+    }
+
+    /// `settled` once the source had time to reach the contract voltage
+    fn contract_line(&self, contract: &Contract, settled: bool) -> (Line, Rgb565) {
+        let mut text = Line::new();
+        if contract.is_pps() {
+            let _ = write!(text, "PPS ");
+        }
+        let _ = write!(
+            text,
+            "{} {}",
+            Volts(contract.voltage_mv),
+            Amps(contract.current_ma)
+        );
+
+        let mismatch = settled
+            && self.input_voltage_mv > 0
+            && voltage_mismatch(contract.voltage_mv, self.input_voltage_mv);
+
+        // Measured voltage right aligned in the 26 columns
+        let mut measured: String<12> = String::new();
+        let _ = write!(
+            measured,
+            "{}{}.{:02}V",
+            if mismatch { "!" } else { "" },
+            self.input_voltage_mv / 1000,
+            self.input_voltage_mv % 1000 / 10
+        );
+        while text.len() + measured.len() < 26 {
+            let _ = text.push(' ');
+        }
+        let _ = text.push_str(&measured);
+
+        (text, if mismatch { COLOR_MISMATCH } else { COLOR_OK })
+    }
+
+    fn lines(&self) -> [(Line, Rgb565); 3] {
+        let mut lines = [
+            (Line::new(), COLOR_IDLE),
+            (Line::new(), COLOR_TEXT),
+            (Line::new(), COLOR_TEXT),
+        ];
+
+        let Some(status) = &self.status else {
+            let _ = write!(lines[0].0, "No PD source");
+            return lines;
+        };
+
+        match (&status.contract, status.state) {
+            (Some(contract), _) => {
+                let settled = self.contract_since.elapsed() >= SETTLE_TIME;
+                lines[0] = self.contract_line(contract, settled);
+            }
+            (None, State::Detached) => {
+                let _ = write!(lines[0].0, "No PD source");
+            }
+            (None, State::Unresponsive) => {
+                let _ = write!(lines[0].0, "Type-C only, no PD");
+            }
+            (None, _) => {
+                let _ = write!(lines[0].0, "Negotiating...");
+            }
+        }
+
+        let selected = status.contract.map(|c| c.position as usize);
+        let (mut fixed, mut pps) = (Line::new(), Line::new());
+        for (i, pdo) in status.source_capabilities.iter().enumerate() {
+            let marked = selected == Some(i + 1);
+            let (line, label) = match *pdo {
+                Pdo::Fixed { voltage_mv, .. } => (&mut fixed, PdoLabel::Fixed(voltage_mv)),
+                Pdo::Pps {
+                    min_voltage_mv,
+                    max_voltage_mv,
+                    ..
+                } => (&mut pps, PdoLabel::Range(min_voltage_mv, max_voltage_mv)),
+                _ => continue,
+            };
+            let _ = if marked {
+                write!(line, " [{}]", label)
+            } else {
+                write!(line, " {}", label)
+            };
+        }
+        if !fixed.is_empty() {
+            let _ = write!(lines[1].0, "PDO{} V", fixed);
+        }
+        if !pps.is_empty() {
+            let _ = write!(lines[2].0, "PPS{} V", pps);
+        }
+        lines
+    }
+
+    pub async fn draw<P: Panel>(&mut self, display: &mut P) -> Result<(), Error> {
+        let lines = self.lines();
+        for (i, (text, color)) in lines.into_iter().enumerate() {
+            if !self.dirty && self.shown[i].0 == text && self.shown[i].1 == color {
+                continue;
+            }
+            self.line.set_text(&text, 0);
+            let y = (LINE_HEIGHT * i) as u16;
+            self.line.blit(display, y, color, Rgb565::BLACK).await?;
+            self.shown[i] = (text, color);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Voltage of a PDO in volts without the unit, e.g. "20" or "3.3-11"
+enum PdoLabel {
+    Fixed(u32),
+    Range(u32, u32),
+}
+
+impl core::fmt::Display for PdoLabel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fn volts(f: &mut core::fmt::Formatter<'_>, mv: u32) -> core::fmt::Result {
+            match mv % 1000 {
+                0 => write!(f, "{}", mv / 1000),
+                rest => write!(f, "{}.{}", mv / 1000, rest / 100),
+            }
+        }
+        match *self {
+            PdoLabel::Fixed(mv) => volts(f, mv),
+            PdoLabel::Range(min, max) => {
+                volts(f, min)?;
+                write!(f, "-")?;
+                volts(f, max)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+
+    fn fixed(voltage_mv: u32) -> Contract {
+        Contract {
+            position: 1,
+            pdo: Pdo::Fixed {
+                voltage_mv,
+                max_current_ma: 3000,
+                unconstrained_power: false,
+                epr_capable: false,
+            },
+            voltage_mv,
+            current_ma: 3000,
+        }
+    }
+
+    fn pps(voltage_mv: u32) -> Contract {
+        Contract {
+            position: 6,
+            pdo: Pdo::Pps {
+                max_voltage_mv: 11_000,
+                min_voltage_mv: 3300,
+                max_current_ma: 5000,
+                power_limited: false,
+            },
+            voltage_mv,
+            current_ma: 2000,
+        }
+    }
+
+    #[test]
+    fn tolerance_is_five_percent_plus_the_cable_drop() {
+        // 20 V: 1 V plus 0.25 V either way
+        assert!(!voltage_mismatch(20_000, 18_750));
+        assert!(voltage_mismatch(20_000, 18_749));
+        assert!(!voltage_mismatch(20_000, 21_250));
+        assert!(voltage_mismatch(20_000, 21_251));
+        // 5 V: 0.25 V plus 0.25 V
+        assert!(!voltage_mismatch(5000, 4500));
+        assert!(voltage_mismatch(5000, 4499));
+    }
+
+    #[test]
+    fn fixed_contract_turns_red_outside_the_tolerance() {
+        let mut page = PdContractPage::new();
+        page.update_input_voltage(14.9);
+        assert_eq!(page.contract_line(&fixed(15_000), true).1, COLOR_OK);
+
+        page.update_input_voltage(12.1);
+        let (text, color) = page.contract_line(&fixed(15_000), true);
+        assert_eq!(color, COLOR_MISMATCH);
+        assert!(text.starts_with("15V 3A") && text.ends_with("!12.10V"));
+        // Not while the source is still moving to the new voltage
+        assert_eq!(page.contract_line(&fixed(15_000), false).1, COLOR_OK);
+        // Nor without a reading
+        page.update_input_voltage(0.0);
+        assert_eq!(page.contract_line(&fixed(15_000), true).1, COLOR_OK);
+    }
+
+    #[test]
+    fn pps_contract_is_checked_against_the_requested_voltage() {
+        let mut page = PdContractPage::new();
+        page.update_input_voltage(9.45);
+        let (text, color) = page.contract_line(&pps(9500), true);
+        assert!(text.starts_with("PPS 9.5V 2A"));
+        assert_eq!(color, COLOR_OK);
+
+        // Still at the 5 V the source started from
+        page.update_input_voltage(5.02);
+        assert_eq!(page.contract_line(&pps(9500), true).1, COLOR_MISMATCH);
+    }
+
+    #[test]
+    fn no_contract_is_never_a_mismatch() {
+        let mut page = PdContractPage::new();
+        page.update_input_voltage(3.0);
+        let (text, color) = &page.lines()[0];
+        assert_eq!((text.as_str(), *color), ("No PD source", COLOR_IDLE));
+
+        page.update_status(PdStatus {
+            state: State::WaitAccept,
+            source_capabilities: Vec::new(),
+            contract: None,
+        });
+        let (text, color) = &page.lines()[0];
+        assert_eq!((text.as_str(), *color), ("Negotiating...", COLOR_IDLE));
+    }
+}
@@ -0,0 +1,116 @@
+// core/src/display/pd_trace.rs
+// PD sniffer page: negotiation summary and the latest messages
+//
+//   20V 3A PDO accepted          <- Analyzer summary
+//   RX SRC Accept                <- previous message
+//   RX SRC PS_RDY                <- latest message
+//
+// GoodCRC replies are left out of the message lines.
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, TextLine};
+use super::dashboard::Error;
+use crate::hal::Panel;
+use crate::pd::decode::{self, Analyzer};
+use crate::pd::message::ControlType;
+use crate::pd::trace::{TraceEvent, TraceRecord};
+
+const COLOR_SUMMARY: Rgb565 = Rgb565::GREEN;
+const COLOR_MESSAGE: Rgb565 = Rgb565::WHITE;
+const COLOR_IDLE: Rgb565 = Rgb565::new(15, 30, 15);
+
+// Longer than the 26 characters on screen, the canvas clips the rest
+type Line = String<32>;
+
+pub struct PdTracePage {
+    analyzer: Analyzer,
+    // Oldest first
+    messages: [Line; 2],
+    dirty: bool,
+    line: TextLine,
+}
+
+impl Default for PdTracePage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PdTracePage {
+    pub fn new() -> Self {
+        Self {
+            analyzer: Analyzer::new(),
+            messages: [Line::new(), Line::new()],
+            dirty: true,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Redraw everything on the next `draw`, e.g. after a page switch
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn feed(&mut self, record: &TraceRecord) {
+        let direction = match record.event {
+            TraceEvent::Received | TraceEvent::HardResetReceived => "RX",
+            TraceEvent::Transmitted | TraceEvent::HardResetSent => "TX",
+        };
+
+        let mut text = Line::new();
+        match record.event {
+            TraceEvent::HardResetReceived | TraceEvent::HardResetSent => {
+                self.analyzer.hard_reset();
+                let _ = write!(text, "{} Hard_Reset", direction);
+            }
+            TraceEvent::Received | TraceEvent::Transmitted => {
+                let Ok(decoded) = decode::decode(record.bytes()) else {
+                    return;
+                };
+                if decoded.is_control(ControlType::GoodCrc) {
+                    return;
+                }
+                self.analyzer.feed(&decoded);
+                let _ = write!(
+                    text,
+                    "{} {} {}",
+                    direction,
+                    decoded.sender(),
+                    decoded.name()
+                );
+            }
+        }
+
+        self.messages.rotate_left(1);
+        self.messages[1] = text;
+        self.dirty = true;
+    }
+
+    pub async fn draw<P: Panel>(&mut self, display: &mut P) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        let (summary, color) = match self.analyzer.summary() {
+            "" => ("No PD traffic", COLOR_IDLE),
+            summary => (summary, COLOR_SUMMARY),
+        };
+        self.line.set_text(summary, 0);
+        self.line.blit(display, 0, color, Rgb565::BLACK).await?;
+
+        for (i, message) in self.messages.iter().enumerate() {
+            self.line.set_text(message, 0);
+            let y = (LINE_HEIGHT * (i + 1)) as u16;
+            self.line
+                .blit(display, y, COLOR_MESSAGE, Rgb565::BLACK)
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+// core/src/display/port.rs
+// Port page: attached device, current or power trend and charge progress
+// of one downstream port
+//
+//   P2 charging 1.234A   taper   <- attach state, current, charge phase
+//   full-width graph             <- last 160 s of the "Graphs" setting,
+//                                   auto scaled, as tall as the panel
+//                                   leaves room for
+//   0.52Wh 0:42  full in 0:35    <- session energy, duration, time to full
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, TextLine};
+use super::dashboard::Error;
+use super::sparkline::{MAX_HEIGHT, Sparkline, Style};
+use crate::attach::AttachState;
+use crate::charge::ChargePhase;
+use crate::hal::Panel;
+use crate::history::{HISTORY_LEN, PortHistory, Trend};
+use crate::usage::Session;
+
+const COLOR_TEXT: Rgb565 = Rgb565::WHITE;
+const COLOR_CHARGING: Rgb565 = Rgb565::GREEN;
+const COLOR_IDLE: Rgb565 = Rgb565::new(15, 30, 15);
+const COLOR_CURRENT: Rgb565 = Rgb565::RED;
+const COLOR_POWER: Rgb565 = Rgb565::GREEN;
+
+type Line = String<32>;
+
+/// `h:mm` from seconds
+struct HoursMinutes(u64);
+
+impl core::fmt::Display for HoursMinutes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let minutes = self.0 / 60;
+        write!(f, "{}:{:02}", minutes / 60, minutes % 60)
+    }
+}
+
+pub struct PortPage {
+    // Text currently on screen, only changed lines are redrawn
+    shown: [(Line, Rgb565); 2],
+    // Port, `PortHistory::count` and trend of the graph on screen
+    shown_graph: Option<(usize, u32, Trend)>,
+    dirty: bool,
+    line: TextLine,
+}
+
+impl Default for PortPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortPage {
+    pub fn new() -> Self {
+        Self {
+            shown: [(Line::new(), COLOR_TEXT), (Line::new(), COLOR_TEXT)],
+            shown_graph: None,
+            dirty: true,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Redraw everything on the next `draw`, e.g. after a page switch
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    fn lines(port: usize, current_ma: u32, session: &Session) -> [(Line, Rgb565); 2] {
+        let mut lines = [(Line::new(), COLOR_IDLE), (Line::new(), COLOR_TEXT)];
+
+        let (state, color) = match session.state {
+            AttachState::Detached => ("empty", COLOR_IDLE),
+            AttachState::Idle => ("attached", COLOR_TEXT),
+            AttachState::Charging => ("charging", COLOR_CHARGING),
+        };
+        let _ = write!(
+            lines[0].0,
+            "P{} {} {}.{:03}A",
+            port + 1,
+            state,
+            current_ma / 1000,
+            current_ma % 1000
+        );
+        if session.state != AttachState::Detached && session.phase != ChargePhase::Unknown {
+            // Phase right aligned in the 26 columns
+            let phase = session.phase.name();
+            while lines[0].0.len() + phase.len() < 26 {
+                let _ = lines[0].0.push(' ');
+            }
+            let _ = lines[0].0.push_str(phase);
+        }
+        lines[0].1 = color;
+
+        let _ = write!(
+            lines[1].0,
+            "{:.2}Wh {}",
+            session.energy_wh(),
+            HoursMinutes(session.duration_ms / 1000)
+        );
+        match session.time_to_full_s {
+            Some(0) | None => {}
+            Some(seconds) => {
+                let _ = write!(lines[1].0, "  full in {}", HoursMinutes(seconds as u64));
+            }
+        }
+        lines
+    }
+
+    pub async fn draw<P: Panel>(
+        &mut self,
+        display: &mut P,
+        port: usize,
+        current_ma: u32,
+        session: &Session,
+        history: &PortHistory,
+        trend: Trend,
+    ) -> Result<(), Error> {
+        // Text on the first and the last whole line, the graph in between
+        let (width, height) = display.size();
+        let bottom = (height as usize / LINE_HEIGHT).saturating_sub(1) * LINE_HEIGHT;
+        let graph = Sparkline {
+            x: 0,
+            y: LINE_HEIGHT as u16,
+            width: HISTORY_LEN.min(width as usize) as u16,
+            height: bottom.saturating_sub(LINE_HEIGHT).min(MAX_HEIGHT) as u16,
+            style: Style::Line,
+            color: match trend {
+                Trend::Current => COLOR_CURRENT,
+                Trend::Power => COLOR_POWER,
+            },
+            background: Rgb565::BLACK,
+        };
+
+        let lines = Self::lines(port, current_ma, session);
+        for (i, (text, color)) in lines.into_iter().enumerate() {
+            if !self.dirty && self.shown[i].0 == text && self.shown[i].1 == color {
+                continue;
+            }
+            self.line.set_text(&text, 0);
+            let y = (bottom * i) as u16;
+            self.line.blit(display, y, color, Rgb565::BLACK).await?;
+            self.shown[i] = (text, color);
+        }
+
+        let shown_graph = Some((port, history.count(), trend));
+        if self.dirty || self.shown_graph != shown_graph {
+            let mut values = [0u16; HISTORY_LEN];
+            let n = history.recent(&mut values[..graph.width as usize], |p| trend.value(p));
+            graph.draw(display, &values[..n]).await?;
+            self.shown_graph = shown_graph;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
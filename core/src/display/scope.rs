@@ -0,0 +1,535 @@
+// core/src/display/scope.rs
+// Scope page: current of one port against time, across the full width
+//
+//   P2 burst 61ms auto done      <- port, source, time base, scale, run
+//   current, the peak of the samples behind each column; the trigger
+//   column of a burst in the marker color
+//   min 0.00 max 4.39 av 2.05A   <- over the visible samples
+//
+// Two sources: the per-second trend history of any port, or the last burst
+// capture. A long BTN3 starts adjusting: the first line then shows one
+// control, BTN2/BTN3 step it, a long BTN3 goes to the next control and a
+// long BTN2 stops adjusting. Short presses switch pages otherwise, like on
+// every page. Holding stops the trend where it is; on the burst source
+// "run" arms a capture with the stored trigger and holding stops it.
+
+use core::fmt::{self, Write};
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, TextLine};
+use super::dashboard::Error;
+use super::sparkline::{MAX_HEIGHT, Sparkline, Style, nice_scale};
+use super::trends::Milliamps;
+use crate::capture::{CAPTURE_SAMPLES, Capture, State, Status, Trigger};
+use crate::hal::{Button, Panel, Press};
+use crate::history::{HISTORY_INTERVAL_MS, HISTORY_LEN, PortHistory};
+use crate::shared::PORT_COUNT;
+
+const COLOR_TEXT: Rgb565 = Rgb565::WHITE;
+const COLOR_EDITING: Rgb565 = Rgb565::new(31, 40, 0);
+const COLOR_IDLE: Rgb565 = Rgb565::new(15, 30, 15);
+const COLOR_CURRENT: Rgb565 = Rgb565::RED;
+const COLOR_TRIGGER: Rgb565 = Rgb565::CYAN;
+
+/// Time bases as fractions of the source: all of it, a half, ... an eighth
+const ZOOMS: usize = 4;
+/// Fixed scales after "auto", mA at the top edge
+const SCALES: [u32; 6] = [100, 200, 500, 1000, 2000, 5000];
+
+type Line = String<32>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// One point per second, `HISTORY_LEN` of them
+    Trend,
+    /// The last burst capture window
+    Burst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Control {
+    Run,
+    Time,
+    Scale,
+    Source,
+    /// Trend only, a burst shows the port it was captured on
+    Port,
+}
+
+impl Control {
+    fn next(self, source: Source) -> Self {
+        match self {
+            Control::Run => Control::Time,
+            Control::Time => Control::Scale,
+            Control::Scale => Control::Source,
+            Control::Source if source == Source::Trend => Control::Port,
+            Control::Source | Control::Port => Control::Run,
+        }
+    }
+}
+
+/// What became of a press on the scope page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Not for the scope: switch pages or open the menu
+    Ignored,
+    Handled,
+    /// Arm a burst capture, or stop the running one
+    Capture {
+        run: bool,
+    },
+}
+
+/// Peak of each column's share of `samples`; fewer samples than columns
+/// repeat over several columns
+pub(super) fn resample(samples: &[u16], columns: &mut [u16]) {
+    let (n, width) = (samples.len(), columns.len());
+    for (i, column) in columns.iter_mut().enumerate() {
+        let start = (i * n / width).min(n.saturating_sub(1));
+        let end = ((i + 1) * n / width).clamp(start + 1, n.max(1));
+        *column = samples[start..end].iter().copied().max().unwrap_or(0);
+    }
+}
+
+/// Length of `samples` taken `period_us` apart, e.g. "80s" or "61ms"
+struct Span {
+    samples: usize,
+    period_us: u32,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = self.samples as u64 * self.period_us as u64 / 1000;
+        if ms >= 10_000 {
+            write!(f, "{}s", ms / 1000)
+        } else {
+            write!(f, "{}ms", ms)
+        }
+    }
+}
+
+/// Amps with two decimals from mA
+struct Amps(u32);
+
+impl fmt::Display for Amps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.0 / 1000, self.0 % 1000 / 10)
+    }
+}
+
+pub struct ScopePage {
+    port: usize,
+    source: Source,
+    // Index into the time bases, 0 shows the whole source
+    zoom: usize,
+    // 0 scales to the visible samples, else `SCALES[scale - 1]`
+    scale: usize,
+    // The trend follows the history; a burst is always held
+    running: bool,
+    adjusting: Option<Control>,
+    // Current in mA, oldest first, as taken from the source
+    samples: [u16; CAPTURE_SAMPLES],
+    len: usize,
+    period_us: u32,
+    trigger_index: Option<usize>,
+    // `PortHistory::count` or capture sequence the samples came from
+    loaded: Option<u32>,
+    status: Status,
+    dirty: bool,
+    line: TextLine,
+}
+
+impl Default for ScopePage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScopePage {
+    pub fn new() -> Self {
+        Self {
+            port: 1,
+            source: Source::Trend,
+            zoom: 0,
+            scale: 0,
+            running: true,
+            adjusting: None,
+            samples: [0; CAPTURE_SAMPLES],
+            len: 0,
+            period_us: HISTORY_INTERVAL_MS * 1000,
+            trigger_index: None,
+            loaded: None,
+            status: Capture::new().status(),
+            dirty: true,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Redraw everything on the next `draw`, e.g. after a page switch
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    /// `button` as seen by the user, already swapped on a turned panel
+    pub fn press(&mut self, button: Button, press: Press) -> Outcome {
+        let forward = button == Button::Right;
+        let Some(control) = self.adjusting else {
+            if press == Press::Long && forward {
+                self.adjusting = Some(Control::Run);
+                self.dirty = true;
+                return Outcome::Handled;
+            }
+            return Outcome::Ignored;
+        };
+        self.dirty = true;
+        match press {
+            Press::Long if forward => self.adjusting = Some(control.next(self.source)),
+            Press::Long => self.adjusting = None,
+            // Holding steps through the values, it doesn't flip back and forth
+            Press::Repeat(_) if matches!(control, Control::Run | Control::Source) => {}
+            Press::Short | Press::Repeat(_) => return self.step(control, forward),
+        }
+        Outcome::Handled
+    }
+
+    fn step(&mut self, control: Control, forward: bool) -> Outcome {
+        match control {
+            Control::Run if self.source == Source::Burst => {
+                return Outcome::Capture {
+                    run: !self.status.is_running(),
+                };
+            }
+            Control::Run => {
+                self.running = !self.running;
+                // Back to the history as it is now
+                self.loaded = None;
+            }
+            // Forward is a longer time base, a larger scale
+            Control::Time if forward => self.zoom = self.zoom.saturating_sub(1),
+            Control::Time => self.zoom = (self.zoom + 1).min(ZOOMS - 1),
+            Control::Scale if forward => self.scale = (self.scale + 1).min(SCALES.len()),
+            Control::Scale => self.scale = self.scale.saturating_sub(1),
+            Control::Source => {
+                self.source = match self.source {
+                    Source::Trend => Source::Burst,
+                    Source::Burst => Source::Trend,
+                };
+                self.loaded = None;
+            }
+            Control::Port => {
+                self.port = match forward {
+                    true => (self.port + 1) % PORT_COUNT,
+                    false => (self.port + PORT_COUNT - 1) % PORT_COUNT,
+                };
+                self.loaded = None;
+            }
+        }
+        Outcome::Handled
+    }
+
+    /// Samples of the source that fill the width at this time base
+    fn nominal(&self) -> usize {
+        let full = match self.source {
+            Source::Trend => HISTORY_LEN,
+            Source::Burst => CAPTURE_SAMPLES,
+        };
+        full >> self.zoom
+    }
+
+    /// First visible sample and how many: the newest of a trend, a burst
+    /// from a quarter of the time base before its trigger
+    fn window(&self) -> (usize, usize) {
+        let count = self.nominal().min(self.len);
+        let start = match self.trigger_index {
+            Some(trigger) => trigger
+                .saturating_sub(self.nominal() / 4)
+                .min(self.len - count),
+            None => self.len - count,
+        };
+        (start, count)
+    }
+
+    fn load(
+        &mut self,
+        histories: &[PortHistory; PORT_COUNT],
+        status: Status,
+        snapshot: impl FnOnce() -> Capture,
+    ) {
+        let version = match self.source {
+            Source::Trend if self.running || self.loaded.is_none() => histories[self.port].count(),
+            Source::Trend => return,
+            Source::Burst => status.seq,
+        };
+        if self.loaded == Some(version) {
+            return;
+        }
+        self.loaded = Some(version);
+        self.dirty = true;
+        match self.source {
+            Source::Trend => {
+                self.len =
+                    histories[self.port].recent(&mut self.samples[..HISTORY_LEN], |p| p.current_ma);
+                self.period_us = HISTORY_INTERVAL_MS * 1000;
+                self.trigger_index = None;
+            }
+            Source::Burst if status.state == State::Done => {
+                let capture = snapshot();
+                for (sample, &current_ua) in self.samples.iter_mut().zip(capture.samples()) {
+                    *sample = (current_ua.max(0) / 1000).min(u16::MAX as i32) as u16;
+                }
+                self.len = capture.samples().len();
+                self.period_us = capture.period_us();
+                self.trigger_index = Some(capture.trigger_index());
+            }
+            Source::Burst => {
+                self.len = 0;
+                self.trigger_index = None;
+            }
+        }
+    }
+
+    fn header(&self, port: usize, scale: u32, line: &mut Line) -> Rgb565 {
+        let run = match self.source {
+            Source::Trend if self.running => "run",
+            Source::Trend => "hold",
+            Source::Burst => self.status.state.name(),
+        };
+        let span = Span {
+            samples: self.nominal(),
+            period_us: self.period_us,
+        };
+        let scale_text = |line: &mut Line| match self.scale {
+            0 => write!(line, "auto"),
+            _ => write!(line, "{}", Milliamps(scale)),
+        };
+        let _ = match self.adjusting {
+            None => {
+                let source = match self.source {
+                    Source::Trend => "trend",
+                    Source::Burst => "burst",
+                };
+                let _ = write!(line, "P{} {} ", port + 1, source);
+                // A burst's period is only known once it is done
+                if self.len > 0 {
+                    let _ = write!(line, "{} ", span);
+                }
+                let _ = scale_text(line);
+                write!(line, " {}", run)
+            }
+            Some(control) => {
+                let _ = write!(line, "< ");
+                let _ = match control {
+                    Control::Run => write!(line, "Run {}", run),
+                    Control::Time if self.len > 0 => write!(line, "Time {}", span),
+                    Control::Time => write!(line, "Time 1/{}", 1 << self.zoom),
+                    Control::Scale => {
+                        let _ = write!(line, "Scale ");
+                        scale_text(line)
+                    }
+                    Control::Source => match self.source {
+                        Source::Trend => write!(line, "Source trend"),
+                        Source::Burst => write!(line, "Source burst"),
+                    },
+                    Control::Port => write!(line, "Port P{}", port + 1),
+                };
+                write!(line, " >")
+            }
+        };
+        match self.adjusting {
+            Some(_) => COLOR_EDITING,
+            None => COLOR_TEXT,
+        }
+    }
+
+    /// `trigger` is what a burst is armed with, `snapshot` is only taken
+    /// when `status` shows a new window
+    pub async fn draw<P: Panel>(
+        &mut self,
+        display: &mut P,
+        histories: &[PortHistory; PORT_COUNT],
+        trigger: Trigger,
+        status: Status,
+        snapshot: impl FnOnce() -> Capture,
+    ) -> Result<(), Error> {
+        if status != self.status {
+            self.status = status;
+            // The trend page doesn't show the capture
+            self.dirty |= self.source == Source::Burst;
+        }
+        self.load(histories, status, snapshot);
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        // Text on the first and the last whole line, the graph in between
+        let (width, height) = display.size();
+        let bottom = (height as usize / LINE_HEIGHT).saturating_sub(1) * LINE_HEIGHT;
+        let graph = Sparkline {
+            x: 0,
+            y: LINE_HEIGHT as u16,
+            width,
+            height: bottom.saturating_sub(LINE_HEIGHT).min(MAX_HEIGHT) as u16,
+            style: Style::Line,
+            color: COLOR_CURRENT,
+            background: Rgb565::BLACK,
+        };
+
+        let (start, count) = self.window();
+        let visible = &self.samples[start..start + count];
+        // A short trend fills the right part, as far as it reaches
+        let columns = (width as usize * count / self.nominal().max(1)).min(width as usize);
+        let mut values = [0u16; CAPTURE_SAMPLES];
+        let values = &mut values[..columns.min(CAPTURE_SAMPLES)];
+        if count > 0 {
+            resample(visible, values);
+        }
+        let scale = match self.scale {
+            0 => nice_scale(visible.iter().copied().max().unwrap_or(0) as u32),
+            i => SCALES[i - 1],
+        };
+        graph.draw_scaled(display, values, scale).await?;
+        let trigger_shown = self
+            .trigger_index
+            .filter(|i| graph.height > 0 && (start..start + count).contains(i));
+        if let Some(trigger) = trigger_shown {
+            // Two pixels at the top of the trigger column
+            let column = (trigger - start) * values.len() / count;
+            display
+                .write_area(column as u16, graph.y, 1, 2, &[COLOR_TRIGGER; 2])
+                .await?;
+        }
+
+        let port = match self.source {
+            Source::Trend => self.port,
+            Source::Burst => status.trigger.unwrap_or(trigger).port,
+        };
+        let mut text = Line::new();
+        let color = self.header(port, scale, &mut text);
+        self.line.set_text(&text, 0);
+        self.line.blit(display, 0, color, Rgb565::BLACK).await?;
+
+        let mut text = Line::new();
+        let color = if count > 0 {
+            let min = visible.iter().copied().min().unwrap_or(0);
+            let max = visible.iter().copied().max().unwrap_or(0);
+            let sum: u32 = visible.iter().map(|&ma| ma as u32).sum();
+            let _ = write!(
+                text,
+                "min {} max {} av {}A",
+                Amps(min as u32),
+                Amps(max as u32),
+                Amps(sum / count as u32)
+            );
+            COLOR_TEXT
+        } else {
+            let _ = match self.source {
+                Source::Trend => write!(text, "No samples yet"),
+                Source::Burst if status.is_running() => {
+                    let level = status.trigger.unwrap_or(trigger).level_ma;
+                    write!(text, "Waiting for >{}mA", level)
+                }
+                Source::Burst => write!(text, "Run arms >{}mA", trigger.level_ma),
+            };
+            COLOR_IDLE
+        };
+        self.line.set_text(&text, 0);
+        self.line
+            .blit(display, bottom as u16, color, Rgb565::BLACK)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_keep_the_peak_of_their_samples() {
+        let mut samples = [0u16; 10];
+        samples[3] = 2500;
+        let mut columns = [u16::MAX; 4];
+        resample(&samples, &mut columns);
+        // Slices of 2, 3, 2 and 3 samples
+        assert_eq!(columns, [0, 2500, 0, 0]);
+        // Stretched: every sample over two columns
+        let mut columns = [u16::MAX; 8];
+        resample(&[1, 2, 3, 4], &mut columns);
+        assert_eq!(columns, [1, 1, 2, 2, 3, 3, 4, 4]);
+    }
+
+    #[test]
+    fn controls_step_and_skip_the_port_of_a_burst() {
+        let mut page = ScopePage::new();
+        assert_eq!(page.press(Button::Right, Press::Short), Outcome::Ignored);
+        assert_eq!(page.press(Button::Left, Press::Long), Outcome::Ignored);
+        assert_eq!(page.press(Button::Right, Press::Long), Outcome::Handled);
+        assert_eq!(page.adjusting, Some(Control::Run));
+
+        // Hold and run again
+        page.press(Button::Right, Press::Short);
+        assert!(!page.running);
+        page.press(Button::Left, Press::Short);
+        assert!(page.running);
+
+        // Time bases and scales stop at their ends
+        page.press(Button::Right, Press::Long);
+        for _ in 0..5 {
+            page.press(Button::Left, Press::Repeat(0));
+        }
+        assert_eq!(page.nominal(), HISTORY_LEN / 8);
+        page.press(Button::Right, Press::Long);
+        for _ in 0..10 {
+            page.press(Button::Right, Press::Short);
+        }
+        assert_eq!(page.scale, SCALES.len());
+
+        page.press(Button::Right, Press::Long);
+        page.press(Button::Right, Press::Long);
+        assert_eq!(page.adjusting, Some(Control::Port));
+        page.press(Button::Left, Press::Short);
+        page.press(Button::Left, Press::Short);
+        assert_eq!(page.port, 2);
+
+        // On a burst, run arms and the controls go round without the port
+        page.press(Button::Right, Press::Long);
+        assert_eq!(page.adjusting, Some(Control::Run));
+        page.adjusting = Some(Control::Source);
+        page.press(Button::Right, Press::Short);
+        assert_eq!(page.source(), Source::Burst);
+        page.press(Button::Right, Press::Long);
+        assert_eq!(page.adjusting, Some(Control::Run));
+        assert_eq!(
+            page.press(Button::Right, Press::Short),
+            Outcome::Capture { run: true }
+        );
+        page.press(Button::Left, Press::Long);
+        assert_eq!(page.adjusting, None);
+    }
+
+    #[test]
+    fn windows_end_at_the_newest_sample_or_surround_the_trigger() {
+        let mut page = ScopePage::new();
+        page.len = 100;
+        assert_eq!(page.window(), (0, 100));
+        page.zoom = 1;
+        assert_eq!(page.window(), (20, 80));
+
+        page.source = Source::Burst;
+        page.len = CAPTURE_SAMPLES;
+        page.trigger_index = Some(128);
+        assert_eq!(page.window(), (64, 256));
+        page.zoom = 3;
+        assert_eq!(page.window(), (112, 64));
+        // An early trigger keeps the window inside the samples
+        page.trigger_index = Some(3);
+        assert_eq!(page.window(), (0, 64));
+    }
+}
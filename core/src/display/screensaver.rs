@@ -0,0 +1,106 @@
+// core/src/display/screensaver.rs
+// When the panel sleeps and where the pages sit on it
+//
+// The panel goes to sleep after `timeout` without a button press or a port
+// event and wakes on the next one; a press that wakes it does nothing else.
+// While awake, the whole layout moves by a pixel every `SHIFT_PERIOD_MS`,
+// round a 2x2 square, so no pixel shows the same thing for hours. Only
+// timing lives here, on the caller's clock, so it is tested on the host;
+// `app` switches the panel and applies the shift.
+
+/// Time on each layout position
+pub const SHIFT_PERIOD_MS: u64 = 5 * 60_000;
+/// (dx, dy) of the layout, in turn
+const SHIFTS: [(u16, u16); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+pub struct ScreenSaver {
+    /// 0 never sleeps
+    timeout_ms: u64,
+    last_activity_ms: u64,
+    asleep: bool,
+}
+
+impl ScreenSaver {
+    pub fn new(timeout_ms: u64, now_ms: u64) -> Self {
+        Self {
+            timeout_ms,
+            last_activity_ms: now_ms,
+            asleep: false,
+        }
+    }
+
+    /// Change the idle time; counts as activity, so a shorter one doesn't
+    /// blank the panel right away
+    pub fn set_timeout(&mut self, timeout_ms: u64, now_ms: u64) {
+        self.timeout_ms = timeout_ms;
+        self.activity(now_ms);
+    }
+
+    /// A button press or a port event; true if it woke the panel
+    pub fn activity(&mut self, now_ms: u64) -> bool {
+        self.last_activity_ms = now_ms;
+        core::mem::replace(&mut self.asleep, false)
+    }
+
+    /// Fall asleep once the idle time has run out
+    pub fn update(&mut self, now_ms: u64) {
+        if self.timeout_ms > 0 && now_ms.saturating_sub(self.last_activity_ms) >= self.timeout_ms {
+            self.asleep = true;
+        }
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    /// Offset of the layout at `now_ms`
+    pub fn shift(&self, now_ms: u64) -> (u16, u16) {
+        SHIFTS[(now_ms / SHIFT_PERIOD_MS) as usize % SHIFTS.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleeps_after_the_idle_time_and_wakes_on_activity() {
+        let mut saver = ScreenSaver::new(60_000, 1000);
+        saver.update(60_999);
+        assert!(!saver.is_asleep());
+        saver.update(61_000);
+        assert!(saver.is_asleep());
+
+        // Waking restarts the idle time
+        assert!(saver.activity(90_000));
+        assert!(!saver.is_asleep());
+        assert!(!saver.activity(100_000));
+        saver.update(159_999);
+        assert!(!saver.is_asleep());
+        saver.update(160_000);
+        assert!(saver.is_asleep());
+    }
+
+    #[test]
+    fn zero_timeout_never_sleeps() {
+        let mut saver = ScreenSaver::new(0, 0);
+        saver.update(u64::MAX);
+        assert!(!saver.is_asleep());
+
+        saver.set_timeout(1000, 5000);
+        saver.update(5999);
+        assert!(!saver.is_asleep());
+        saver.update(6000);
+        assert!(saver.is_asleep());
+        saver.set_timeout(0, 7000);
+        assert!(!saver.is_asleep());
+    }
+
+    #[test]
+    fn layout_walks_a_square() {
+        let saver = ScreenSaver::new(0, 0);
+        let shifts: [_; 5] = core::array::from_fn(|i| saver.shift(i as u64 * SHIFT_PERIOD_MS + 1));
+        assert_eq!(shifts, [(0, 0), (1, 0), (1, 1), (0, 1), (0, 0)]);
+        assert_eq!(saver.shift(SHIFT_PERIOD_MS - 1), (0, 0));
+    }
+}
@@ -0,0 +1,105 @@
+// core/src/display/selftest.rs
+// Boot screens of the power-on self-test: colour bars, then the summary
+//
+//   Self-test 1 failed 1 warn
+//   P3 sensor no answer
+//   P2 zero 85mA warn
+//
+// The summary lists failures, then warnings, as far as the panel reaches;
+// with room to spare the last line tells why the MCU reset.
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, TextLine};
+use super::dashboard::Error;
+use crate::event_log::ring::ResetCause;
+use crate::hal::Panel;
+use crate::selftest::{Report, Verdict};
+
+/// Bars left to right; a missing or swapped colour shows a wiring or
+/// colour order fault at a glance
+const PATTERN: [Rgb565; 8] = [
+    Rgb565::WHITE,
+    Rgb565::YELLOW,
+    Rgb565::CYAN,
+    Rgb565::GREEN,
+    Rgb565::MAGENTA,
+    Rgb565::RED,
+    Rgb565::BLUE,
+    Rgb565::BLACK,
+];
+
+const COLOR_PASS: Rgb565 = Rgb565::GREEN;
+const COLOR_WARN: Rgb565 = Rgb565::new(31, 40, 0);
+const COLOR_FAIL: Rgb565 = Rgb565::RED;
+const COLOR_TEXT: Rgb565 = Rgb565::WHITE;
+
+type Line = String<32>;
+
+fn color(verdict: Verdict) -> Rgb565 {
+    match verdict {
+        Verdict::Pass => COLOR_PASS,
+        Verdict::Warn => COLOR_WARN,
+        Verdict::Fail => COLOR_FAIL,
+    }
+}
+
+/// Eight colour bars across the whole panel
+pub async fn draw_pattern<P: Panel>(display: &mut P) -> Result<(), Error> {
+    let (width, height) = display.size();
+    for (i, &bar) in PATTERN.iter().enumerate() {
+        let column = [bar; 64];
+        let (x0, x1) = (
+            width as usize * i / PATTERN.len(),
+            width as usize * (i + 1) / PATTERN.len(),
+        );
+        for x in x0..x1 {
+            for y in (0..height).step_by(column.len()) {
+                let rows = (height - y).min(column.len() as u16);
+                display
+                    .write_area(x as u16, y, 1, rows, &column[..rows as usize])
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn draw_summary<P: Panel>(
+    display: &mut P,
+    report: &Report,
+    reset: ResetCause,
+) -> Result<(), Error> {
+    display.fill_color(Rgb565::BLACK).await?;
+    let rows = (display.size().1 as usize / LINE_HEIGHT).max(1);
+
+    let mut lines: heapless::Vec<(Line, Rgb565), 8> = heapless::Vec::new();
+    let mut heading = Line::new();
+    let _ = match (report.failed(), report.warnings()) {
+        (0, 0) => write!(heading, "Self-test passed"),
+        (0, warnings) => write!(heading, "Self-test {} warn", warnings),
+        (failed, 0) => write!(heading, "Self-test {} failed", failed),
+        (failed, warnings) => write!(heading, "Self-test {} failed {} warn", failed, warnings),
+    };
+    let _ = lines.push((heading, color(report.verdict())));
+    for check in report.problems() {
+        let mut line = Line::new();
+        let _ = write!(line, "{}", check);
+        let _ = lines.push((line, color(check.verdict())));
+    }
+    let mut line = Line::new();
+    let _ = write!(line, "Reset: {}", reset.name());
+    let _ = lines.push((line, COLOR_TEXT));
+
+    let mut text_line = TextLine::new();
+    for (row, (text, color)) in lines.iter().take(rows).enumerate() {
+        text_line.set_text(text, 0);
+        let y = (LINE_HEIGHT * row) as u16;
+        text_line.blit(display, y, *color, Rgb565::BLACK).await?;
+    }
+    Ok(())
+}
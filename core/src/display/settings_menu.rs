@@ -0,0 +1,711 @@
+// core/src/display/settings_menu.rs
+// Settings menu: the stored `Settings` edited with the two front buttons
+//
+//   >Brightness          80%
+//    Theme              dark
+//    Buzzer               on
+//
+// A long press on any page opens the menu on a copy of the settings, only a
+// long BTN2 on the scope page, where a long BTN3 adjusts the scope. In the
+// list, BTN2 moves up and BTN3 down, holding scrolls; a long BTN3 flips a
+// switch, runs an action or starts editing the value, a long BTN2 leaves.
+// While editing, BTN2 steps down and BTN3 up, held they repeat and speed
+// up; a long BTN3 keeps the value, a long BTN2 puts the old one back.
+// Nothing is applied or stored before "Save", leaving drops the changes.
+// The calibration actions are the exception: they start a run right away
+// and `App` stores its result, which `set_corrections` brings into the menu.
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, TextLine};
+use super::dashboard::Error;
+use crate::calibration::Request;
+use crate::hal::{Button, Panel, Press};
+use crate::history::Trend;
+use crate::shared::PORT_COUNT;
+use crate::storage::settings::{CurrentCorrection, SensorProfile, Settings, Theme};
+
+// Bright enough to show on monochrome panels
+const COLOR_ITEM: Rgb565 = Rgb565::new(20, 40, 20);
+const COLOR_SELECTED: Rgb565 = Rgb565::WHITE;
+const COLOR_EDITING: Rgb565 = Rgb565::new(31, 40, 0);
+
+/// Rows shown on panels taller than the 40 px one
+const MAX_ROWS: usize = 8;
+
+/// Shunts the hub is built with, and the current range for each
+pub const SENSOR_PRESETS: [(&str, SensorProfile); 3] = [
+    (
+        "5mR 4A",
+        SensorProfile {
+            shunt_micro_ohms: 5000,
+            max_current_ma: 4000,
+        },
+    ),
+    (
+        "10mR 4A",
+        SensorProfile {
+            shunt_micro_ohms: 10_000,
+            max_current_ma: 4000,
+        },
+    ),
+    (
+        "20mR 2A",
+        SensorProfile {
+            shunt_micro_ohms: 20_000,
+            max_current_ma: 2000,
+        },
+    ),
+];
+
+type Line = String<32>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Item {
+    Brightness,
+    Theme,
+    Buzzer,
+    Rotate,
+    Sleep,
+    CounterSave,
+    SelfTest,
+    Graph,
+    OverCurrent(usize),
+    OverVoltage(usize),
+    Sensor(usize),
+    CapturePort,
+    CaptureLevel,
+    CalZero,
+    /// Current set on the load for a gain run, kept by the menu only
+    CalReference,
+    CalGain(usize),
+    Save,
+    Defaults,
+}
+
+const ITEM_COUNT: usize = 14 + 4 * PORT_COUNT;
+const ITEMS: [Item; ITEM_COUNT] = items();
+
+const fn items() -> [Item; ITEM_COUNT] {
+    let mut items = [Item::Save; ITEM_COUNT];
+    let general = [
+        Item::Brightness,
+        Item::Theme,
+        Item::Buzzer,
+        Item::Rotate,
+        Item::Sleep,
+        Item::CounterSave,
+        Item::SelfTest,
+        Item::Graph,
+    ];
+    let mut i = 0;
+    while i < general.len() {
+        items[i] = general[i];
+        i += 1;
+    }
+    let mut port = 0;
+    while port < PORT_COUNT {
+        items[i] = Item::OverCurrent(port);
+        items[i + 1] = Item::OverVoltage(port);
+        items[i + 2] = Item::Sensor(port);
+        i += 3;
+        port += 1;
+    }
+    items[i] = Item::CapturePort;
+    items[i + 1] = Item::CaptureLevel;
+    items[i + 2] = Item::CalZero;
+    items[i + 3] = Item::CalReference;
+    i += 4;
+    port = 0;
+    while port < PORT_COUNT {
+        items[i] = Item::CalGain(port);
+        i += 1;
+        port += 1;
+    }
+    items[i] = Item::Save;
+    items[i + 1] = Item::Defaults;
+    items
+}
+
+/// Limits and step of a numeric value
+#[derive(Clone, Copy)]
+struct Range {
+    min: u32,
+    max: u32,
+    step: u32,
+}
+
+enum Kind {
+    Number(Range),
+    /// One of a few values, stepped through in turn
+    Choice,
+    /// On or off, flipped without editing
+    Switch,
+    Action,
+}
+
+/// Step multiplier while a button is held, by the repeats so far: single
+/// steps for the first second, then tens, then hundreds
+fn acceleration(repeats: u16) -> u32 {
+    match repeats {
+        0..10 => 1,
+        10..30 => 10,
+        _ => 100,
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+impl Item {
+    fn kind(self) -> Kind {
+        let range = |min, max, step| Kind::Number(Range { min, max, step });
+        match self {
+            // Not down to black, the menu has to stay readable
+            Item::Brightness => range(5, 100, 5),
+            Item::Sleep => range(0, 240, 1),
+            Item::CounterSave => range(0, 1440, 5),
+            Item::OverCurrent(_) => range(100, 10_000, 50),
+            Item::OverVoltage(_) => range(1000, 30_000, 100),
+            Item::CaptureLevel => range(50, 4000, 50),
+            Item::CalReference => range(100, 4000, 50),
+            Item::Theme | Item::Graph | Item::Sensor(_) | Item::CapturePort => Kind::Choice,
+            Item::Buzzer | Item::Rotate | Item::SelfTest => Kind::Switch,
+            Item::CalZero | Item::CalGain(_) | Item::Save | Item::Defaults => Kind::Action,
+        }
+    }
+
+    fn label(self, line: &mut Line) {
+        let _ = match self {
+            Item::Brightness => write!(line, "Brightness"),
+            Item::Theme => write!(line, "Theme"),
+            Item::Buzzer => write!(line, "Buzzer"),
+            Item::Rotate => write!(line, "Rotate"),
+            Item::Sleep => write!(line, "Sleep"),
+            Item::CounterSave => write!(line, "Counter save"),
+            Item::SelfTest => write!(line, "Self-test"),
+            Item::Graph => write!(line, "Graphs"),
+            Item::OverCurrent(port) => write!(line, "P{} OCP", port + 1),
+            Item::OverVoltage(port) => write!(line, "P{} OVP", port + 1),
+            Item::Sensor(port) => write!(line, "P{} sensor", port + 1),
+            Item::CapturePort => write!(line, "Capture"),
+            Item::CaptureLevel => write!(line, "Trigger"),
+            Item::CalZero => write!(line, "Zero cal"),
+            Item::CalReference => write!(line, "Cal current"),
+            Item::CalGain(port) => write!(line, "P{} gain cal", port + 1),
+            Item::Save => write!(line, "Save"),
+            Item::Defaults => write!(line, "Defaults"),
+        };
+    }
+
+    fn value(self, settings: &Settings, reference_ma: u32, unsaved: bool, line: &mut Line) {
+        let _ = match self {
+            Item::Brightness => write!(line, "{}%", settings.brightness),
+            Item::Theme => match settings.theme {
+                Theme::Dark => write!(line, "dark"),
+                Theme::Light => write!(line, "light"),
+            },
+            Item::Buzzer => write!(line, "{}", on_off(!settings.buzzer_muted)),
+            Item::Rotate => write!(line, "{}", on_off(settings.rotated)),
+            Item::SelfTest => write!(line, "{}", on_off(settings.self_test)),
+            Item::Graph => match settings.trend {
+                Trend::Current => write!(line, "current"),
+                Trend::Power => write!(line, "power"),
+            },
+            Item::Sleep if settings.sleep_minutes == 0 => write!(line, "never"),
+            Item::Sleep => write!(line, "{}min", settings.sleep_minutes),
+            Item::CounterSave if settings.counter_save_minutes == 0 => write!(line, "off"),
+            Item::CounterSave => write!(line, "{}min", settings.counter_save_minutes),
+            Item::OverCurrent(port) => {
+                write!(line, "{}mA", settings.thresholds[port].over_current_ma)
+            }
+            Item::OverVoltage(port) => {
+                write!(line, "{}mV", settings.thresholds[port].over_voltage_mv)
+            }
+            Item::Sensor(port) => match preset(&settings.sensors[port]) {
+                Some(i) => write!(line, "{}", SENSOR_PRESETS[i].0),
+                // Set on the console
+                None => write!(line, "custom"),
+            },
+            Item::CapturePort => write!(line, "P{}", settings.capture_trigger.port + 1),
+            Item::CaptureLevel => write!(line, "{}mA", settings.capture_trigger.level_ma),
+            Item::CalReference => write!(line, "{}mA", reference_ma),
+            Item::CalGain(port) => write!(line, "x{:.4}", settings.corrections[port].gain()),
+            Item::Save if unsaved => write!(line, "changed"),
+            Item::CalZero | Item::Save | Item::Defaults => Ok(()),
+        };
+    }
+
+    fn number(self, settings: &Settings) -> u32 {
+        match self {
+            Item::Brightness => settings.brightness as u32,
+            Item::Sleep => settings.sleep_minutes as u32,
+            Item::CounterSave => settings.counter_save_minutes as u32,
+            Item::OverCurrent(port) => settings.thresholds[port].over_current_ma,
+            Item::OverVoltage(port) => settings.thresholds[port].over_voltage_mv,
+            Item::CaptureLevel => settings.capture_trigger.level_ma,
+            _ => 0,
+        }
+    }
+
+    fn set_number(self, settings: &mut Settings, value: u32) {
+        match self {
+            Item::Brightness => settings.brightness = value as u8,
+            Item::Sleep => settings.sleep_minutes = value as u16,
+            Item::CounterSave => settings.counter_save_minutes = value as u16,
+            Item::OverCurrent(port) => settings.thresholds[port].over_current_ma = value,
+            Item::OverVoltage(port) => settings.thresholds[port].over_voltage_mv = value,
+            Item::CaptureLevel => settings.capture_trigger.level_ma = value,
+            _ => {}
+        }
+    }
+
+    fn choose(self, settings: &mut Settings, forward: bool) {
+        match self {
+            Item::Theme => {
+                settings.theme = match settings.theme {
+                    Theme::Dark => Theme::Light,
+                    Theme::Light => Theme::Dark,
+                }
+            }
+            Item::Graph => {
+                settings.trend = match settings.trend {
+                    Trend::Current => Trend::Power,
+                    Trend::Power => Trend::Current,
+                }
+            }
+            Item::Sensor(port) => {
+                let count = SENSOR_PRESETS.len();
+                let i = match (preset(&settings.sensors[port]), forward) {
+                    (Some(i), true) => (i + 1) % count,
+                    (Some(i), false) => (i + count - 1) % count,
+                    (None, true) => 0,
+                    (None, false) => count - 1,
+                };
+                settings.sensors[port] = SENSOR_PRESETS[i].1;
+            }
+            Item::CapturePort => {
+                let port = &mut settings.capture_trigger.port;
+                *port = match forward {
+                    true => (*port + 1) % PORT_COUNT,
+                    false => (*port + PORT_COUNT - 1) % PORT_COUNT,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn toggle(self, settings: &mut Settings) {
+        match self {
+            Item::Buzzer => settings.buzzer_muted = !settings.buzzer_muted,
+            Item::Rotate => settings.rotated = !settings.rotated,
+            Item::SelfTest => settings.self_test = !settings.self_test,
+            _ => {}
+        }
+    }
+}
+
+fn preset(profile: &SensorProfile) -> Option<usize> {
+    SENSOR_PRESETS.iter().position(|(_, p)| p == profile)
+}
+
+/// What became of the menu after a press
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Open,
+    /// Left without saving; `discarded` if there were changes
+    Closed {
+        discarded: bool,
+    },
+    /// "Save" chosen: apply and store these, the menu is closed
+    Save(Settings),
+    /// A calibration action chosen, the menu stays open
+    Calibrate(Request),
+}
+
+pub struct SettingsMenu {
+    open: bool,
+    saved: Settings,
+    draft: Settings,
+    cursor: usize,
+    // First item on the panel
+    top: usize,
+    reference_ma: u32,
+    // The draft and reference before the current edit, to put them back
+    editing: Option<(Settings, u32)>,
+    drawn: bool,
+    line: TextLine,
+}
+
+impl Default for SettingsMenu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SettingsMenu {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            saved: Settings::default(),
+            draft: Settings::default(),
+            cursor: 0,
+            top: 0,
+            reference_ma: 1000,
+            editing: None,
+            drawn: false,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Start on the first item with a copy of `settings`
+    pub fn open(&mut self, settings: Settings) {
+        self.open = true;
+        self.saved = settings;
+        self.draft = settings;
+        self.cursor = 0;
+        self.top = 0;
+        self.editing = None;
+        self.drawn = false;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// The settings as edited so far, while the menu is open
+    pub fn draft(&self) -> Option<&Settings> {
+        self.open.then_some(&self.draft)
+    }
+
+    /// Corrections stored by a calibration run, taken over without touching
+    /// the other changes
+    pub fn set_corrections(&mut self, corrections: [CurrentCorrection; PORT_COUNT]) {
+        self.saved.corrections = corrections;
+        self.draft.corrections = corrections;
+        self.drawn = false;
+    }
+
+    /// Redraw everything on the next `draw`
+    pub fn invalidate(&mut self) {
+        self.drawn = false;
+    }
+
+    /// `button` as seen by the user, already swapped on a turned panel
+    pub fn press(&mut self, button: Button, press: Press) -> Outcome {
+        if !self.open {
+            return Outcome::Closed { discarded: false };
+        }
+        self.drawn = false;
+        let item = ITEMS[self.cursor];
+        let forward = button == Button::Right;
+
+        if let Some((draft, reference_ma)) = self.editing {
+            match press {
+                Press::Short => self.step(item, forward, 1),
+                Press::Repeat(repeats) => self.step(item, forward, acceleration(repeats)),
+                Press::Long => {
+                    if !forward {
+                        self.draft = draft;
+                        self.reference_ma = reference_ma;
+                    }
+                    self.editing = None;
+                }
+            }
+            return Outcome::Open;
+        }
+
+        match (press, forward) {
+            (Press::Short | Press::Repeat(_), true) => self.cursor = (self.cursor + 1) % ITEM_COUNT,
+            (Press::Short | Press::Repeat(_), false) => {
+                self.cursor = (self.cursor + ITEM_COUNT - 1) % ITEM_COUNT
+            }
+            (Press::Long, false) => {
+                self.open = false;
+                return Outcome::Closed {
+                    discarded: self.draft != self.saved,
+                };
+            }
+            (Press::Long, true) => match item.kind() {
+                Kind::Number(_) | Kind::Choice => {
+                    self.editing = Some((self.draft, self.reference_ma))
+                }
+                Kind::Switch => item.toggle(&mut self.draft),
+                Kind::Action => return self.act(item),
+            },
+        }
+        Outcome::Open
+    }
+
+    fn act(&mut self, item: Item) -> Outcome {
+        match item {
+            Item::Save => {
+                self.open = false;
+                return Outcome::Save(self.draft);
+            }
+            // The calibration belongs to the board, not to the user's taste
+            Item::Defaults => {
+                self.draft = Settings {
+                    corrections: self.draft.corrections,
+                    ..Settings::default()
+                }
+            }
+            Item::CalZero => return Outcome::Calibrate(Request::Zero),
+            Item::CalGain(port) => {
+                return Outcome::Calibrate(Request::Gain {
+                    port,
+                    reference_ma: self.reference_ma,
+                });
+            }
+            _ => {}
+        }
+        Outcome::Open
+    }
+
+    fn step(&mut self, item: Item, forward: bool, times: u32) {
+        match item.kind() {
+            Kind::Number(range) => {
+                let value = match item {
+                    Item::CalReference => self.reference_ma,
+                    _ => item.number(&self.draft),
+                };
+                let delta = range.step.saturating_mul(times);
+                let value = if forward {
+                    value.saturating_add(delta)
+                } else {
+                    value.saturating_sub(delta)
+                }
+                .clamp(range.min, range.max);
+                match item {
+                    Item::CalReference => self.reference_ma = value,
+                    _ => item.set_number(&mut self.draft, value),
+                }
+            }
+            Kind::Choice => item.choose(&mut self.draft, forward),
+            Kind::Switch | Kind::Action => {}
+        }
+    }
+
+    pub async fn draw<P: Panel>(&mut self, display: &mut P) -> Result<(), Error> {
+        if self.drawn {
+            return Ok(());
+        }
+        self.drawn = true;
+
+        // Scroll just far enough to show the cursor
+        let rows = (display.size().1 as usize / LINE_HEIGHT).clamp(1, MAX_ROWS);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + rows {
+            self.top = self.cursor + 1 - rows;
+        }
+
+        let unsaved = self.draft != self.saved;
+        for row in 0..rows.min(ITEM_COUNT - self.top) {
+            let index = self.top + row;
+            let item = ITEMS[index];
+            let (mut label, mut value) = (Line::new(), Line::new());
+            item.label(&mut label);
+            item.value(&self.draft, self.reference_ma, unsaved, &mut value);
+
+            let selected = index == self.cursor;
+            let color = match (selected, self.editing.is_some()) {
+                (true, true) => COLOR_EDITING,
+                (true, false) => COLOR_SELECTED,
+                (false, _) => COLOR_ITEM,
+            };
+            let mut text = Line::new();
+            let marker = if selected { '>' } else { ' ' };
+            let _ = write!(
+                text,
+                "{}{:<13}{:>12}",
+                marker,
+                label.as_str(),
+                value.as_str()
+            );
+            self.line.set_text(&text, 0);
+            let y = (LINE_HEIGHT * row) as u16;
+            self.line.blit(display, y, color, Rgb565::BLACK).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(item: Item) -> usize {
+        ITEMS.iter().position(|i| *i == item).unwrap()
+    }
+
+    /// A menu open on the defaults with the cursor on `item`
+    fn at(item: Item) -> SettingsMenu {
+        let mut menu = SettingsMenu::new();
+        menu.open(Settings::default());
+        for _ in 0..index(item) {
+            menu.press(Button::Right, Press::Short);
+        }
+        menu
+    }
+
+    #[test]
+    fn list_wraps_and_leaving_drops_changes() {
+        let mut menu = SettingsMenu::new();
+        menu.open(Settings::default());
+        menu.press(Button::Left, Press::Short);
+        assert_eq!(ITEMS[menu.cursor], Item::Defaults);
+        menu.press(Button::Right, Press::Repeat(0));
+        assert_eq!(ITEMS[menu.cursor], Item::Brightness);
+        assert_eq!(
+            menu.press(Button::Left, Press::Long),
+            Outcome::Closed { discarded: false }
+        );
+        assert!(!menu.is_open());
+
+        let mut menu = at(Item::Buzzer);
+        menu.press(Button::Right, Press::Long);
+        assert!(menu.draft().unwrap().buzzer_muted);
+        assert_eq!(
+            menu.press(Button::Left, Press::Long),
+            Outcome::Closed { discarded: true }
+        );
+        assert_eq!(menu.draft(), None);
+    }
+
+    #[test]
+    fn held_buttons_speed_up_and_stop_at_the_limits() {
+        let mut menu = at(Item::OverCurrent(1));
+        menu.press(Button::Right, Press::Long);
+        menu.press(Button::Right, Press::Short);
+        assert_eq!(menu.draft.thresholds[1].over_current_ma, 3050);
+        for repeats in 0..12 {
+            menu.press(Button::Right, Press::Repeat(repeats));
+        }
+        // Ten single steps, then two of ten
+        assert_eq!(
+            menu.draft.thresholds[1].over_current_ma,
+            3050 + 10 * 50 + 2 * 500
+        );
+        for repeats in 0..40 {
+            menu.press(Button::Left, Press::Repeat(repeats));
+        }
+        assert_eq!(menu.draft.thresholds[1].over_current_ma, 100);
+
+        // A long BTN2 puts the old value back, a long BTN3 keeps the new one
+        menu.press(Button::Left, Press::Long);
+        assert_eq!(menu.draft.thresholds[1].over_current_ma, 3000);
+        menu.press(Button::Right, Press::Long);
+        menu.press(Button::Left, Press::Short);
+        menu.press(Button::Right, Press::Long);
+        assert_eq!(menu.draft.thresholds[1].over_current_ma, 2950);
+        // Back in the list, short presses move again
+        menu.press(Button::Right, Press::Short);
+        assert_eq!(ITEMS[menu.cursor], Item::OverVoltage(1));
+    }
+
+    #[test]
+    fn pickers_cycle_and_save_returns_the_draft() {
+        let mut settings = Settings::default();
+        settings.sensors[2].shunt_micro_ohms = 7500;
+        let mut menu = SettingsMenu::new();
+        menu.open(settings);
+        for _ in 0..index(Item::Sensor(2)) {
+            menu.press(Button::Right, Press::Short);
+        }
+        // A custom shunt goes to the first preset, or back to the last
+        menu.press(Button::Right, Press::Long);
+        menu.press(Button::Left, Press::Short);
+        assert_eq!(menu.draft.sensors[2], SENSOR_PRESETS[2].1);
+        menu.press(Button::Right, Press::Short);
+        menu.press(Button::Right, Press::Short);
+        assert_eq!(menu.draft.sensors[2], SENSOR_PRESETS[1].1);
+        menu.press(Button::Right, Press::Long);
+
+        // The capture port wraps from P1 to P3
+        for _ in index(Item::Sensor(2))..index(Item::CapturePort) {
+            menu.press(Button::Right, Press::Short);
+        }
+        menu.press(Button::Right, Press::Long);
+        menu.press(Button::Left, Press::Short);
+        menu.press(Button::Left, Press::Short);
+        menu.press(Button::Right, Press::Long);
+
+        for _ in index(Item::CapturePort)..index(Item::Save) {
+            menu.press(Button::Right, Press::Short);
+        }
+        let mut expected = settings;
+        expected.sensors[2] = SENSOR_PRESETS[1].1;
+        expected.capture_trigger.port = 2;
+        assert_eq!(
+            menu.press(Button::Right, Press::Long),
+            Outcome::Save(expected)
+        );
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn calibration_actions_keep_the_menu_open() {
+        let mut menu = at(Item::CalReference);
+        menu.press(Button::Right, Press::Long);
+        menu.press(Button::Left, Press::Short);
+        menu.press(Button::Left, Press::Short);
+        menu.press(Button::Right, Press::Long);
+        menu.press(Button::Right, Press::Short);
+        menu.press(Button::Right, Press::Short);
+        assert_eq!(ITEMS[menu.cursor], Item::CalGain(1));
+        assert_eq!(
+            menu.press(Button::Right, Press::Long),
+            Outcome::Calibrate(Request::Gain {
+                port: 1,
+                reference_ma: 900
+            })
+        );
+        assert!(menu.is_open());
+
+        // The result arrives while other changes are pending; both survive
+        // "Defaults" and go out with "Save"
+        menu.draft.brightness = 40;
+        let mut corrections = [CurrentCorrection::NONE; PORT_COUNT];
+        corrections[1].gain_ppm = 990_000;
+        menu.set_corrections(corrections);
+        assert_eq!(menu.draft.corrections, corrections);
+        assert!(menu.draft != menu.saved);
+        menu.cursor = index(Item::Defaults);
+        menu.press(Button::Right, Press::Long);
+        menu.press(Button::Left, Press::Short);
+        let expected = Settings {
+            corrections,
+            ..Settings::default()
+        };
+        assert_eq!(
+            menu.press(Button::Right, Press::Long),
+            Outcome::Save(expected)
+        );
+    }
+
+    #[test]
+    fn defaults_replace_the_draft_until_saved() {
+        let settings = Settings {
+            brightness: 30,
+            rotated: true,
+            ..Settings::default()
+        };
+        let mut menu = SettingsMenu::new();
+        menu.open(settings);
+        menu.press(Button::Left, Press::Short);
+        menu.press(Button::Right, Press::Long);
+        assert_eq!(menu.draft(), Some(&Settings::default()));
+        assert!(menu.is_open());
+        menu.press(Button::Left, Press::Short);
+        assert_eq!(
+            menu.press(Button::Right, Press::Long),
+            Outcome::Save(Settings::default())
+        );
+    }
+}
@@ -0,0 +1,428 @@
+// core/src/display/snapshots/mod.rs
+// Golden image tests of every page
+//
+// Each test draws a page from fixed data into a `Framebuffer` and compares
+// it with the PNG of the same name in this directory, pixel for pixel. On a
+// mismatch `<name>.actual.png` and `<name>.diff.png` (differing pixels in
+// magenta over the dimmed reference) are written next to it. After an
+// intended change, run the tests with `BLESS_SNAPSHOTS=1` to rewrite the
+// references and look at them before committing.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+use embassy_futures::block_on;
+use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+use embedded_graphics::prelude::RgbColor;
+
+use super::dashboard::Dashboard;
+use super::event_log::EventLogPage;
+use super::notice::Notice;
+use super::orientation::Oriented;
+use super::pd_contract::PdContractPage;
+use super::pd_trace::PdTracePage;
+use super::port::PortPage;
+use super::scope::ScopePage;
+use super::selftest;
+use super::settings_menu::SettingsMenu;
+use super::trends::TrendsPage;
+use crate::attach::AttachState;
+use crate::capture::{CAPTURE_SAMPLES, Capture, Trigger};
+use crate::charge::ChargePhase;
+use crate::event_log::ring::{Event, EventRing, ResetCause};
+use crate::hal::{Button, Panel, Press};
+use crate::history::{PortHistory, Trend};
+use crate::mock::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+use crate::pd::message::{ControlType, DataType, MAX_MESSAGE_LEN, Message, SpecRevision};
+use crate::pd::policy::{Action, PdStatus, SinkConfig, SinkPolicy};
+use crate::pd::trace::{TraceEvent, TraceRecord};
+use crate::selftest::{Check, Report, StoredSettings};
+use crate::shared::PORT_COUNT;
+use crate::storage::settings::{Settings, Theme};
+use crate::usage::{Session, TripKind};
+
+const BLESS_VAR: &str = "BLESS_SNAPSHOTS";
+
+fn path(name: &str, suffix: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/display/snapshots")
+        .join(format!("{name}{suffix}.png"))
+}
+
+fn rgb(color: Rgb565) -> [u8; 3] {
+    let color = Rgb888::from(color);
+    [color.r(), color.g(), color.b()]
+}
+
+fn save(path: &Path, data: &[u8]) {
+    let file = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(data).unwrap();
+}
+
+fn load(path: &Path) -> Option<Vec<u8>> {
+    let mut reader = png::Decoder::new(File::open(path).ok()?)
+        .read_info()
+        .unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!(
+        (info.width, info.height, info.color_type, info.bit_depth),
+        (
+            WIDTH as u32,
+            HEIGHT as u32,
+            png::ColorType::Rgb,
+            png::BitDepth::Eight
+        ),
+        "{} isn't a {WIDTH}x{HEIGHT} RGB PNG",
+        path.display()
+    );
+    data.truncate(info.buffer_size());
+    Some(data)
+}
+
+/// Compare the panel with the reference `name`
+fn check(name: &str, panel: &Framebuffer) {
+    let actual: Vec<u8> = panel.pixels().iter().flat_map(|&p| rgb(p)).collect();
+    let reference = path(name, "");
+    if std::env::var_os(BLESS_VAR).is_some() {
+        save(&reference, &actual);
+        return;
+    }
+
+    let Some(expected) = load(&reference) else {
+        save(&path(name, ".actual"), &actual);
+        panic!(
+            "no reference {}, run with {BLESS_VAR}=1 to create it",
+            reference.display()
+        );
+    };
+    if expected == actual {
+        return;
+    }
+
+    let mut diff = Vec::with_capacity(actual.len());
+    let mut differing = 0;
+    for (expected, actual) in expected.chunks(3).zip(actual.chunks(3)) {
+        if expected == actual {
+            diff.extend(expected.iter().map(|c| c / 4));
+        } else {
+            diff.extend([255, 0, 255]);
+            differing += 1;
+        }
+    }
+    save(&path(name, ".actual"), &actual);
+    save(&path(name, ".diff"), &diff);
+    panic!(
+        "{differing} pixels differ from {}, see {}",
+        reference.display(),
+        path(name, ".diff").display()
+    );
+}
+
+/// A charger offering 5/9/15/20 V and PPS, and the sink taking 20 V, as
+/// the trace records of both sides
+fn negotiation() -> (PdStatus, Vec<TraceRecord>) {
+    fn record(records: &mut Vec<TraceRecord>, event: TraceEvent, message: &Message) {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let len = message.encode(&mut buf);
+        let timestamp_us = 200_000 + 1500 * records.len() as u64;
+        records.push(TraceRecord::new(timestamp_us, event, &buf[..len]));
+    }
+
+    let capabilities = [
+        0x0801_912C, // 5 V 3 A, unconstrained
+        0x0002_D12C, // 9 V 3 A
+        0x0004_B12C, // 15 V 3 A
+        0x0006_4145, // 20 V 3.25 A
+        0xC1A4_213C, // PPS 3.3-21 V 3 A
+    ];
+    let mut source = [
+        Message::data(
+            DataType::SourceCapabilities,
+            SpecRevision::R3_0,
+            &capabilities,
+        ),
+        Message::control(ControlType::Accept, SpecRevision::R3_0),
+        Message::control(ControlType::PsRdy, SpecRevision::R3_0),
+    ];
+    let mut policy = SinkPolicy::new(SinkConfig::default());
+    let mut records = Vec::new();
+    policy.attach(0);
+    for (message_id, message) in source.iter_mut().enumerate() {
+        message.header.power_role_source = true;
+        message.header.data_role_dfp = true;
+        message.header.message_id = message_id as u8;
+        record(&mut records, TraceEvent::Received, message);
+        if let Action::Send(reply) = policy.handle(message, 0) {
+            record(&mut records, TraceEvent::Transmitted, &reply);
+        }
+    }
+    (policy.status(), records)
+}
+
+#[test]
+fn dashboard() {
+    let mut panel = Framebuffer::new();
+    let mut dashboard = Dashboard::new();
+    dashboard.update_data([(20.0, 0.56, 11.33), (4.93, 2.0, 9.9), (5.09, 0.0, 0.0)]);
+    block_on(dashboard.draw(&mut panel)).unwrap();
+    check("dashboard", &panel);
+}
+
+#[test]
+fn dashboard_light() {
+    let mut panel = Framebuffer::new();
+    let mut dashboard = Dashboard::new();
+    dashboard.update_data([(20.0, 0.56, 11.33), (4.93, 2.0, 9.9), (5.09, 0.0, 0.0)]);
+    let mut light = Oriented::new(&mut panel, false, (0, 0), Theme::Light);
+    block_on(light.fill_color(Rgb565::BLACK)).unwrap();
+    block_on(dashboard.draw(&mut light)).unwrap();
+    check("dashboard_light", &panel);
+}
+
+#[test]
+fn dashboard_leaves_no_residue_when_values_shrink() {
+    let shrunk = [(5.0, 0.5, 2.5), (5.1, 0.05, 0.25), (0.0, 0.0, 0.0)];
+
+    let mut panel = Framebuffer::new();
+    let mut dashboard = Dashboard::new();
+    dashboard.update_data([(12.0, 10.5, 126.0), (15.0, 2.0, 30.0), (9.0, 1.0, 9.0)]);
+    block_on(dashboard.draw(&mut panel)).unwrap();
+    dashboard.update_data(shrunk);
+    block_on(dashboard.draw(&mut panel)).unwrap();
+
+    let mut fresh = Framebuffer::new();
+    let mut dashboard = Dashboard::new();
+    dashboard.update_data(shrunk);
+    block_on(dashboard.draw(&mut fresh)).unwrap();
+    check("dashboard_shrunk", &fresh);
+    assert!(
+        panel.pixels() == fresh.pixels(),
+        "redrawing with shorter values left stale pixels behind"
+    );
+}
+
+#[test]
+fn trends() {
+    let mut histories = [PortHistory::new(); PORT_COUNT];
+    for second in 0..120u32 {
+        let phone = if second < 60 {
+            2000
+        } else {
+            2000 - (second - 60) * 30
+        };
+        let earbuds = if (20..100).contains(&second) { 120 } else { 0 };
+        for (history, ma) in histories
+            .iter_mut()
+            .zip([phone + earbuds + 300, phone, earbuds])
+        {
+            for _ in 0..10 {
+                history.update(ma, ma * 5, 100);
+            }
+        }
+    }
+
+    let mut panel = Framebuffer::new();
+    block_on(TrendsPage::new().draw(&mut panel, &histories, Trend::Current)).unwrap();
+    check("trends", &panel);
+
+    let mut panel = Framebuffer::new();
+    block_on(TrendsPage::new().draw(&mut panel, &histories, Trend::Power)).unwrap();
+    check("trends_power", &panel);
+}
+
+#[test]
+fn port() {
+    let mut history = PortHistory::new();
+    for second in 0..160u32 {
+        let ma = 2000u32.saturating_sub(second.saturating_sub(100) * 25);
+        for _ in 0..10 {
+            history.update(ma, ma * 5, 100);
+        }
+    }
+    let session = Session {
+        state: AttachState::Charging,
+        energy_mj: 7_200_000,
+        duration_ms: 1_260_000,
+        phase: ChargePhase::Taper,
+        time_to_full_s: Some(540),
+    };
+
+    let mut panel = Framebuffer::new();
+    block_on(PortPage::new().draw(&mut panel, 1, 500, &session, &history, Trend::Current)).unwrap();
+    check("port", &panel);
+}
+
+#[test]
+fn scope_trend() {
+    let mut histories = [PortHistory::new(); PORT_COUNT];
+    // The phone's current steps down every 20 s
+    for second in 0..200u32 {
+        let ma = 2400 - second / 20 * 200;
+        for _ in 0..10 {
+            histories[1].update(ma, ma * 5, 100);
+        }
+    }
+    let mut page = ScopePage::new();
+    // Half the history on a fixed 2A scale
+    page.press(Button::Right, Press::Long);
+    page.press(Button::Right, Press::Long);
+    page.press(Button::Left, Press::Short);
+    page.press(Button::Right, Press::Long);
+    for _ in 0..5 {
+        page.press(Button::Right, Press::Short);
+    }
+    page.press(Button::Left, Press::Long);
+
+    let mut panel = Framebuffer::new();
+    let capture = Capture::new();
+    block_on(page.draw(
+        &mut panel,
+        &histories,
+        Trigger::default(),
+        capture.status(),
+        || capture.clone(),
+    ))
+    .unwrap();
+    check("scope_trend", &panel);
+}
+
+#[test]
+fn scope_burst() {
+    let trigger = Trigger {
+        port: 1,
+        level_ma: 500,
+    };
+    let mut capture = Capture::new();
+    capture.arm(trigger);
+    // A phone plugged in: inrush decaying to its charge current
+    for i in 0..CAPTURE_SAMPLES as u64 + 100 {
+        let ua = match i.checked_sub(200) {
+            None => 3000,
+            Some(t) => 1_500_000 + (2_400_000.0 * libm::expf(-(t as f32) / 20.0)) as i32,
+        };
+        if capture.feed(ua, i * 480) {
+            break;
+        }
+    }
+    let mut page = ScopePage::new();
+    // Burst source, a quarter of the window around the trigger
+    page.press(Button::Right, Press::Long);
+    page.press(Button::Right, Press::Long);
+    page.press(Button::Left, Press::Short);
+    page.press(Button::Left, Press::Short);
+    page.press(Button::Right, Press::Long);
+    page.press(Button::Right, Press::Long);
+    page.press(Button::Right, Press::Short);
+    page.press(Button::Left, Press::Long);
+
+    let mut panel = Framebuffer::new();
+    block_on(page.draw(
+        &mut panel,
+        &[PortHistory::new(); PORT_COUNT],
+        trigger,
+        capture.status(),
+        || capture.clone(),
+    ))
+    .unwrap();
+    check("scope_burst", &panel);
+}
+
+#[test]
+fn pd_contract() {
+    let mut page = PdContractPage::new();
+    page.update_status(negotiation().0);
+    page.update_input_voltage(19.98);
+
+    let mut panel = Framebuffer::new();
+    block_on(page.draw(&mut panel)).unwrap();
+    check("pd_contract", &panel);
+}
+
+#[test]
+fn pd_trace() {
+    let mut page = PdTracePage::new();
+    for record in &negotiation().1 {
+        page.feed(record);
+    }
+
+    let mut panel = Framebuffer::new();
+    block_on(page.draw(&mut panel)).unwrap();
+    check("pd_trace", &panel);
+}
+
+#[test]
+fn event_log() {
+    let mut ring = EventRing::new();
+    ring.push(3, 0, Event::Boot(ResetCause::Watchdog));
+    ring.push(3, 41, Event::Plugged { port: 1 });
+    ring.push(
+        3,
+        763,
+        Event::Trip {
+            port: 2,
+            kind: TripKind::OverCurrent,
+        },
+    );
+
+    let mut panel = Framebuffer::new();
+    block_on(EventLogPage::new().draw(&mut panel, ring.next_seq(), || ring.clone())).unwrap();
+    check("event_log", &panel);
+}
+
+#[test]
+fn notice() {
+    let mut notice = Notice::new();
+    notice.show("P2 plugged", Rgb565::WHITE);
+
+    let mut panel = Framebuffer::new();
+    block_on(notice.draw(&mut panel)).unwrap();
+    check("notice", &panel);
+}
+
+#[test]
+fn settings_menu() {
+    let mut menu = SettingsMenu::new();
+    menu.open(Settings::default());
+    // Editing the brightness, one step down
+    menu.press(Button::Right, Press::Long);
+    menu.press(Button::Left, Press::Short);
+
+    let mut panel = Framebuffer::new();
+    block_on(menu.draw(&mut panel)).unwrap();
+    check("settings_menu", &panel);
+}
+
+#[test]
+fn selftest_pattern() {
+    let mut panel = Framebuffer::new();
+    block_on(selftest::draw_pattern(&mut panel)).unwrap();
+    check("selftest_pattern", &panel);
+}
+
+#[test]
+fn selftest_summary() {
+    let mut report = Report::new();
+    report.push(Check::Panel { ok: true });
+    report.push(Check::SensorZero {
+        port: 1,
+        current_ma: Some(85),
+    });
+    report.push(Check::SensorId { port: 2, ids: None });
+    report.push(Check::Settings(StoredSettings::Valid));
+
+    let mut panel = Framebuffer::new();
+    block_on(selftest::draw_summary(
+        &mut panel,
+        &report,
+        ResetCause::Watchdog,
+    ))
+    .unwrap();
+    check("selftest_summary", &panel);
+}
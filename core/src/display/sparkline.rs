@@ -0,0 +1,178 @@
+// core/src/display/sparkline.rs
+// Scrolling trend graph of one value, e.g. the current of a port
+//
+// Drawn into any rectangle of the panel: a 53 px dashboard column as well
+// as the full 160 px width. The newest value is in the rightmost column,
+// one value per column. The Y axis scales to the largest visible value,
+// rounded up to 1, 2 or 5 times a power of ten so the scale label stays
+// readable.
+
+use embedded_graphics::pixelcolor::Rgb565;
+
+use super::dashboard::Error;
+use crate::hal::Panel;
+
+// Columns sent per write_area call
+const BLIT_COLUMNS: usize = 16;
+pub const MAX_HEIGHT: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// Connected line, one pixel thick
+    Line,
+    /// Filled bars from the bottom
+    Bars,
+}
+
+pub struct Sparkline {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    /// At most `MAX_HEIGHT`
+    pub height: u16,
+    pub style: Style,
+    pub color: Rgb565,
+    pub background: Rgb565,
+}
+
+/// Smallest 1, 2 or 5 times a power of ten that is at least `max`
+pub fn nice_scale(max: u32) -> u32 {
+    let mut decade = 1u32;
+    loop {
+        for step in [1, 2, 5] {
+            let Some(scale) = decade.checked_mul(step) else {
+                return u32::MAX;
+            };
+            if scale >= max {
+                return scale;
+            }
+        }
+        match decade.checked_mul(10) {
+            Some(next) => decade = next,
+            None => return u32::MAX,
+        }
+    }
+}
+
+/// Pixel rows from the bottom a value reaches, 0 only for 0
+fn level(value: u16, scale: u32, height: usize) -> usize {
+    let rows = (value as u64 * height as u64).div_ceil(scale as u64);
+    (rows as usize).min(height)
+}
+
+/// Lit rows of a column, counted from the top, as a half-open range
+fn lit_rows(
+    style: Style,
+    previous: Option<u16>,
+    value: u16,
+    scale: u32,
+    height: usize,
+) -> (usize, usize) {
+    let top = height - level(value, scale, height);
+    match style {
+        Style::Bars => (top, height),
+        Style::Line => {
+            // A zero value still shows on the bottom row
+            let row = top.min(height - 1);
+            let previous_row =
+                previous.map_or(row, |p| (height - level(p, scale, height)).min(height - 1));
+            (row.min(previous_row), row.max(previous_row) + 1)
+        }
+    }
+}
+
+impl Sparkline {
+    /// Draw the last `width` of `values` (oldest first) and return the
+    /// scale, the value at the top edge
+    pub async fn draw<P: Panel>(&self, display: &mut P, values: &[u16]) -> Result<u32, Error> {
+        let values = &values[values.len().saturating_sub(self.width as usize)..];
+        let scale = nice_scale(values.iter().copied().max().unwrap_or(0) as u32);
+        self.draw_scaled(display, values, scale).await?;
+        Ok(scale)
+    }
+
+    /// Like `draw` with a fixed `scale`; larger values stop at the top
+    pub async fn draw_scaled<P: Panel>(
+        &self,
+        display: &mut P,
+        values: &[u16],
+        scale: u32,
+    ) -> Result<(), Error> {
+        let width = self.width as usize;
+        let height = (self.height as usize).min(MAX_HEIGHT);
+        let values = &values[values.len().saturating_sub(width)..];
+        if height == 0 {
+            // No room for the graph on this panel
+            return Ok(());
+        }
+        // Columns left of the oldest value stay empty
+        let offset = width - values.len();
+
+        let mut pixels = [self.background; BLIT_COLUMNS * MAX_HEIGHT];
+        for x0 in (0..width).step_by(BLIT_COLUMNS) {
+            let columns = BLIT_COLUMNS.min(width - x0);
+            for col in 0..columns {
+                let x = x0 + col;
+                let (start, end) = match x.checked_sub(offset) {
+                    Some(i) => {
+                        let previous = i.checked_sub(1).map(|p| values[p]);
+                        lit_rows(self.style, previous, values[i], scale, height)
+                    }
+                    None => (0, 0),
+                };
+                // Column-major, like the font bitmaps
+                for row in 0..height {
+                    pixels[col * height + row] = if (start..end).contains(&row) {
+                        self.color
+                    } else {
+                        self.background
+                    };
+                }
+            }
+            display
+                .write_area(
+                    self.x + x0 as u16,
+                    self.y,
+                    columns as u16,
+                    height as u16,
+                    &pixels[..columns * height],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_are_round() {
+        assert_eq!(nice_scale(0), 1);
+        assert_eq!(nice_scale(1), 1);
+        assert_eq!(nice_scale(3), 5);
+        assert_eq!(nice_scale(1500), 2000);
+        assert_eq!(nice_scale(2001), 5000);
+        assert_eq!(nice_scale(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn bars_fill_from_the_bottom() {
+        assert_eq!(lit_rows(Style::Bars, None, 0, 100, 10), (10, 10));
+        assert_eq!(lit_rows(Style::Bars, None, 100, 100, 10), (0, 10));
+        // Any value above zero lights at least one row
+        assert_eq!(lit_rows(Style::Bars, None, 1, 100, 10), (9, 10));
+        assert_eq!(lit_rows(Style::Bars, None, 45, 100, 10), (5, 10));
+    }
+
+    #[test]
+    fn line_connects_to_the_previous_column() {
+        assert_eq!(lit_rows(Style::Line, None, 0, 100, 10), (9, 10));
+        assert_eq!(lit_rows(Style::Line, None, 50, 100, 10), (5, 6));
+        // Rising from 20 % to 80 %: rows 2 down to 8
+        assert_eq!(lit_rows(Style::Line, Some(20), 80, 100, 10), (2, 9));
+        assert_eq!(lit_rows(Style::Line, Some(80), 20, 100, 10), (2, 9));
+        assert_eq!(lit_rows(Style::Line, Some(100), 0, 100, 10), (0, 10));
+    }
+}
@@ -0,0 +1,141 @@
+// core/src/display/trends.rs
+// Trends page: current or power of every port over the last 52 s, in the
+// dashboard's three columns
+//
+//   5A        500mA     2A        <- scale of each column
+//   bars, newest at the right, one per second, the peak of that second
+//
+// The "Graphs" setting picks current or power, for this page and the port
+// page alike.
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use heapless::String;
+
+use super::canvas::{LINE_HEIGHT, LINE_WIDTH, TextLine};
+use super::dashboard::Error;
+use super::sparkline::{MAX_HEIGHT, Sparkline, Style};
+use crate::hal::Panel;
+use crate::history::{HISTORY_LEN, PortHistory, Trend};
+use crate::shared::PORT_COUNT;
+
+const COLOR_CURRENT: Rgb565 = Rgb565::RED;
+const COLOR_POWER: Rgb565 = Rgb565::GREEN;
+const COLOR_SCALE: Rgb565 = Rgb565::new(15, 30, 15);
+
+/// Current scale label, e.g. "500mA" or "2A"
+pub(super) struct Milliamps(pub u32);
+
+impl core::fmt::Display for Milliamps {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.0 >= 1000 {
+            write!(f, "{}A", self.0 / 1000)
+        } else {
+            write!(f, "{}mA", self.0)
+        }
+    }
+}
+
+/// Power scale label from centiwatts, e.g. "500mW" or "10W"
+struct Centiwatts(pub u32);
+
+impl core::fmt::Display for Centiwatts {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.0 >= 100 {
+            write!(f, "{}W", self.0 / 100)
+        } else {
+            write!(f, "{}mW", self.0 * 10)
+        }
+    }
+}
+
+/// Scale label of a current or power graph
+struct Scale(pub Trend, pub u32);
+
+impl core::fmt::Display for Scale {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Trend::Current => Milliamps(self.1).fmt(f),
+            Trend::Power => Centiwatts(self.1).fmt(f),
+        }
+    }
+}
+
+pub struct TrendsPage {
+    // `PortHistory::count` and the trend at the last draw
+    shown: Option<(u32, Trend)>,
+    line: TextLine,
+}
+
+impl Default for TrendsPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrendsPage {
+    pub fn new() -> Self {
+        Self {
+            shown: None,
+            line: TextLine::new(),
+        }
+    }
+
+    /// Redraw everything on the next `draw`, e.g. after a page switch
+    pub fn invalidate(&mut self) {
+        self.shown = None;
+    }
+
+    pub async fn draw<P: Panel>(
+        &mut self,
+        display: &mut P,
+        histories: &[PortHistory; PORT_COUNT],
+        trend: Trend,
+    ) -> Result<(), Error> {
+        // All histories get their points in the same loop
+        let shown = Some((histories[0].count(), trend));
+        if self.shown == shown {
+            return Ok(());
+        }
+        self.shown = shown;
+        let color = match trend {
+            Trend::Current => COLOR_CURRENT,
+            Trend::Power => COLOR_POWER,
+        };
+
+        // Columns as wide as the scale line reaches, graphs below it
+        let (width, height) = display.size();
+        let column_width = LINE_WIDTH.min(width as usize) / PORT_COUNT;
+        let graph_height = (height as usize)
+            .saturating_sub(LINE_HEIGHT)
+            .min(MAX_HEIGHT);
+
+        let mut labels: String<32> = String::new();
+        let mut values = [0u16; HISTORY_LEN];
+        for (port, history) in histories.iter().enumerate() {
+            // One pixel gap between the columns
+            let graph = Sparkline {
+                x: (port * column_width) as u16,
+                y: LINE_HEIGHT as u16,
+                width: column_width as u16 - 1,
+                height: graph_height as u16,
+                style: Style::Bars,
+                color,
+                background: Rgb565::BLACK,
+            };
+            let n = history.recent(&mut values[..graph.width as usize], |p| trend.value(p));
+            let scale = graph.draw(display, &values[..n]).await?;
+
+            // FONT_6X10 character cell at or after the column start
+            let column_start = (port * column_width).div_ceil(6);
+            while labels.len() < column_start {
+                let _ = labels.push(' ');
+            }
+            let _ = write!(labels, "{}", Scale(trend, scale));
+        }
+        self.line.set_text(&labels, 0);
+        self.line.blit(display, 0, COLOR_SCALE, Rgb565::BLACK).await
+    }
+}
@@ -0,0 +1,4 @@
+// core/src/event_log/mod.rs
+// Event record format and ring; the firmware mirrors the ring to flash
+
+pub mod ring;
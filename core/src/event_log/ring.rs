@@ -0,0 +1,453 @@
+// core/src/event_log/ring.rs
+// Event records and the fixed-size ring holding the most recent ones
+//
+// A record is 16 bytes, little endian:
+//
+//   [seq u32][boot u16][kind u8][port u8][uptime_s u32][value u32]
+//
+// `seq` grows across reboots and picks the ring slot (`seq % CAPACITY`), so
+// the flash mirror can store every slot under its own key and the ring is
+// rebuilt from whatever slots survive.
+
+use core::fmt;
+
+use crate::usage::TripKind;
+
+pub const CAPACITY: usize = 24;
+pub const RECORD_LEN: usize = 16;
+
+const KIND_BOOT: u8 = 1;
+const KIND_SENSOR_ERROR: u8 = 2;
+const KIND_TRIP: u8 = 3;
+const KIND_PLUGGED: u8 = 4;
+const KIND_UNPLUGGED: u8 = 5;
+const KIND_PD_CONTRACT: u8 = 6;
+const KIND_PD_NO_CONTRACT: u8 = 7;
+const KIND_SETTINGS_CHANGED: u8 = 8;
+const KIND_SELF_TEST: u8 = 9;
+
+/// Why the MCU started, from the RCC reset flags and the crash record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetCause {
+    Unknown,
+    PowerOn,
+    /// Supply dipped below the brown-out level but RAM survived
+    BrownOut,
+    Pin,
+    Software,
+    Watchdog,
+    LowPower,
+    OptionBytes,
+    /// Software reset by the panic handler, see `crash`
+    Panic,
+}
+
+impl ResetCause {
+    const ALL: [ResetCause; 9] = [
+        ResetCause::Unknown,
+        ResetCause::PowerOn,
+        ResetCause::BrownOut,
+        ResetCause::Pin,
+        ResetCause::Software,
+        ResetCause::Watchdog,
+        ResetCause::LowPower,
+        ResetCause::OptionBytes,
+        ResetCause::Panic,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ResetCause::Unknown => "unknown",
+            ResetCause::PowerOn => "power-on",
+            ResetCause::BrownOut => "brown-out",
+            ResetCause::Pin => "reset pin",
+            ResetCause::Software => "software",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::LowPower => "low-power",
+            ResetCause::OptionBytes => "option bytes",
+            ResetCause::Panic => "panic",
+        }
+    }
+
+    fn code(self) -> u32 {
+        self as u32
+    }
+
+    // Causes this build doesn't know, e.g. from a newer firmware, are `Unknown`
+    fn from_code(code: u32) -> Self {
+        Self::ALL
+            .get(code as usize)
+            .copied()
+            .unwrap_or(ResetCause::Unknown)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Boot(ResetCause),
+    /// An INA226 stopped answering; ports are numbered from 0
+    SensorError {
+        port: u8,
+    },
+    Trip {
+        port: u8,
+        kind: TripKind,
+    },
+    Plugged {
+        port: u8,
+    },
+    Unplugged {
+        port: u8,
+    },
+    PdContract {
+        voltage_mv: u16,
+        current_ma: u16,
+    },
+    PdNoContract,
+    SettingsChanged,
+    /// Power-on self-test finished, see `selftest`
+    SelfTest {
+        failed: u8,
+        warnings: u8,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventRecord {
+    pub seq: u32,
+    /// Boots since the log was started, gives `uptime_s` a reference
+    pub boot: u16,
+    pub uptime_s: u32,
+    pub event: Event,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Length,
+    UnknownKind(u8),
+}
+
+impl EventRecord {
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let (kind, port, value) = match self.event {
+            Event::Boot(cause) => (KIND_BOOT, 0, cause.code()),
+            Event::SensorError { port } => (KIND_SENSOR_ERROR, port, 0),
+            Event::Trip { port, kind } => (
+                KIND_TRIP,
+                port,
+                match kind {
+                    TripKind::OverCurrent => 0,
+                    TripKind::OverVoltage => 1,
+                },
+            ),
+            Event::Plugged { port } => (KIND_PLUGGED, port, 0),
+            Event::Unplugged { port } => (KIND_UNPLUGGED, port, 0),
+            Event::PdContract {
+                voltage_mv,
+                current_ma,
+            } => (
+                KIND_PD_CONTRACT,
+                0,
+                voltage_mv as u32 | (current_ma as u32) << 16,
+            ),
+            Event::PdNoContract => (KIND_PD_NO_CONTRACT, 0, 0),
+            Event::SettingsChanged => (KIND_SETTINGS_CHANGED, 0, 0),
+            Event::SelfTest { failed, warnings } => {
+                (KIND_SELF_TEST, 0, failed as u32 | (warnings as u32) << 8)
+            }
+        };
+
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0..4].copy_from_slice(&self.seq.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.boot.to_le_bytes());
+        buf[6] = kind;
+        buf[7] = port;
+        buf[8..12].copy_from_slice(&self.uptime_s.to_le_bytes());
+        buf[12..16].copy_from_slice(&value.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes: &[u8; RECORD_LEN] = bytes.try_into().map_err(|_| DecodeError::Length)?;
+        let u32_at = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let port = bytes[7];
+        let value = u32_at(12);
+
+        let event = match bytes[6] {
+            KIND_BOOT => Event::Boot(ResetCause::from_code(value)),
+            KIND_SENSOR_ERROR => Event::SensorError { port },
+            KIND_TRIP => Event::Trip {
+                port,
+                kind: if value == 1 {
+                    TripKind::OverVoltage
+                } else {
+                    TripKind::OverCurrent
+                },
+            },
+            KIND_PLUGGED => Event::Plugged { port },
+            KIND_UNPLUGGED => Event::Unplugged { port },
+            KIND_PD_CONTRACT => Event::PdContract {
+                voltage_mv: value as u16,
+                current_ma: (value >> 16) as u16,
+            },
+            KIND_PD_NO_CONTRACT => Event::PdNoContract,
+            KIND_SETTINGS_CHANGED => Event::SettingsChanged,
+            KIND_SELF_TEST => Event::SelfTest {
+                failed: value as u8,
+                warnings: (value >> 8) as u8,
+            },
+            kind => return Err(DecodeError::UnknownKind(kind)),
+        };
+        Ok(Self {
+            seq: u32_at(0),
+            boot: u16::from_le_bytes([bytes[4], bytes[5]]),
+            uptime_s: u32_at(8),
+            event,
+        })
+    }
+}
+
+/// The most recent `CAPACITY` events, older ones are overwritten
+#[derive(Clone)]
+pub struct EventRing {
+    slots: [Option<EventRecord>; CAPACITY],
+    next_seq: u32,
+}
+
+impl EventRing {
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; CAPACITY],
+            next_seq: 0,
+        }
+    }
+
+    /// Put back a record read from the flash mirror, in any order
+    pub fn restore(&mut self, record: EventRecord) {
+        let slot = &mut self.slots[record.seq as usize % CAPACITY];
+        if slot.is_none_or(|old| old.seq < record.seq) {
+            *slot = Some(record);
+        }
+        self.next_seq = self.next_seq.max(record.seq.wrapping_add(1));
+    }
+
+    /// Append an event, returns the record and the slot it went into
+    pub fn push(&mut self, boot: u16, uptime_s: u32, event: Event) -> (usize, EventRecord) {
+        let record = EventRecord {
+            seq: self.next_seq,
+            boot,
+            uptime_s,
+            event,
+        };
+        let slot = record.seq as usize % CAPACITY;
+        self.slots[slot] = Some(record);
+        self.next_seq = self.next_seq.wrapping_add(1);
+        (slot, record)
+    }
+
+    /// Sequence number of the next event, changes with every push
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+
+    pub fn latest(&self) -> Option<&EventRecord> {
+        self.iter().next_back()
+    }
+
+    /// Oldest first; slots lost from the mirror are skipped
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &EventRecord> + '_ {
+        let first = self.next_seq.saturating_sub(CAPACITY as u32);
+        (first..self.next_seq).filter_map(move |seq| {
+            self.slots[seq as usize % CAPACITY]
+                .as_ref()
+                .filter(|record| record.seq == seq)
+        })
+    }
+}
+
+impl Default for EventRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Event::Boot(cause) => write!(f, "Boot {}", cause.name()),
+            Event::SensorError { port } => write!(f, "P{} sensor error", port + 1),
+            Event::Trip {
+                port,
+                kind: TripKind::OverCurrent,
+            } => write!(f, "P{} over-current", port + 1),
+            Event::Trip {
+                port,
+                kind: TripKind::OverVoltage,
+            } => write!(f, "P{} over-voltage", port + 1),
+            Event::Plugged { port } => write!(f, "P{} plugged", port + 1),
+            Event::Unplugged { port } => write!(f, "P{} unplugged", port + 1),
+            Event::PdContract {
+                voltage_mv,
+                current_ma,
+            } => write!(
+                f,
+                "PD {}.{:02}V {}.{:02}A",
+                voltage_mv / 1000,
+                voltage_mv % 1000 / 10,
+                current_ma / 1000,
+                current_ma % 1000 / 10
+            ),
+            Event::PdNoContract => write!(f, "PD no contract"),
+            Event::SettingsChanged => write!(f, "Settings changed"),
+            Event::SelfTest {
+                failed: 0,
+                warnings: 0,
+            } => write!(f, "Self-test passed"),
+            Event::SelfTest { failed, warnings } => {
+                write!(f, "Self-test {} failed {} warn", failed, warnings)
+            }
+        }
+    }
+}
+
+/// "b3 1:02:03 P2 plugged", boot number, uptime and event
+impl fmt::Display for EventRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "b{} {}:{:02}:{:02} {}",
+            self.boot,
+            self.uptime_s / 3600,
+            self.uptime_s / 60 % 60,
+            self.uptime_s % 60,
+            self.event
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_events() -> [Event; 11] {
+        [
+            Event::Boot(ResetCause::Watchdog),
+            Event::Boot(ResetCause::OptionBytes),
+            Event::Boot(ResetCause::Panic),
+            Event::SensorError { port: 2 },
+            Event::Trip {
+                port: 1,
+                kind: TripKind::OverVoltage,
+            },
+            Event::Plugged { port: 0 },
+            Event::Unplugged { port: 1 },
+            Event::PdContract {
+                voltage_mv: 20_000,
+                current_ma: 3250,
+            },
+            Event::PdNoContract,
+            Event::SettingsChanged,
+            Event::SelfTest {
+                failed: 2,
+                warnings: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn record_round_trip() {
+        for (i, event) in all_events().into_iter().enumerate() {
+            let record = EventRecord {
+                seq: 1000 + i as u32,
+                boot: 7,
+                uptime_s: 86_400 + i as u32,
+                event,
+            };
+            assert_eq!(EventRecord::decode(&record.encode()), Ok(record));
+        }
+    }
+
+    #[test]
+    fn decode_rejects_garbage() {
+        let mut bytes = EventRecord {
+            seq: 0,
+            boot: 0,
+            uptime_s: 0,
+            event: Event::Boot(ResetCause::PowerOn),
+        }
+        .encode();
+        assert_eq!(EventRecord::decode(&bytes[..15]), Err(DecodeError::Length));
+        // Out of range cause from a newer firmware
+        bytes[12] = 200;
+        assert_eq!(
+            EventRecord::decode(&bytes).unwrap().event,
+            Event::Boot(ResetCause::Unknown)
+        );
+        bytes[6] = 0xEE;
+        assert_eq!(
+            EventRecord::decode(&bytes),
+            Err(DecodeError::UnknownKind(0xEE))
+        );
+    }
+
+    #[test]
+    fn ring_keeps_the_newest() {
+        let mut ring = EventRing::new();
+        assert!(ring.latest().is_none());
+        for i in 0..30u32 {
+            ring.push(1, i, Event::Plugged { port: 0 });
+        }
+        let uptimes: Vec<u32> = ring.iter().map(|r| r.uptime_s).collect();
+        assert_eq!(uptimes, (6..30).collect::<Vec<_>>());
+        assert_eq!(ring.latest().unwrap().seq, 29);
+        assert_eq!(ring.next_seq(), 30);
+    }
+
+    #[test]
+    fn restore_from_partial_mirror() {
+        let mut original = EventRing::new();
+        let mut mirror = Vec::new();
+        for i in 0..40u32 {
+            let (slot, record) = original.push((i / 10) as u16, i, Event::SettingsChanged);
+            mirror.push((slot, record));
+        }
+        // Only the newest record per slot survives, one slot got lost
+        let mut slots: [Option<EventRecord>; CAPACITY] = [None; CAPACITY];
+        for (slot, record) in mirror {
+            slots[slot] = Some(record);
+        }
+        slots[20] = None;
+
+        let mut ring = EventRing::new();
+        for record in slots.iter().rev().flatten() {
+            ring.restore(*record);
+        }
+        assert_eq!(ring.next_seq(), 40);
+        let seqs: Vec<u32> = ring.iter().map(|r| r.seq).collect();
+        let expected: Vec<u32> = (16..40).filter(|&s| s != 20).collect();
+        assert_eq!(seqs, expected);
+
+        let (_, next) = ring.push(4, 0, Event::Boot(ResetCause::Pin));
+        assert_eq!(next.seq, 40);
+    }
+
+    #[test]
+    fn display() {
+        let record = EventRecord {
+            seq: 0,
+            boot: 3,
+            uptime_s: 3723,
+            event: Event::Plugged { port: 1 },
+        };
+        assert_eq!(record.to_string(), "b3 1:02:03 P2 plugged");
+        let pd = Event::PdContract {
+            voltage_mv: 9000,
+            current_ma: 2220,
+        };
+        assert_eq!(pd.to_string(), "PD 9.00V 2.22A");
+        let boot = Event::Boot(ResetCause::BrownOut);
+        assert_eq!(boot.to_string(), "Boot brown-out");
+    }
+}
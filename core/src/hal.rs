@@ -0,0 +1,154 @@
+// core/src/hal.rs
+// What the application needs from the board
+//
+// `app` and the display pages only reach the hardware through these traits
+// and types. The firmware implements them in `board` on top of the GC9D01,
+// the event log in flash and the buzzer; the simulator in `simulator/` on
+// top of a frame buffer and RAM.
+
+use embedded_graphics::pixelcolor::Rgb565;
+
+use crate::capture::{Capture, Status, Trigger};
+use crate::display::dashboard::Error;
+use crate::event_log::ring::{Event, EventRing};
+use crate::storage::counters::Counters;
+use crate::storage::settings::Settings;
+
+/// Held this long, a press is long rather than short
+pub const LONG_PRESS_MS: u64 = 500;
+/// Held this long, a press starts repeating
+pub const REPEAT_DELAY_MS: u64 = 1000;
+/// Time between repeats while the button stays down
+pub const REPEAT_PERIOD_MS: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    /// BTN2
+    Left,
+    /// BTN3
+    Right,
+}
+
+impl Button {
+    /// The other button, for a panel mounted upside down
+    pub fn mirrored(self) -> Self {
+        match self {
+            Button::Left => Button::Right,
+            Button::Right => Button::Left,
+        }
+    }
+}
+
+/// How a button was pressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Press {
+    /// Released before `LONG_PRESS_MS`
+    Short,
+    /// Released between `LONG_PRESS_MS` and `REPEAT_DELAY_MS`
+    Long,
+    /// Still held after `REPEAT_DELAY_MS`, sent every `REPEAT_PERIOD_MS`
+    /// with the number of repeats before it; nothing follows the release
+    Repeat(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    /// Rising click pair
+    Attach,
+    /// Falling click pair
+    Detach,
+}
+
+impl Sound {
+    /// (frequency Hz, duration ms), 0 Hz is a pause
+    pub fn tones(self) -> &'static [(u32, u64)] {
+        match self {
+            Sound::Attach => &[(3000, 15), (0, 40), (4000, 15)],
+            Sound::Detach => &[(4000, 15), (0, 40), (3000, 15)],
+        }
+    }
+}
+
+/// The display, whatever controller drives it
+///
+/// The pages draw through `write_area` and `fill_color`; the rest is for
+/// bring-up and power. Monochrome panels threshold the colours.
+#[allow(async_fn_in_trait)]
+pub trait Panel {
+    /// (width, height) in pixels, in the orientation the UI draws in
+    fn size(&self) -> (u16, u16);
+
+    /// Reset the controller and turn the display on
+    async fn init(&mut self) -> Result<(), Error>;
+
+    /// Select the block the next `write_pixels` fills
+    async fn set_window(&mut self, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error>;
+
+    /// Fill the window from the last `set_window`; `pixels` are
+    /// column-major, like the font bitmaps, and cover the whole window
+    async fn write_pixels(&mut self, pixels: &[Rgb565]) -> Result<(), Error>;
+
+    /// Fill a `width` x `height` block at (`x`, `y`)
+    async fn write_area(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        pixels: &[Rgb565],
+    ) -> Result<(), Error> {
+        self.set_window(x, y, width, height).await?;
+        self.write_pixels(pixels).await
+    }
+
+    async fn fill_color(&mut self, color: Rgb565) -> Result<(), Error> {
+        let (width, height) = self.size();
+        let column = [color; 256];
+        for x in 0..width {
+            for y in (0..height).step_by(column.len()) {
+                let rows = (height - y).min(column.len() as u16);
+                self.write_area(x, y, 1, rows, &column[..rows as usize])
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 0 is as dim as the panel goes, 255 full brightness
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Error>;
+
+    /// Blank the display and stop refreshing it, or wake it up again with
+    /// the old contents
+    async fn sleep(&mut self, asleep: bool) -> Result<(), Error>;
+}
+
+/// Everything else the application touches besides the panel
+pub trait Platform {
+    /// Append an event to the event log
+    fn record(&mut self, event: Event);
+
+    /// Sequence number of the next event, to notice new ones
+    fn next_event_seq(&self) -> u32;
+
+    /// Copy of the event log, oldest event first when iterated
+    fn events(&self) -> EventRing;
+
+    /// Queue a sound; dropped while the buzzer is muted
+    fn play(&mut self, sound: Sound);
+
+    /// Persist the usage counters; a failure is logged, not retried
+    fn save_counters(&mut self, counters: &Counters);
+
+    /// Apply and persist settings changed on the device; a failure is
+    /// logged, the settings stay applied
+    fn save_settings(&mut self, settings: &Settings);
+
+    /// Arm a burst capture, or stop the running one with `None`
+    fn arm_capture(&mut self, trigger: Option<Trigger>);
+
+    /// What the burst capture is doing
+    fn capture_status(&self) -> Status;
+
+    /// Copy of the burst capture
+    fn capture(&self) -> Capture;
+}
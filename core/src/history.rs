@@ -0,0 +1,170 @@
+// core/src/history.rs
+// Per-port history of current and power for the trend graphs
+//
+// One point per second, the highest current and power seen in that second,
+// so short spikes stay visible in the graphs. The ring holds as many points
+// as the panel is wide.
+
+// One column per point at full panel width
+pub const HISTORY_LEN: usize = 160;
+pub const HISTORY_INTERVAL_MS: u32 = 1000;
+
+/// What the trends and port pages plot, chosen in the settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trend {
+    Current,
+    Power,
+}
+
+impl Trend {
+    /// The plotted field of a point, mA or cW
+    pub fn value(self, point: TrendPoint) -> u16 {
+        match self {
+            Trend::Current => point.current_ma,
+            Trend::Power => point.power_cw,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrendPoint {
+    pub current_ma: u16,
+    /// Centiwatts, 655 W full scale
+    pub power_cw: u16,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PortHistory {
+    points: [TrendPoint; HISTORY_LEN],
+    // Index of the oldest point once the ring is full
+    head: usize,
+    len: usize,
+    // Points ever added, tells the graphs when to redraw
+    count: u32,
+    pending: TrendPoint,
+    pending_ms: u32,
+}
+
+impl Default for PortHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortHistory {
+    pub const fn new() -> Self {
+        Self {
+            points: [TrendPoint {
+                current_ma: 0,
+                power_cw: 0,
+            }; HISTORY_LEN],
+            head: 0,
+            len: 0,
+            count: 0,
+            pending: TrendPoint {
+                current_ma: 0,
+                power_cw: 0,
+            },
+            pending_ms: 0,
+        }
+    }
+
+    /// Feed one sample that stood for the last `dt_ms` milliseconds
+    pub fn update(&mut self, current_ma: u32, power_mw: u32, dt_ms: u32) {
+        let sample = TrendPoint {
+            current_ma: current_ma.min(u16::MAX as u32) as u16,
+            power_cw: (power_mw / 10).min(u16::MAX as u32) as u16,
+        };
+        self.pending.current_ma = self.pending.current_ma.max(sample.current_ma);
+        self.pending.power_cw = self.pending.power_cw.max(sample.power_cw);
+        self.pending_ms += dt_ms;
+        if self.pending_ms < HISTORY_INTERVAL_MS {
+            return;
+        }
+
+        // A long gap, e.g. a stalled loop, still adds only one point
+        self.pending_ms = 0;
+        if self.len < HISTORY_LEN {
+            self.points[self.len] = self.pending;
+            self.len += 1;
+        } else {
+            self.points[self.head] = self.pending;
+            self.head = (self.head + 1) % HISTORY_LEN;
+        }
+        self.count = self.count.wrapping_add(1);
+        self.pending = TrendPoint::default();
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Points oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = TrendPoint> + '_ {
+        let (newer, older) = self.points[..self.len].split_at(self.head);
+        older.iter().chain(newer).copied()
+    }
+
+    /// Copy the newest `out.len()` values of one field, oldest first;
+    /// returns how many there were
+    pub fn recent(&self, out: &mut [u16], field: impl Fn(TrendPoint) -> u16) -> usize {
+        let n = self.len.min(out.len());
+        for (slot, point) in out[..n].iter_mut().zip(self.iter().skip(self.len - n)) {
+            *slot = field(point);
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_peak_of_every_second() {
+        let mut history = PortHistory::new();
+        for ma in [100, 900, 200, 100, 100, 100, 100, 100, 100, 100] {
+            history.update(ma, ma * 5, 100);
+        }
+        assert_eq!(history.count(), 1);
+        assert_eq!(
+            history.iter().next(),
+            Some(TrendPoint {
+                current_ma: 900,
+                power_cw: 450
+            })
+        );
+        history.update(50, 250, 900);
+        assert_eq!(history.count(), 1, "peak starts over");
+        history.update(50, 250, 100);
+        assert_eq!(history.iter().last().unwrap().current_ma, 50);
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_points() {
+        let mut history = PortHistory::new();
+        for second in 0..HISTORY_LEN as u32 + 40 {
+            history.update(second, 0, HISTORY_INTERVAL_MS);
+        }
+        let currents: std::vec::Vec<u16> = history.iter().map(|p| p.current_ma).collect();
+        assert_eq!(currents.len(), HISTORY_LEN);
+        assert_eq!(currents[0], 40);
+        assert_eq!(*currents.last().unwrap(), HISTORY_LEN as u16 + 39);
+
+        let mut out = [0u16; 53];
+        assert_eq!(history.recent(&mut out, |p| p.current_ma), 53);
+        assert_eq!(out[0], HISTORY_LEN as u16 + 39 - 52);
+        assert_eq!(out[52], HISTORY_LEN as u16 + 39);
+    }
+
+    #[test]
+    fn recent_on_a_short_history() {
+        let mut history = PortHistory::new();
+        for watts in [1, 2, 3] {
+            history.update(0, watts * 1000, HISTORY_INTERVAL_MS);
+        }
+        let mut out = [0u16; 8];
+        assert_eq!(history.recent(&mut out, |p| p.power_cw), 3);
+        assert_eq!(out[..3], [100, 200, 300]);
+    }
+}
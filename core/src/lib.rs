@@ -0,0 +1,35 @@
+// core/src/lib.rs
+// Hardware independent part of the hub firmware
+//
+// Everything between the sensors and the panel that doesn't touch a
+// peripheral: the port measurements, their calibration, burst captures and
+// the usage counters, attach and charge detection, the PD sink and its
+// trace decoder, the stored forms of the settings, counters and event log,
+// the telemetry frames, the self-test verdicts, the display pages and the
+// drivers of the panels other than the GC9D01. The board is only reached
+// through the `hal` traits and embedded-hal. Builds for the MCU and for the
+// host, where `cargo test` runs every module's tests.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod app;
+pub mod attach;
+pub mod calibration;
+pub mod capture;
+pub mod charge;
+pub mod crash;
+pub mod display;
+pub mod event_log;
+pub mod hal;
+pub mod history;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod panel;
+pub mod pd;
+pub mod protocol;
+pub mod selftest;
+pub mod shared;
+pub mod storage;
+pub mod usage;
@@ -0,0 +1,105 @@
+// core/src/mock/framebuffer.rs
+// A `Panel` in RAM, for the simulator and the display snapshot tests
+//
+// Writes outside the panel are rejected as a real controller would garble
+// them, so layout mistakes show up as errors.
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+
+use crate::display::dashboard::Error;
+use crate::hal::Panel;
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 40;
+
+pub struct Framebuffer {
+    // Row-major
+    pixels: [Rgb565; WIDTH * HEIGHT],
+    // (x, y, width, height) from the last `set_window`
+    window: (usize, usize, usize, usize),
+    brightness: u8,
+    asleep: bool,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            pixels: [Rgb565::BLACK; WIDTH * HEIGHT],
+            window: (0, 0, 0, 0),
+            brightness: 255,
+            asleep: false,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb565 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    /// Every pixel, row by row from the top left
+    pub fn pixels(&self) -> &[Rgb565] {
+        &self.pixels
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+}
+
+impl Panel for Framebuffer {
+    fn size(&self) -> (u16, u16) {
+        (WIDTH as u16, HEIGHT as u16)
+    }
+
+    async fn init(&mut self) -> Result<(), Error> {
+        self.asleep = false;
+        Ok(())
+    }
+
+    async fn set_window(&mut self, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error> {
+        let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+        if x + width > WIDTH || y + height > HEIGHT {
+            return Err(Error::DriverError);
+        }
+        self.window = (x, y, width, height);
+        Ok(())
+    }
+
+    async fn write_pixels(&mut self, pixels: &[Rgb565]) -> Result<(), Error> {
+        let (x, y, width, height) = self.window;
+        if pixels.len() < width * height {
+            return Err(Error::DriverError);
+        }
+        for col in 0..width {
+            for row in 0..height {
+                self.pixels[(y + row) * WIDTH + x + col] = pixels[col * height + row];
+            }
+        }
+        Ok(())
+    }
+
+    async fn fill_color(&mut self, color: Rgb565) -> Result<(), Error> {
+        self.pixels.fill(color);
+        Ok(())
+    }
+
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Error> {
+        self.brightness = level;
+        Ok(())
+    }
+
+    async fn sleep(&mut self, asleep: bool) -> Result<(), Error> {
+        self.asleep = asleep;
+        Ok(())
+    }
+}
@@ -0,0 +1,613 @@
+// core/src/mock/ina226.rs
+// INA226 register model behind an async I2C bus
+//
+// `Ina226Bus` implements `embedded_hal_async::i2c::I2c` and answers for
+// every attached `Ina226` by address, so the drivers used on the board can
+// talk to it unchanged (share it with an `I2cDevice` like the real bus).
+// Each device samples its bus voltage and current waveforms whenever it
+// converts and fills the registers the way the datasheet describes:
+//
+//   shunt   = I * R_shunt / 2.5 uV         bus     = V / 1.25 mV
+//   current = shunt * CAL / 2048           power   = |current| * bus / 20000
+//
+// Conversions are instantaneous: a continuous mode converts at every
+// `Ina226Bus::advance`, a triggered mode once when the configuration is
+// written. Averaging and conversion times are stored but not modelled.
+// Faults make a device NACK, report a wrong die ID or read full scale, and
+// the whole bus can be held stuck.
+
+use embedded_hal_async::i2c::{
+    ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress,
+};
+use heapless::Vec;
+
+use super::waveform::Waveform;
+
+pub const MANUFACTURER_ID: u16 = 0x5449;
+pub const DIE_ID: u16 = 0x2260;
+/// Configuration after power-up or a reset: 1 sample, 1.1 ms conversions,
+/// shunt and bus continuous
+pub const CONFIG_DEFAULT: u16 = 0x4127;
+pub const MAX_DEVICES: usize = 16;
+
+const SHUNT_LSB_V: f32 = 2.5e-6;
+const BUS_LSB_V: f32 = 1.25e-3;
+
+const CONFIG_RESET: u16 = 1 << 15;
+const CONFIG_MODE: u16 = 0b111;
+const MODE_SHUNT: u16 = 0b001;
+const MODE_BUS: u16 = 0b010;
+const MODE_CONTINUOUS: u16 = 0b100;
+
+/// Mask/Enable bits
+pub mod mask {
+    /// Shunt voltage over the alert limit
+    pub const SOL: u16 = 1 << 15;
+    /// Shunt voltage under the alert limit
+    pub const SUL: u16 = 1 << 14;
+    /// Bus voltage over the alert limit
+    pub const BOL: u16 = 1 << 13;
+    /// Bus voltage under the alert limit
+    pub const BUL: u16 = 1 << 12;
+    /// Power over the alert limit
+    pub const POL: u16 = 1 << 11;
+    /// Alert when a conversion is ready
+    pub const CNVR: u16 = 1 << 10;
+    /// Alert function flag
+    pub const AFF: u16 = 1 << 4;
+    /// Conversion ready flag
+    pub const CVRF: u16 = 1 << 3;
+    /// Current or power overflowed
+    pub const OVF: u16 = 1 << 2;
+    /// Alert pin active high
+    pub const APOL: u16 = 1 << 1;
+    /// Latch the alert until Mask/Enable is read
+    pub const LEN: u16 = 1 << 0;
+
+    pub(super) const FUNCTIONS: u16 = SOL | SUL | BOL | BUL | POL | CNVR;
+    pub(super) const WRITABLE: u16 = FUNCTIONS | APOL | LEN;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Register {
+    Config = 0x00,
+    ShuntVoltage = 0x01,
+    BusVoltage = 0x02,
+    Power = 0x03,
+    Current = 0x04,
+    Calibration = 0x05,
+    MaskEnable = 0x06,
+    AlertLimit = 0x07,
+    ManufacturerId = 0xFE,
+    DieId = 0xFF,
+}
+
+impl Register {
+    fn from_u8(pointer: u8) -> Option<Self> {
+        Some(match pointer {
+            0x00 => Register::Config,
+            0x01 => Register::ShuntVoltage,
+            0x02 => Register::BusVoltage,
+            0x03 => Register::Power,
+            0x04 => Register::Current,
+            0x05 => Register::Calibration,
+            0x06 => Register::MaskEnable,
+            0x07 => Register::AlertLimit,
+            0xFE => Register::ManufacturerId,
+            0xFF => Register::DieId,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The address isn't acknowledged, as if the chip were missing
+    Nack,
+    /// The die ID register reads this instead of `DIE_ID`
+    WrongDieId(u16),
+    /// Both ADCs read positive full scale whatever the input
+    Saturated,
+}
+
+pub struct Ina226 {
+    shunt_ohms: f32,
+    bus_volts: Waveform,
+    amps: Waveform,
+    fault: Option<Fault>,
+    pointer: u8,
+    config: u16,
+    shunt: i16,
+    bus: u16,
+    power: u16,
+    current: i16,
+    calibration: u16,
+    mask_enable: u16,
+    alert_limit: u16,
+}
+
+impl Ina226 {
+    pub fn new(shunt_ohms: f32, bus_volts: Waveform, amps: Waveform) -> Self {
+        let mut device = Self {
+            shunt_ohms,
+            bus_volts,
+            amps,
+            fault: None,
+            pointer: 0,
+            config: 0,
+            shunt: 0,
+            bus: 0,
+            power: 0,
+            current: 0,
+            calibration: 0,
+            mask_enable: 0,
+            alert_limit: 0,
+        };
+        device.reset();
+        device
+    }
+
+    pub fn set_bus_volts(&mut self, waveform: Waveform) {
+        self.bus_volts = waveform;
+    }
+
+    pub fn set_amps(&mut self, waveform: Waveform) {
+        self.amps = waveform;
+    }
+
+    /// Inject a fault, or clear it with `None`
+    pub fn inject(&mut self, fault: Option<Fault>) {
+        self.fault = fault;
+    }
+
+    /// Register content as the chip holds it, without the side effects of
+    /// reading it over the bus
+    pub fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::Config => self.config,
+            Register::ShuntVoltage => self.shunt as u16,
+            Register::BusVoltage => self.bus,
+            Register::Power => self.power,
+            Register::Current => self.current as u16,
+            Register::Calibration => self.calibration,
+            Register::MaskEnable => self.mask_enable,
+            Register::AlertLimit => self.alert_limit,
+            Register::ManufacturerId => MANUFACTURER_ID,
+            Register::DieId => match self.fault {
+                Some(Fault::WrongDieId(id)) => id,
+                _ => DIE_ID,
+            },
+        }
+    }
+
+    /// Whether the ALERT pin signals an alert, in whichever polarity APOL
+    /// selects
+    pub fn alert_asserted(&self) -> bool {
+        self.mask_enable & mask::AFF != 0
+    }
+
+    fn reset(&mut self) {
+        self.config = CONFIG_DEFAULT;
+        self.shunt = 0;
+        self.bus = 0;
+        self.power = 0;
+        self.current = 0;
+        self.calibration = 0;
+        self.mask_enable = 0;
+        self.alert_limit = 0;
+    }
+
+    fn read(&mut self) -> u16 {
+        let Some(register) = Register::from_u8(self.pointer) else {
+            return 0;
+        };
+        let value = self.register(register);
+        if register == Register::MaskEnable {
+            // Reading Mask/Enable clears the conversion ready flag and a
+            // latched alert
+            self.mask_enable &= !mask::CVRF;
+            if self.mask_enable & mask::LEN != 0 {
+                self.mask_enable &= !mask::AFF;
+            }
+        }
+        value
+    }
+
+    fn write(&mut self, value: u16, now_ms: u32) {
+        match Register::from_u8(self.pointer) {
+            Some(Register::Config) => {
+                if value & CONFIG_RESET != 0 {
+                    self.reset();
+                    return;
+                }
+                // Bits 14..12 are fixed
+                self.config = (value & 0x0FFF) | (CONFIG_DEFAULT & 0x7000);
+                self.mask_enable &= !mask::CVRF;
+                let mode = self.config & CONFIG_MODE;
+                if mode & MODE_CONTINUOUS == 0 && mode != 0 {
+                    self.convert(now_ms);
+                }
+            }
+            Some(Register::Calibration) => self.calibration = value & 0x7FFF,
+            Some(Register::MaskEnable) => {
+                self.mask_enable = (self.mask_enable & !mask::WRITABLE) | (value & mask::WRITABLE);
+            }
+            Some(Register::AlertLimit) => self.alert_limit = value,
+            // Read-only
+            _ => {}
+        }
+    }
+
+    fn convert(&mut self, now_ms: u32) {
+        let mode = self.config & CONFIG_MODE;
+        if mode & MODE_SHUNT != 0 {
+            self.shunt = match self.fault {
+                Some(Fault::Saturated) => i16::MAX,
+                _ => {
+                    let volts = self.amps.at(now_ms) * self.shunt_ohms;
+                    libm::roundf(volts / SHUNT_LSB_V).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+                }
+            };
+        }
+        if mode & MODE_BUS != 0 {
+            self.bus = match self.fault {
+                Some(Fault::Saturated) => 0x7FFF,
+                _ => libm::roundf(self.bus_volts.at(now_ms) / BUS_LSB_V).clamp(0.0, 32767.0) as u16,
+            };
+        }
+
+        // Power is computed from the current register, so only the current
+        // can overflow
+        let current = self.shunt as i32 * self.calibration as i32 / 2048;
+        self.current = current.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        self.power = (self.current.unsigned_abs() as u32 * self.bus as u32 / 20000) as u16;
+        let overflow = current != self.current as i32;
+
+        let mut flags = mask::CVRF;
+        if overflow {
+            flags |= mask::OVF;
+        }
+        if self.alert_condition() {
+            flags |= mask::AFF;
+        }
+        let latched = self.mask_enable & (mask::LEN | mask::AFF) == mask::LEN | mask::AFF;
+        self.mask_enable &= !(mask::OVF | if latched { 0 } else { mask::AFF });
+        self.mask_enable |= flags;
+    }
+
+    fn alert_condition(&self) -> bool {
+        let limit = self.alert_limit;
+        // Only the highest enabled function is active
+        match self.mask_enable & mask::FUNCTIONS {
+            0 => false,
+            f if f & mask::SOL != 0 => self.shunt > limit as i16,
+            f if f & mask::SUL != 0 => self.shunt < limit as i16,
+            f if f & mask::BOL != 0 => self.bus > limit,
+            f if f & mask::BUL != 0 => self.bus < limit,
+            f if f & mask::POL != 0 => self.power > limit,
+            _ => true,
+        }
+    }
+}
+
+/// One I2C bus with INA226s on it
+pub struct Ina226Bus {
+    devices: Vec<(SevenBitAddress, Ina226), MAX_DEVICES>,
+    now_ms: u32,
+    stuck: bool,
+}
+
+impl Default for Ina226Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ina226Bus {
+    pub fn new() -> Self {
+        Self {
+            devices: Vec::new(),
+            now_ms: 0,
+            stuck: false,
+        }
+    }
+
+    /// Put a device on the bus, replacing any at the same address
+    pub fn attach(&mut self, address: SevenBitAddress, device: Ina226) {
+        self.detach(address);
+        if self.devices.push((address, device)).is_err() {
+            panic!("more than {} mock INA226s on one bus", MAX_DEVICES);
+        }
+    }
+
+    pub fn detach(&mut self, address: SevenBitAddress) {
+        self.devices.retain(|(a, _)| *a != address);
+    }
+
+    pub fn device(&mut self, address: SevenBitAddress) -> Option<&mut Ina226> {
+        self.devices
+            .iter_mut()
+            .find(|(a, _)| *a == address)
+            .map(|(_, device)| device)
+    }
+
+    /// Hold SDA low: every transaction fails with a bus error until released
+    pub fn set_stuck(&mut self, stuck: bool) {
+        self.stuck = stuck;
+    }
+
+    pub fn now_ms(&self) -> u32 {
+        self.now_ms
+    }
+
+    /// Move the clock on; devices in a continuous mode convert once
+    pub fn advance(&mut self, ms: u32) {
+        self.now_ms += ms;
+        for (_, device) in self.devices.iter_mut() {
+            if device.config & MODE_CONTINUOUS != 0
+                && device.config & CONFIG_MODE != MODE_CONTINUOUS
+            {
+                device.convert(self.now_ms);
+            }
+        }
+    }
+}
+
+impl ErrorType for Ina226Bus {
+    type Error = ErrorKind;
+}
+
+impl I2c<SevenBitAddress> for Ina226Bus {
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if self.stuck {
+            return Err(ErrorKind::Bus);
+        }
+        let now_ms = self.now_ms;
+        let device = match self.device(address) {
+            Some(device) if device.fault != Some(Fault::Nack) => device,
+            _ => return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+        };
+
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    let Some((&pointer, data)) = bytes.split_first() else {
+                        continue;
+                    };
+                    device.pointer = pointer;
+                    // Registers are written MSB first, a lone byte is dropped
+                    if let [msb, lsb, ..] = data {
+                        device.write(u16::from_be_bytes([*msb, *lsb]), now_ms);
+                    }
+                }
+                Operation::Read(buf) => {
+                    // Reads past two bytes repeat the register
+                    let bytes = device.read().to_be_bytes();
+                    for (i, byte) in buf.iter_mut().enumerate() {
+                        *byte = bytes[i % 2];
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    const SENSOR: SevenBitAddress = 0x40;
+
+    fn bus_with(amps: Waveform) -> Ina226Bus {
+        let mut bus = Ina226Bus::new();
+        bus.attach(SENSOR, Ina226::new(0.01, Waveform::Constant(5.0), amps));
+        bus
+    }
+
+    fn read(bus: &mut Ina226Bus, address: SevenBitAddress, register: Register) -> u16 {
+        let mut buf = [0; 2];
+        block_on(bus.write_read(address, &[register as u8], &mut buf)).unwrap();
+        u16::from_be_bytes(buf)
+    }
+
+    fn write(bus: &mut Ina226Bus, address: SevenBitAddress, register: Register, value: u16) {
+        let [msb, lsb] = value.to_be_bytes();
+        block_on(bus.write(address, &[register as u8, msb, lsb])).unwrap();
+    }
+
+    #[test]
+    fn identifies_itself_with_reset_defaults() {
+        let mut bus = bus_with(Waveform::Constant(0.0));
+        assert_eq!(read(&mut bus, SENSOR, Register::ManufacturerId), 0x5449);
+        assert_eq!(read(&mut bus, SENSOR, Register::DieId), 0x2260);
+        assert_eq!(read(&mut bus, SENSOR, Register::Config), 0x4127);
+        assert_eq!(read(&mut bus, SENSOR, Register::Calibration), 0);
+    }
+
+    #[test]
+    fn computes_current_and_power_from_the_calibration() {
+        let mut bus = bus_with(Waveform::Constant(2.0));
+        // 1 mA per bit through 10 mOhm
+        write(&mut bus, SENSOR, Register::Calibration, 512);
+        bus.advance(2);
+
+        // 20 mV, 5 V, 2000 mA and 400 * 25 mW
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 8000);
+        assert_eq!(read(&mut bus, SENSOR, Register::BusVoltage), 4000);
+        assert_eq!(read(&mut bus, SENSOR, Register::Current), 2000);
+        assert_eq!(read(&mut bus, SENSOR, Register::Power), 400);
+    }
+
+    #[test]
+    fn samples_the_waveforms_on_every_conversion() {
+        let mut bus = bus_with(Waveform::Step {
+            before: 0.5,
+            after: -1.0,
+            at_ms: 100,
+        });
+        bus.advance(50);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 2000);
+        bus.advance(50);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage) as i16, -4000);
+
+        bus.device(SENSOR)
+            .unwrap()
+            .set_bus_volts(Waveform::Constant(20.0));
+        bus.advance(10);
+        assert_eq!(read(&mut bus, SENSOR, Register::BusVoltage), 16000);
+    }
+
+    #[test]
+    fn addresses_devices_on_one_bus() {
+        let mut bus = bus_with(Waveform::Constant(1.0));
+        bus.attach(
+            0x44,
+            Ina226::new(0.005, Waveform::Constant(12.0), Waveform::Constant(3.0)),
+        );
+        bus.advance(1);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 4000);
+        assert_eq!(read(&mut bus, 0x44, Register::ShuntVoltage), 6000);
+        assert_eq!(read(&mut bus, 0x44, Register::BusVoltage), 9600);
+        assert_eq!(
+            block_on(bus.read(0x41, &mut [0; 2])),
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+    }
+
+    #[test]
+    fn triggered_mode_converts_once_per_config_write() {
+        let mut bus = bus_with(Waveform::Ramp {
+            from: 0.0,
+            to: 1.0,
+            start_ms: 0,
+            end_ms: 1000,
+        });
+        bus.advance(250);
+        write(&mut bus, SENSOR, Register::Config, 0x4123);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 1000);
+        assert_ne!(read(&mut bus, SENSOR, Register::MaskEnable) & mask::CVRF, 0);
+        assert_eq!(read(&mut bus, SENSOR, Register::MaskEnable) & mask::CVRF, 0);
+
+        bus.advance(250);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 1000);
+        write(&mut bus, SENSOR, Register::Config, 0x4123);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 2000);
+    }
+
+    #[test]
+    fn reset_bit_restores_the_defaults() {
+        let mut bus = bus_with(Waveform::Constant(1.0));
+        write(&mut bus, SENSOR, Register::Calibration, 512);
+        write(&mut bus, SENSOR, Register::Config, 0x4000);
+        write(&mut bus, SENSOR, Register::Config, 0x8000);
+        assert_eq!(read(&mut bus, SENSOR, Register::Config), 0x4127);
+        assert_eq!(read(&mut bus, SENSOR, Register::Calibration), 0);
+    }
+
+    #[test]
+    fn latched_alert_holds_until_mask_enable_is_read() {
+        let mut bus = bus_with(Waveform::Step {
+            before: 1.0,
+            after: 4.0,
+            at_ms: 100,
+        });
+        // Over 3 A through 10 mOhm, latched
+        write(&mut bus, SENSOR, Register::AlertLimit, 12000);
+        write(
+            &mut bus,
+            SENSOR,
+            Register::MaskEnable,
+            mask::SOL | mask::LEN,
+        );
+        bus.advance(50);
+        assert!(!bus.device(SENSOR).unwrap().alert_asserted());
+
+        bus.advance(50);
+        assert!(bus.device(SENSOR).unwrap().alert_asserted());
+        bus.device(SENSOR)
+            .unwrap()
+            .set_amps(Waveform::Constant(1.0));
+        bus.advance(50);
+        assert!(bus.device(SENSOR).unwrap().alert_asserted());
+
+        assert_ne!(read(&mut bus, SENSOR, Register::MaskEnable) & mask::AFF, 0);
+        assert!(!bus.device(SENSOR).unwrap().alert_asserted());
+    }
+
+    #[test]
+    fn transparent_alert_follows_the_power() {
+        let mut bus = bus_with(Waveform::Square {
+            low: 0.1,
+            high: 3.0,
+            period_ms: 200,
+            high_ms: 100,
+        });
+        write(&mut bus, SENSOR, Register::Calibration, 512);
+        // 10 W in 25 mW steps, active high
+        write(&mut bus, SENSOR, Register::AlertLimit, 400);
+        write(
+            &mut bus,
+            SENSOR,
+            Register::MaskEnable,
+            mask::POL | mask::APOL,
+        );
+        assert!(!bus.device(SENSOR).unwrap().alert_asserted());
+
+        bus.advance(50);
+        assert!(bus.device(SENSOR).unwrap().alert_asserted());
+        bus.advance(100);
+        assert!(!bus.device(SENSOR).unwrap().alert_asserted());
+    }
+
+    #[test]
+    fn injected_faults() {
+        let mut bus = bus_with(Waveform::Constant(1.0));
+
+        bus.device(SENSOR)
+            .unwrap()
+            .inject(Some(Fault::WrongDieId(0x2270)));
+        assert_eq!(read(&mut bus, SENSOR, Register::DieId), 0x2270);
+
+        bus.device(SENSOR).unwrap().inject(Some(Fault::Nack));
+        assert_eq!(
+            block_on(bus.write(SENSOR, &[0x01])),
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+
+        bus.device(SENSOR).unwrap().inject(Some(Fault::Saturated));
+        bus.advance(1);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 0x7FFF);
+        assert_eq!(read(&mut bus, SENSOR, Register::BusVoltage), 0x7FFF);
+
+        // The driver sees the chip again once the fault is gone
+        bus.set_stuck(true);
+        assert_eq!(block_on(bus.write(SENSOR, &[0x01])), Err(ErrorKind::Bus));
+        bus.set_stuck(false);
+        bus.device(SENSOR).unwrap().inject(None);
+        bus.advance(1);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 4000);
+    }
+
+    #[test]
+    fn inputs_beyond_full_scale_saturate() {
+        // 20 A through 10 mOhm is past the 81.92 mV range
+        let mut bus = bus_with(Waveform::Constant(-20.0));
+        write(&mut bus, SENSOR, Register::Calibration, 0x7FFF);
+        bus.device(SENSOR)
+            .unwrap()
+            .set_bus_volts(Waveform::Constant(48.0));
+        bus.advance(1);
+        assert_eq!(read(&mut bus, SENSOR, Register::ShuntVoltage), 0x8000);
+        assert_eq!(read(&mut bus, SENSOR, Register::BusVoltage), 0x7FFF);
+        assert_eq!(read(&mut bus, SENSOR, Register::Current), 0x8000);
+        assert_ne!(read(&mut bus, SENSOR, Register::MaskEnable) & mask::OVF, 0);
+    }
+}
@@ -0,0 +1,9 @@
+// core/src/mock/mod.rs
+// Simulated devices for host tests and the simulator
+//
+// Built for tests and with the `mock` feature only, never into the
+// firmware.
+
+pub mod framebuffer;
+pub mod ina226;
+pub mod waveform;
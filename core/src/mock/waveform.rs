@@ -0,0 +1,165 @@
+// core/src/mock/waveform.rs
+// Signal sources for mock sensors, as a function of time
+//
+// A waveform is sampled at the mock's clock whenever the device converts.
+// `Table` replays a recorded trace with linear interpolation between its
+// points; `Fn` covers anything the other shapes don't.
+
+use core::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Waveform {
+    Constant(f32),
+    /// `before` until `at_ms`, then `after`
+    Step {
+        before: f32,
+        after: f32,
+        at_ms: u32,
+    },
+    /// Straight line from `from` at `start_ms` to `to` at `end_ms`, flat
+    /// outside
+    Ramp {
+        from: f32,
+        to: f32,
+        start_ms: u32,
+        end_ms: u32,
+    },
+    Sine {
+        offset: f32,
+        amplitude: f32,
+        period_ms: u32,
+    },
+    /// `high` for the first `high_ms` of every period, then `low`
+    Square {
+        low: f32,
+        high: f32,
+        period_ms: u32,
+        high_ms: u32,
+    },
+    /// `(t_ms, value)` points in time order; the first and last value hold
+    /// before and after the table
+    Table(&'static [(u32, f32)]),
+    Fn(fn(u32) -> f32),
+}
+
+impl Waveform {
+    pub fn at(&self, t_ms: u32) -> f32 {
+        match *self {
+            Waveform::Constant(value) => value,
+            Waveform::Step {
+                before,
+                after,
+                at_ms,
+            } => {
+                if t_ms < at_ms {
+                    before
+                } else {
+                    after
+                }
+            }
+            Waveform::Ramp {
+                from,
+                to,
+                start_ms,
+                end_ms,
+            } => interpolate((start_ms, from), (end_ms, to), t_ms),
+            Waveform::Sine {
+                offset,
+                amplitude,
+                period_ms,
+            } => {
+                let phase = (t_ms % period_ms.max(1)) as f32 / period_ms.max(1) as f32;
+                offset + amplitude * libm::sinf(2.0 * PI * phase)
+            }
+            Waveform::Square {
+                low,
+                high,
+                period_ms,
+                high_ms,
+            } => {
+                if t_ms % period_ms.max(1) < high_ms {
+                    high
+                } else {
+                    low
+                }
+            }
+            Waveform::Table(points) => {
+                let next = points.partition_point(|&(at_ms, _)| at_ms <= t_ms);
+                match (next.checked_sub(1).map(|i| points[i]), points.get(next)) {
+                    (Some(before), Some(&after)) => interpolate(before, after, t_ms),
+                    (Some((_, value)), None) | (None, Some(&(_, value))) => value,
+                    (None, None) => 0.0,
+                }
+            }
+            Waveform::Fn(f) => f(t_ms),
+        }
+    }
+}
+
+fn interpolate((t0, v0): (u32, f32), (t1, v1): (u32, f32), t_ms: u32) -> f32 {
+    if t_ms <= t0 || t1 <= t0 {
+        v0
+    } else if t_ms >= t1 {
+        v1
+    } else {
+        v0 + (v1 - v0) * (t_ms - t0) as f32 / (t1 - t0) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_and_ramp() {
+        let step = Waveform::Step {
+            before: 0.0,
+            after: 2.0,
+            at_ms: 100,
+        };
+        assert_eq!(step.at(99), 0.0);
+        assert_eq!(step.at(100), 2.0);
+
+        let ramp = Waveform::Ramp {
+            from: 5.0,
+            to: 20.0,
+            start_ms: 1000,
+            end_ms: 2500,
+        };
+        assert_eq!(ramp.at(0), 5.0);
+        assert_eq!(ramp.at(2000), 15.0);
+        assert_eq!(ramp.at(9000), 20.0);
+    }
+
+    #[test]
+    fn periodic_shapes() {
+        let sine = Waveform::Sine {
+            offset: 1.0,
+            amplitude: 0.5,
+            period_ms: 400,
+        };
+        assert!((sine.at(100) - 1.5).abs() < 1e-5);
+        assert!((sine.at(700) - 0.5).abs() < 1e-5);
+
+        let square = Waveform::Square {
+            low: 0.1,
+            high: 3.0,
+            period_ms: 1000,
+            high_ms: 200,
+        };
+        assert_eq!(square.at(1150), 3.0);
+        assert_eq!(square.at(1200), 0.1);
+    }
+
+    #[test]
+    fn table_interpolates_and_holds_its_ends() {
+        static TRACE: [(u32, f32); 3] = [(1000, 0.0), (2000, 2.0), (4000, 1.0)];
+        let table = Waveform::Table(&TRACE);
+        assert_eq!(table.at(0), 0.0);
+        assert_eq!(table.at(1500), 1.0);
+        assert_eq!(table.at(2000), 2.0);
+        assert_eq!(table.at(3000), 1.5);
+        assert_eq!(table.at(10_000), 1.0);
+        assert_eq!(Waveform::Table(&[]).at(5), 0.0);
+    }
+}
@@ -0,0 +1,9 @@
+// core/src/panel/mod.rs
+// `hal::Panel` for the displays of other hardware revisions
+//
+// The GC9D01 of the current board has its own driver crate and is wrapped
+// in the firmware's `board`. These drive their controllers directly through
+// the embedded-hal traits, so the same pages run on them.
+
+pub mod ssd1306;
+pub mod st7735;
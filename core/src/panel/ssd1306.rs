@@ -0,0 +1,266 @@
+// core/src/panel/ssd1306.rs
+// SSD1306 monochrome OLED over I2C, as a colour `Panel`
+//
+// The pages draw in colour on a black background, so a pixel is lit when
+// its brightest channel is past half scale: text and graphs in any of the
+// page colours show, the dim grey of idle values doesn't. The controller
+// RAM holds 8 rows per byte ("pages"), which would make every write a
+// read-modify-write over I2C; a copy is kept here instead and each write
+// sends the columns and pages it touched.
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::RgbColor;
+use embedded_hal_async::i2c::I2c;
+
+use crate::display::dashboard::Error;
+use crate::hal::Panel;
+
+pub const WIDTH: u16 = 128;
+/// 0x3C, or 0x3D with the address pin high
+pub const DEFAULT_ADDRESS: u8 = 0x3C;
+
+const MAX_HEIGHT: usize = 64;
+
+/// Control bytes in front of a command list or of RAM data
+const CONTROL_COMMAND: u8 = 0x00;
+const CONTROL_DATA: u8 = 0x40;
+
+const SET_CONTRAST: u8 = 0x81;
+const DISPLAY_OFF: u8 = 0xAE;
+const DISPLAY_ON: u8 = 0xAF;
+const COLUMN_ADDRESS: u8 = 0x21;
+const PAGE_ADDRESS: u8 = 0x22;
+
+pub struct Ssd1306<I2C> {
+    i2c: I2C,
+    address: u8,
+    height: u16,
+    // Page-major: byte `page * WIDTH + x` holds rows `page * 8..` of
+    // column `x`, lowest bit on top
+    ram: [u8; WIDTH as usize * MAX_HEIGHT / 8],
+    // (x, y, width, height) from the last `set_window`
+    window: (u16, u16, u16, u16),
+}
+
+impl<I2C: I2c> Ssd1306<I2C> {
+    /// A 128x64 or 128x32 module
+    pub fn new(i2c: I2C, address: u8, height: u16) -> Self {
+        assert!(
+            height == 32 || height == 64,
+            "SSD1306 modules are 32 or 64 rows"
+        );
+        Self {
+            i2c,
+            address,
+            height,
+            ram: [0; WIDTH as usize * MAX_HEIGHT / 8],
+            window: (0, 0, 0, 0),
+        }
+    }
+
+    async fn commands(&mut self, commands: &[u8]) -> Result<(), Error> {
+        let mut buf = [0u8; 32];
+        buf[0] = CONTROL_COMMAND;
+        buf[1..=commands.len()].copy_from_slice(commands);
+        self.i2c
+            .write(self.address, &buf[..=commands.len()])
+            .await
+            .map_err(|_| Error::DriverError)
+    }
+
+    /// Send columns `x..x + width` of the pages holding rows `y..y + height`
+    async fn flush(&mut self, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error> {
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let (first_page, last_page) = (y / 8, (y + height - 1) / 8);
+        self.commands(&[
+            COLUMN_ADDRESS,
+            x as u8,
+            (x + width - 1) as u8,
+            PAGE_ADDRESS,
+            first_page as u8,
+            last_page as u8,
+        ])
+        .await?;
+
+        let mut buf = [0u8; WIDTH as usize + 1];
+        buf[0] = CONTROL_DATA;
+        for page in first_page..=last_page {
+            let start = (page * WIDTH + x) as usize;
+            let width = width as usize;
+            buf[1..=width].copy_from_slice(&self.ram[start..start + width]);
+            self.i2c
+                .write(self.address, &buf[..=width])
+                .await
+                .map_err(|_| Error::DriverError)?;
+        }
+        Ok(())
+    }
+}
+
+fn lit(color: Rgb565) -> bool {
+    color.r() > Rgb565::MAX_R / 2 || color.g() > Rgb565::MAX_G / 2 || color.b() > Rgb565::MAX_B / 2
+}
+
+impl<I2C: I2c> Panel for Ssd1306<I2C> {
+    fn size(&self) -> (u16, u16) {
+        (WIDTH, self.height)
+    }
+
+    async fn init(&mut self) -> Result<(), Error> {
+        let com_pins = if self.height == 64 { 0x12 } else { 0x02 };
+        #[rustfmt::skip]
+        let setup = [
+            DISPLAY_OFF,
+            0xD5, 0x80, // clock divide
+            0xA8, self.height as u8 - 1, // multiplex ratio
+            0xD3, 0x00, // display offset
+            0x40, // start line 0
+            0x8D, 0x14, // charge pump on
+            0x20, 0x00, // horizontal addressing
+            0xA1, // column 127 on the left
+            0xC8, // scan from the bottom
+            0xDA, com_pins,
+            SET_CONTRAST, 0xCF,
+            0xD9, 0xF1, // precharge
+            0xDB, 0x40, // VCOMH deselect level
+            0xA4, // show the RAM
+            0xA6, // not inverted
+        ];
+        self.commands(&setup).await?;
+        self.ram.fill(0);
+        self.flush(0, 0, WIDTH, self.height).await?;
+        self.commands(&[DISPLAY_ON]).await
+    }
+
+    async fn set_window(&mut self, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error> {
+        if x + width > WIDTH || y + height > self.height {
+            return Err(Error::DriverError);
+        }
+        self.window = (x, y, width, height);
+        Ok(())
+    }
+
+    async fn write_pixels(&mut self, pixels: &[Rgb565]) -> Result<(), Error> {
+        let (x, y, width, height) = self.window;
+        if pixels.len() < width as usize * height as usize {
+            return Err(Error::DriverError);
+        }
+        for col in 0..width {
+            for row in 0..height {
+                let pixel = pixels[(col * height + row) as usize];
+                let (x, y) = (x + col, y + row);
+                let byte = &mut self.ram[((y / 8) * WIDTH + x) as usize];
+                if lit(pixel) {
+                    *byte |= 1 << (y % 8);
+                } else {
+                    *byte &= !(1 << (y % 8));
+                }
+            }
+        }
+        self.flush(x, y, width, height).await
+    }
+
+    async fn fill_color(&mut self, color: Rgb565) -> Result<(), Error> {
+        self.ram.fill(if lit(color) { 0xFF } else { 0x00 });
+        self.flush(0, 0, WIDTH, self.height).await
+    }
+
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Error> {
+        self.commands(&[SET_CONTRAST, level]).await
+    }
+
+    async fn sleep(&mut self, asleep: bool) -> Result<(), Error> {
+        // The RAM survives, waking shows the old contents
+        self.commands(&[if asleep { DISPLAY_OFF } else { DISPLAY_ON }])
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embedded_hal_async::i2c::{ErrorType, Operation};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Bus {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl ErrorType for Bus {
+        type Error = Infallible;
+    }
+
+    impl I2c for Bus {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Infallible> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            for operation in operations {
+                if let Operation::Write(bytes) = operation {
+                    self.writes.push(bytes.to_vec());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn colours_are_thresholded_into_pages() {
+        let mut panel = Ssd1306::new(Bus::default(), DEFAULT_ADDRESS, 32);
+        // One column of 4 rows across the page boundary at row 8
+        let pixels = [
+            Rgb565::RED,
+            Rgb565::new(15, 30, 15),
+            Rgb565::BLACK,
+            Rgb565::new(0, 0, 20),
+        ];
+        block_on(panel.write_area(3, 6, 1, 4, &pixels)).unwrap();
+
+        assert_eq!(
+            panel.i2c.writes,
+            [
+                vec![CONTROL_COMMAND, COLUMN_ADDRESS, 3, 3, PAGE_ADDRESS, 0, 1],
+                vec![CONTROL_DATA, 0b0100_0000],
+                vec![CONTROL_DATA, 0b0000_0010],
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_keep_the_rest_of_the_page() {
+        let mut panel = Ssd1306::new(Bus::default(), DEFAULT_ADDRESS, 64);
+        block_on(panel.fill_color(Rgb565::WHITE)).unwrap();
+        assert_eq!(panel.i2c.writes.len(), 1 + 8);
+
+        panel.i2c.writes.clear();
+        block_on(panel.write_area(0, 9, 1, 1, &[Rgb565::BLACK])).unwrap();
+        assert_eq!(panel.i2c.writes[1], [CONTROL_DATA, 0b1111_1101]);
+
+        assert!(block_on(panel.set_window(120, 0, 9, 1)).is_err());
+    }
+
+    #[test]
+    fn sleep_and_brightness_are_commands() {
+        let mut panel = Ssd1306::new(Bus::default(), DEFAULT_ADDRESS, 64);
+        block_on(panel.set_brightness(0x40)).unwrap();
+        block_on(panel.sleep(true)).unwrap();
+        block_on(panel.sleep(false)).unwrap();
+        assert_eq!(
+            panel.i2c.writes,
+            [
+                vec![CONTROL_COMMAND, SET_CONTRAST, 0x40],
+                vec![CONTROL_COMMAND, DISPLAY_OFF],
+                vec![CONTROL_COMMAND, DISPLAY_ON],
+            ]
+        );
+    }
+}
@@ -0,0 +1,455 @@
+// core/src/panel/st7735.rs
+// ST7735S and ST7789 colour panels over SPI
+//
+// Both controllers take the same MIPI DCS commands for windows, pixels and
+// sleep; only the ST7735 wants its frame rate and power registers set up.
+// The glass is driven in landscape, so the common 0.96" 80x160 module is
+// 160 wide and 80 high, the same way round as the GC9D01 strip. Modules put
+// their glass at an offset in the controller RAM and differ in colour order
+// and inversion; the presets in `Config` carry that.
+//
+// Pages hand over column-major pixels while the controller fills a window
+// row by row, so `write_pixels` transposes through a small buffer. The
+// backlight is a PWM channel.
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::IntoStorage;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::pwm::SetDutyCycle;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice;
+
+use crate::display::dashboard::Error;
+use crate::hal::Panel;
+
+const SWRESET: u8 = 0x01;
+const SLPIN: u8 = 0x10;
+const SLPOUT: u8 = 0x11;
+const NORON: u8 = 0x13;
+const INVOFF: u8 = 0x20;
+const INVON: u8 = 0x21;
+const DISPOFF: u8 = 0x28;
+const DISPON: u8 = 0x29;
+const CASET: u8 = 0x2A;
+const RASET: u8 = 0x2B;
+const RAMWR: u8 = 0x2C;
+const MADCTL: u8 = 0x36;
+const COLMOD: u8 = 0x3A;
+
+const MADCTL_MX: u8 = 0x40;
+const MADCTL_MV: u8 = 0x20;
+const MADCTL_BGR: u8 = 0x08;
+/// 16 bits per pixel on both the RGB and the SPI interface
+const COLMOD_RGB565: u8 = 0x55;
+
+/// ST7735 frame rate and power control, the datasheet's recommended values
+const ST7735_SETUP: [(u8, &[u8]); 10] = [
+    (0xB1, &[0x01, 0x2C, 0x2D]),
+    (0xB2, &[0x01, 0x2C, 0x2D]),
+    (0xB3, &[0x01, 0x2C, 0x2D, 0x01, 0x2C, 0x2D]),
+    (0xB4, &[0x07]),
+    (0xC0, &[0xA2, 0x02, 0x84]),
+    (0xC1, &[0xC5]),
+    (0xC2, &[0x0A, 0x00]),
+    (0xC3, &[0x8A, 0x2A]),
+    (0xC4, &[0x8A, 0xEE]),
+    (0xC5, &[0x0E]),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Controller {
+    St7735,
+    St7789,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub controller: Controller,
+    /// Landscape size of the glass
+    pub width: u16,
+    pub height: u16,
+    /// Where the glass starts in the controller RAM, in landscape
+    pub x_offset: u16,
+    pub y_offset: u16,
+    pub bgr: bool,
+    pub inverted: bool,
+}
+
+impl Config {
+    /// 0.96" 80x160 IPS module
+    pub const ST7735_80X160: Config = Config {
+        controller: Controller::St7735,
+        width: 160,
+        height: 80,
+        x_offset: 1,
+        y_offset: 26,
+        bgr: true,
+        inverted: true,
+    };
+
+    /// 1.14" 135x240 IPS module
+    pub const ST7789_135X240: Config = Config {
+        controller: Controller::St7789,
+        width: 240,
+        height: 135,
+        x_offset: 40,
+        y_offset: 53,
+        bgr: false,
+        inverted: true,
+    };
+}
+
+pub struct St7735<SPI, DC, RST, BL, DELAY> {
+    spi: SPI,
+    dc: DC,
+    rst: RST,
+    backlight: BL,
+    delay: DELAY,
+    config: Config,
+    // (width, height) from the last `set_window`
+    window: (u16, u16),
+    brightness: u8,
+}
+
+impl<SPI, DC, RST, BL, DELAY> St7735<SPI, DC, RST, BL, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BL: SetDutyCycle,
+    DELAY: DelayNs,
+{
+    pub fn new(spi: SPI, dc: DC, rst: RST, backlight: BL, delay: DELAY, config: Config) -> Self {
+        Self {
+            spi,
+            dc,
+            rst,
+            backlight,
+            delay,
+            config,
+            window: (0, 0),
+            brightness: 255,
+        }
+    }
+
+    async fn command(&mut self, command: u8, params: &[u8]) -> Result<(), Error> {
+        self.dc.set_low().map_err(|_| Error::DriverError)?;
+        self.spi
+            .write(&[command])
+            .await
+            .map_err(|_| Error::DriverError)?;
+        if !params.is_empty() {
+            self.data(params).await?;
+        }
+        Ok(())
+    }
+
+    async fn data(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.dc.set_high().map_err(|_| Error::DriverError)?;
+        self.spi.write(data).await.map_err(|_| Error::DriverError)
+    }
+
+    async fn backlight(&mut self, level: u8) -> Result<(), Error> {
+        self.backlight
+            .set_duty_cycle_fraction(level as u16, 255)
+            .map_err(|_| Error::DriverError)
+    }
+}
+
+impl<SPI, DC, RST, BL, DELAY> Panel for St7735<SPI, DC, RST, BL, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BL: SetDutyCycle,
+    DELAY: DelayNs,
+{
+    fn size(&self) -> (u16, u16) {
+        (self.config.width, self.config.height)
+    }
+
+    async fn init(&mut self) -> Result<(), Error> {
+        self.rst.set_low().map_err(|_| Error::DriverError)?;
+        self.delay.delay_ms(10).await;
+        self.rst.set_high().map_err(|_| Error::DriverError)?;
+        self.delay.delay_ms(120).await;
+
+        self.command(SWRESET, &[]).await?;
+        self.delay.delay_ms(150).await;
+        self.command(SLPOUT, &[]).await?;
+        self.delay.delay_ms(120).await;
+        if self.config.controller == Controller::St7735 {
+            for (command, params) in ST7735_SETUP {
+                self.command(command, params).await?;
+            }
+        }
+
+        let inversion = if self.config.inverted { INVON } else { INVOFF };
+        self.command(inversion, &[]).await?;
+        let mut madctl = MADCTL_MX | MADCTL_MV;
+        if self.config.bgr {
+            madctl |= MADCTL_BGR;
+        }
+        self.command(MADCTL, &[madctl]).await?;
+        self.command(COLMOD, &[COLMOD_RGB565]).await?;
+        self.command(NORON, &[]).await?;
+        self.delay.delay_ms(10).await;
+        self.command(DISPON, &[]).await?;
+        self.delay.delay_ms(10).await;
+        self.backlight(self.brightness).await
+    }
+
+    async fn set_window(&mut self, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error> {
+        if x + width > self.config.width || y + height > self.config.height {
+            return Err(Error::DriverError);
+        }
+        self.window = (width, height);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let x0 = x + self.config.x_offset;
+        let y0 = y + self.config.y_offset;
+        let [x0h, x0l] = x0.to_be_bytes();
+        let [x1h, x1l] = (x0 + width - 1).to_be_bytes();
+        let [y0h, y0l] = y0.to_be_bytes();
+        let [y1h, y1l] = (y0 + height - 1).to_be_bytes();
+        self.command(CASET, &[x0h, x0l, x1h, x1l]).await?;
+        self.command(RASET, &[y0h, y0l, y1h, y1l]).await?;
+        self.command(RAMWR, &[]).await
+    }
+
+    async fn write_pixels(&mut self, pixels: &[Rgb565]) -> Result<(), Error> {
+        let (width, height) = (self.window.0 as usize, self.window.1 as usize);
+        if pixels.len() < width * height {
+            return Err(Error::DriverError);
+        }
+
+        let mut buf = [0u8; 128];
+        let mut len = 0;
+        for row in 0..height {
+            for col in 0..width {
+                let [high, low] = pixels[col * height + row].into_storage().to_be_bytes();
+                buf[len] = high;
+                buf[len + 1] = low;
+                len += 2;
+                if len == buf.len() {
+                    self.data(&buf).await?;
+                    len = 0;
+                }
+            }
+        }
+        if len > 0 {
+            self.data(&buf[..len]).await?;
+        }
+        Ok(())
+    }
+
+    async fn fill_color(&mut self, color: Rgb565) -> Result<(), Error> {
+        let (width, height) = self.size();
+        self.set_window(0, 0, width, height).await?;
+
+        let mut buf = [0u8; 128];
+        for pair in buf.chunks_exact_mut(2) {
+            pair.copy_from_slice(&color.into_storage().to_be_bytes());
+        }
+        let mut remaining = width as usize * height as usize * 2;
+        while remaining > 0 {
+            let len = remaining.min(buf.len());
+            self.data(&buf[..len]).await?;
+            remaining -= len;
+        }
+        Ok(())
+    }
+
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Error> {
+        self.brightness = level;
+        self.backlight(level).await
+    }
+
+    async fn sleep(&mut self, asleep: bool) -> Result<(), Error> {
+        if asleep {
+            self.backlight(0).await?;
+            self.command(DISPOFF, &[]).await?;
+            self.command(SLPIN, &[]).await?;
+            self.delay.delay_ms(5).await;
+            Ok(())
+        } else {
+            self.command(SLPOUT, &[]).await?;
+            self.delay.delay_ms(120).await;
+            self.command(DISPON, &[]).await?;
+            self.backlight(self.brightness).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embedded_graphics::prelude::RgbColor;
+    use embedded_hal_async::spi::{ErrorType, Operation};
+
+    use super::*;
+
+    /// Every SPI write with the level D/C had during it
+    #[derive(Default)]
+    struct Wire {
+        dc: Cell<bool>,
+        writes: RefCell<Vec<(bool, Vec<u8>)>>,
+    }
+
+    struct Spi(Rc<Wire>);
+    struct Dc(Rc<Wire>);
+    struct Pin;
+    struct Backlight(Rc<Cell<u16>>);
+    struct Delay;
+
+    impl ErrorType for Spi {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice for Spi {
+        async fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u8>],
+        ) -> Result<(), Infallible> {
+            for operation in operations {
+                if let Operation::Write(bytes) = operation {
+                    let dc = self.0.dc.get();
+                    self.0.writes.borrow_mut().push((dc, bytes.to_vec()));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl embedded_hal::digital::ErrorType for Dc {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Dc {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.dc.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.dc.set(true);
+            Ok(())
+        }
+    }
+
+    impl embedded_hal::digital::ErrorType for Pin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Pin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    impl embedded_hal::pwm::ErrorType for Backlight {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for Backlight {
+        fn max_duty_cycle(&self) -> u16 {
+            1000
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+            self.0.set(duty);
+            Ok(())
+        }
+    }
+
+    impl DelayNs for Delay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    type Panel80x160 = St7735<Spi, Dc, Pin, Backlight, Delay>;
+
+    fn panel() -> (Panel80x160, Rc<Wire>, Rc<Cell<u16>>) {
+        let wire = Rc::new(Wire::default());
+        let duty = Rc::new(Cell::new(0));
+        let panel = St7735::new(
+            Spi(wire.clone()),
+            Dc(wire.clone()),
+            Pin,
+            Backlight(duty.clone()),
+            Delay,
+            Config::ST7735_80X160,
+        );
+        (panel, wire, duty)
+    }
+
+    #[test]
+    fn window_is_offset_and_pixels_go_out_row_by_row() {
+        let (mut panel, wire, _) = panel();
+        // 2 columns of 2: red over green, then blue over white
+        let pixels = [Rgb565::RED, Rgb565::GREEN, Rgb565::BLUE, Rgb565::WHITE];
+        block_on(panel.write_area(10, 5, 2, 2, &pixels)).unwrap();
+
+        let writes = wire.writes.borrow();
+        assert_eq!(
+            *writes,
+            [
+                (false, vec![CASET]),
+                (true, vec![0, 11, 0, 12]),
+                (false, vec![RASET]),
+                (true, vec![0, 31, 0, 32]),
+                (false, vec![RAMWR]),
+                (true, vec![0xF8, 0x00, 0x00, 0x1F, 0x07, 0xE0, 0xFF, 0xFF]),
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_covers_the_glass_and_windows_stay_on_it() {
+        let (mut panel, wire, _) = panel();
+        block_on(panel.fill_color(Rgb565::BLUE)).unwrap();
+        let pixel_bytes: usize = wire
+            .writes
+            .borrow()
+            .iter()
+            .skip(5)
+            .map(|(dc, bytes)| {
+                assert!(dc);
+                bytes.len()
+            })
+            .sum();
+        assert_eq!(pixel_bytes, 160 * 80 * 2);
+
+        assert!(block_on(panel.set_window(150, 0, 11, 1)).is_err());
+        assert!(block_on(panel.set_window(0, 79, 1, 2)).is_err());
+    }
+
+    #[test]
+    fn sleep_turns_the_backlight_off_and_wake_restores_it() {
+        let (mut panel, wire, duty) = panel();
+        block_on(panel.init()).unwrap();
+        assert_eq!(duty.get(), 1000);
+        block_on(panel.set_brightness(51)).unwrap();
+        assert_eq!(duty.get(), 200);
+
+        wire.writes.borrow_mut().clear();
+        block_on(panel.sleep(true)).unwrap();
+        assert_eq!(duty.get(), 0);
+        assert_eq!(
+            *wire.writes.borrow(),
+            [(false, vec![DISPOFF]), (false, vec![SLPIN])]
+        );
+        block_on(panel.sleep(false)).unwrap();
+        assert_eq!(duty.get(), 200);
+    }
+}
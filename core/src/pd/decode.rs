@@ -0,0 +1,810 @@
+// core/src/pd/decode.rs
+// PD message decoder and analyzer for the sniffer
+//
+// Turns the raw bytes seen on the CC line (header, optional extended header
+// and data objects) into a `Decoded` message that can be printed, and keeps
+// enough context in `Analyzer` to summarise a negotiation in one line.
+// Hardware agnostic, like `message`.
+
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+use super::message::{self, ControlType, DataType, Header, MessageType, ParseError, Pdo};
+
+/// SPR capabilities carry up to 7 PDOs, EPR capabilities up to 11
+pub const MAX_PDOS: usize = 11;
+/// Data bytes carried by one chunk of a chunked extended message
+const MAX_CHUNK_DATA: usize = 26;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtendedHeader {
+    pub chunked: bool,
+    pub chunk_number: u8,
+    pub request_chunk: bool,
+    pub data_size: u16,
+}
+
+impl ExtendedHeader {
+    pub fn from_bits(bits: u16) -> Self {
+        Self {
+            chunked: bits & 0x8000 != 0,
+            chunk_number: ((bits >> 11) & 0x0F) as u8,
+            request_chunk: bits & (1 << 10) != 0,
+            data_size: bits & 0x1FF,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EprModeAction {
+    Enter,
+    EnterAcknowledged,
+    EnterSucceeded,
+    EnterFailed,
+    Exit,
+    Other(u8),
+}
+
+impl EprModeAction {
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::Enter,
+            2 => Self::EnterAcknowledged,
+            3 => Self::EnterSucceeded,
+            4 => Self::EnterFailed,
+            5 => Self::Exit,
+            other => Self::Other(other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VdmCommandType {
+    Request,
+    Ack,
+    Nak,
+    Busy,
+}
+
+/// Structured VDM header fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StructuredVdm {
+    pub command: u8,
+    pub command_type: VdmCommandType,
+    pub object_position: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Vdm {
+    pub svid: u16,
+    /// None for unstructured VDMs
+    pub structured: Option<StructuredVdm>,
+    /// VDOs following the VDM header
+    pub vdo_count: u8,
+}
+
+impl Vdm {
+    fn parse(header: u32, vdo_count: u8) -> Self {
+        let structured = (header & (1 << 15) != 0).then_some(StructuredVdm {
+            command: (header & 0x1F) as u8,
+            command_type: match (header >> 6) & 0x03 {
+                0 => VdmCommandType::Request,
+                1 => VdmCommandType::Ack,
+                2 => VdmCommandType::Nak,
+                _ => VdmCommandType::Busy,
+            },
+            object_position: ((header >> 8) & 0x07) as u8,
+        });
+        Self {
+            svid: (header >> 16) as u16,
+            structured,
+            vdo_count,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Body {
+    Control,
+    /// Source / Sink (EPR) capabilities, possibly one chunk of them
+    Capabilities(Vec<Pdo, MAX_PDOS>),
+    Request {
+        rdo: u32,
+    },
+    /// Request plus a copy of the requested PDO
+    EprRequest {
+        rdo: u32,
+        pdo: Pdo,
+    },
+    EprMode {
+        action: EprModeAction,
+        data: u8,
+    },
+    Vdm(Vdm),
+    /// Any other data message, objects are not interpreted
+    Data,
+    /// Any other extended message
+    Extended(ExtendedHeader),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decoded {
+    pub header: Header,
+    pub body: Body,
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Decoded, ParseError> {
+    let message = message::Message::parse(bytes)?;
+    let header = message.header;
+    let objects = &message.objects;
+
+    let body = match header.message_type {
+        MessageType::Control(_) => Body::Control,
+        MessageType::Data(DataType::SourceCapabilities | DataType::SinkCapabilities) => {
+            Body::Capabilities(objects.iter().map(|raw| Pdo::parse(*raw)).collect())
+        }
+        MessageType::Data(DataType::Request) if !objects.is_empty() => {
+            Body::Request { rdo: objects[0] }
+        }
+        MessageType::Data(DataType::EprRequest) if objects.len() >= 2 => Body::EprRequest {
+            rdo: objects[0],
+            pdo: Pdo::parse(objects[1]),
+        },
+        MessageType::Data(DataType::EprMode) if !objects.is_empty() => Body::EprMode {
+            action: EprModeAction::from_code((objects[0] >> 24) as u8),
+            data: (objects[0] >> 16) as u8,
+        },
+        MessageType::Data(DataType::VendorDefined) if !objects.is_empty() => {
+            Body::Vdm(Vdm::parse(objects[0], objects.len() as u8 - 1))
+        }
+        MessageType::Data(_) => Body::Data,
+        MessageType::Extended(code) => {
+            if bytes.len() < 4 {
+                return Err(ParseError::TooShort);
+            }
+            let extended = ExtendedHeader::from_bits(u16::from_le_bytes([bytes[2], bytes[3]]));
+            match code {
+                EXT_EPR_SOURCE_CAPABILITIES | EXT_EPR_SINK_CAPABILITIES
+                    if !extended.request_chunk =>
+                {
+                    Body::Capabilities(extended_pdos(&extended, &bytes[4..]))
+                }
+                _ => Body::Extended(extended),
+            }
+        }
+    };
+
+    Ok(Decoded { header, body })
+}
+
+const EXT_EPR_SOURCE_CAPABILITIES: u8 = 17;
+const EXT_EPR_SINK_CAPABILITIES: u8 = 18;
+
+/// PDOs carried by this chunk; later chunks continue the PDO list
+fn extended_pdos(extended: &ExtendedHeader, data: &[u8]) -> Vec<Pdo, MAX_PDOS> {
+    let offset = extended.chunk_number as usize * MAX_CHUNK_DATA;
+    let remaining = (extended.data_size as usize).saturating_sub(offset);
+    let len = remaining.min(MAX_CHUNK_DATA).min(data.len());
+    data[..len]
+        .chunks_exact(4)
+        .map(|c| Pdo::parse(u32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+        .collect()
+}
+
+fn control_name(kind: ControlType) -> &'static str {
+    match kind {
+        ControlType::GoodCrc => "GoodCRC",
+        ControlType::GotoMin => "GotoMin",
+        ControlType::Accept => "Accept",
+        ControlType::Reject => "Reject",
+        ControlType::Ping => "Ping",
+        ControlType::PsRdy => "PS_RDY",
+        ControlType::GetSourceCap => "Get_Source_Cap",
+        ControlType::GetSinkCap => "Get_Sink_Cap",
+        ControlType::DrSwap => "DR_Swap",
+        ControlType::PrSwap => "PR_Swap",
+        ControlType::VconnSwap => "VCONN_Swap",
+        ControlType::Wait => "Wait",
+        ControlType::SoftReset => "Soft_Reset",
+        ControlType::DataReset => "Data_Reset",
+        ControlType::DataResetComplete => "Data_Reset_Complete",
+        ControlType::NotSupported => "Not_Supported",
+        ControlType::GetSourceCapExtended => "Get_Source_Cap_Extended",
+        ControlType::GetStatus => "Get_Status",
+        ControlType::FrSwap => "FR_Swap",
+        ControlType::GetPpsStatus => "Get_PPS_Status",
+        ControlType::GetCountryCodes => "Get_Country_Codes",
+        ControlType::GetSinkCapExtended => "Get_Sink_Cap_Extended",
+        ControlType::GetSourceInfo => "Get_Source_Info",
+        ControlType::GetRevision => "Get_Revision",
+        ControlType::Other(_) => "Control",
+    }
+}
+
+fn data_name(kind: DataType) -> &'static str {
+    match kind {
+        DataType::SourceCapabilities => "Source_Capabilities",
+        DataType::Request => "Request",
+        DataType::Bist => "BIST",
+        DataType::SinkCapabilities => "Sink_Capabilities",
+        DataType::BatteryStatus => "Battery_Status",
+        DataType::Alert => "Alert",
+        DataType::GetCountryInfo => "Get_Country_Info",
+        DataType::EnterUsb => "Enter_USB",
+        DataType::EprRequest => "EPR_Request",
+        DataType::EprMode => "EPR_Mode",
+        DataType::SourceInfo => "Source_Info",
+        DataType::Revision => "Revision",
+        DataType::VendorDefined => "Vendor_Defined",
+        DataType::Other(_) => "Data",
+    }
+}
+
+fn extended_name(code: u8) -> &'static str {
+    match code {
+        1 => "Source_Capabilities_Extended",
+        2 => "Status",
+        3 => "Get_Battery_Cap",
+        4 => "Get_Battery_Status",
+        5 => "Battery_Capabilities",
+        6 => "Get_Manufacturer_Info",
+        7 => "Manufacturer_Info",
+        8 => "Security_Request",
+        9 => "Security_Response",
+        10 => "Firmware_Update_Request",
+        11 => "Firmware_Update_Response",
+        12 => "PPS_Status",
+        13 => "Country_Info",
+        14 => "Country_Codes",
+        15 => "Sink_Capabilities_Extended",
+        16 => "Extended_Control",
+        EXT_EPR_SOURCE_CAPABILITIES => "EPR_Source_Capabilities",
+        EXT_EPR_SINK_CAPABILITIES => "EPR_Sink_Capabilities",
+        30 => "Vendor_Defined_Extended",
+        _ => "Extended",
+    }
+}
+
+fn vdm_command_name(command: u8) -> &'static str {
+    match command {
+        1 => "Discover_Identity",
+        2 => "Discover_SVIDs",
+        3 => "Discover_Modes",
+        4 => "Enter_Mode",
+        5 => "Exit_Mode",
+        6 => "Attention",
+        16..=31 => "SVID_Specific",
+        _ => "Reserved",
+    }
+}
+
+impl Decoded {
+    /// Message name as used in the PD specification
+    pub fn name(&self) -> &'static str {
+        match self.header.message_type {
+            MessageType::Control(kind) => control_name(kind),
+            MessageType::Data(kind) => data_name(kind),
+            MessageType::Extended(code) => extended_name(code),
+        }
+    }
+
+    /// Sender of the message, "SRC" or "SNK"
+    pub fn sender(&self) -> &'static str {
+        if self.header.power_role_source {
+            "SRC"
+        } else {
+            "SNK"
+        }
+    }
+
+    pub fn is_control(&self, kind: ControlType) -> bool {
+        self.header.message_type == MessageType::Control(kind)
+    }
+}
+
+/// Millivolts printed as volts without trailing zeros, e.g. "3.3V"
+pub struct Volts(pub u32);
+
+/// Milliamps printed as amps without trailing zeros, e.g. "3.25A"
+pub struct Amps(pub u32);
+
+fn write_milli(f: &mut fmt::Formatter<'_>, value: u32, unit: char) -> fmt::Result {
+    let (whole, mut frac) = (value / 1000, value % 1000);
+    if frac == 0 {
+        return write!(f, "{}{}", whole, unit);
+    }
+    let mut digits = 3;
+    while frac % 10 == 0 {
+        frac /= 10;
+        digits -= 1;
+    }
+    write!(f, "{}.{:0width$}{}", whole, frac, unit, width = digits)
+}
+
+impl fmt::Display for Volts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_milli(f, self.0, 'V')
+    }
+}
+
+impl fmt::Display for Amps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_milli(f, self.0, 'A')
+    }
+}
+
+impl fmt::Display for Pdo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            // Unused SPR positions in EPR capabilities
+            Pdo::Fixed { voltage_mv: 0, .. } => write!(f, "-"),
+            Pdo::Fixed {
+                voltage_mv,
+                max_current_ma,
+                ..
+            } => write!(f, "{} {}", Volts(voltage_mv), Amps(max_current_ma)),
+            Pdo::Battery {
+                min_voltage_mv,
+                max_voltage_mv,
+                max_power_mw,
+            } => write!(
+                f,
+                "Batt {}-{} {}W",
+                Volts(min_voltage_mv),
+                Volts(max_voltage_mv),
+                max_power_mw / 1000
+            ),
+            Pdo::Variable {
+                min_voltage_mv,
+                max_voltage_mv,
+                max_current_ma,
+            } => write!(
+                f,
+                "Var {}-{} {}",
+                Volts(min_voltage_mv),
+                Volts(max_voltage_mv),
+                Amps(max_current_ma)
+            ),
+            Pdo::Pps {
+                min_voltage_mv,
+                max_voltage_mv,
+                max_current_ma,
+                ..
+            } => write!(
+                f,
+                "PPS {}-{} {}",
+                Volts(min_voltage_mv),
+                Volts(max_voltage_mv),
+                Amps(max_current_ma)
+            ),
+            Pdo::EprAvs {
+                min_voltage_mv,
+                max_voltage_mv,
+                pdp_w,
+            } => write!(
+                f,
+                "AVS {}-{} {}W",
+                Volts(min_voltage_mv),
+                Volts(max_voltage_mv),
+                pdp_w
+            ),
+            Pdo::Augmented(raw) => write!(f, "APDO {:08x}", raw),
+        }
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} #{} {}",
+            self.sender(),
+            self.header.message_id,
+            self.name()
+        )?;
+
+        match &self.body {
+            Body::Control | Body::Data => Ok(()),
+            Body::Capabilities(pdos) => {
+                for (i, pdo) in pdos.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { " " } else { ", " }, pdo)?;
+                }
+                Ok(())
+            }
+            Body::Request { rdo } | Body::EprRequest { rdo, .. } => {
+                write!(f, " pos {}", message::request_position(*rdo))?;
+                if let Body::EprRequest { pdo, .. } = &self.body {
+                    write!(f, " ({})", pdo)?;
+                }
+                write!(f, " rdo {:08x}", rdo)
+            }
+            Body::EprMode { action, data } => match action {
+                EprModeAction::Enter => write!(f, " enter {}W", data),
+                EprModeAction::EnterAcknowledged => write!(f, " enter ack"),
+                EprModeAction::EnterSucceeded => write!(f, " enter ok"),
+                EprModeAction::EnterFailed => write!(f, " enter failed ({})", data),
+                EprModeAction::Exit => write!(f, " exit"),
+                EprModeAction::Other(code) => write!(f, " action {}", code),
+            },
+            Body::Vdm(vdm) => {
+                write!(f, " svid {:04x}", vdm.svid)?;
+                match vdm.structured {
+                    Some(svdm) => {
+                        let kind = match svdm.command_type {
+                            VdmCommandType::Request => "REQ",
+                            VdmCommandType::Ack => "ACK",
+                            VdmCommandType::Nak => "NAK",
+                            VdmCommandType::Busy => "BUSY",
+                        };
+                        write!(f, " {} {}", vdm_command_name(svdm.command), kind)?;
+                    }
+                    None => write!(f, " unstructured")?,
+                }
+                if vdm.vdo_count > 0 {
+                    write!(f, " +{} VDO", vdm.vdo_count)?;
+                }
+                Ok(())
+            }
+            Body::Extended(extended) => {
+                write!(f, " {} bytes", extended.data_size)?;
+                if extended.chunked {
+                    write!(f, " chunk {}", extended.chunk_number)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Supply {
+    Fixed,
+    Pps,
+    Avs,
+}
+
+/// Requested operating point, resolved against the source capabilities
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Requested {
+    supply: Supply,
+    voltage_mv: u32,
+    current_ma: u32,
+}
+
+impl fmt::Display for Requested {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (voltage, current) = (Volts(self.voltage_mv), Amps(self.current_ma));
+        match self.supply {
+            Supply::Fixed => write!(f, "{} {} PDO", voltage, current),
+            Supply::Pps => write!(f, "PPS {} {}", voltage, current),
+            Supply::Avs => write!(f, "AVS {} {}", voltage, current),
+        }
+    }
+}
+
+fn resolve(pdo: Pdo, rdo: u32) -> Option<Requested> {
+    match pdo {
+        Pdo::Fixed { voltage_mv, .. } => Some(Requested {
+            supply: Supply::Fixed,
+            voltage_mv,
+            current_ma: message::fixed_request_current_ma(rdo),
+        }),
+        Pdo::Pps { .. } => {
+            let (voltage_mv, current_ma) = message::pps_request_output(rdo);
+            Some(Requested {
+                supply: Supply::Pps,
+                voltage_mv,
+                current_ma,
+            })
+        }
+        Pdo::EprAvs { .. } => {
+            let (voltage_mv, current_ma) = message::avs_request_output(rdo);
+            Some(Requested {
+                supply: Supply::Avs,
+                voltage_mv,
+                current_ma,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Highest power a list of source PDOs offers, in W. PPS ranges are left
+/// out, their maximum voltage times current usually exceeds the PDP.
+fn max_power_w(pdos: &[Pdo]) -> u32 {
+    pdos.iter()
+        .map(|pdo| match *pdo {
+            Pdo::Fixed {
+                voltage_mv,
+                max_current_ma,
+                ..
+            } => voltage_mv * max_current_ma / 1_000_000,
+            Pdo::Battery { max_power_mw, .. } => max_power_mw / 1000,
+            Pdo::EprAvs { pdp_w, .. } => pdp_w,
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Line long enough for the display and the console
+pub type Summary = String<32>;
+
+/// Follows a negotiation and summarises it, e.g. "20V 3A PDO accepted"
+#[derive(Default)]
+pub struct Analyzer {
+    capabilities: Vec<Pdo, MAX_PDOS>,
+    requested: Option<Requested>,
+    summary: Summary,
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    /// Returns true when the summary changed
+    pub fn feed(&mut self, decoded: &Decoded) -> bool {
+        let previous = self.summary.clone();
+        self.summary.clear();
+
+        match (&decoded.header.message_type, &decoded.body) {
+            (MessageType::Data(DataType::SourceCapabilities), Body::Capabilities(pdos)) => {
+                self.capabilities = pdos.clone();
+                self.requested = None;
+                let _ = write!(
+                    self.summary,
+                    "{} PDOs, {}W source",
+                    pdos.len(),
+                    max_power_w(pdos)
+                );
+            }
+            (MessageType::Extended(EXT_EPR_SOURCE_CAPABILITIES), Body::Capabilities(pdos)) => {
+                self.capabilities = pdos.clone();
+                self.requested = None;
+                let _ = write!(self.summary, "EPR {}W source", max_power_w(pdos));
+            }
+            (_, Body::Request { rdo }) => {
+                let position = message::request_position(*rdo) as usize;
+                let pdo = self.capabilities.get(position.wrapping_sub(1)).copied();
+                self.requested = pdo.and_then(|pdo| resolve(pdo, *rdo));
+                self.write_requested("requested");
+            }
+            (_, Body::EprRequest { rdo, pdo }) => {
+                self.requested = resolve(*pdo, *rdo);
+                self.write_requested("requested");
+            }
+            (_, Body::EprMode { action, data }) => {
+                let _ = match action {
+                    EprModeAction::Enter => write!(self.summary, "EPR enter {}W", data),
+                    EprModeAction::EnterSucceeded => write!(self.summary, "EPR mode entered"),
+                    EprModeAction::EnterFailed => write!(self.summary, "EPR enter failed"),
+                    EprModeAction::Exit => write!(self.summary, "EPR mode exit"),
+                    _ => Ok(()),
+                };
+            }
+            (MessageType::Control(ControlType::Accept), _) if decoded.header.power_role_source => {
+                self.write_requested("accepted")
+            }
+            (MessageType::Control(ControlType::Reject), _) if decoded.header.power_role_source => {
+                self.write_requested("rejected")
+            }
+            (MessageType::Control(ControlType::Wait), _) if decoded.header.power_role_source => {
+                self.write_requested("wait")
+            }
+            (MessageType::Control(ControlType::PsRdy), _) if decoded.header.power_role_source => {
+                self.write_requested("ready")
+            }
+            (MessageType::Control(ControlType::SoftReset), _) => {
+                self.requested = None;
+                let _ = write!(self.summary, "Soft reset");
+            }
+            _ => {}
+        }
+
+        if self.summary.is_empty() {
+            self.summary = previous;
+            false
+        } else {
+            self.summary != previous
+        }
+    }
+
+    /// A Hard Reset is signalled out of band, there is no message for it
+    pub fn hard_reset(&mut self) {
+        self.capabilities.clear();
+        self.requested = None;
+        self.summary.clear();
+        let _ = write!(self.summary, "Hard reset");
+    }
+
+    fn write_requested(&mut self, what: &str) {
+        if let Some(requested) = self.requested {
+            let _ = write!(self.summary, "{} {}", requested, what);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+
+    // Raw messages as captured on the CC line (header first, no CRC)
+    const SOURCE_CAPS_65W: &[u8] = &[
+        0xA1, 0x71, 0x2C, 0x91, 0x01, 0x08, 0x2C, 0xD1, 0x02, 0x00, 0x2C, 0xC1, 0x03, 0x00, 0x2C,
+        0xB1, 0x04, 0x00, 0x45, 0x41, 0x06, 0x00, 0x64, 0x21, 0xDC, 0xC0, 0x41, 0x21, 0xA4, 0xC1,
+    ];
+    const GOOD_CRC_SNK: &[u8] = &[0x41, 0x00];
+    const REQUEST_20V_3A: &[u8] = &[0x82, 0x12, 0x2C, 0xB1, 0x04, 0x51];
+    const REQUEST_PPS_9V5_2A: &[u8] = &[0x82, 0x14, 0x28, 0xB6, 0x03, 0x61];
+    const ACCEPT: &[u8] = &[0xA3, 0x03];
+    const REJECT: &[u8] = &[0xA4, 0x05];
+    const PS_RDY: &[u8] = &[0xA6, 0x05];
+    const SOFT_RESET: &[u8] = &[0x8D, 0x00];
+    const GET_SINK_CAP: &[u8] = &[0xA8, 0x07];
+    const NOT_SUPPORTED: &[u8] = &[0x90, 0x02];
+    const SINK_CAPS: &[u8] = &[0x84, 0x24, 0x2C, 0x91, 0x01, 0x06, 0x3C, 0x21, 0xA4, 0xC1];
+    // Discover Identity REQ from the source (DFP) to the SOP partner
+    const VDM_DISCOVER_IDENTITY: &[u8] = &[0xAF, 0x19, 0x01, 0xA0, 0x00, 0xFF];
+    // Discover Identity ACK with ID header, cert stat and product VDO
+    const VDM_IDENTITY_ACK: &[u8] = &[
+        0x8F, 0x42, 0x41, 0xA0, 0x00, 0xFF, 0x5E, 0x04, 0x00, 0x54, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x34, 0x12,
+    ];
+    const EPR_MODE_ENTER: &[u8] = &[0x8A, 0x14, 0x00, 0x00, 0x8C, 0x01];
+    const EPR_MODE_SUCCEEDED: &[u8] = &[0xAA, 0x17, 0x00, 0x00, 0x00, 0x03];
+    // 28 V / 5 A fixed PDO (position 8) requested in EPR mode
+    const EPR_REQUEST_28V: &[u8] = &[0x89, 0x26, 0xF4, 0xD1, 0x07, 0x81, 0xF4, 0xC1, 0x08, 0x00];
+    // 36 V / 3 A from the 15-48 V 140 W AVS APDO (position 9) in EPR mode
+    const EPR_REQUEST_AVS_36V: &[u8] =
+        &[0x89, 0x28, 0x3C, 0x40, 0x4B, 0x90, 0x8C, 0x96, 0xC0, 0xD3];
+    // First chunk of EPR_Source_Capabilities: 5/9/15/20 V 5 A, 2 empty slots
+    const EPR_SOURCE_CAPS_CHUNK0: &[u8] = &[
+        0xB1, 0xF1, 0x18, 0x80, 0xF4, 0x91, 0x01, 0x00, 0xF4, 0xD1, 0x02, 0x00, 0xF4, 0xB1, 0x04,
+        0x00, 0xF4, 0x41, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    const GET_MANUFACTURER_INFO: &[u8] = &[0x86, 0x92, 0x02, 0x80, 0x00, 0x00];
+
+    #[test]
+    fn decodes_every_message_type() {
+        let table: &[(&[u8], &str, &str)] = &[
+            (
+                SOURCE_CAPS_65W,
+                "Source_Capabilities",
+                "SRC #0 Source_Capabilities 5V 3A, 9V 3A, 12V 3A, 15V 3A, 20V 3.25A, \
+                 PPS 3.3V-11V 5A, PPS 3.3V-21V 3.25A",
+            ),
+            (GOOD_CRC_SNK, "GoodCRC", "SNK #0 GoodCRC"),
+            (
+                REQUEST_20V_3A,
+                "Request",
+                "SNK #1 Request pos 5 rdo 5104b12c",
+            ),
+            (ACCEPT, "Accept", "SRC #1 Accept"),
+            (REJECT, "Reject", "SRC #2 Reject"),
+            (PS_RDY, "PS_RDY", "SRC #2 PS_RDY"),
+            (SOFT_RESET, "Soft_Reset", "SNK #0 Soft_Reset"),
+            (GET_SINK_CAP, "Get_Sink_Cap", "SRC #3 Get_Sink_Cap"),
+            (NOT_SUPPORTED, "Not_Supported", "SNK #1 Not_Supported"),
+            (
+                SINK_CAPS,
+                "Sink_Capabilities",
+                "SNK #2 Sink_Capabilities 5V 3A, PPS 3.3V-21V 3A",
+            ),
+            (
+                VDM_DISCOVER_IDENTITY,
+                "Vendor_Defined",
+                "SRC #4 Vendor_Defined svid ff00 Discover_Identity REQ",
+            ),
+            (
+                VDM_IDENTITY_ACK,
+                "Vendor_Defined",
+                "SNK #1 Vendor_Defined svid ff00 Discover_Identity ACK +3 VDO",
+            ),
+            (EPR_MODE_ENTER, "EPR_Mode", "SNK #2 EPR_Mode enter 140W"),
+            (EPR_MODE_SUCCEEDED, "EPR_Mode", "SRC #3 EPR_Mode enter ok"),
+            (
+                EPR_REQUEST_28V,
+                "EPR_Request",
+                "SNK #3 EPR_Request pos 8 (28V 5A) rdo 8107d1f4",
+            ),
+            (
+                EPR_REQUEST_AVS_36V,
+                "EPR_Request",
+                "SNK #4 EPR_Request pos 9 (AVS 15V-48V 140W) rdo 904b403c",
+            ),
+            (
+                EPR_SOURCE_CAPS_CHUNK0,
+                "EPR_Source_Capabilities",
+                "SRC #0 EPR_Source_Capabilities 5V 5A, 9V 5A, 15V 5A, 20V 5A, -, -",
+            ),
+            (
+                GET_MANUFACTURER_INFO,
+                "Get_Manufacturer_Info",
+                "SNK #1 Get_Manufacturer_Info 2 bytes chunk 0",
+            ),
+        ];
+
+        for (bytes, name, text) in table {
+            let decoded = decode(bytes).unwrap();
+            assert_eq!(decoded.name(), *name);
+            assert_eq!(decoded.to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert_eq!(decode(&[0xA1]), Err(ParseError::TooShort));
+        assert_eq!(
+            decode(&SOURCE_CAPS_65W[..10]),
+            Err(ParseError::LengthMismatch)
+        );
+        // Extended header missing
+        assert_eq!(decode(&[0x86, 0x92]), Err(ParseError::TooShort));
+    }
+
+    #[test]
+    fn prints_units_without_trailing_zeros() {
+        assert_eq!(Volts(20_000).to_string(), "20V");
+        assert_eq!(Volts(3_300).to_string(), "3.3V");
+        assert_eq!(Amps(3_250).to_string(), "3.25A");
+        assert_eq!(Amps(50).to_string(), "0.05A");
+    }
+
+    fn feed(analyzer: &mut Analyzer, bytes: &[u8]) -> bool {
+        analyzer.feed(&decode(bytes).unwrap())
+    }
+
+    #[test]
+    fn summarises_fixed_negotiation() {
+        let mut analyzer = Analyzer::new();
+        let trace: &[(&[u8], &str)] = &[
+            (SOURCE_CAPS_65W, "7 PDOs, 65W source"),
+            (GOOD_CRC_SNK, "7 PDOs, 65W source"),
+            (REQUEST_20V_3A, "20V 3A PDO requested"),
+            (ACCEPT, "20V 3A PDO accepted"),
+            (PS_RDY, "20V 3A PDO ready"),
+        ];
+        for (bytes, summary) in trace {
+            feed(&mut analyzer, bytes);
+            assert_eq!(analyzer.summary(), *summary);
+        }
+        assert!(!feed(&mut analyzer, GOOD_CRC_SNK));
+    }
+
+    #[test]
+    fn summarises_pps_and_epr_requests() {
+        let mut analyzer = Analyzer::new();
+        feed(&mut analyzer, SOURCE_CAPS_65W);
+        feed(&mut analyzer, REQUEST_PPS_9V5_2A);
+        assert!(feed(&mut analyzer, REJECT));
+        assert_eq!(analyzer.summary(), "PPS 9.5V 2A rejected");
+
+        feed(&mut analyzer, EPR_MODE_ENTER);
+        assert_eq!(analyzer.summary(), "EPR enter 140W");
+        feed(&mut analyzer, EPR_MODE_SUCCEEDED);
+        assert_eq!(analyzer.summary(), "EPR mode entered");
+        feed(&mut analyzer, EPR_SOURCE_CAPS_CHUNK0);
+        assert_eq!(analyzer.summary(), "EPR 100W source");
+        feed(&mut analyzer, EPR_REQUEST_28V);
+        feed(&mut analyzer, ACCEPT);
+        assert_eq!(analyzer.summary(), "28V 5A PDO accepted");
+        // AVS counts the voltage in 25 mV units, PPS in 20 mV
+        feed(&mut analyzer, EPR_REQUEST_AVS_36V);
+        assert_eq!(analyzer.summary(), "AVS 36V 3A requested");
+
+        analyzer.hard_reset();
+        assert_eq!(analyzer.summary(), "Hard reset");
+        // Accept without a known request keeps the summary
+        assert!(!feed(&mut analyzer, ACCEPT));
+    }
+}
@@ -0,0 +1,550 @@
+// core/src/pd/message.rs
+// USB Power Delivery message encoding / decoding (USB PD 3.1, chapter 6)
+//
+// Hardware agnostic: works on the raw bytes exchanged with the PHY (header
+// followed by the data objects, little-endian, without SOP and CRC).
+
+use heapless::Vec;
+
+/// A message carries at most 7 data objects
+pub const MAX_DATA_OBJECTS: usize = 7;
+pub const MAX_MESSAGE_LEN: usize = 2 + 4 * MAX_DATA_OBJECTS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    TooShort,
+    /// Length does not match the object count in the header
+    LengthMismatch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpecRevision {
+    R1_0,
+    R2_0,
+    R3_0,
+}
+
+impl SpecRevision {
+    fn from_bits(bits: u16) -> Self {
+        match bits {
+            0 => Self::R1_0,
+            1 => Self::R2_0,
+            // 3 is reserved, treat like the highest known revision
+            _ => Self::R3_0,
+        }
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            Self::R1_0 => 0,
+            Self::R2_0 => 1,
+            Self::R3_0 => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlType {
+    GoodCrc,
+    GotoMin,
+    Accept,
+    Reject,
+    Ping,
+    PsRdy,
+    GetSourceCap,
+    GetSinkCap,
+    DrSwap,
+    PrSwap,
+    VconnSwap,
+    Wait,
+    SoftReset,
+    DataReset,
+    DataResetComplete,
+    NotSupported,
+    GetSourceCapExtended,
+    GetStatus,
+    FrSwap,
+    GetPpsStatus,
+    GetCountryCodes,
+    GetSinkCapExtended,
+    GetSourceInfo,
+    GetRevision,
+    Other(u8),
+}
+
+impl ControlType {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::GoodCrc,
+            2 => Self::GotoMin,
+            3 => Self::Accept,
+            4 => Self::Reject,
+            5 => Self::Ping,
+            6 => Self::PsRdy,
+            7 => Self::GetSourceCap,
+            8 => Self::GetSinkCap,
+            9 => Self::DrSwap,
+            10 => Self::PrSwap,
+            11 => Self::VconnSwap,
+            12 => Self::Wait,
+            13 => Self::SoftReset,
+            14 => Self::DataReset,
+            15 => Self::DataResetComplete,
+            16 => Self::NotSupported,
+            17 => Self::GetSourceCapExtended,
+            18 => Self::GetStatus,
+            19 => Self::FrSwap,
+            20 => Self::GetPpsStatus,
+            21 => Self::GetCountryCodes,
+            22 => Self::GetSinkCapExtended,
+            23 => Self::GetSourceInfo,
+            24 => Self::GetRevision,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Self::GoodCrc => 1,
+            Self::GotoMin => 2,
+            Self::Accept => 3,
+            Self::Reject => 4,
+            Self::Ping => 5,
+            Self::PsRdy => 6,
+            Self::GetSourceCap => 7,
+            Self::GetSinkCap => 8,
+            Self::DrSwap => 9,
+            Self::PrSwap => 10,
+            Self::VconnSwap => 11,
+            Self::Wait => 12,
+            Self::SoftReset => 13,
+            Self::DataReset => 14,
+            Self::DataResetComplete => 15,
+            Self::NotSupported => 16,
+            Self::GetSourceCapExtended => 17,
+            Self::GetStatus => 18,
+            Self::FrSwap => 19,
+            Self::GetPpsStatus => 20,
+            Self::GetCountryCodes => 21,
+            Self::GetSinkCapExtended => 22,
+            Self::GetSourceInfo => 23,
+            Self::GetRevision => 24,
+            Self::Other(code) => code,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    SourceCapabilities,
+    Request,
+    Bist,
+    SinkCapabilities,
+    BatteryStatus,
+    Alert,
+    GetCountryInfo,
+    EnterUsb,
+    EprRequest,
+    EprMode,
+    SourceInfo,
+    Revision,
+    VendorDefined,
+    Other(u8),
+}
+
+impl DataType {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::SourceCapabilities,
+            2 => Self::Request,
+            3 => Self::Bist,
+            4 => Self::SinkCapabilities,
+            5 => Self::BatteryStatus,
+            6 => Self::Alert,
+            7 => Self::GetCountryInfo,
+            8 => Self::EnterUsb,
+            9 => Self::EprRequest,
+            10 => Self::EprMode,
+            11 => Self::SourceInfo,
+            12 => Self::Revision,
+            15 => Self::VendorDefined,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Self::SourceCapabilities => 1,
+            Self::Request => 2,
+            Self::Bist => 3,
+            Self::SinkCapabilities => 4,
+            Self::BatteryStatus => 5,
+            Self::Alert => 6,
+            Self::GetCountryInfo => 7,
+            Self::EnterUsb => 8,
+            Self::EprRequest => 9,
+            Self::EprMode => 10,
+            Self::SourceInfo => 11,
+            Self::Revision => 12,
+            Self::VendorDefined => 15,
+            Self::Other(code) => code,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Control(ControlType),
+    Data(DataType),
+    /// Extended messages are not used by the sink, only the type is kept
+    Extended(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub message_type: MessageType,
+    /// true = DFP
+    pub data_role_dfp: bool,
+    pub spec_revision: SpecRevision,
+    /// true = Source
+    pub power_role_source: bool,
+    pub message_id: u8,
+    pub object_count: u8,
+}
+
+impl Header {
+    /// Header for a message sent by this device (UFP, Sink)
+    pub fn sink(message_type: MessageType, spec_revision: SpecRevision, message_id: u8) -> Self {
+        Self {
+            message_type,
+            data_role_dfp: false,
+            spec_revision,
+            power_role_source: false,
+            message_id,
+            object_count: 0,
+        }
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        let code = (bits & 0x1F) as u8;
+        let object_count = ((bits >> 12) & 0x07) as u8;
+        let extended = bits & 0x8000 != 0;
+        let message_type = if extended {
+            MessageType::Extended(code)
+        } else if object_count == 0 {
+            MessageType::Control(ControlType::from_code(code))
+        } else {
+            MessageType::Data(DataType::from_code(code))
+        };
+        Self {
+            message_type,
+            data_role_dfp: bits & (1 << 5) != 0,
+            spec_revision: SpecRevision::from_bits((bits >> 6) & 0x03),
+            power_role_source: bits & (1 << 8) != 0,
+            message_id: ((bits >> 9) & 0x07) as u8,
+            object_count,
+        }
+    }
+
+    pub fn bits(&self) -> u16 {
+        let (code, extended) = match self.message_type {
+            MessageType::Control(t) => (t.code(), false),
+            MessageType::Data(t) => (t.code(), false),
+            MessageType::Extended(code) => (code, true),
+        };
+        (code as u16 & 0x1F)
+            | (self.data_role_dfp as u16) << 5
+            | self.spec_revision.bits() << 6
+            | (self.power_role_source as u16) << 8
+            | (self.message_id as u16 & 0x07) << 9
+            | (self.object_count as u16 & 0x07) << 12
+            | (extended as u16) << 15
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub header: Header,
+    pub objects: Vec<u32, MAX_DATA_OBJECTS>,
+}
+
+impl Message {
+    pub fn control(kind: ControlType, spec_revision: SpecRevision) -> Self {
+        Self {
+            header: Header::sink(MessageType::Control(kind), spec_revision, 0),
+            objects: Vec::new(),
+        }
+    }
+
+    pub fn data(kind: DataType, spec_revision: SpecRevision, objects: &[u32]) -> Self {
+        let mut message = Self {
+            header: Header::sink(MessageType::Data(kind), spec_revision, 0),
+            objects: Vec::new(),
+        };
+        let count = objects.len().min(MAX_DATA_OBJECTS);
+        let _ = message.objects.extend_from_slice(&objects[..count]);
+        message.header.object_count = count as u8;
+        message
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() < 2 {
+            return Err(ParseError::TooShort);
+        }
+        let header = Header::from_bits(u16::from_le_bytes([bytes[0], bytes[1]]));
+        let mut objects = Vec::new();
+
+        // Extended messages carry their own data size, objects are not decoded
+        if !matches!(header.message_type, MessageType::Extended(_)) {
+            let expected = 2 + 4 * header.object_count as usize;
+            if bytes.len() != expected {
+                return Err(ParseError::LengthMismatch);
+            }
+            for chunk in bytes[2..].chunks_exact(4) {
+                let _ = objects.push(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+            }
+        }
+
+        Ok(Self { header, objects })
+    }
+
+    /// Serialise into `buf`, returning the number of bytes written
+    pub fn encode(&self, buf: &mut [u8; MAX_MESSAGE_LEN]) -> usize {
+        let mut header = self.header;
+        header.object_count = self.objects.len() as u8;
+        buf[..2].copy_from_slice(&header.bits().to_le_bytes());
+        for (i, object) in self.objects.iter().enumerate() {
+            buf[2 + 4 * i..6 + 4 * i].copy_from_slice(&object.to_le_bytes());
+        }
+        2 + 4 * self.objects.len()
+    }
+
+    pub fn is_control(&self, kind: ControlType) -> bool {
+        self.header.message_type == MessageType::Control(kind)
+    }
+}
+
+/// Power Data Object advertised by a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pdo {
+    Fixed {
+        voltage_mv: u32,
+        max_current_ma: u32,
+        /// Only meaningful in the first PDO
+        unconstrained_power: bool,
+        epr_capable: bool,
+    },
+    Battery {
+        min_voltage_mv: u32,
+        max_voltage_mv: u32,
+        max_power_mw: u32,
+    },
+    Variable {
+        min_voltage_mv: u32,
+        max_voltage_mv: u32,
+        max_current_ma: u32,
+    },
+    /// SPR Programmable Power Supply
+    Pps {
+        min_voltage_mv: u32,
+        max_voltage_mv: u32,
+        max_current_ma: u32,
+        power_limited: bool,
+    },
+    /// EPR Adjustable Voltage Supply
+    EprAvs {
+        min_voltage_mv: u32,
+        max_voltage_mv: u32,
+        pdp_w: u32,
+    },
+    /// Other augmented PDOs (SPR AVS)
+    Augmented(u32),
+}
+
+impl Pdo {
+    pub fn parse(raw: u32) -> Self {
+        match raw >> 30 {
+            0b00 => Self::Fixed {
+                voltage_mv: ((raw >> 10) & 0x3FF) * 50,
+                max_current_ma: (raw & 0x3FF) * 10,
+                unconstrained_power: raw & (1 << 27) != 0,
+                epr_capable: raw & (1 << 23) != 0,
+            },
+            0b01 => Self::Battery {
+                max_voltage_mv: ((raw >> 20) & 0x3FF) * 50,
+                min_voltage_mv: ((raw >> 10) & 0x3FF) * 50,
+                max_power_mw: (raw & 0x3FF) * 250,
+            },
+            0b10 => Self::Variable {
+                max_voltage_mv: ((raw >> 20) & 0x3FF) * 50,
+                min_voltage_mv: ((raw >> 10) & 0x3FF) * 50,
+                max_current_ma: (raw & 0x3FF) * 10,
+            },
+            _ if (raw >> 28) & 0x03 == 0b00 => Self::Pps {
+                max_voltage_mv: ((raw >> 17) & 0xFF) * 100,
+                min_voltage_mv: ((raw >> 8) & 0xFF) * 100,
+                max_current_ma: (raw & 0x7F) * 50,
+                power_limited: raw & (1 << 27) != 0,
+            },
+            _ if (raw >> 28) & 0x03 == 0b01 => Self::EprAvs {
+                max_voltage_mv: ((raw >> 17) & 0x1FF) * 100,
+                min_voltage_mv: ((raw >> 8) & 0xFF) * 100,
+                pdp_w: raw & 0xFF,
+            },
+            _ => Self::Augmented(raw),
+        }
+    }
+
+    /// Sink side fixed PDO, used in Sink_Capabilities
+    pub fn fixed_sink(voltage_mv: u32, current_ma: u32) -> u32 {
+        ((voltage_mv / 50) & 0x3FF) << 10 | ((current_ma / 10) & 0x3FF)
+    }
+
+    /// Sink side PPS APDO, used in Sink_Capabilities
+    pub fn pps_sink(min_voltage_mv: u32, max_voltage_mv: u32, current_ma: u32) -> u32 {
+        0b11 << 30
+            | ((max_voltage_mv / 100) & 0xFF) << 17
+            | ((min_voltage_mv / 100) & 0xFF) << 8
+            | ((current_ma / 50) & 0x7F)
+    }
+}
+
+/// Request Data Object for a fixed / variable supply
+pub fn fixed_request(position: u8, current_ma: u32, max_current_ma: u32, usb_comm: bool) -> u32 {
+    ((position as u32) & 0x0F) << 28
+        | (usb_comm as u32) << 25
+        // No USB Suspend: the hub keeps drawing power while the host sleeps
+        | 1 << 24
+        | ((current_ma / 10) & 0x3FF) << 10
+        | ((max_current_ma / 10) & 0x3FF)
+}
+
+/// Request Data Object for a Programmable Power Supply
+pub fn pps_request(position: u8, voltage_mv: u32, current_ma: u32, usb_comm: bool) -> u32 {
+    ((position as u32) & 0x0F) << 28
+        | (usb_comm as u32) << 25
+        | 1 << 24
+        | ((voltage_mv / 20) & 0xFFF) << 9
+        | ((current_ma / 50) & 0x7F)
+}
+
+/// Object position (1-based) of a Request Data Object
+pub fn request_position(rdo: u32) -> u8 {
+    ((rdo >> 28) & 0x0F) as u8
+}
+
+/// Operating current of a fixed / variable Request Data Object
+pub fn fixed_request_current_ma(rdo: u32) -> u32 {
+    ((rdo >> 10) & 0x3FF) * 10
+}
+
+/// Output voltage and operating current of a PPS Request Data Object
+pub fn pps_request_output(rdo: u32) -> (u32, u32) {
+    (((rdo >> 9) & 0xFFF) * 20, (rdo & 0x7F) * 50)
+}
+
+/// Output voltage and operating current of an EPR AVS Request Data Object,
+/// the voltage counts in 25 mV units there
+pub fn avs_request_output(rdo: u32) -> (u32, u32) {
+    (((rdo >> 9) & 0xFFF) * 25, (rdo & 0x7F) * 50)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Source_Capabilities captured from a 65 W GaN charger:
+    // 5V/3A, 9V/3A, 12V/3A, 15V/3A, 20V/3.25A, PPS 3.3-11V/5A, PPS 3.3-21V/3.25A
+    pub const SOURCE_CAPS_65W: [u8; 30] = [
+        0xA1, 0x71, 0x2C, 0x91, 0x01, 0x08, 0x2C, 0xD1, 0x02, 0x00, 0x2C, 0xC1, 0x03, 0x00, 0x2C,
+        0xB1, 0x04, 0x00, 0x45, 0x41, 0x06, 0x00, 0x64, 0x21, 0xDC, 0xC0, 0x41, 0x21, 0xA4, 0xC1,
+    ];
+
+    #[test]
+    fn parses_header() {
+        let header = Header::from_bits(0x71A1);
+        assert_eq!(
+            header.message_type,
+            MessageType::Data(DataType::SourceCapabilities)
+        );
+        assert_eq!(header.spec_revision, SpecRevision::R3_0);
+        assert!(header.power_role_source);
+        assert!(header.data_role_dfp);
+        assert_eq!(header.message_id, 0);
+        assert_eq!(header.object_count, 7);
+        assert_eq!(header.bits(), 0x71A1);
+    }
+
+    #[test]
+    fn parses_source_capabilities() {
+        let message = Message::parse(&SOURCE_CAPS_65W).unwrap();
+        let pdos: Vec<Pdo, 7> = message.objects.iter().map(|raw| Pdo::parse(*raw)).collect();
+
+        assert_eq!(
+            pdos[0],
+            Pdo::Fixed {
+                voltage_mv: 5000,
+                max_current_ma: 3000,
+                unconstrained_power: true,
+                epr_capable: false,
+            }
+        );
+        assert!(matches!(
+            pdos[4],
+            Pdo::Fixed {
+                voltage_mv: 20000,
+                max_current_ma: 3250,
+                ..
+            }
+        ));
+        assert_eq!(
+            pdos[6],
+            Pdo::Pps {
+                min_voltage_mv: 3300,
+                max_voltage_mv: 21000,
+                max_current_ma: 3250,
+                power_limited: false,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_message() {
+        assert_eq!(
+            Message::parse(&SOURCE_CAPS_65W[..20]),
+            Err(ParseError::LengthMismatch)
+        );
+        assert_eq!(Message::parse(&[0x41]), Err(ParseError::TooShort));
+    }
+
+    #[test]
+    fn encodes_request() {
+        let mut message = Message::data(
+            DataType::Request,
+            SpecRevision::R3_0,
+            &[fixed_request(5, 3000, 3000, false)],
+        );
+        message.header.message_id = 1;
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let len = message.encode(&mut buf);
+        assert_eq!(&buf[..len], &[0x82, 0x12, 0x2C, 0xB1, 0x04, 0x51]);
+        assert_eq!(Message::parse(&buf[..len]).unwrap(), message);
+    }
+
+    #[test]
+    fn encodes_pps_request() {
+        let rdo = pps_request(7, 9000, 2000, false);
+        assert_eq!(request_position(rdo), 7);
+        assert_eq!((rdo >> 9) & 0xFFF, 450);
+        assert_eq!(rdo & 0x7F, 40);
+        assert_eq!(pps_request_output(rdo), (9000, 2000));
+    }
+
+    #[test]
+    fn decodes_avs_request_output() {
+        // 36 V / 3 A from the AVS APDO at position 9
+        let rdo = 0x904B_403C;
+        assert_eq!(request_position(rdo), 9);
+        assert_eq!(avs_request_output(rdo), (36_000, 3000));
+    }
+}
@@ -0,0 +1,12 @@
+// core/src/pd/mod.rs
+// USB Power Delivery sink, without the UCPD peripheral
+//
+// `message`, `protocol`, `policy` and `decode` are tested against recorded
+// traces; `trace` publishes every message for the sniffer views. The
+// firmware's `pd::ucpd` binds them to UCPD1.
+
+pub mod decode;
+pub mod message;
+pub mod policy;
+pub mod protocol;
+pub mod trace;
@@ -0,0 +1,518 @@
+// core/src/pd/policy.rs
+// Sink policy engine (USB PD 3.1, 8.3.3.3), SPR only
+//
+// Pure state machine driven by received messages and a millisecond clock.
+// It picks the best source PDO for the hub input (fixed or PPS) and keeps
+// the contract alive; the caller owns the PHY and executes the `Action`s.
+
+use heapless::Vec;
+
+use super::message::{
+    self, ControlType, DataType, MAX_DATA_OBJECTS, Message, MessageType, Pdo, SpecRevision,
+};
+
+// Timer values in ms, mid-range of the spec limits
+const SINK_WAIT_CAP_MS: u64 = 465;
+const SENDER_RESPONSE_MS: u64 = 30;
+const PS_TRANSITION_MS: u64 = 500;
+const SINK_REQUEST_MS: u64 = 100;
+// A PPS contract is lost when no Request arrives within 15 s
+const PPS_REFRESH_MS: u64 = 8_000;
+// Source needs up to tSrcRecover + tSrcTurnOn after a Hard Reset
+const HARD_RESET_RECOVERY_MS: u64 = 1_500;
+const HARD_RESET_LIMIT: u8 = 2;
+
+/// vSafe5V is always the first PDO
+const SAFE_5V_POSITION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SinkConfig {
+    pub min_voltage_mv: u32,
+    pub max_voltage_mv: u32,
+    pub max_current_ma: u32,
+    pub use_pps: bool,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        Self {
+            min_voltage_mv: 5_000,
+            max_voltage_mv: 20_000,
+            max_current_ma: 3_000,
+            use_pps: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Contract {
+    /// 1-based object position in the source capabilities
+    pub position: u8,
+    pub pdo: Pdo,
+    pub voltage_mv: u32,
+    pub current_ma: u32,
+}
+
+impl Contract {
+    pub fn power_mw(&self) -> u32 {
+        self.voltage_mv * self.current_ma / 1000
+    }
+
+    pub fn is_pps(&self) -> bool {
+        matches!(self.pdo, Pdo::Pps { .. })
+    }
+
+    fn request_object(&self) -> u32 {
+        if self.is_pps() {
+            message::pps_request(self.position, self.voltage_mv, self.current_ma, false)
+        } else {
+            message::fixed_request(self.position, self.current_ma, self.current_ma, false)
+        }
+    }
+}
+
+/// Highest power PDO within `config`; on a tie fixed PDOs win over PPS
+pub fn select_pdo(capabilities: &[Pdo], config: &SinkConfig) -> Option<Contract> {
+    let mut best: Option<Contract> = None;
+
+    for (index, pdo) in capabilities.iter().enumerate() {
+        let candidate = match *pdo {
+            Pdo::Fixed {
+                voltage_mv,
+                max_current_ma,
+                ..
+            } if (config.min_voltage_mv..=config.max_voltage_mv).contains(&voltage_mv) => {
+                (voltage_mv, max_current_ma.min(config.max_current_ma))
+            }
+            Pdo::Pps {
+                min_voltage_mv,
+                max_voltage_mv,
+                max_current_ma,
+                ..
+            } if config.use_pps
+                && min_voltage_mv <= config.max_voltage_mv
+                && max_voltage_mv >= config.min_voltage_mv =>
+            {
+                (
+                    max_voltage_mv.min(config.max_voltage_mv),
+                    max_current_ma.min(config.max_current_ma),
+                )
+            }
+            _ => continue,
+        };
+
+        let contract = Contract {
+            position: index as u8 + 1,
+            pdo: *pdo,
+            voltage_mv: candidate.0,
+            current_ma: candidate.1,
+        };
+        let better = match &best {
+            None => true,
+            Some(best) => contract.power_mw() > best.power_mw(),
+        };
+        if better {
+            best = Some(contract);
+        }
+    }
+
+    best
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Send(Message),
+    HardReset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Detached,
+    WaitCapabilities,
+    WaitAccept,
+    WaitPsRdy,
+    Ready,
+    /// No PD source after repeated Hard Resets, running on Type-C current
+    Unresponsive,
+}
+
+/// Snapshot published for the display and USB functions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PdStatus {
+    pub state: State,
+    pub source_capabilities: Vec<Pdo, MAX_DATA_OBJECTS>,
+    pub contract: Option<Contract>,
+}
+
+pub struct SinkPolicy {
+    config: SinkConfig,
+    state: State,
+    deadline: Option<u64>,
+    spec_revision: SpecRevision,
+    capabilities: Vec<Pdo, MAX_DATA_OBJECTS>,
+    pending: Option<Contract>,
+    contract: Option<Contract>,
+    hard_reset_count: u8,
+}
+
+impl SinkPolicy {
+    pub fn new(config: SinkConfig) -> Self {
+        Self {
+            config,
+            state: State::Detached,
+            deadline: None,
+            spec_revision: SpecRevision::R3_0,
+            capabilities: Vec::new(),
+            pending: None,
+            contract: None,
+            hard_reset_count: 0,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn contract(&self) -> Option<&Contract> {
+        self.contract.as_ref()
+    }
+
+    pub fn source_capabilities(&self) -> &[Pdo] {
+        &self.capabilities
+    }
+
+    pub fn status(&self) -> PdStatus {
+        PdStatus {
+            state: self.state,
+            source_capabilities: self.capabilities.clone(),
+            contract: self.contract,
+        }
+    }
+
+    /// Absolute time at which `poll` needs to run next
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    pub fn attach(&mut self, now_ms: u64) {
+        *self = Self::new(self.config);
+        self.wait_capabilities(now_ms + SINK_WAIT_CAP_MS);
+    }
+
+    pub fn detach(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    /// The source signalled a Hard Reset, VBUS goes back to vSafe5V
+    pub fn hard_reset_received(&mut self, now_ms: u64) {
+        self.contract = None;
+        self.wait_capabilities(now_ms + HARD_RESET_RECOVERY_MS);
+    }
+
+    pub fn handle(&mut self, message: &Message, now_ms: u64) -> Action {
+        match message.header.message_type {
+            MessageType::Data(DataType::SourceCapabilities) => {
+                self.on_source_capabilities(message, now_ms)
+            }
+            MessageType::Control(ControlType::Accept) if self.state == State::WaitAccept => {
+                self.state = State::WaitPsRdy;
+                self.deadline = Some(now_ms + PS_TRANSITION_MS);
+                Action::None
+            }
+            MessageType::Control(ControlType::Reject) if self.state == State::WaitAccept => {
+                if self.contract.is_some() {
+                    // Keep the previous contract
+                    self.ready(now_ms);
+                    Action::None
+                } else {
+                    self.request_safe_5v(now_ms)
+                }
+            }
+            MessageType::Control(ControlType::Wait) if self.state == State::WaitAccept => {
+                // Keep the previous contract and ask again later
+                self.state = State::Ready;
+                self.deadline = Some(now_ms + SINK_REQUEST_MS);
+                Action::None
+            }
+            MessageType::Control(ControlType::PsRdy) if self.state == State::WaitPsRdy => {
+                self.contract = self.pending.take();
+                self.hard_reset_count = 0;
+                self.ready(now_ms);
+                Action::None
+            }
+            MessageType::Control(ControlType::GetSinkCap) => Action::Send(self.sink_capabilities()),
+            MessageType::Control(ControlType::SoftReset) => {
+                self.wait_capabilities(now_ms + SINK_WAIT_CAP_MS);
+                Action::Send(Message::control(ControlType::Accept, self.spec_revision))
+            }
+            MessageType::Control(ControlType::GoodCrc | ControlType::Ping) => Action::None,
+            // Structured VDMs are only answered by the DFP; unstructured ones are ignored
+            MessageType::Data(DataType::VendorDefined) => Action::None,
+            _ if self.state == State::Detached => Action::None,
+            _ => Action::Send(self.not_supported()),
+        }
+    }
+
+    /// Run expired timers
+    pub fn poll(&mut self, now_ms: u64) -> Action {
+        match self.deadline {
+            Some(deadline) if now_ms >= deadline => {}
+            _ => return Action::None,
+        }
+        self.deadline = None;
+
+        match self.state {
+            State::WaitCapabilities | State::WaitAccept | State::WaitPsRdy => {
+                if self.hard_reset_count < HARD_RESET_LIMIT {
+                    self.hard_reset_count += 1;
+                    self.hard_reset_received(now_ms);
+                    Action::HardReset
+                } else {
+                    self.state = State::Unresponsive;
+                    Action::None
+                }
+            }
+            // Wait retry or PPS keep-alive
+            State::Ready => match self.contract.or(self.pending) {
+                Some(contract) => self.request(contract, now_ms),
+                None => Action::None,
+            },
+            State::Detached | State::Unresponsive => Action::None,
+        }
+    }
+
+    fn on_source_capabilities(&mut self, message: &Message, now_ms: u64) -> Action {
+        self.spec_revision = message
+            .header
+            .spec_revision
+            .clamp(SpecRevision::R2_0, SpecRevision::R3_0);
+        self.capabilities = message.objects.iter().map(|raw| Pdo::parse(*raw)).collect();
+
+        match select_pdo(&self.capabilities, &self.config) {
+            Some(contract) => self.request(contract, now_ms),
+            None => self.request_safe_5v(now_ms),
+        }
+    }
+
+    fn request(&mut self, contract: Contract, now_ms: u64) -> Action {
+        self.pending = Some(contract);
+        self.state = State::WaitAccept;
+        self.deadline = Some(now_ms + SENDER_RESPONSE_MS);
+        Action::Send(Message::data(
+            DataType::Request,
+            self.spec_revision,
+            &[contract.request_object()],
+        ))
+    }
+
+    fn request_safe_5v(&mut self, now_ms: u64) -> Action {
+        match self.capabilities.first() {
+            Some(pdo @ Pdo::Fixed { max_current_ma, .. }) => {
+                let contract = Contract {
+                    position: SAFE_5V_POSITION,
+                    pdo: *pdo,
+                    voltage_mv: 5_000,
+                    current_ma: (*max_current_ma).min(self.config.max_current_ma),
+                };
+                self.request(contract, now_ms)
+            }
+            _ => {
+                self.wait_capabilities(now_ms + SINK_WAIT_CAP_MS);
+                Action::None
+            }
+        }
+    }
+
+    fn ready(&mut self, now_ms: u64) {
+        self.state = State::Ready;
+        self.deadline = match self.contract {
+            Some(contract) if contract.is_pps() => Some(now_ms + PPS_REFRESH_MS),
+            _ => None,
+        };
+    }
+
+    fn wait_capabilities(&mut self, deadline: u64) {
+        self.state = State::WaitCapabilities;
+        self.pending = None;
+        self.deadline = Some(deadline);
+    }
+
+    fn sink_capabilities(&self) -> Message {
+        let mut objects: Vec<u32, 2> = Vec::new();
+        let _ = objects.push(Pdo::fixed_sink(5_000, self.config.max_current_ma));
+        if self.config.use_pps && self.spec_revision >= SpecRevision::R3_0 {
+            let _ = objects.push(Pdo::pps_sink(
+                self.config.min_voltage_mv,
+                self.config.max_voltage_mv,
+                self.config.max_current_ma,
+            ));
+        }
+        Message::data(DataType::SinkCapabilities, self.spec_revision, &objects)
+    }
+
+    fn not_supported(&self) -> Message {
+        let kind = if self.spec_revision >= SpecRevision::R3_0 {
+            ControlType::NotSupported
+        } else {
+            ControlType::Reject
+        };
+        Message::control(kind, self.spec_revision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pd::message::MAX_MESSAGE_LEN;
+
+    // Recorded from a 65 W charger (PD 3.0), see message::tests
+    const SOURCE_CAPS_65W: [u8; 30] = [
+        0xA1, 0x71, 0x2C, 0x91, 0x01, 0x08, 0x2C, 0xD1, 0x02, 0x00, 0x2C, 0xC1, 0x03, 0x00, 0x2C,
+        0xB1, 0x04, 0x00, 0x45, 0x41, 0x06, 0x00, 0x64, 0x21, 0xDC, 0xC0, 0x41, 0x21, 0xA4, 0xC1,
+    ];
+    // Recorded from a 5 V / 9 V only PD 2.0 phone charger: 5V/2A, 9V/2A
+    const SOURCE_CAPS_18W: [u8; 10] = [0x61, 0x21, 0xC8, 0x90, 0x01, 0x00, 0xC8, 0xD0, 0x02, 0x00];
+    const ACCEPT: [u8; 2] = [0xA3, 0x03];
+    const PS_RDY: [u8; 2] = [0xA6, 0x05];
+    const WAIT: [u8; 2] = [0xAC, 0x03];
+    const GET_SINK_CAP: [u8; 2] = [0xA8, 0x07];
+
+    fn rx(policy: &mut SinkPolicy, bytes: &[u8], now_ms: u64) -> Action {
+        policy.handle(&Message::parse(bytes).unwrap(), now_ms)
+    }
+
+    fn sent(action: Action) -> ([u8; MAX_MESSAGE_LEN], usize) {
+        let Action::Send(message) = action else {
+            panic!("expected a message, got {:?}", action);
+        };
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let len = message.encode(&mut buf);
+        (buf, len)
+    }
+
+    #[test]
+    fn negotiates_20v_from_65w_charger() {
+        let mut policy = SinkPolicy::new(SinkConfig::default());
+        policy.attach(0);
+
+        // Request PDO 5 (20 V), 3.25 A capped to 3 A
+        let (buf, len) = sent(rx(&mut policy, &SOURCE_CAPS_65W, 100));
+        assert_eq!(&buf[..len], &[0x82, 0x10, 0x2C, 0xB1, 0x04, 0x51]);
+        assert_eq!(policy.state(), State::WaitAccept);
+
+        assert_eq!(rx(&mut policy, &ACCEPT, 105), Action::None);
+        assert_eq!(policy.state(), State::WaitPsRdy);
+        assert_eq!(policy.contract(), None);
+
+        assert_eq!(rx(&mut policy, &PS_RDY, 300), Action::None);
+        let contract = policy.contract().unwrap();
+        assert_eq!(contract.position, 5);
+        assert_eq!((contract.voltage_mv, contract.current_ma), (20_000, 3_000));
+        assert_eq!(policy.deadline(), None);
+        assert_eq!(policy.source_capabilities().len(), 7);
+    }
+
+    #[test]
+    fn prefers_pps_when_it_delivers_more_power() {
+        let config = SinkConfig {
+            max_voltage_mv: 10_000,
+            ..SinkConfig::default()
+        };
+        let mut policy = SinkPolicy::new(config);
+        policy.attach(0);
+
+        // PPS 3.3-11 V at 10 V / 3 A beats fixed 9 V / 3 A
+        let (buf, len) = sent(rx(&mut policy, &SOURCE_CAPS_65W, 100));
+        let request = Message::parse(&buf[..len]).unwrap();
+        assert_eq!(message::request_position(request.objects[0]), 6);
+
+        rx(&mut policy, &ACCEPT, 105);
+        rx(&mut policy, &PS_RDY, 200);
+        assert!(policy.contract().unwrap().is_pps());
+
+        // PPS contracts must be refreshed periodically
+        assert_eq!(policy.deadline(), Some(200 + PPS_REFRESH_MS));
+        assert_eq!(policy.poll(5_000), Action::None);
+        let (buf, len) = sent(policy.poll(200 + PPS_REFRESH_MS));
+        assert_eq!(
+            Message::parse(&buf[..len]).unwrap().objects,
+            request.objects
+        );
+        assert_eq!(policy.state(), State::WaitAccept);
+    }
+
+    #[test]
+    fn respects_voltage_limit_with_pd2_source() {
+        let config = SinkConfig {
+            max_voltage_mv: 5_000,
+            ..SinkConfig::default()
+        };
+        let mut policy = SinkPolicy::new(config);
+        policy.attach(0);
+
+        // PD 2.0 source: request 5 V / 2 A with a 2.0 header
+        let (buf, len) = sent(rx(&mut policy, &SOURCE_CAPS_18W, 50));
+        assert_eq!(&buf[..len], &[0x42, 0x10, 0xC8, 0x20, 0x03, 0x11]);
+    }
+
+    #[test]
+    fn retries_after_wait() {
+        let mut policy = SinkPolicy::new(SinkConfig::default());
+        policy.attach(0);
+        let (first, len) = sent(rx(&mut policy, &SOURCE_CAPS_65W, 10));
+
+        assert_eq!(rx(&mut policy, &WAIT, 12), Action::None);
+        assert_eq!(policy.poll(50), Action::None);
+        let (retry, retry_len) = sent(policy.poll(12 + SINK_REQUEST_MS));
+        assert_eq!(&retry[..retry_len], &first[..len]);
+    }
+
+    #[test]
+    fn hard_resets_without_capabilities_then_gives_up() {
+        let mut policy = SinkPolicy::new(SinkConfig::default());
+        policy.attach(0);
+
+        for _ in 0..HARD_RESET_LIMIT {
+            let now = policy.deadline().unwrap();
+            assert_eq!(policy.poll(now), Action::HardReset);
+        }
+        let now = policy.deadline().unwrap();
+        assert_eq!(policy.poll(now), Action::None);
+        assert_eq!(policy.state(), State::Unresponsive);
+    }
+
+    #[test]
+    fn hard_resets_when_ps_rdy_is_missing() {
+        let mut policy = SinkPolicy::new(SinkConfig::default());
+        policy.attach(0);
+        rx(&mut policy, &SOURCE_CAPS_65W, 10);
+        rx(&mut policy, &ACCEPT, 12);
+        assert_eq!(policy.poll(12 + PS_TRANSITION_MS), Action::HardReset);
+        assert_eq!(policy.state(), State::WaitCapabilities);
+    }
+
+    #[test]
+    fn answers_sink_capabilities() {
+        let mut policy = SinkPolicy::new(SinkConfig::default());
+        policy.attach(0);
+        rx(&mut policy, &SOURCE_CAPS_65W, 10);
+
+        let Action::Send(reply) = rx(&mut policy, &GET_SINK_CAP, 20) else {
+            panic!("no reply");
+        };
+        assert_eq!(
+            reply.header.message_type,
+            MessageType::Data(DataType::SinkCapabilities)
+        );
+        assert_eq!(
+            Pdo::parse(reply.objects[0]),
+            Pdo::Fixed {
+                voltage_mv: 5_000,
+                max_current_ma: 3_000,
+                unconstrained_power: false,
+                epr_capable: false,
+            }
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// core/src/pd/protocol.rs
+// PD protocol layer bookkeeping: MessageID counters and GoodCRC replies
+//
+// The UCPD peripheral does not acknowledge messages by itself, so every
+// received message is answered with a GoodCRC carrying its MessageID and
+// every transmitted message waits for the GoodCRC of the partner.
+
+use super::message::{ControlType, Message, SpecRevision};
+
+// nRetryCount for PD 3.0
+pub const RETRY_COUNT: u8 = 2;
+
+#[derive(Default)]
+pub struct ProtocolLayer {
+    tx_message_id: u8,
+    last_rx_message_id: Option<u8>,
+    retries: u8,
+}
+
+impl ProtocolLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Soft / hard reset and detach clear both counters
+    pub fn reset(&mut self) {
+        self.tx_message_id = 0;
+        self.last_rx_message_id = None;
+    }
+
+    /// Stamp an outgoing message with the current MessageID
+    pub fn prepare(&mut self, message: &mut Message) {
+        message.header.message_id = self.tx_message_id;
+        self.retries = 0;
+    }
+
+    /// The partner acknowledged our message
+    pub fn is_ack(&self, message: &Message) -> bool {
+        message.is_control(ControlType::GoodCrc) && message.header.message_id == self.tx_message_id
+    }
+
+    pub fn transmitted(&mut self) {
+        self.tx_message_id = (self.tx_message_id + 1) & 0x07;
+    }
+
+    /// No GoodCRC for the prepared message: true while another attempt is
+    /// allowed. Once the retries are used up the MessageID advances anyway,
+    /// as if the message had been acknowledged.
+    pub fn retry(&mut self) -> bool {
+        if self.retries < RETRY_COUNT {
+            self.retries += 1;
+            true
+        } else {
+            self.transmitted();
+            false
+        }
+    }
+
+    /// GoodCRC to send back for `received`
+    pub fn good_crc(&self, received: &Message) -> Message {
+        let revision = received.header.spec_revision.min(SpecRevision::R3_0);
+        let mut ack = Message::control(ControlType::GoodCrc, revision);
+        ack.header.message_id = received.header.message_id;
+        ack
+    }
+
+    /// Returns false for a retransmission of the previous message, which
+    /// has to be acknowledged again but not processed twice. A Soft_Reset
+    /// always starts over.
+    pub fn accept(&mut self, received: &Message) -> bool {
+        if received.is_control(ControlType::SoftReset) {
+            self.reset();
+        } else if self.last_rx_message_id == Some(received.header.message_id) {
+            return false;
+        }
+        self.last_rx_message_id = Some(received.header.message_id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pd::message::DataType;
+
+    fn request(message_id: u8) -> Message {
+        let mut message = Message::data(DataType::Request, SpecRevision::R3_0, &[0x1304_B12C]);
+        message.header.message_id = message_id;
+        message
+    }
+
+    fn good_crc(message_id: u8) -> Message {
+        let mut ack = Message::control(ControlType::GoodCrc, SpecRevision::R3_0);
+        ack.header.message_id = message_id;
+        ack
+    }
+
+    #[test]
+    fn discards_a_repeated_message_id() {
+        let mut protocol = ProtocolLayer::new();
+        assert!(protocol.accept(&request(3)));
+        assert!(!protocol.accept(&request(3)));
+        assert!(protocol.accept(&request(4)));
+
+        // The retransmission is still acknowledged with its own MessageID
+        assert_eq!(protocol.good_crc(&request(4)).header.message_id, 4);
+    }
+
+    #[test]
+    fn soft_reset_is_always_accepted() {
+        let mut protocol = ProtocolLayer::new();
+        assert!(protocol.accept(&request(0)));
+        let soft_reset = Message::control(ControlType::SoftReset, SpecRevision::R3_0);
+        assert!(protocol.accept(&soft_reset));
+        assert!(protocol.accept(&soft_reset));
+    }
+
+    #[test]
+    fn gives_up_after_the_retry_count() {
+        let mut protocol = ProtocolLayer::new();
+        let mut message = request(0);
+        protocol.prepare(&mut message);
+        for _ in 0..RETRY_COUNT {
+            assert!(protocol.retry());
+        }
+        assert!(!protocol.retry());
+
+        // The next message gets the next MessageID and a fresh set of retries
+        let mut next = request(0);
+        protocol.prepare(&mut next);
+        assert_eq!(next.header.message_id, 1);
+        assert!(protocol.retry());
+    }
+
+    #[test]
+    fn message_id_rolls_over_after_seven() {
+        let mut protocol = ProtocolLayer::new();
+        for id in 0..8 {
+            let mut message = request(0);
+            protocol.prepare(&mut message);
+            assert_eq!(message.header.message_id, id);
+            assert!(protocol.is_ack(&good_crc(id)));
+            assert!(!protocol.is_ack(&good_crc((id + 1) & 0x07)));
+            protocol.transmitted();
+        }
+        let mut message = request(0);
+        protocol.prepare(&mut message);
+        assert_eq!(message.header.message_id, 0);
+
+        protocol.reset();
+        assert!(protocol.accept(&request(7)));
+        assert!(protocol.accept(&request(0)));
+    }
+}
@@ -0,0 +1,54 @@
+// core/src/pd/trace.rs
+// Timestamped record of everything seen or sent on the CC line
+//
+// Records are published on `shared::PD_TRACE`; the display, the telemetry
+// stream and the console decode them on their own.
+
+use embassy_time::Instant;
+
+use super::message::MAX_MESSAGE_LEN;
+use crate::shared::PD_TRACE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TraceEvent {
+    Received = 0,
+    Transmitted = 1,
+    HardResetReceived = 2,
+    HardResetSent = 3,
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceRecord {
+    pub timestamp_us: u64,
+    pub event: TraceEvent,
+    len: u8,
+    bytes: [u8; MAX_MESSAGE_LEN],
+}
+
+impl TraceRecord {
+    /// Messages longer than `MAX_MESSAGE_LEN` are cut short
+    pub fn new(timestamp_us: u64, event: TraceEvent, message: &[u8]) -> Self {
+        let len = message.len().min(MAX_MESSAGE_LEN);
+        let mut bytes = [0; MAX_MESSAGE_LEN];
+        bytes[..len].copy_from_slice(&message[..len]);
+        Self {
+            timestamp_us,
+            event,
+            len: len as u8,
+            bytes,
+        }
+    }
+
+    /// Raw message (header first, no CRC); empty for hard resets
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// Publish a record; slow subscribers lose the oldest ones
+pub fn record(event: TraceEvent, message: &[u8]) {
+    PD_TRACE
+        .immediate_publisher()
+        .publish_immediate(TraceRecord::new(Instant::now().as_micros(), event, message));
+}
@@ -0,0 +1,148 @@
+// core/src/protocol.rs
+// Binary frame format used by the USB telemetry and HID functions
+//
+// Frame layout (all integers little-endian):
+//
+//   SYNC (0xA5) | kind | seq | len | payload[len] | checksum
+//
+// `checksum` is the bitwise NOT of the 8-bit sum of `kind`, `seq`, `len`
+// and the payload bytes.
+
+use heapless::Vec;
+
+use crate::pd::trace::TraceRecord;
+use crate::shared::PortReadings;
+
+pub const FRAME_SYNC: u8 = 0xA5;
+pub const FRAME_OVERHEAD: usize = 5;
+/// One frame always fits into a single full-speed bulk/interrupt packet
+pub const MAX_FRAME_LEN: usize = 64;
+pub const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - FRAME_OVERHEAD;
+
+pub type Frame = Vec<u8, MAX_FRAME_LEN>;
+/// HID input report: one frame, zero padded to the fixed report length
+pub type Report = [u8; MAX_FRAME_LEN];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// u32 uptime ms, then per port: i32 mV, i32 mA, i32 mW
+    Readings = 0x01,
+    /// u32 timestamp us, u8 event (see `TraceEvent`), raw PD message
+    PdMessage = 0x02,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    PayloadTooLong,
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+pub fn encode_frame(kind: FrameKind, seq: u8, payload: &[u8]) -> Result<Frame, FrameError> {
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(FrameError::PayloadTooLong);
+    }
+
+    let mut frame = Frame::new();
+    // Capacity is checked above, the pushes below cannot fail
+    let _ = frame.push(FRAME_SYNC);
+    let _ = frame.push(kind as u8);
+    let _ = frame.push(seq);
+    let _ = frame.push(payload.len() as u8);
+    let _ = frame.extend_from_slice(payload);
+    let sum = checksum(&frame[1..]);
+    let _ = frame.push(sum);
+    Ok(frame)
+}
+
+fn milli(value: f32) -> i32 {
+    libm::roundf(value * 1000.0) as i32
+}
+
+pub fn encode_readings(seq: u8, uptime_ms: u32, readings: &PortReadings) -> Frame {
+    let mut payload: Vec<u8, MAX_PAYLOAD_LEN> = Vec::new();
+    let _ = payload.extend_from_slice(&uptime_ms.to_le_bytes());
+    for (voltage, current, power) in readings {
+        let _ = payload.extend_from_slice(&milli(*voltage).to_le_bytes());
+        let _ = payload.extend_from_slice(&milli(*current).to_le_bytes());
+        let _ = payload.extend_from_slice(&milli(*power).to_le_bytes());
+    }
+    // 4 + 3 * 12 = 40 bytes, always below MAX_PAYLOAD_LEN
+    encode_frame(FrameKind::Readings, seq, &payload).unwrap_or_default()
+}
+
+pub fn encode_pd_message(seq: u8, record: &TraceRecord) -> Frame {
+    let mut payload: Vec<u8, MAX_PAYLOAD_LEN> = Vec::new();
+    // Wraps after ~71 minutes, hosts unwrap it against the previous frame
+    let _ = payload.extend_from_slice(&(record.timestamp_us as u32).to_le_bytes());
+    let _ = payload.push(record.event as u8);
+    let _ = payload.extend_from_slice(record.bytes());
+    // 5 + 30 bytes at most, always below MAX_PAYLOAD_LEN
+    encode_frame(FrameKind::PdMessage, seq, &payload).unwrap_or_default()
+}
+
+pub fn encode_report(frame: &Frame) -> Report {
+    let mut report = [0; MAX_FRAME_LEN];
+    report[..frame.len()].copy_from_slice(frame);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pd::trace::TraceEvent;
+
+    #[test]
+    fn frames_carry_header_payload_and_checksum() {
+        let frame = encode_frame(FrameKind::PdMessage, 7, &[0x10, 0x20]).unwrap();
+        let sum = !(0x02u8 + 7 + 2 + 0x10 + 0x20);
+        assert_eq!(&frame[..], &[FRAME_SYNC, 0x02, 7, 2, 0x10, 0x20, sum]);
+
+        assert!(encode_frame(FrameKind::Readings, 0, &[0; MAX_PAYLOAD_LEN]).is_ok());
+        assert_eq!(
+            encode_frame(FrameKind::Readings, 0, &[0; MAX_PAYLOAD_LEN + 1]),
+            Err(FrameError::PayloadTooLong)
+        );
+    }
+
+    #[test]
+    fn readings_are_encoded_in_milli_units() {
+        let readings = [(5.02, 1.5, 7.53), (0.0, 0.0, 0.0), (12.0, -0.0004, 0.0)];
+        let frame = encode_readings(0xFF, 0x0102_0304, &readings);
+        assert_eq!(frame.len(), FRAME_OVERHEAD + 40);
+        assert_eq!(
+            &frame[..4],
+            &[FRAME_SYNC, FrameKind::Readings as u8, 0xFF, 40]
+        );
+
+        let payload = &frame[4..frame.len() - 1];
+        let word = |i: usize| i32::from_le_bytes(payload[4 * i..4 * i + 4].try_into().unwrap());
+        assert_eq!(word(0) as u32, 0x0102_0304);
+        assert_eq!([word(1), word(2), word(3)], [5020, 1500, 7530]);
+        assert_eq!([word(7), word(8), word(9)], [12_000, 0, 0]);
+        assert_eq!(frame[frame.len() - 1], checksum(&frame[1..frame.len() - 1]));
+    }
+
+    #[test]
+    fn pd_messages_keep_the_raw_bytes() {
+        let accept = [0xA3, 0x03];
+        let record = TraceRecord::new(0x1_0000_0010, TraceEvent::Received, &accept);
+        let frame = encode_pd_message(3, &record);
+        // The timestamp is cut to 32 bits
+        assert_eq!(
+            &frame[..frame.len() - 1],
+            &[FRAME_SYNC, 0x02, 3, 7, 0x10, 0, 0, 0, 0, 0xA3, 0x03]
+        );
+    }
+
+    #[test]
+    fn reports_pad_the_frame_with_zeros() {
+        let frame = encode_frame(FrameKind::Readings, 1, &[9; 3]).unwrap();
+        let report = encode_report(&frame);
+        assert_eq!(&report[..frame.len()], &frame[..]);
+        assert!(report[frame.len()..].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,289 @@
+// core/src/selftest.rs
+// Power-on self-test: the checks and how their results are judged
+//
+// `main` runs the checks against the hardware at boot, unless the setting
+// turns them off, and collects what it measured in a `Report`; the
+// verdicts, the summary lines and the event log entry come from here, so
+// they are tested on the host. `display::selftest` draws the summary.
+
+use core::fmt;
+
+use heapless::Vec;
+
+use crate::event_log::ring::Event;
+use crate::shared::PORT_COUNT;
+
+/// Manufacturer ID register (0xFE) of every INA226, "TI"
+pub const INA226_MANUFACTURER_ID: u16 = 0x5449;
+/// Die ID register (0xFF) of the INA226
+pub const INA226_DIE_ID: u16 = 0x2260;
+/// Current an idle port may read and still count as zero; more is either a
+/// shunt amplifier offset or a device already drawing power at boot
+pub const ZERO_TOLERANCE_MA: i32 = 20;
+
+/// Panel, settings and USB clock plus two checks per sensor
+pub const MAX_CHECKS: usize = 3 + 2 * PORT_COUNT;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Suspicious but possibly fine, e.g. a load on a port
+    Warn,
+    Fail,
+}
+
+/// State of the stored settings record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoredSettings {
+    Valid,
+    /// Nothing stored yet, the defaults are in use
+    Missing,
+    /// The newest record fails its CRC or doesn't decode
+    Damaged,
+    /// The storage region couldn't be read at all
+    Unreadable,
+}
+
+/// One check with what it measured
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// Init and the test pattern went through
+    Panel {
+        ok: bool,
+    },
+    /// (manufacturer, die) ID registers, `None` if the sensor didn't answer
+    SensorId {
+        port: u8,
+        ids: Option<(u16, u16)>,
+    },
+    /// Current read with the outputs idle, `None` if it couldn't be read
+    SensorZero {
+        port: u8,
+        current_ma: Option<i32>,
+    },
+    Settings(StoredSettings),
+    /// The 48 MHz clock of the USB peripheral is running
+    UsbClock {
+        ok: bool,
+    },
+}
+
+impl Check {
+    pub fn verdict(&self) -> Verdict {
+        let pass_if = |ok| if ok { Verdict::Pass } else { Verdict::Fail };
+        match *self {
+            Check::Panel { ok } | Check::UsbClock { ok } => pass_if(ok),
+            Check::SensorId { ids, .. } => {
+                pass_if(ids == Some((INA226_MANUFACTURER_ID, INA226_DIE_ID)))
+            }
+            Check::SensorZero {
+                current_ma: Some(ma),
+                ..
+            } if ma.abs() <= ZERO_TOLERANCE_MA => Verdict::Pass,
+            Check::SensorZero {
+                current_ma: Some(_),
+                ..
+            } => Verdict::Warn,
+            Check::SensorZero {
+                current_ma: None, ..
+            } => Verdict::Fail,
+            Check::Settings(StoredSettings::Valid | StoredSettings::Missing) => Verdict::Pass,
+            Check::Settings(StoredSettings::Damaged | StoredSettings::Unreadable) => Verdict::Fail,
+        }
+    }
+}
+
+/// One line of the summary, at most 26 characters
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = match self.verdict() {
+            Verdict::Pass => "ok",
+            Verdict::Warn => "warn",
+            Verdict::Fail => "FAIL",
+        };
+        match *self {
+            Check::Panel { .. } => write!(f, "Panel {}", result),
+            Check::SensorId { port, ids: None } => write!(f, "P{} sensor no answer", port + 1),
+            Check::SensorId {
+                port,
+                ids: Some((manufacturer, die)),
+            } => write!(
+                f,
+                "P{} ID {:04X}/{:04X} {}",
+                port + 1,
+                manufacturer,
+                die,
+                result
+            ),
+            Check::SensorZero {
+                port,
+                current_ma: None,
+            } => write!(f, "P{} zero no reading", port + 1),
+            Check::SensorZero {
+                port,
+                current_ma: Some(ma),
+            } => write!(f, "P{} zero {}mA {}", port + 1, ma, result),
+            Check::Settings(stored) => {
+                let state = match stored {
+                    StoredSettings::Valid => "CRC ok",
+                    StoredSettings::Missing => "defaults",
+                    StoredSettings::Damaged => "CRC FAIL",
+                    StoredSettings::Unreadable => "unreadable",
+                };
+                write!(f, "Settings {}", state)
+            }
+            Check::UsbClock { .. } => write!(f, "USB clock {}", result),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    checks: Vec<Check, MAX_CHECKS>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks past `MAX_CHECKS` are dropped
+    pub fn push(&mut self, check: Check) {
+        let _ = self.checks.push(check);
+    }
+
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    fn count(&self, verdict: Verdict) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.verdict() == verdict)
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(Verdict::Fail)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Verdict::Warn)
+    }
+
+    /// The worst verdict of any check
+    pub fn verdict(&self) -> Verdict {
+        if self.failed() > 0 {
+            Verdict::Fail
+        } else if self.warnings() > 0 {
+            Verdict::Warn
+        } else {
+            Verdict::Pass
+        }
+    }
+
+    /// Failures first, then warnings, in the order they were checked
+    pub fn problems(&self) -> impl Iterator<Item = &Check> + '_ {
+        let with = |verdict| {
+            self.checks
+                .iter()
+                .filter(move |check| check.verdict() == verdict)
+        };
+        with(Verdict::Fail).chain(with(Verdict::Warn))
+    }
+
+    /// The event log entry
+    pub fn event(&self) -> Event {
+        Event::SelfTest {
+            failed: self.failed() as u8,
+            warnings: self.warnings() as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::ToString;
+
+    use super::*;
+
+    const GOOD_IDS: Option<(u16, u16)> = Some((INA226_MANUFACTURER_ID, INA226_DIE_ID));
+
+    #[test]
+    fn verdicts() {
+        assert_eq!(Check::Panel { ok: true }.verdict(), Verdict::Pass);
+        assert_eq!(Check::UsbClock { ok: false }.verdict(), Verdict::Fail);
+        let id = |ids| Check::SensorId { port: 0, ids }.verdict();
+        assert_eq!(id(GOOD_IDS), Verdict::Pass);
+        // An INA219 or something else at the address
+        assert_eq!(id(Some((INA226_MANUFACTURER_ID, 0x2270))), Verdict::Fail);
+        assert_eq!(id(None), Verdict::Fail);
+        let zero = |current_ma| {
+            Check::SensorZero {
+                port: 0,
+                current_ma,
+            }
+            .verdict()
+        };
+        assert_eq!(zero(Some(-ZERO_TOLERANCE_MA)), Verdict::Pass);
+        assert_eq!(zero(Some(ZERO_TOLERANCE_MA + 1)), Verdict::Warn);
+        assert_eq!(zero(None), Verdict::Fail);
+        // First boot is not a fault
+        assert_eq!(
+            Check::Settings(StoredSettings::Missing).verdict(),
+            Verdict::Pass
+        );
+        assert_eq!(
+            Check::Settings(StoredSettings::Damaged).verdict(),
+            Verdict::Fail
+        );
+    }
+
+    #[test]
+    fn report_lists_failures_before_warnings() {
+        let mut report = Report::new();
+        report.push(Check::Panel { ok: true });
+        report.push(Check::SensorZero {
+            port: 1,
+            current_ma: Some(85),
+        });
+        report.push(Check::SensorId { port: 2, ids: None });
+        report.push(Check::Settings(StoredSettings::Valid));
+        assert_eq!((report.failed(), report.warnings()), (1, 1));
+        assert_eq!(report.verdict(), Verdict::Fail);
+        assert_eq!(
+            report.event(),
+            Event::SelfTest {
+                failed: 1,
+                warnings: 1
+            }
+        );
+
+        let lines: std::vec::Vec<_> = report.problems().map(|c| c.to_string()).collect();
+        assert_eq!(lines, ["P3 sensor no answer", "P2 zero 85mA warn"]);
+    }
+
+    #[test]
+    fn summary_lines_fit_the_panel() {
+        let checks = [
+            Check::SensorId {
+                port: 0,
+                ids: Some((0xFFFF, 0xFFFF)),
+            },
+            Check::SensorZero {
+                port: 2,
+                current_ma: Some(-10_000),
+            },
+            Check::Settings(StoredSettings::Unreadable),
+        ];
+        for check in checks {
+            assert!(check.to_string().len() <= 26, "{check}");
+        }
+        let mut report = Report::new();
+        for _ in 0..MAX_CHECKS + 1 {
+            report.push(Check::Panel { ok: true });
+        }
+        assert_eq!(report.checks().len(), MAX_CHECKS);
+        assert_eq!(report.verdict(), Verdict::Pass);
+    }
+}
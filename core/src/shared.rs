@@ -0,0 +1,51 @@
+// core/src/shared.rs
+// State shared between the main loop and background tasks
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::pubsub::PubSubChannel;
+use embassy_sync::watch::Watch;
+
+use crate::calibration;
+use crate::hal::{Button, Press};
+use crate::pd::policy::PdStatus;
+use crate::pd::trace::TraceRecord;
+use crate::storage::counters::Counters;
+use crate::storage::settings::Settings;
+use crate::usage::Session;
+
+/// Number of ports monitored by the INA226 sensors
+pub const PORT_COUNT: usize = 3;
+
+/// INA226 at 0x40 (5 mΩ shunt) sits on the upstream input, the rest are
+/// downstream ports
+pub const INPUT_PORT: usize = 0;
+
+/// Latest (voltage V, current A, power W) reading of every port
+pub type PortReadings = [(f32, f32, f32); PORT_COUNT];
+
+/// Readings published by the sampling loop; USB functions subscribe to it
+pub static READINGS: Watch<CriticalSectionRawMutex, PortReadings, 4> = Watch::new();
+
+/// USB PD negotiation state of the hub input, published by the PD task
+pub static PD_STATUS: Watch<CriticalSectionRawMutex, PdStatus, 2> = Watch::new();
+
+/// Every PD message on the CC line, for the display, telemetry and console
+pub static PD_TRACE: PubSubChannel<CriticalSectionRawMutex, TraceRecord, 16, 3, 1> =
+    PubSubChannel::new();
+
+/// Current user settings, loaded at boot and republished on every change
+pub static SETTINGS: Watch<CriticalSectionRawMutex, Settings, 4> = Watch::new();
+
+/// Lifetime usage counters, updated with every sample; the brown-out handler
+/// saves the latest value
+pub static COUNTERS: Watch<CriticalSectionRawMutex, Counters, 2> = Watch::new();
+
+/// Attach state and energy of the current session on every port
+pub static SESSIONS: Watch<CriticalSectionRawMutex, [Session; PORT_COUNT], 2> = Watch::new();
+
+/// Debounced button presses, consumed by the main loop
+pub static BUTTONS: Channel<CriticalSectionRawMutex, (Button, Press), 8> = Channel::new();
+
+/// Calibration runs asked for on the USB console, consumed by the main loop
+pub static CALIBRATION: Channel<CriticalSectionRawMutex, calibration::Request, 1> = Channel::new();
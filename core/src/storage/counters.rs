@@ -0,0 +1,135 @@
+// core/src/storage/counters.rs
+// Lifetime usage counters and their stored form
+//
+//   [version] [port_count] per port:
+//     [energy_mj u64][peak_current_ma u32][plug_events u32][protection_trips u32]
+//
+// All little endian. Later versions may append fields to the end of every
+// port entry; older firmware reads the prefix it knows.
+
+use crate::shared::PORT_COUNT;
+
+pub const COUNTERS_VERSION: u8 = 1;
+const PORT_LEN: usize = 20;
+pub const ENCODED_LEN: usize = 2 + PORT_COUNT * PORT_LEN;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortCounters {
+    /// Energy delivered through the port in millijoules
+    pub energy_mj: u64,
+    pub peak_current_ma: u32,
+    pub plug_events: u32,
+    pub protection_trips: u32,
+}
+
+impl PortCounters {
+    pub fn energy_wh(&self) -> f32 {
+        self.energy_mj as f32 / 3_600_000.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub ports: [PortCounters; PORT_COUNT],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    /// Stored by a build with a different number of ports
+    PortCount,
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+impl Counters {
+    pub fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut buf = [0u8; ENCODED_LEN];
+        buf[0] = COUNTERS_VERSION;
+        buf[1] = PORT_COUNT as u8;
+        for (port, entry) in self.ports.iter().zip(buf[2..].chunks_exact_mut(PORT_LEN)) {
+            entry[0..8].copy_from_slice(&port.energy_mj.to_le_bytes());
+            entry[8..12].copy_from_slice(&port.peak_current_ma.to_le_bytes());
+            entry[12..16].copy_from_slice(&port.plug_events.to_le_bytes());
+            entry[16..20].copy_from_slice(&port.protection_trips.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let [_version, port_count, entries @ ..] = bytes else {
+            return Err(DecodeError::Truncated);
+        };
+        if *port_count as usize != PORT_COUNT {
+            return Err(DecodeError::PortCount);
+        }
+        let entry_len = entries.len() / PORT_COUNT;
+        if !entries.len().is_multiple_of(PORT_COUNT) || entry_len < PORT_LEN {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut counters = Self::default();
+        for (port, entry) in counters
+            .ports
+            .iter_mut()
+            .zip(entries.chunks_exact(entry_len))
+        {
+            let mut energy = [0u8; 8];
+            energy.copy_from_slice(&entry[0..8]);
+            *port = PortCounters {
+                energy_mj: u64::from_le_bytes(energy),
+                peak_current_ma: u32_at(entry, 8),
+                plug_events: u32_at(entry, 12),
+                protection_trips: u32_at(entry, 16),
+            };
+        }
+        Ok(counters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Counters {
+        let mut counters = Counters::default();
+        counters.ports[0].energy_mj = 123_456_789_012;
+        counters.ports[1].peak_current_ma = 2870;
+        counters.ports[2].plug_events = 41;
+        counters.ports[2].protection_trips = 2;
+        counters
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = sample().encode();
+        assert_eq!(bytes[..2], [COUNTERS_VERSION, PORT_COUNT as u8]);
+        assert_eq!(Counters::decode(&bytes), Ok(sample()));
+    }
+
+    #[test]
+    fn newer_record_with_longer_entries() {
+        let bytes = sample().encode();
+        let mut newer = vec![COUNTERS_VERSION + 1, PORT_COUNT as u8];
+        for entry in bytes[2..].chunks(PORT_LEN) {
+            newer.extend_from_slice(entry);
+            newer.extend_from_slice(&[0xAB; 4]);
+        }
+        assert_eq!(Counters::decode(&newer), Ok(sample()));
+    }
+
+    #[test]
+    fn malformed_records() {
+        let bytes = sample().encode();
+        assert_eq!(Counters::decode(&bytes[..1]), Err(DecodeError::Truncated));
+        assert_eq!(
+            Counters::decode(&bytes[..ENCODED_LEN - 1]),
+            Err(DecodeError::Truncated)
+        );
+        let mut other = bytes;
+        other[1] = 4;
+        assert_eq!(Counters::decode(&other), Err(DecodeError::PortCount));
+    }
+}
@@ -0,0 +1,582 @@
+// core/src/storage/log.rs
+// Log-structured key/value store on NOR flash
+//
+// Records are appended to the active page; the newest valid record of a key
+// wins. When the page is full the next page is erased, the newest record of
+// every key is copied over and the page header is written last, so a power
+// cut during the copy leaves the old page in charge. Pages are used in turn,
+// which spreads the erase cycles over the whole region.
+//
+//   page   [magic u32][sequence u32] record record ... 0xFF
+//   record [key u8][len u16][check u8][crc32 u32] value, padded to 8 bytes
+//
+// `check` catches torn headers, the CRC-32 covers the header and the value.
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+use embedded_storage::nor_flash::NorFlash;
+
+/// Keys are small integers so the newest record of each can be tracked
+pub const KEY_COUNT: usize = 32;
+pub const MAX_RECORD_LEN: usize = 512;
+pub const MAX_VALUE_LEN: usize = MAX_RECORD_LEN - RECORD_HEADER_LEN;
+
+const MAGIC: u32 = 0x4B56_4C47;
+const PAGE_HEADER_LEN: u32 = 8;
+const RECORD_HEADER_LEN: usize = 8;
+// Record and page headers are programmed in 8-byte units
+const ALIGN: usize = 8;
+// Erased flash reads as 0xFF, i.e. no record
+const ERASED: u8 = 0xFF;
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    Flash(E),
+    /// Fewer than two pages, or a write size that does not divide 8
+    Layout,
+    InvalidKey,
+    /// Value longer than `MAX_VALUE_LEN` or the caller's buffer
+    TooLarge,
+    /// The newest record of every key does not fit in one page
+    Full,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Flash(e)
+    }
+}
+
+/// Page currently accepting records
+#[derive(Clone, Copy, Debug)]
+struct Active {
+    page: u32,
+    sequence: u32,
+    /// Offset of the first free byte within the page
+    end: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    /// Offset of the record within the page
+    offset: u32,
+    len: usize,
+}
+
+/// Newest valid record of every key in a page
+struct PageIndex {
+    entries: [Option<Entry>; KEY_COUNT],
+    /// The newest record of the key failed its CRC
+    damaged: [bool; KEY_COUNT],
+    end: u32,
+}
+
+enum Slot {
+    /// Erased, the page continues to be writable from here
+    End,
+    /// Half-written header or garbage, nothing after it can be trusted
+    Torn,
+    Record {
+        key: u8,
+        len: usize,
+        valid: bool,
+    },
+}
+
+pub struct LogStore<F> {
+    flash: F,
+    page_size: u32,
+    page_count: u32,
+    active: Option<Active>,
+}
+
+fn record_len(value_len: usize) -> usize {
+    (RECORD_HEADER_LEN + value_len).next_multiple_of(ALIGN)
+}
+
+fn header_check(header: &[u8]) -> u8 {
+    header[0] ^ header[1] ^ header[2] ^ 0xA5
+}
+
+impl<F: NorFlash> LogStore<F> {
+    /// Find the newest page; a blank region is formatted on the first write
+    pub fn open(flash: F) -> Result<Self, Error<F::Error>> {
+        let page_size = F::ERASE_SIZE as u32;
+        let page_count = flash.capacity() as u32 / page_size;
+        if page_count < 2
+            || !ALIGN.is_multiple_of(F::WRITE_SIZE)
+            || page_size as usize <= MAX_RECORD_LEN
+        {
+            return Err(Error::Layout);
+        }
+
+        let mut store = Self {
+            flash,
+            page_size,
+            page_count,
+            active: None,
+        };
+        for page in 0..page_count {
+            let Some(sequence) = store.page_sequence(page)? else {
+                continue;
+            };
+            if store.active.is_none_or(|a| sequence > a.sequence) {
+                store.active = Some(Active {
+                    page,
+                    sequence,
+                    end: 0,
+                });
+            }
+        }
+        if let Some(mut active) = store.active {
+            active.end = store.index(active.page)?.end;
+            store.active = Some(active);
+        }
+        Ok(store)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Copy the newest value of `key` into `buf`, returns its length
+    pub fn read(&mut self, key: u8, buf: &mut [u8]) -> Result<Option<usize>, Error<F::Error>> {
+        if key as usize >= KEY_COUNT {
+            return Err(Error::InvalidKey);
+        }
+        let Some(active) = self.active else {
+            return Ok(None);
+        };
+        let Some(entry) = self.index(active.page)?.entries[key as usize] else {
+            return Ok(None);
+        };
+        if entry.len > buf.len() {
+            return Err(Error::TooLarge);
+        }
+        let address = self.address(active.page, entry.offset) + RECORD_HEADER_LEN as u32;
+        self.flash.read(address, &mut buf[..entry.len])?;
+        Ok(Some(entry.len))
+    }
+
+    /// True if the newest record of `key` fails its CRC, so `read` returns
+    /// an older value or none
+    pub fn is_damaged(&mut self, key: u8) -> Result<bool, Error<F::Error>> {
+        if key as usize >= KEY_COUNT {
+            return Err(Error::InvalidKey);
+        }
+        match self.active {
+            Some(active) => Ok(self.index(active.page)?.damaged[key as usize]),
+            None => Ok(false),
+        }
+    }
+
+    pub fn write(&mut self, key: u8, value: &[u8]) -> Result<(), Error<F::Error>> {
+        if key as usize >= KEY_COUNT {
+            return Err(Error::InvalidKey);
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::TooLarge);
+        }
+
+        let mut record = [ERASED; MAX_RECORD_LEN];
+        let len = record_len(value.len());
+        record[0] = key;
+        record[1..3].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        record[3] = header_check(&record);
+        record[RECORD_HEADER_LEN..][..value.len()].copy_from_slice(value);
+        let mut digest = CRC32.digest();
+        digest.update(&record[..4]);
+        digest.update(value);
+        record[4..8].copy_from_slice(&digest.finalize().to_le_bytes());
+
+        match self.active {
+            Some(active) if active.end + len as u32 <= self.page_size => {
+                let address = self.address(active.page, active.end);
+                if let Err(e) = self.flash.write(address, &record[..len]) {
+                    // The slot may be partly programmed, move on to a new page
+                    self.active = Some(Active {
+                        end: self.page_size,
+                        ..active
+                    });
+                    return Err(e.into());
+                }
+                self.active = Some(Active {
+                    end: active.end + len as u32,
+                    ..active
+                });
+                Ok(())
+            }
+            _ => self.compact(key, &record[..len]),
+        }
+    }
+
+    /// Start the next page with the newest record of every other key
+    fn compact(&mut self, key: u8, record: &[u8]) -> Result<(), Error<F::Error>> {
+        let (page, sequence) = match self.active {
+            Some(active) => ((active.page + 1) % self.page_count, active.sequence + 1),
+            None => (0, 1),
+        };
+        let start = self.address(page, 0);
+        self.flash.erase(start, start + self.page_size)?;
+
+        let mut end = PAGE_HEADER_LEN;
+        if let Some(active) = self.active {
+            let index = self.index(active.page)?;
+            let mut buf = [0u8; MAX_RECORD_LEN];
+            for (k, entry) in index.entries.iter().enumerate() {
+                let Some(entry) = entry else {
+                    continue;
+                };
+                if k == key as usize {
+                    continue;
+                }
+                let len = record_len(entry.len);
+                if end + len as u32 + record.len() as u32 > self.page_size {
+                    return Err(Error::Full);
+                }
+                self.flash
+                    .read(self.address(active.page, entry.offset), &mut buf[..len])?;
+                self.flash.write(self.address(page, end), &buf[..len])?;
+                end += len as u32;
+            }
+        }
+
+        self.flash.write(self.address(page, end), record)?;
+        end += record.len() as u32;
+
+        let mut header = [0u8; PAGE_HEADER_LEN as usize];
+        header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+        self.flash.write(start, &header)?;
+
+        self.active = Some(Active {
+            page,
+            sequence,
+            end,
+        });
+        Ok(())
+    }
+
+    fn address(&self, page: u32, offset: u32) -> u32 {
+        page * self.page_size + offset
+    }
+
+    fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, Error<F::Error>> {
+        let mut header = [0u8; PAGE_HEADER_LEN as usize];
+        self.flash.read(self.address(page, 0), &mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        Ok((magic == MAGIC && sequence != u32::MAX).then_some(sequence))
+    }
+
+    fn index(&mut self, page: u32) -> Result<PageIndex, Error<F::Error>> {
+        let mut index = PageIndex {
+            entries: [None; KEY_COUNT],
+            damaged: [false; KEY_COUNT],
+            end: self.page_size,
+        };
+        let mut offset = PAGE_HEADER_LEN;
+        while offset + RECORD_HEADER_LEN as u32 <= self.page_size {
+            match self.slot(page, offset)? {
+                Slot::End => {
+                    index.end = offset;
+                    break;
+                }
+                // Not writable any more, the next write starts a new page
+                Slot::Torn => break,
+                Slot::Record { key, len, valid } => {
+                    if valid {
+                        index.entries[key as usize] = Some(Entry { offset, len });
+                    }
+                    index.damaged[key as usize] = !valid;
+                    offset += record_len(len) as u32;
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    fn slot(&mut self, page: u32, offset: u32) -> Result<Slot, Error<F::Error>> {
+        let address = self.address(page, offset);
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        self.flash.read(address, &mut header)?;
+        if header.iter().all(|&b| b == ERASED) {
+            return Ok(Slot::End);
+        }
+
+        let key = header[0];
+        let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+        if header[3] != header_check(&header)
+            || key as usize >= KEY_COUNT
+            || len > MAX_VALUE_LEN
+            || offset + record_len(len) as u32 > self.page_size
+        {
+            return Ok(Slot::Torn);
+        }
+
+        let mut digest = CRC32.digest();
+        digest.update(&header[..4]);
+        let mut chunk = [0u8; 32];
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(chunk.len());
+            let at = address + (RECORD_HEADER_LEN + done) as u32;
+            self.flash.read(at, &mut chunk[..n])?;
+            digest.update(&chunk[..n]);
+            done += n;
+        }
+        let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        Ok(Slot::Record {
+            key,
+            len,
+            valid: digest.finalize() == crc,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+    const PAGE: usize = 2048;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum MockError {
+        OutOfBounds,
+        NotAligned,
+        /// Programming a double word that is not erased, as the G4 refuses
+        NotErased,
+    }
+
+    impl NorFlashError for MockError {
+        fn kind(&self) -> NorFlashErrorKind {
+            match self {
+                MockError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+                MockError::NotAligned => NorFlashErrorKind::NotAligned,
+                MockError::NotErased => NorFlashErrorKind::Other,
+            }
+        }
+    }
+
+    /// RAM-backed flash with the STM32G4 rules: 2K pages, 8-byte writes
+    /// into erased double words only
+    struct MockFlash {
+        data: Vec<u8>,
+        erase_counts: Vec<u32>,
+        /// Fail every write after this many bytes, to simulate a power cut
+        write_budget: Option<usize>,
+    }
+
+    impl MockFlash {
+        fn new(pages: usize) -> Self {
+            Self {
+                data: vec![ERASED; pages * PAGE],
+                erase_counts: vec![0; pages],
+                write_budget: None,
+            }
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = MockError;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), MockError> {
+            let offset = offset as usize;
+            let src = self
+                .data
+                .get(offset..offset + bytes.len())
+                .ok_or(MockError::OutOfBounds)?;
+            bytes.copy_from_slice(src);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 8;
+        const ERASE_SIZE: usize = PAGE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), MockError> {
+            let (from, to) = (from as usize, to as usize);
+            if !from.is_multiple_of(PAGE) || !to.is_multiple_of(PAGE) {
+                return Err(MockError::NotAligned);
+            }
+            if to > self.data.len() {
+                return Err(MockError::OutOfBounds);
+            }
+            self.data[from..to].fill(ERASED);
+            for page in from / PAGE..to / PAGE {
+                self.erase_counts[page] += 1;
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MockError> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(8) || !bytes.len().is_multiple_of(8) {
+                return Err(MockError::NotAligned);
+            }
+            if offset + bytes.len() > self.data.len() {
+                return Err(MockError::OutOfBounds);
+            }
+            for (i, word) in bytes.chunks(8).enumerate() {
+                if let Some(budget) = self.write_budget.as_mut() {
+                    if *budget == 0 {
+                        return Err(MockError::OutOfBounds);
+                    }
+                    *budget -= 1;
+                }
+                let at = offset + i * 8;
+                if self.data[at..at + 8].iter().any(|&b| b != ERASED) {
+                    return Err(MockError::NotErased);
+                }
+                self.data[at..at + 8].copy_from_slice(word);
+            }
+            Ok(())
+        }
+    }
+
+    fn read_value(store: &mut LogStore<MockFlash>, key: u8) -> Option<Vec<u8>> {
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        let len = store.read(key, &mut buf).unwrap()?;
+        Some(buf[..len].to_vec())
+    }
+
+    fn reopen(store: LogStore<MockFlash>) -> LogStore<MockFlash> {
+        LogStore::open(store.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn blank_flash_has_no_values() {
+        let mut store = LogStore::open(MockFlash::new(2)).unwrap();
+        assert_eq!(read_value(&mut store, 0), None);
+    }
+
+    #[test]
+    fn newest_value_wins_across_reopen() {
+        let mut store = LogStore::open(MockFlash::new(2)).unwrap();
+        store.write(1, b"first").unwrap();
+        store.write(2, b"other key").unwrap();
+        store.write(1, b"second").unwrap();
+        assert_eq!(read_value(&mut store, 1).as_deref(), Some(&b"second"[..]));
+
+        let mut store = reopen(store);
+        assert_eq!(read_value(&mut store, 1).as_deref(), Some(&b"second"[..]));
+        assert_eq!(
+            read_value(&mut store, 2).as_deref(),
+            Some(&b"other key"[..])
+        );
+        assert_eq!(read_value(&mut store, 3), None);
+    }
+
+    #[test]
+    fn full_page_compacts_and_spreads_erases() {
+        let mut store = LogStore::open(MockFlash::new(2)).unwrap();
+        store.write(7, b"kept across compaction").unwrap();
+        for i in 0..1000u32 {
+            store.write(1, &i.to_le_bytes()).unwrap();
+        }
+        let mut store = reopen(store);
+        assert_eq!(
+            read_value(&mut store, 1),
+            Some(999u32.to_le_bytes().to_vec())
+        );
+        assert_eq!(
+            read_value(&mut store, 7).as_deref(),
+            Some(&b"kept across compaction"[..])
+        );
+
+        let counts = &store.into_inner().erase_counts;
+        assert!(counts[0] > 0 && counts[0].abs_diff(counts[1]) <= 1);
+    }
+
+    #[test]
+    fn torn_record_falls_back_to_previous_value() {
+        let mut store = LogStore::open(MockFlash::new(2)).unwrap();
+        store.write(1, b"good").unwrap();
+        let mut flash = store.into_inner();
+        // Power cut after the header and the first value word
+        flash.write_budget = Some(2);
+        let mut store = LogStore::open(flash).unwrap();
+        assert!(store.write(1, &[0x55; 40]).is_err());
+
+        let mut flash = store.into_inner();
+        flash.write_budget = None;
+        let mut store = LogStore::open(flash).unwrap();
+        assert_eq!(read_value(&mut store, 1).as_deref(), Some(&b"good"[..]));
+        // The damaged slot is skipped, later writes still land
+        store.write(1, b"after").unwrap();
+        let mut store = reopen(store);
+        assert_eq!(read_value(&mut store, 1).as_deref(), Some(&b"after"[..]));
+    }
+
+    #[test]
+    fn interrupted_compaction_keeps_old_page() {
+        let mut store = LogStore::open(MockFlash::new(2)).unwrap();
+        store.write(2, b"survives").unwrap();
+        let mut i = 0u32;
+        // Fill the first page up to the last record that fits
+        while store.active.unwrap().end + 16 <= PAGE as u32 {
+            store.write(1, &i.to_le_bytes()).unwrap();
+            i += 1;
+        }
+        let mut flash = store.into_inner();
+        // Erase succeeds, the copy stops half way and no header is written
+        flash.write_budget = Some(3);
+        let mut store = LogStore::open(flash).unwrap();
+        assert!(store.write(1, b"lost").is_err());
+
+        let mut flash = store.into_inner();
+        flash.write_budget = None;
+        let mut store = LogStore::open(flash).unwrap();
+        assert_eq!(
+            read_value(&mut store, 1),
+            Some((i - 1).to_le_bytes().to_vec())
+        );
+        assert_eq!(read_value(&mut store, 2).as_deref(), Some(&b"survives"[..]));
+    }
+
+    #[test]
+    fn corrupted_value_is_ignored() {
+        let mut store = LogStore::open(MockFlash::new(2)).unwrap();
+        store.write(1, b"old").unwrap();
+        store.write(1, b"new").unwrap();
+        let mut flash = store.into_inner();
+        // Flip a bit in the value of the second record
+        let second = PAGE_HEADER_LEN as usize + record_len(3) + RECORD_HEADER_LEN;
+        flash.data[second] ^= 0x01;
+        let mut store = LogStore::open(flash).unwrap();
+        assert_eq!(read_value(&mut store, 1).as_deref(), Some(&b"old"[..]));
+        assert_eq!(store.is_damaged(1), Ok(true));
+        assert_eq!(store.is_damaged(2), Ok(false));
+
+        // A good record on top clears it
+        store.write(1, b"newer").unwrap();
+        assert_eq!(store.is_damaged(1), Ok(false));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let mut store = LogStore::open(MockFlash::new(2)).unwrap();
+        assert_eq!(store.write(KEY_COUNT as u8, b"x"), Err(Error::InvalidKey));
+        assert_eq!(
+            store.write(0, &[0; MAX_VALUE_LEN + 1]),
+            Err(Error::TooLarge)
+        );
+        store.write(0, b"longer than the buffer").unwrap();
+        assert_eq!(store.read(0, &mut [0u8; 4]), Err(Error::TooLarge));
+        assert!(matches!(
+            LogStore::open(MockFlash::new(1)),
+            Err(Error::Layout)
+        ));
+    }
+}
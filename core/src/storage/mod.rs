@@ -0,0 +1,10 @@
+// core/src/storage/mod.rs
+// Stored data formats, tested against a RAM flash
+//
+// `log` is a wear-levelled key/value store over any `NorFlash`, `settings`
+// and `counters` the stored form of the user settings and the lifetime usage
+// counters. The firmware opens the store on the STORAGE flash region.
+
+pub mod counters;
+pub mod log;
+pub mod settings;
@@ -0,0 +1,479 @@
+// core/src/storage/settings.rs
+// User settings and their stored form
+//
+// The stored value is a version byte followed by tagged fields:
+//
+//   [version] [tag u8][len u8][value] [tag][len][value] ...
+//
+// Fields missing from an older record keep their default, unknown tags from
+// a newer firmware are skipped, so both upgrades and downgrades keep what
+// they can. A tag never changes meaning; when a field has to be stored
+// differently it gets a new tag and `decode` converts records older than the
+// version that introduced it.
+//
+// Version 2 marks the records written since the screen saver. The version 1
+// firmware before it never stored a sleep time and kept the display on, so a
+// version 1 record without one keeps it on rather than taking the default.
+
+use crate::capture::Trigger;
+use crate::history::Trend;
+use crate::shared::PORT_COUNT;
+
+pub const SETTINGS_VERSION: u8 = 2;
+/// Upper bound of the encoded size
+pub const MAX_ENCODED_LEN: usize = 1 + 6 * 3 + 2 * (2 + 2) + (2 + 5) + 3 * PORT_COUNT * (2 + 8);
+
+const TAG_BRIGHTNESS: u8 = 0x01;
+const TAG_THEME: u8 = 0x02;
+const TAG_BUZZER_MUTED: u8 = 0x03;
+const TAG_COUNTER_SAVE_MINUTES: u8 = 0x04;
+const TAG_ROTATED: u8 = 0x05;
+const TAG_SLEEP_MINUTES: u8 = 0x06;
+const TAG_SELF_TEST: u8 = 0x07;
+const TAG_CAPTURE_TRIGGER: u8 = 0x08;
+const TAG_TREND: u8 = 0x09;
+// Plus the port index
+const TAG_THRESHOLDS: u8 = 0x10;
+const TAG_SENSOR: u8 = 0x20;
+const TAG_CORRECTION: u8 = 0x30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+/// Alarm limits of one port
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortThresholds {
+    pub over_current_ma: u32,
+    pub over_voltage_mv: u32,
+}
+
+/// INA226 calibration of one port
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SensorProfile {
+    pub shunt_micro_ohms: u32,
+    /// Full scale of the current register
+    pub max_current_ma: u32,
+}
+
+impl SensorProfile {
+    pub fn shunt_ohms(&self) -> f64 {
+        self.shunt_micro_ohms as f64 / 1_000_000.0
+    }
+
+    pub fn max_current_amps(&self) -> f64 {
+        self.max_current_ma as f64 / 1000.0
+    }
+}
+
+/// Correction of one port's current readings, measured by `calibration`:
+/// corrected = (raw - offset) * gain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CurrentCorrection {
+    /// Reading with no current flowing
+    pub offset_ua: i32,
+    /// Scale of the reading once the offset is removed, in millionths
+    pub gain_ppm: u32,
+}
+
+impl CurrentCorrection {
+    /// Readings as they come from the sensor
+    pub const NONE: Self = Self {
+        offset_ua: 0,
+        gain_ppm: 1_000_000,
+    };
+    /// Gains a shunt within a few percent of its nominal value can need;
+    /// anything else is a wrong reference or a wrong sensor profile
+    pub const GAIN_RANGE_PPM: core::ops::RangeInclusive<u32> = 800_000..=1_250_000;
+
+    /// Corrected current of a raw reading, both in A
+    pub fn apply(&self, amps: f32) -> f32 {
+        (amps - self.offset_ua as f32 / 1_000_000.0) * self.gain()
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain_ppm as f32 / 1_000_000.0
+    }
+}
+
+impl Default for CurrentCorrection {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// Backlight in percent
+    pub brightness: u8,
+    pub theme: Theme,
+    pub buzzer_muted: bool,
+    /// Minimum time between periodic saves of the usage counters, 0 saves
+    /// only on brown-out
+    pub counter_save_minutes: u16,
+    /// Display turned by 180° and the buttons swapped, for an enclosure
+    /// mounted upside down
+    pub rotated: bool,
+    /// Idle time before the display sleeps, 0 keeps it on
+    pub sleep_minutes: u16,
+    /// Run the power-on self-test and show its summary at boot
+    pub self_test: bool,
+    /// Port and level of burst captures armed on the device
+    pub capture_trigger: Trigger,
+    /// Current or power in the trends and port page graphs
+    pub trend: Trend,
+    pub thresholds: [PortThresholds; PORT_COUNT],
+    pub sensors: [SensorProfile; PORT_COUNT],
+    pub corrections: [CurrentCorrection; PORT_COUNT],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Empty,
+    /// A field runs past the end of the record
+    Truncated,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        // Port 1 is the upstream input, which may negotiate up to 20 V
+        let input = PortThresholds {
+            over_current_ma: 5000,
+            over_voltage_mv: 21_000,
+        };
+        let downstream = PortThresholds {
+            over_current_ma: 3000,
+            over_voltage_mv: 5500,
+        };
+        Self {
+            brightness: 80,
+            theme: Theme::Dark,
+            buzzer_muted: false,
+            // 48 writes a day, the storage pages last decades
+            counter_save_minutes: 30,
+            rotated: false,
+            sleep_minutes: 10,
+            self_test: true,
+            capture_trigger: Trigger::default(),
+            trend: Trend::Current,
+            thresholds: [input, downstream, downstream],
+            sensors: [
+                SensorProfile {
+                    shunt_micro_ohms: 5000,
+                    max_current_ma: 4000,
+                },
+                SensorProfile {
+                    shunt_micro_ohms: 10_000,
+                    max_current_ma: 4000,
+                },
+                SensorProfile {
+                    shunt_micro_ohms: 10_000,
+                    max_current_ma: 4000,
+                },
+            ],
+            corrections: [CurrentCorrection::NONE; PORT_COUNT],
+        }
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8; MAX_ENCODED_LEN],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn field(&mut self, tag: u8, value: &[u8]) {
+        self.buf[self.len] = tag;
+        self.buf[self.len + 1] = value.len() as u8;
+        self.buf[self.len + 2..][..value.len()].copy_from_slice(value);
+        self.len += 2 + value.len();
+    }
+
+    fn pair(&mut self, tag: u8, a: u32, b: u32) {
+        let mut value = [0u8; 8];
+        value[..4].copy_from_slice(&a.to_le_bytes());
+        value[4..].copy_from_slice(&b.to_le_bytes());
+        self.field(tag, &value);
+    }
+}
+
+fn read_pair(value: &[u8]) -> Option<(u32, u32)> {
+    let value: &[u8; 8] = value.try_into().ok()?;
+    Some((
+        u32::from_le_bytes([value[0], value[1], value[2], value[3]]),
+        u32::from_le_bytes([value[4], value[5], value[6], value[7]]),
+    ))
+}
+
+impl Settings {
+    /// Serialise into `buf`, returns the length
+    pub fn encode(&self, buf: &mut [u8; MAX_ENCODED_LEN]) -> usize {
+        buf[0] = SETTINGS_VERSION;
+        let mut w = Writer { buf, len: 1 };
+        w.field(TAG_BRIGHTNESS, &[self.brightness]);
+        w.field(
+            TAG_THEME,
+            &[match self.theme {
+                Theme::Dark => 0,
+                Theme::Light => 1,
+            }],
+        );
+        w.field(TAG_BUZZER_MUTED, &[self.buzzer_muted as u8]);
+        w.field(
+            TAG_COUNTER_SAVE_MINUTES,
+            &self.counter_save_minutes.to_le_bytes(),
+        );
+        w.field(TAG_ROTATED, &[self.rotated as u8]);
+        w.field(TAG_SLEEP_MINUTES, &self.sleep_minutes.to_le_bytes());
+        w.field(TAG_SELF_TEST, &[self.self_test as u8]);
+        let [l0, l1, l2, l3] = self.capture_trigger.level_ma.to_le_bytes();
+        w.field(
+            TAG_CAPTURE_TRIGGER,
+            &[self.capture_trigger.port as u8, l0, l1, l2, l3],
+        );
+        w.field(
+            TAG_TREND,
+            &[match self.trend {
+                Trend::Current => 0,
+                Trend::Power => 1,
+            }],
+        );
+        for (i, t) in self.thresholds.iter().enumerate() {
+            w.pair(
+                TAG_THRESHOLDS + i as u8,
+                t.over_current_ma,
+                t.over_voltage_mv,
+            );
+        }
+        for (i, s) in self.sensors.iter().enumerate() {
+            w.pair(TAG_SENSOR + i as u8, s.shunt_micro_ohms, s.max_current_ma);
+        }
+        for (i, c) in self.corrections.iter().enumerate() {
+            w.pair(TAG_CORRECTION + i as u8, c.offset_ua as u32, c.gain_ppm);
+        }
+        w.len
+    }
+
+    /// What a record of `version` means by the fields it lacks. Newer
+    /// records lack nothing this firmware knows, so they use the defaults.
+    fn defaults_for(version: u8) -> Self {
+        let mut settings = Self::default();
+        if version < 2 {
+            settings.sleep_minutes = 0;
+        }
+        settings
+    }
+
+    /// Decode a stored record; fields that are missing or out of range keep
+    /// their defaults
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (&version, mut rest) = bytes.split_first().ok_or(DecodeError::Empty)?;
+        let mut settings = Self::defaults_for(version);
+
+        while let [tag, len, tail @ ..] = rest {
+            let len = *len as usize;
+            if tail.len() < len {
+                return Err(DecodeError::Truncated);
+            }
+            let (value, tail) = tail.split_at(len);
+            rest = tail;
+
+            match (*tag, value) {
+                (TAG_BRIGHTNESS, &[percent]) => settings.brightness = percent.min(100),
+                (TAG_THEME, &[0]) => settings.theme = Theme::Dark,
+                (TAG_THEME, &[1]) => settings.theme = Theme::Light,
+                (TAG_BUZZER_MUTED, &[muted]) => settings.buzzer_muted = muted != 0,
+                (TAG_COUNTER_SAVE_MINUTES, &[lo, hi]) => {
+                    settings.counter_save_minutes = u16::from_le_bytes([lo, hi])
+                }
+                (TAG_ROTATED, &[rotated]) => settings.rotated = rotated != 0,
+                (TAG_SLEEP_MINUTES, &[lo, hi]) => {
+                    settings.sleep_minutes = u16::from_le_bytes([lo, hi])
+                }
+                (TAG_SELF_TEST, &[enabled]) => settings.self_test = enabled != 0,
+                (TAG_CAPTURE_TRIGGER, &[port, l0, l1, l2, l3]) => {
+                    let trigger = Trigger {
+                        port: port as usize,
+                        level_ma: u32::from_le_bytes([l0, l1, l2, l3]),
+                    };
+                    if trigger.is_valid() {
+                        settings.capture_trigger = trigger;
+                    }
+                }
+                (TAG_TREND, &[0]) => settings.trend = Trend::Current,
+                (TAG_TREND, &[1]) => settings.trend = Trend::Power,
+                (tag, value)
+                    if (TAG_THRESHOLDS..TAG_THRESHOLDS + PORT_COUNT as u8).contains(&tag) =>
+                {
+                    if let Some((current, voltage)) = read_pair(value) {
+                        settings.thresholds[(tag - TAG_THRESHOLDS) as usize] = PortThresholds {
+                            over_current_ma: current,
+                            over_voltage_mv: voltage,
+                        };
+                    }
+                }
+                (tag, value) if (TAG_SENSOR..TAG_SENSOR + PORT_COUNT as u8).contains(&tag) => {
+                    match read_pair(value) {
+                        // A zero shunt would divide by zero in the calibration
+                        Some((shunt, max_current)) if shunt > 0 && max_current > 0 => {
+                            settings.sensors[(tag - TAG_SENSOR) as usize] = SensorProfile {
+                                shunt_micro_ohms: shunt,
+                                max_current_ma: max_current,
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+                (tag, value)
+                    if (TAG_CORRECTION..TAG_CORRECTION + PORT_COUNT as u8).contains(&tag) =>
+                {
+                    match read_pair(value) {
+                        Some((offset, gain))
+                            if CurrentCorrection::GAIN_RANGE_PPM.contains(&gain) =>
+                        {
+                            settings.corrections[(tag - TAG_CORRECTION) as usize] =
+                                CurrentCorrection {
+                                    offset_ua: offset as i32,
+                                    gain_ppm: gain,
+                                };
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !rest.is_empty() {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom() -> Settings {
+        let mut settings = Settings {
+            brightness: 35,
+            theme: Theme::Light,
+            buzzer_muted: true,
+            counter_save_minutes: 0,
+            rotated: true,
+            sleep_minutes: 0,
+            self_test: false,
+            capture_trigger: Trigger {
+                port: 2,
+                level_ma: 1200,
+            },
+            trend: Trend::Power,
+            ..Default::default()
+        };
+        settings.thresholds[2].over_current_ma = 1500;
+        settings.sensors[1].shunt_micro_ohms = 9870;
+        settings.corrections[2] = CurrentCorrection {
+            offset_ua: -3200,
+            gain_ppm: 1_012_000,
+        };
+        settings
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0u8; MAX_ENCODED_LEN];
+        let len = custom().encode(&mut buf);
+        assert_eq!(len, MAX_ENCODED_LEN);
+        assert_eq!(buf[0], SETTINGS_VERSION);
+        assert_eq!(Settings::decode(&buf[..len]), Ok(custom()));
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults() {
+        // Version byte and the brightness only
+        let bytes = [SETTINGS_VERSION, TAG_BRIGHTNESS, 1, 10];
+        let settings = Settings::decode(&bytes).unwrap();
+        assert_eq!(settings.brightness, 10);
+        assert_eq!(settings.sleep_minutes, Settings::default().sleep_minutes);
+        assert_eq!(settings.thresholds, Settings::default().thresholds);
+        assert_eq!(settings.sensors, Settings::default().sensors);
+    }
+
+    #[test]
+    fn version_1_record_keeps_the_display_on() {
+        // Written before the screen saver existed
+        #[rustfmt::skip]
+        let bytes = [
+            1,
+            TAG_BRIGHTNESS, 1, 60,
+            TAG_THEME, 1, 1,
+            TAG_BUZZER_MUTED, 1, 1,
+            TAG_THRESHOLDS + 1, 8, 0xDC, 0x05, 0, 0, 0x7C, 0x15, 0, 0,
+        ];
+        let settings = Settings::decode(&bytes).unwrap();
+        let expected = Settings {
+            brightness: 60,
+            theme: Theme::Light,
+            buzzer_muted: true,
+            sleep_minutes: 0,
+            thresholds: [
+                Settings::default().thresholds[0],
+                PortThresholds {
+                    over_current_ma: 1500,
+                    over_voltage_mv: 5500,
+                },
+                Settings::default().thresholds[2],
+            ],
+            ..Default::default()
+        };
+        assert_eq!(settings, expected);
+
+        // A later version 1 record with a sleep time keeps it
+        let bytes = [1, TAG_SLEEP_MINUTES, 2, 5, 0];
+        assert_eq!(Settings::decode(&bytes).unwrap().sleep_minutes, 5);
+    }
+
+    #[test]
+    fn newer_record_skips_unknown_fields() {
+        #[rustfmt::skip]
+        let bytes = [
+            SETTINGS_VERSION + 1,
+            0x7F, 3, 0xAA, 0xBB, 0xCC,
+            TAG_THEME, 1, 1,
+            // Known tag with a longer value in the future layout
+            TAG_BUZZER_MUTED, 2, 1, 0,
+        ];
+        let settings = Settings::decode(&bytes).unwrap();
+        assert_eq!(settings.theme, Theme::Light);
+        assert!(!settings.buzzer_muted);
+        assert_eq!(settings.sleep_minutes, Settings::default().sleep_minutes);
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let mut bytes = vec![1, TAG_BRIGHTNESS, 1, 250, TAG_THEME, 1, 9];
+        bytes.extend_from_slice(&[TAG_SENSOR + 2, 8, 0, 0, 0, 0, 0xA0, 0x0F, 0, 0]);
+        // A gain of 0.5
+        bytes.extend_from_slice(&[TAG_CORRECTION + 1, 8, 0, 0, 0, 0, 0x20, 0xA1, 0x07, 0]);
+        let settings = Settings::decode(&bytes).unwrap();
+        assert_eq!(settings.brightness, 100);
+        assert_eq!(settings.theme, Theme::Dark);
+        assert_eq!(settings.sensors[2], Settings::default().sensors[2]);
+        assert_eq!(settings.corrections[1], CurrentCorrection::NONE);
+    }
+
+    #[test]
+    fn malformed_records() {
+        assert_eq!(Settings::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            Settings::decode(&[1, TAG_BRIGHTNESS, 4, 50]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            Settings::decode(&[1, TAG_BRIGHTNESS]),
+            Err(DecodeError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,298 @@
+// core/src/usage.rs
+// Lifetime usage accounting from the sampled readings
+//
+// Integrates energy, keeps the peak current, counts plug events and
+// protection trips, and decides when the counters are worth a flash write.
+// Saving is rate limited by the configured interval; the brown-out handler
+// saves whatever changed since, so little is lost on power failure.
+//
+// Downstream ports also get an attach detector; every attach counts as a
+// plug event and starts a new session, which has its own energy counter and
+// follows the charge phase of the device.
+
+use heapless::Vec;
+
+use crate::attach::{AttachDetector, AttachEvent, AttachState};
+use crate::charge::{ChargeClassifier, ChargePhase};
+use crate::shared::{INPUT_PORT, PORT_COUNT, PortReadings};
+use crate::storage::counters::Counters;
+use crate::storage::settings::PortThresholds;
+
+// A trip clears once both values are back below this share of the limits
+const TRIP_CLEAR_PERCENT: u32 = 95;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TripKind {
+    OverCurrent,
+    OverVoltage,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UsageEvent {
+    Attach { port: usize, event: AttachEvent },
+    Trip { port: usize, kind: TripKind },
+}
+
+pub type UsageEvents = Vec<UsageEvent, { 2 * PORT_COUNT }>;
+
+/// What is plugged into a port since the last attach
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    pub state: AttachState,
+    /// Energy delivered since the attach, millijoules
+    pub energy_mj: u64,
+    pub duration_ms: u64,
+    pub phase: ChargePhase,
+    pub time_to_full_s: Option<u32>,
+}
+
+impl Session {
+    pub fn energy_wh(&self) -> f32 {
+        self.energy_mj as f32 / 3_600_000.0
+    }
+}
+
+pub struct UsageTracker {
+    counters: Counters,
+    // Sub-millijoule energy not yet in the counters
+    remainder_mj: [f32; PORT_COUNT],
+    detectors: [AttachDetector; PORT_COUNT],
+    sessions: [Session; PORT_COUNT],
+    chargers: [ChargeClassifier; PORT_COUNT],
+    tripped: [bool; PORT_COUNT],
+    dirty: bool,
+    last_save_ms: u64,
+}
+
+impl UsageTracker {
+    /// Continue from the stored counters
+    pub fn new(counters: Counters, now_ms: u64) -> Self {
+        Self {
+            counters,
+            remainder_mj: [0.0; PORT_COUNT],
+            detectors: [AttachDetector::new(); PORT_COUNT],
+            sessions: [Session::default(); PORT_COUNT],
+            chargers: [ChargeClassifier::new(); PORT_COUNT],
+            tripped: [false; PORT_COUNT],
+            dirty: false,
+            last_save_ms: now_ms,
+        }
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Current or last session of every port; the input port has none
+    pub fn sessions(&self) -> &[Session; PORT_COUNT] {
+        &self.sessions
+    }
+
+    /// Account one sample that stood for the last `dt_ms` milliseconds
+    pub fn update(
+        &mut self,
+        readings: &PortReadings,
+        thresholds: &[PortThresholds; PORT_COUNT],
+        dt_ms: u32,
+    ) -> UsageEvents {
+        let mut events = UsageEvents::new();
+        for (port, &(volts, amps, watts)) in readings.iter().enumerate() {
+            let counters = &mut self.counters.ports[port];
+            let current_ma = libm::roundf(libm::fabsf(amps) * 1000.0) as u32;
+            let voltage_mv = libm::roundf(volts.max(0.0) * 1000.0) as u32;
+
+            // W * ms = mJ
+            let energy = self.remainder_mj[port] + watts.max(0.0) * dt_ms as f32;
+            let whole = energy as u64;
+            self.remainder_mj[port] = energy - whole as f32;
+            if whole > 0 {
+                counters.energy_mj += whole;
+                self.sessions[port].energy_mj += whole;
+                self.dirty = true;
+            }
+            self.sessions[port].duration_ms += dt_ms as u64;
+
+            if current_ma > counters.peak_current_ma {
+                counters.peak_current_ma = current_ma;
+                self.dirty = true;
+            }
+
+            let attach = if port == INPUT_PORT {
+                None
+            } else {
+                self.detectors[port].update(voltage_mv, current_ma, dt_ms)
+            };
+            if let Some(event) = attach {
+                if event == AttachEvent::Attached {
+                    counters.plug_events += 1;
+                    self.dirty = true;
+                    self.sessions[port] = Session::default();
+                    self.chargers[port] = ChargeClassifier::new();
+                }
+                self.sessions[port].state = self.detectors[port].state();
+                let _ = events.push(UsageEvent::Attach { port, event });
+            }
+            let session = &mut self.sessions[port];
+            if session.state == AttachState::Detached {
+                session.phase = ChargePhase::Unknown;
+                session.time_to_full_s = None;
+            } else {
+                let charger = &mut self.chargers[port];
+                session.phase = charger.update(voltage_mv, current_ma, dt_ms);
+                session.time_to_full_s = charger.time_to_full_s();
+            }
+
+            let limits = &thresholds[port];
+            let kind = if current_ma > limits.over_current_ma {
+                Some(TripKind::OverCurrent)
+            } else if voltage_mv > limits.over_voltage_mv {
+                Some(TripKind::OverVoltage)
+            } else {
+                None
+            };
+            match kind {
+                Some(kind) if !self.tripped[port] => {
+                    self.tripped[port] = true;
+                    counters.protection_trips += 1;
+                    self.dirty = true;
+                    let _ = events.push(UsageEvent::Trip { port, kind });
+                }
+                None if self.tripped[port]
+                    && current_ma * 100 < limits.over_current_ma * TRIP_CLEAR_PERCENT
+                    && voltage_mv * 100 < limits.over_voltage_mv * TRIP_CLEAR_PERCENT =>
+                {
+                    self.tripped[port] = false;
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Counters changed and the last save is at least `interval_ms` ago;
+    /// an interval of 0 leaves saving to the brown-out handler
+    pub fn save_due(&self, now_ms: u64, interval_ms: u64) -> bool {
+        self.dirty && interval_ms > 0 && now_ms.saturating_sub(self.last_save_ms) >= interval_ms
+    }
+
+    pub fn mark_saved(&mut self, now_ms: u64) {
+        self.dirty = false;
+        self.last_save_ms = now_ms;
+    }
+
+    /// Start over from zero, e.g. on user request
+    pub fn reset(&mut self) {
+        self.counters = Counters::default();
+        self.remainder_mj = [0.0; PORT_COUNT];
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::settings::Settings;
+
+    const IDLE: (f32, f32, f32) = (5.0, 0.0, 0.0);
+
+    fn thresholds() -> [PortThresholds; PORT_COUNT] {
+        Settings::default().thresholds
+    }
+
+    #[test]
+    fn energy_accumulates_fractions() {
+        let mut tracker = UsageTracker::new(Counters::default(), 0);
+        // 2.5 W on port 2 for 3600 samples of 100 ms, 0.00025 W on port 3
+        let readings = [IDLE, (5.0, 0.5, 2.5), (5.0, 0.00005, 0.00025)];
+        for _ in 0..3600 {
+            tracker.update(&readings, &thresholds(), 100);
+        }
+        let ports = &tracker.counters().ports;
+        assert_eq!(ports[0].energy_mj, 0);
+        assert_eq!(ports[1].energy_mj, 900_000);
+        assert!((ports[1].energy_wh() - 0.25).abs() < 1e-6);
+        // 0.025 mJ per sample still adds up, give or take f32 rounding
+        assert!(ports[2].energy_mj.abs_diff(90) <= 1);
+    }
+
+    #[test]
+    fn attach_starts_a_session() {
+        let mut tracker = UsageTracker::new(Counters::default(), 0);
+        // 10 s at 1 A, unplugged for 5 s, then 1 s at 2 A
+        let current = [(100, 1.0), (50, 0.0), (10, 2.0)];
+        let mut events = std::vec::Vec::new();
+        for (samples, amps) in current {
+            for _ in 0..samples {
+                let readings = [(5.0, 3.0, 15.0), (5.0, amps, 5.0 * amps), IDLE];
+                events.extend(tracker.update(&readings, &thresholds(), 100));
+            }
+        }
+        let attached = |port| UsageEvent::Attach {
+            port,
+            event: AttachEvent::Attached,
+        };
+        assert_eq!(events.iter().filter(|&&e| e == attached(1)).count(), 2);
+        assert!(
+            events
+                .iter()
+                .all(|e| matches!(e, UsageEvent::Attach { port: 1, .. }))
+        );
+        assert_eq!(tracker.counters().ports[1].plug_events, 2);
+        assert_eq!(tracker.counters().ports[0].plug_events, 0, "input port");
+
+        // Attached after 0.5 s of the last second
+        let session = tracker.sessions()[1];
+        assert_eq!(session.state, AttachState::Idle);
+        assert_eq!(session.energy_mj, 5 * 1000);
+        assert_eq!(session.duration_ms, 500);
+        assert_eq!(tracker.counters().ports[1].energy_mj, 5_000 * 10 + 10_000);
+    }
+
+    #[test]
+    fn trips_count_once_until_cleared() {
+        let mut tracker = UsageTracker::new(Counters::default(), 0);
+        // Limit 3000 mA, clears below 2850 mA
+        let current = [1.0, 3.2, 3.5, 2.9, 3.1, 2.0, 3.01];
+        let mut trips = 0;
+        for amps in current {
+            let readings = [IDLE, IDLE, (5.0, amps, 5.0 * amps)];
+            for event in tracker.update(&readings, &thresholds(), 100) {
+                if let UsageEvent::Trip { port, kind } = event {
+                    assert_eq!((port, kind), (2, TripKind::OverCurrent));
+                    trips += 1;
+                }
+            }
+        }
+        assert_eq!(trips, 2);
+        assert_eq!(tracker.counters().ports[2].protection_trips, 2);
+
+        let readings = [(20.5, 1.0, 20.5), IDLE, IDLE];
+        let events = tracker.update(&readings, &thresholds(), 100);
+        assert!(!events.contains(&UsageEvent::Trip {
+            port: 0,
+            kind: TripKind::OverVoltage
+        }));
+        let readings = [(21.5, 1.0, 21.5), IDLE, IDLE];
+        let events = tracker.update(&readings, &thresholds(), 100);
+        assert!(events.contains(&UsageEvent::Trip {
+            port: 0,
+            kind: TripKind::OverVoltage
+        }));
+    }
+
+    #[test]
+    fn saves_are_rate_limited() {
+        let mut tracker = UsageTracker::new(Counters::default(), 1_000);
+        let interval = 60_000;
+        assert!(!tracker.save_due(100_000, interval), "nothing changed");
+
+        let readings = [IDLE, (5.0, 1.0, 5.0), IDLE];
+        tracker.update(&readings, &thresholds(), 100);
+        assert!(!tracker.save_due(30_000, interval));
+        assert!(tracker.save_due(61_000, interval));
+        assert!(!tracker.save_due(61_000, 0), "brown-out only");
+
+        tracker.mark_saved(61_000);
+        assert!(!tracker.save_due(200_000, interval));
+    }
+}
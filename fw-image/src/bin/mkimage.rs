@@ -0,0 +1,72 @@
+//! Wrap an application binary into an update image for the DFU bootloader
+//!
+//! ```text
+//! mkimage <app.bin> <out.img> <major.minor.patch> [ed25519-secret-key-file]
+//! ```
+//!
+//! The binary must be linked for the ACTIVE partition (`--features bootloader`)
+//! and converted with `objcopy -O binary`. The optional key file holds the 32
+//! raw secret key bytes.
+
+use std::{env, fs, process};
+
+use fw_image::{FirmwareVersion, ImageHeader};
+
+fn parse_version(s: &str) -> Option<FirmwareVersion> {
+    let mut parts = s.split('.');
+    let version = FirmwareVersion {
+        major: parts.next()?.parse().ok()?,
+        minor: parts.next()?.parse().ok()?,
+        patch: parts.next()?.parse().ok()?,
+    };
+    parts.next().is_none().then_some(version)
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [input, output, version, rest @ ..] = args else {
+        return Err("usage: mkimage <app.bin> <out.img> <major.minor.patch> [secret-key]".into());
+    };
+    let version = parse_version(version).ok_or("version must be major.minor.patch")?;
+    let payload = fs::read(input).map_err(|e| format!("{input}: {e}"))?;
+
+    #[cfg_attr(not(feature = "ed25519"), allow(unused_mut))]
+    let mut header = ImageHeader::for_payload(&payload, version);
+    match rest {
+        [] => {}
+        #[cfg(feature = "ed25519")]
+        [key_file] => {
+            let key = fs::read(key_file).map_err(|e| format!("{key_file}: {e}"))?;
+            let key: [u8; 32] = key
+                .try_into()
+                .map_err(|_| format!("{key_file}: expected 32 key bytes"))?;
+            header.sign(&key);
+        }
+        _ => return Err("signing requires the `ed25519` feature".into()),
+    }
+
+    let mut image = header.to_bytes().to_vec();
+    image.extend_from_slice(&payload);
+    fs::write(output, &image).map_err(|e| format!("{output}: {e}"))?;
+
+    println!(
+        "{output}: {} bytes, version {}.{}.{}, {}",
+        payload.len(),
+        version.major,
+        version.minor,
+        version.patch,
+        if header.is_signed() {
+            "signed"
+        } else {
+            "unsigned"
+        }
+    );
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("mkimage: {e}");
+        process::exit(1);
+    }
+}
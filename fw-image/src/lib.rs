@@ -0,0 +1,400 @@
+//! Firmware image format shared by the bootloader, the application and the
+//! host side `mkimage` tool.
+//!
+//! An update file is a fixed size [`ImageHeader`] followed by the raw
+//! application binary (linked for the ACTIVE partition). Only the payload is
+//! written to the DFU partition; the header is consumed by the [`Verifier`]
+//! while the image is streamed in.
+//!
+//! Header layout (little-endian):
+//!
+//! | Offset | Size | Field                                       |
+//! | ------ | ---- | ------------------------------------------- |
+//! | 0      | 4    | magic `IHFW`                                |
+//! | 4      | 2    | header version                              |
+//! | 6      | 2    | flags, bit 0 = signed                       |
+//! | 8      | 4    | payload length                              |
+//! | 12     | 4    | firmware version `major.minor.patch`        |
+//! | 16     | 4    | CRC-32 (ISO-HDLC) of the payload            |
+//! | 20     | 32   | SHA-256 of the payload                      |
+//! | 52     | 64   | Ed25519 signature of the SHA-256, or zeroes |
+//! | 116    | 8    | reserved, zero                              |
+//! | 124    | 4    | CRC-32 of header bytes 0..124               |
+
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+use crc::{CRC_32_ISO_HDLC, Crc, Digest};
+use sha2::{Digest as _, Sha256};
+
+pub const HEADER_LEN: usize = 128;
+pub const MAGIC: [u8; 4] = *b"IHFW";
+pub const HEADER_VERSION: u16 = 1;
+
+pub const FLAG_SIGNED: u16 = 1 << 0;
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    HeaderCrc,
+    /// Payload does not fit into the DFU partition
+    TooLarge,
+    /// More or fewer payload bytes than announced in the header
+    LengthMismatch,
+    PayloadCrc,
+    Digest,
+    /// A signature is required but the image is not signed
+    Unsigned,
+    Signature,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u16,
+}
+
+impl FirmwareVersion {
+    pub const fn to_u32(self) -> u32 {
+        ((self.major as u32) << 24) | ((self.minor as u32) << 16) | self.patch as u32
+    }
+
+    pub const fn from_u32(value: u32) -> Self {
+        Self {
+            major: (value >> 24) as u8,
+            minor: (value >> 16) as u8,
+            patch: value as u16,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub flags: u16,
+    pub payload_len: u32,
+    pub version: FirmwareVersion,
+    pub crc32: u32,
+    pub sha256: [u8; 32],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl ImageHeader {
+    pub fn is_signed(&self) -> bool {
+        self.flags & FLAG_SIGNED != 0
+    }
+
+    pub fn parse(bytes: &[u8; HEADER_LEN]) -> Result<Self, ImageError> {
+        if bytes[0..4] != MAGIC {
+            return Err(ImageError::BadMagic);
+        }
+        let header_version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if header_version != HEADER_VERSION {
+            return Err(ImageError::UnsupportedVersion(header_version));
+        }
+        if CRC32.checksum(&bytes[..124]) != read_u32(bytes, 124) {
+            return Err(ImageError::HeaderCrc);
+        }
+
+        let mut sha256 = [0; 32];
+        sha256.copy_from_slice(&bytes[20..52]);
+        let mut signature = [0; SIGNATURE_LEN];
+        signature.copy_from_slice(&bytes[52..116]);
+
+        Ok(Self {
+            flags: u16::from_le_bytes([bytes[6], bytes[7]]),
+            payload_len: read_u32(bytes, 8),
+            version: FirmwareVersion::from_u32(read_u32(bytes, 12)),
+            crc32: read_u32(bytes, 16),
+            sha256,
+            signature,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&HEADER_VERSION.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.payload_len.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.version.to_u32().to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes[20..52].copy_from_slice(&self.sha256);
+        bytes[52..116].copy_from_slice(&self.signature);
+        let crc = CRC32.checksum(&bytes[..124]);
+        bytes[124..128].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Describe `payload` with an unsigned header
+    pub fn for_payload(payload: &[u8], version: FirmwareVersion) -> Self {
+        Self {
+            flags: 0,
+            payload_len: payload.len() as u32,
+            version,
+            crc32: CRC32.checksum(payload),
+            sha256: Sha256::digest(payload).into(),
+            signature: [0; SIGNATURE_LEN],
+        }
+    }
+
+    /// Sign the payload digest with an Ed25519 secret key
+    #[cfg(all(feature = "std", feature = "ed25519"))]
+    pub fn sign(&mut self, secret_key: &[u8; 32]) {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let key = SigningKey::from_bytes(secret_key);
+        self.signature = key.sign(&self.sha256).to_bytes();
+        self.flags |= FLAG_SIGNED;
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Incremental verification of a payload streamed in arbitrary chunks
+pub struct Verifier {
+    header: ImageHeader,
+    crc: Digest<'static, u32>,
+    sha: Sha256,
+    received: u32,
+}
+
+impl Verifier {
+    /// `capacity` is the size of the partition the payload runs from, ACTIVE;
+    /// the DFU partition it is downloaded into is larger
+    pub fn new(header: ImageHeader, capacity: u32) -> Result<Self, ImageError> {
+        if header.payload_len > capacity {
+            return Err(ImageError::TooLarge);
+        }
+        Ok(Self {
+            header,
+            crc: CRC32.digest(),
+            sha: Sha256::new(),
+            received: 0,
+        })
+    }
+
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), ImageError> {
+        let received = self.received as usize + chunk.len();
+        if received > self.header.payload_len as usize {
+            return Err(ImageError::LengthMismatch);
+        }
+        self.crc.update(chunk);
+        self.sha.update(chunk);
+        self.received = received as u32;
+        Ok(())
+    }
+
+    /// Check length, CRC and digest. When `public_key` is given the image
+    /// must also carry a valid Ed25519 signature made with the matching key.
+    pub fn finish(
+        self,
+        public_key: Option<&[u8; PUBLIC_KEY_LEN]>,
+    ) -> Result<ImageHeader, ImageError> {
+        if self.received != self.header.payload_len {
+            return Err(ImageError::LengthMismatch);
+        }
+        if self.crc.finalize() != self.header.crc32 {
+            return Err(ImageError::PayloadCrc);
+        }
+        let digest: [u8; 32] = self.sha.finalize().into();
+        if digest != self.header.sha256 {
+            return Err(ImageError::Digest);
+        }
+        if let Some(key) = public_key {
+            if !self.header.is_signed() {
+                return Err(ImageError::Unsigned);
+            }
+            verify_signature(key, &digest, &self.header.signature)?;
+        }
+        Ok(self.header)
+    }
+}
+
+#[cfg(feature = "ed25519")]
+fn verify_signature(
+    public_key: &[u8; PUBLIC_KEY_LEN],
+    digest: &[u8; 32],
+    signature: &[u8; SIGNATURE_LEN],
+) -> Result<(), ImageError> {
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    let key = VerifyingKey::from_bytes(public_key).map_err(|_| ImageError::Signature)?;
+    key.verify_strict(digest, &Signature::from_bytes(signature))
+        .map_err(|_| ImageError::Signature)
+}
+
+#[cfg(not(feature = "ed25519"))]
+fn verify_signature(
+    _public_key: &[u8; PUBLIC_KEY_LEN],
+    _digest: &[u8; 32],
+    _signature: &[u8; SIGNATURE_LEN],
+) -> Result<(), ImageError> {
+    // Built without signature support, a required signature can never be valid
+    Err(ImageError::Signature)
+}
+
+/// Verify a complete image held in memory, header included
+pub fn verify_image(
+    image: &[u8],
+    capacity: u32,
+    public_key: Option<&[u8; PUBLIC_KEY_LEN]>,
+) -> Result<ImageHeader, ImageError> {
+    let Some((header, payload)) = image.split_first_chunk::<HEADER_LEN>() else {
+        return Err(ImageError::LengthMismatch);
+    };
+    let mut verifier = Verifier::new(ImageHeader::parse(header)?, capacity)?;
+    verifier.update(payload)?;
+    verifier.finish(public_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION: FirmwareVersion = FirmwareVersion {
+        major: 1,
+        minor: 2,
+        patch: 3,
+    };
+
+    fn payload() -> Vec<u8> {
+        (0..5000u32).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    fn image(header: &ImageHeader, payload: &[u8]) -> Vec<u8> {
+        let mut image = header.to_bytes().to_vec();
+        image.extend_from_slice(payload);
+        image
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = ImageHeader::for_payload(&payload(), VERSION);
+        let parsed = ImageHeader::parse(&header.to_bytes()).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.version, VERSION);
+        assert!(!parsed.is_signed());
+    }
+
+    #[test]
+    fn rejects_corrupted_header() {
+        let mut bytes = ImageHeader::for_payload(&payload(), VERSION).to_bytes();
+        bytes[9] ^= 0x01;
+        assert_eq!(ImageHeader::parse(&bytes), Err(ImageError::HeaderCrc));
+
+        bytes[0] = b'X';
+        assert_eq!(ImageHeader::parse(&bytes), Err(ImageError::BadMagic));
+    }
+
+    #[test]
+    fn verifies_streamed_payload() {
+        let payload = payload();
+        let header = ImageHeader::for_payload(&payload, VERSION);
+        let mut verifier = Verifier::new(header.clone(), 8192).unwrap();
+        for chunk in payload.chunks(64) {
+            verifier.update(chunk).unwrap();
+        }
+        assert_eq!(verifier.finish(None), Ok(header));
+    }
+
+    #[test]
+    fn detects_payload_errors() {
+        let payload = payload();
+        let header = ImageHeader::for_payload(&payload, VERSION);
+
+        let mut corrupted = payload.clone();
+        corrupted[1234] ^= 0x80;
+        assert_eq!(
+            verify_image(&image(&header, &corrupted), 8192, None),
+            Err(ImageError::PayloadCrc)
+        );
+
+        assert_eq!(
+            verify_image(&image(&header, &payload[..4000]), 8192, None),
+            Err(ImageError::LengthMismatch)
+        );
+        assert_eq!(
+            verify_image(&image(&header, &payload), 4096, None),
+            Err(ImageError::TooLarge)
+        );
+
+        // Matching CRC but a different digest means the header was forged
+        let mut forged = header.clone();
+        forged.sha256[0] ^= 0xFF;
+        assert_eq!(
+            verify_image(&image(&forged, &payload), 8192, None),
+            Err(ImageError::Digest)
+        );
+    }
+
+    #[test]
+    fn rejects_images_larger_than_active() {
+        // The hub's layout: ACTIVE 48K, DFU 50K
+        const ACTIVE: u32 = 48 * 1024;
+        const DFU: u32 = 50 * 1024;
+        let payload: Vec<u8> = (0..49 * 1024u32).map(|i| i as u8).collect();
+        let header = ImageHeader::for_payload(&payload, VERSION);
+        assert!(Verifier::new(header.clone(), DFU).is_ok());
+        assert!(matches!(
+            Verifier::new(header.clone(), ACTIVE),
+            Err(ImageError::TooLarge)
+        ));
+        assert_eq!(
+            verify_image(&image(&header, &payload), ACTIVE, None),
+            Err(ImageError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn requires_signature_when_key_is_given() {
+        let payload = payload();
+        let header = ImageHeader::for_payload(&payload, VERSION);
+        assert_eq!(
+            verify_image(&image(&header, &payload), 8192, Some(&[0; 32])),
+            Err(ImageError::Unsigned)
+        );
+    }
+
+    #[cfg(all(feature = "std", feature = "ed25519"))]
+    #[test]
+    fn verifies_signature() {
+        use ed25519_dalek::SigningKey;
+
+        let secret = [0x42; 32];
+        let public = SigningKey::from_bytes(&secret).verifying_key().to_bytes();
+        let payload = payload();
+        let mut header = ImageHeader::for_payload(&payload, VERSION);
+        header.sign(&secret);
+
+        assert!(verify_image(&image(&header, &payload), 8192, Some(&public)).is_ok());
+
+        let other = SigningKey::from_bytes(&[0x24; 32])
+            .verifying_key()
+            .to_bytes();
+        assert_eq!(
+            verify_image(&image(&header, &payload), 8192, Some(&other)),
+            Err(ImageError::Signature)
+        );
+    }
+}
@@ -0,0 +1,22 @@
+/* STM32G431CB, firmware linked for the ACTIVE partition behind bootloader/ */
+/* Keep in sync with bootloader/memory.x */
+MEMORY
+{
+  BOOTLOADER       : ORIGIN = 0x08000000, LENGTH = 24K
+  BOOTLOADER_STATE : ORIGIN = 0x08006000, LENGTH = 2K
+  FLASH            : ORIGIN = 0x08006800, LENGTH = 48K
+  /* DFU must be one page larger than ACTIVE for the swap */
+  DFU              : ORIGIN = 0x08012800, LENGTH = 50K
+  /* Reserved for persistent data, identical in every layout */
+  STORAGE          : ORIGIN = 0x0801F000, LENGTH = 4K
+  RAM              : ORIGIN = 0x20000000, LENGTH = 32K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
+
+__storage_start = ORIGIN(STORAGE) - ORIGIN(BOOTLOADER);
+__storage_end = ORIGIN(STORAGE) + LENGTH(STORAGE) - ORIGIN(BOOTLOADER);
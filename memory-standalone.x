@@ -0,0 +1,11 @@
+/* STM32G431CB, firmware flashed directly at the start of flash (no bootloader) */
+MEMORY
+{
+  FLASH   : ORIGIN = 0x08000000, LENGTH = 124K
+  /* Reserved for persistent data, identical in every layout */
+  STORAGE : ORIGIN = 0x0801F000, LENGTH = 4K
+  RAM     : ORIGIN = 0x20000000, LENGTH = 32K
+}
+
+__storage_start = ORIGIN(STORAGE) - ORIGIN(FLASH);
+__storage_end = ORIGIN(STORAGE) + LENGTH(STORAGE) - ORIGIN(FLASH);
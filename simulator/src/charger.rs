@@ -0,0 +1,128 @@
+// simulator/src/charger.rs
+// A USB PD charger on the hub input, talking to the firmware's sink policy
+//
+// Offers 5/9/15/20 V and a PPS range, accepts every request and switches
+// its output at PS_RDY. Every message in both directions goes to the PD
+// trace, as the UCPD glue does on the board, so the contract and trace
+// pages show a real negotiation.
+
+use iso_usb_hub_core::pd::message::{
+    ControlType, DataType, MAX_MESSAGE_LEN, Message, MessageType, SpecRevision,
+};
+use iso_usb_hub_core::pd::policy::{Action, PdStatus, SinkConfig, SinkPolicy};
+use iso_usb_hub_core::pd::trace::{self, TraceEvent};
+
+// The source applies Rp a moment after power-up
+const ATTACH_MS: u64 = 200;
+const SAFE_5V_MV: u32 = 5000;
+
+fn fixed_pdo(voltage_mv: u32, current_ma: u32) -> u32 {
+    ((voltage_mv / 50) << 10) | (current_ma / 10)
+}
+
+fn pps_apdo(min_voltage_mv: u32, max_voltage_mv: u32, current_ma: u32) -> u32 {
+    (0b11 << 30)
+        | ((max_voltage_mv / 100) << 17)
+        | ((min_voltage_mv / 100) << 8)
+        | (current_ma / 50)
+}
+
+pub struct Charger {
+    policy: SinkPolicy,
+    attached: bool,
+    output_mv: u32,
+    message_id: u8,
+}
+
+impl Charger {
+    pub fn new() -> Self {
+        Self {
+            policy: SinkPolicy::new(SinkConfig::default()),
+            attached: false,
+            output_mv: SAFE_5V_MV,
+            message_id: 0,
+        }
+    }
+
+    /// VBUS the charger currently supplies
+    pub fn output_mv(&self) -> u32 {
+        self.output_mv
+    }
+
+    /// Run one loop period; returns the sink status when it changed
+    pub fn step(&mut self, now_ms: u64) -> Option<PdStatus> {
+        let before = self.policy.status();
+        if !self.attached && now_ms >= ATTACH_MS {
+            self.attached = true;
+            self.policy.attach(now_ms);
+            self.send_capabilities(now_ms);
+        }
+        let action = self.policy.poll(now_ms);
+        self.act(action, now_ms);
+
+        let status = self.policy.status();
+        (status != before).then_some(status)
+    }
+
+    fn send_capabilities(&mut self, now_ms: u64) {
+        let capabilities = [
+            fixed_pdo(5000, 3000) | 1 << 27,
+            fixed_pdo(9000, 3000),
+            fixed_pdo(15000, 3000),
+            fixed_pdo(20000, 3250),
+            pps_apdo(3300, 21000, 3000),
+        ];
+        let message = Message::data(
+            DataType::SourceCapabilities,
+            SpecRevision::R3_0,
+            &capabilities,
+        );
+        self.deliver(message, now_ms);
+    }
+
+    /// Hand a message from the charger to the sink
+    fn deliver(&mut self, mut message: Message, now_ms: u64) {
+        message.header.power_role_source = true;
+        message.header.data_role_dfp = true;
+        message.header.message_id = self.message_id;
+        self.message_id = (self.message_id + 1) % 8;
+        record(TraceEvent::Received, &message);
+
+        let action = self.policy.handle(&message, now_ms);
+        self.act(action, now_ms);
+    }
+
+    /// Answer what the sink does
+    fn act(&mut self, action: Action, now_ms: u64) {
+        match action {
+            Action::None => {}
+            Action::Send(message) => {
+                record(TraceEvent::Transmitted, &message);
+                if message.header.message_type == MessageType::Data(DataType::Request) {
+                    self.deliver(
+                        Message::control(ControlType::Accept, SpecRevision::R3_0),
+                        now_ms,
+                    );
+                    self.deliver(
+                        Message::control(ControlType::PsRdy, SpecRevision::R3_0),
+                        now_ms,
+                    );
+                    if let Some(contract) = self.policy.contract() {
+                        self.output_mv = contract.voltage_mv;
+                    }
+                }
+            }
+            Action::HardReset => {
+                trace::record(TraceEvent::HardResetSent, &[]);
+                self.output_mv = SAFE_5V_MV;
+                self.send_capabilities(now_ms);
+            }
+        }
+    }
+}
+
+fn record(event: TraceEvent, message: &Message) {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let len = message.encode(&mut buf);
+    trace::record(event, &buf[..len]);
+}
@@ -0,0 +1,156 @@
+// simulator/src/host.rs
+// The `hal` traits on the host: the core crate's frame buffer, written to
+// PNG or the terminal, a RAM event log and a burst capture fed by the
+// simulator
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use embassy_time::Instant;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::RgbColor;
+use iso_usb_hub_core::capture::{Capture, Status, Trigger};
+use iso_usb_hub_core::event_log::ring::{Event, EventRing, ResetCause};
+use iso_usb_hub_core::hal::{Platform, Sound};
+use iso_usb_hub_core::mock::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+use iso_usb_hub_core::storage::counters::Counters;
+use iso_usb_hub_core::storage::settings::Settings;
+
+/// What the glass shows: nothing while the panel sleeps
+fn shown(panel: &Framebuffer, x: usize, y: usize) -> Rgb888 {
+    if panel.is_asleep() {
+        Rgb888::BLACK
+    } else {
+        Rgb888::from(panel.pixel(x, y))
+    }
+}
+
+/// Write the panel as an RGB PNG, every pixel `scale` x `scale`
+pub fn save_png(panel: &Framebuffer, path: &Path, scale: usize) -> io::Result<()> {
+    let mut data = Vec::with_capacity(WIDTH * HEIGHT * scale * scale * 3);
+    for y in 0..HEIGHT * scale {
+        for x in 0..WIDTH * scale {
+            let color = shown(panel, x / scale, y / scale);
+            data.extend_from_slice(&[color.r(), color.g(), color.b()]);
+        }
+    }
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, (WIDTH * scale) as u32, (HEIGHT * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)
+}
+
+/// Print the panel with 24-bit ANSI colours, two pixel rows per line
+pub fn print_ansi(panel: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    for y in (0..HEIGHT).step_by(2) {
+        for x in 0..WIDTH {
+            let top = shown(panel, x, y);
+            let bottom = shown(panel, x, y + 1);
+            write!(
+                out,
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                top.r(),
+                top.g(),
+                top.b(),
+                bottom.r(),
+                bottom.g(),
+                bottom.b()
+            )?;
+        }
+        writeln!(out, "\x1b[0m")?;
+    }
+    Ok(())
+}
+
+/// Event log in RAM; events, sounds and saves go to stdout
+pub struct Host {
+    ring: EventRing,
+    saved_settings: Option<Settings>,
+    capture: Capture,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        let mut host = Self {
+            ring: EventRing::new(),
+            saved_settings: None,
+            capture: Capture::new(),
+        };
+        host.record(Event::Boot(ResetCause::PowerOn));
+        host
+    }
+
+    /// Settings saved from the menu or by a calibration since the last call
+    pub fn take_saved_settings(&mut self) -> Option<Settings> {
+        self.saved_settings.take()
+    }
+
+    /// The trigger of a running capture
+    pub fn capturing(&self) -> Option<Trigger> {
+        let status = self.capture.status();
+        status.trigger.filter(|_| status.is_running())
+    }
+
+    /// One burst sample of the port being captured
+    pub fn feed_capture(&mut self, current_ua: i32, at_us: u64) {
+        if self.capture.feed(current_ua, at_us) {
+            println!(
+                "capture done: peak {} uA, {} us per sample",
+                self.capture.peak_ua(),
+                self.capture.period_us()
+            );
+        }
+    }
+}
+
+impl Platform for Host {
+    fn record(&mut self, event: Event) {
+        let uptime_s = Instant::now().as_secs() as u32;
+        let (_, record) = self.ring.push(0, uptime_s, event);
+        println!("event: {}", record);
+    }
+
+    fn next_event_seq(&self) -> u32 {
+        self.ring.next_seq()
+    }
+
+    fn events(&self) -> EventRing {
+        self.ring.clone()
+    }
+
+    fn play(&mut self, sound: Sound) {
+        println!("sound: {:?}", sound);
+    }
+
+    fn save_counters(&mut self, counters: &Counters) {
+        let energy_mj: u64 = counters.ports.iter().map(|p| p.energy_mj).sum();
+        println!("counters saved: {} mJ in total", energy_mj);
+    }
+
+    fn save_settings(&mut self, settings: &Settings) {
+        println!("settings saved: {:?}", settings);
+        self.record(Event::SettingsChanged);
+        self.saved_settings = Some(*settings);
+    }
+
+    fn arm_capture(&mut self, trigger: Option<Trigger>) {
+        match trigger {
+            Some(trigger) => {
+                println!("capture armed: {:?}", trigger);
+                self.capture.arm(trigger);
+            }
+            None => self.capture.stop(),
+        }
+    }
+
+    fn capture_status(&self) -> Status {
+        self.capture.status()
+    }
+
+    fn capture(&self) -> Capture {
+        self.capture.clone()
+    }
+}
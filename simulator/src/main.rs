@@ -0,0 +1,304 @@
+// simulator/src/main.rs
+// Host simulator of the hub's display UI
+//
+// Runs the firmware's `App` and display pages from the core crate against a
+// frame buffer, a scripted or random sensor source and a simulated PD
+// charger, on a virtual clock. Commands from stdin or a script
+// press or hold BTN2/BTN3 and advance time; the panel is written to a PNG
+// after every command. Burst captures get samples between the loops, as
+// often as the firmware's capture task reads them.
+//
+//   cargo run --target x86_64-unknown-linux-gnu -- [--random SEED]
+//       [--script FILE] [--out FILE] [--scale N] [--ansi]
+
+mod charger;
+mod host;
+mod scenario;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::Subscriber;
+use embassy_time::{Duration, Instant, MockDriver};
+use iso_usb_hub_core::app::App;
+use iso_usb_hub_core::calibration::Request;
+use iso_usb_hub_core::capture::{SAMPLE_PERIOD_US, Trigger};
+use iso_usb_hub_core::hal::{
+    Button, LONG_PRESS_MS, Platform, Press, REPEAT_DELAY_MS, REPEAT_PERIOD_MS,
+};
+use iso_usb_hub_core::mock::framebuffer::Framebuffer;
+use iso_usb_hub_core::pd::trace::TraceRecord;
+use iso_usb_hub_core::shared::{self, PORT_COUNT};
+use iso_usb_hub_core::storage::counters::Counters;
+use iso_usb_hub_core::storage::settings::Settings;
+
+use charger::Charger;
+use host::Host;
+use scenario::Scenario;
+
+// The firmware main loop runs every 100 ms
+const LOOP_PERIOD_MS: u64 = 100;
+
+const HELP: &str = "\
+l, a        press BTN2 (previous page, next when rotated)
+r, d        press BTN3 (next page, previous when rotated)
+L, R        long press BTN2/BTN3 (settings menu: leave, select)
+hold l|r S  hold BTN2/BTN3 for S seconds, repeating after 1 s
+rotate      turn the display by 180°, like `set rotate` on the console
+sleep MIN   idle minutes before the display sleeps, 0 never, like `set sleep`
+cal zero    zero calibration, like `calibrate zero` on the console
+cal P MA    gain calibration of port P carrying MA, like `calibrate P MA`
+cap P MA    arm a burst capture of port P above MA, like `capture P MA`
+<enter>     run one loop (100 ms)
+w SECONDS   run for a while, e.g. `w 30` or `w 0.5`
+png FILE    write the panel to FILE
+q           quit";
+
+struct Options {
+    scenario: Scenario,
+    script: Option<PathBuf>,
+    out: PathBuf,
+    scale: usize,
+    ansi: bool,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        scenario: Scenario::demo(),
+        script: None,
+        out: PathBuf::from("sim.png"),
+        scale: 4,
+        ansi: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--random" => {
+                let seed = value()?.parse().map_err(|_| "bad seed".to_string())?;
+                options.scenario = Scenario::random(seed);
+            }
+            "--script" => options.script = Some(value()?.into()),
+            "--out" => options.out = value()?.into(),
+            "--scale" => {
+                options.scale = value()?.parse().map_err(|_| "bad scale".to_string())?;
+            }
+            "--ansi" => options.ansi = true,
+            _ => return Err(format!("unknown option {arg}")),
+        }
+    }
+    Ok(options)
+}
+
+struct Simulator {
+    app: App,
+    settings: Settings,
+    host: Host,
+    panel: Framebuffer,
+    scenario: Scenario,
+    charger: Charger,
+    scale: usize,
+    pd_trace: Subscriber<'static, CriticalSectionRawMutex, TraceRecord, 16, 3, 1>,
+}
+
+impl Simulator {
+    fn new(scenario: Scenario, scale: usize) -> Self {
+        let Ok(pd_trace) = shared::PD_TRACE.subscriber() else {
+            unreachable!("the simulator is the only PD_TRACE subscriber");
+        };
+        Self {
+            app: App::new(Settings::default(), Counters::default()),
+            settings: Settings::default(),
+            host: Host::new(),
+            panel: Framebuffer::new(),
+            scenario,
+            charger: Charger::new(),
+            scale,
+            pd_trace,
+        }
+    }
+
+    /// One pass of the firmware main loop
+    fn step(&mut self) -> Result<(), String> {
+        let start_us = Instant::now().as_micros();
+        MockDriver::get().advance(Duration::from_millis(LOOP_PERIOD_MS));
+        let now_ms = Instant::now().as_millis();
+        if let Some(trigger) = self.host.capturing() {
+            for t_us in (start_us..now_ms * 1000).step_by(SAMPLE_PERIOD_US as usize) {
+                let amps = self.scenario.burst_amps(trigger.port, t_us);
+                self.host.feed_capture((amps * 1_000_000.0) as i32, t_us);
+            }
+        }
+
+        let status = self.charger.step(now_ms);
+        let readings = self.scenario.sample(now_ms, self.charger.output_mv());
+        self.app
+            .update(&mut self.host, readings, [true; PORT_COUNT]);
+        while let Some(record) = self.pd_trace.try_next_message_pure() {
+            self.app.pd_trace(&record);
+        }
+        if let Some(status) = status {
+            self.app.pd_status(&mut self.host, status);
+        }
+        // The firmware hears back through `shared::SETTINGS`
+        if let Some(settings) = self.host.take_saved_settings() {
+            self.settings = settings;
+        }
+        block_on(self.app.draw(&mut self.panel, &self.host))
+            .map_err(|e| format!("draw failed on {:?}: {:?}", self.app.page(), e))
+    }
+
+    fn press(&mut self, button: Button, press: Press) -> Result<(), String> {
+        self.app.press(&mut self.host, button, press);
+        self.step()
+    }
+
+    /// Run one command; false to quit
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => self.step()?,
+            (Some("l" | "a"), None) => self.press(Button::Left, Press::Short)?,
+            (Some("r" | "d"), None) => self.press(Button::Right, Press::Short)?,
+            (Some("L"), None) => self.press(Button::Left, Press::Long)?,
+            (Some("R"), None) => self.press(Button::Right, Press::Long)?,
+            (Some("hold"), Some(button)) => {
+                let button = match button {
+                    "l" => Button::Left,
+                    "r" => Button::Right,
+                    _ => return Err("hold l or r".into()),
+                };
+                let seconds: f32 = words
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or("bad duration")?;
+                let held_ms = (seconds * 1000.0) as u64;
+                // Like the button task: a loop period between repeats
+                let repeats = held_ms.saturating_sub(REPEAT_DELAY_MS) / REPEAT_PERIOD_MS;
+                if held_ms < LONG_PRESS_MS {
+                    self.press(button, Press::Short)?;
+                } else if repeats == 0 {
+                    self.press(button, Press::Long)?;
+                }
+                for i in 0..repeats {
+                    self.press(button, Press::Repeat(i as u16))?;
+                }
+            }
+            (Some("rotate"), None) => {
+                self.settings.rotated = !self.settings.rotated;
+                self.app.set_settings(self.settings);
+                self.step()?;
+            }
+            (Some("sleep"), Some(minutes)) => {
+                self.settings.sleep_minutes = minutes.parse().map_err(|_| "bad minutes")?;
+                self.app.set_settings(self.settings);
+                self.step()?;
+            }
+            (Some("cal"), Some("zero")) => {
+                self.app.calibrate(Request::Zero);
+                self.step()?;
+            }
+            (Some("cal"), Some(port)) => {
+                let port: usize = port.parse().map_err(|_| "bad port")?;
+                let port = port
+                    .checked_sub(1)
+                    .filter(|&i| i < PORT_COUNT)
+                    .ok_or("no such port")?;
+                let reference_ma = words
+                    .next()
+                    .and_then(|ma| ma.parse().ok())
+                    .ok_or("bad current")?;
+                self.app.calibrate(Request::Gain { port, reference_ma });
+                self.step()?;
+            }
+            (Some("cap"), Some(port)) => {
+                let port: usize = port.parse().map_err(|_| "bad port")?;
+                let level_ma = words
+                    .next()
+                    .and_then(|ma| ma.parse().ok())
+                    .ok_or("bad current")?;
+                let trigger = Trigger {
+                    port: port.checked_sub(1).ok_or("no such port")?,
+                    level_ma,
+                };
+                if !trigger.is_valid() {
+                    return Err("no such port or zero level".into());
+                }
+                self.host.arm_capture(Some(trigger));
+                self.step()?;
+            }
+            (Some("w"), Some(seconds)) => {
+                let seconds: f32 = seconds.parse().map_err(|_| "bad duration")?;
+                let loops = (seconds * 1000.0 / LOOP_PERIOD_MS as f32).round() as u32;
+                for _ in 0..loops.max(1) {
+                    self.step()?;
+                }
+            }
+            (Some("png"), Some(path)) => host::save_png(&self.panel, path.as_ref(), self.scale)
+                .map_err(|e| format!("{path}: {e}"))?,
+            (Some("q"), None) => return Ok(false),
+            _ => return Err(format!("unknown command `{line}`\n{HELP}")),
+        }
+        Ok(true)
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let input: Box<dyn BufRead> = match &options.script {
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdin().lock()),
+    };
+    let interactive = options.script.is_none() && io::stdin().is_terminal();
+    if interactive {
+        println!("{HELP}");
+    }
+
+    let mut simulator = Simulator::new(options.scenario, options.scale);
+    if let Err(e) = simulator.step() {
+        eprintln!("{e}");
+        return ExitCode::FAILURE;
+    }
+    for line in input.lines() {
+        let Ok(line) = line else { break };
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        match simulator.command(line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            // A typo shouldn't end an interactive session
+            Err(e) if interactive => eprintln!("{e}"),
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        if let Err(e) = host::save_png(&simulator.panel, &options.out, simulator.scale) {
+            eprintln!("{}: {e}", options.out.display());
+            return ExitCode::FAILURE;
+        }
+        if options.ansi {
+            let _ = host::print_ansi(&simulator.panel, &mut io::stdout());
+        }
+        let _ = io::stdout().flush();
+    }
+    ExitCode::SUCCESS
+}
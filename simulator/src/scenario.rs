@@ -0,0 +1,162 @@
+// simulator/src/scenario.rs
+// Sensor readings fed to the app instead of the INA226s
+//
+// `Demo` plays a fixed story on a fast clock: a phone on P2 charging through
+// CC, taper and trickle within a few minutes, and earbuds on P3 that are
+// plugged in and out again. `Random` wanders every downstream port between
+// idle and a few amps, with devices coming and going. The input port always
+// draws what the downstream ports take, at the charger's voltage. Burst
+// captures see the downstream currents at a finer time scale, where the
+// demo devices draw an inrush spike when they are plugged in.
+
+use iso_usb_hub_core::shared::{INPUT_PORT, PORT_COUNT, PortReadings};
+
+const DOWNSTREAM_MV: f32 = 5100.0;
+// Cable and switch drop per amp
+const DROP_MV_PER_A: f32 = 80.0;
+const HUB_EFFICIENCY: f32 = 0.9;
+const HUB_IDLE_W: f32 = 0.3;
+
+/// xorshift32, good enough for noise
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Uniform in `-1.0..1.0`
+    pub fn noise(&mut self) -> f32 {
+        (self.next() % 2001) as f32 / 1000.0 - 1.0
+    }
+}
+
+pub enum Scenario {
+    Demo(Rng),
+    Random { rng: Rng, amps: [f32; PORT_COUNT] },
+}
+
+impl Scenario {
+    pub fn demo() -> Self {
+        Scenario::Demo(Rng::new(1))
+    }
+
+    pub fn random(seed: u32) -> Self {
+        Scenario::Random {
+            rng: Rng::new(seed),
+            amps: [0.0; PORT_COUNT],
+        }
+    }
+
+    /// Readings at `t_ms` with the charger supplying `input_mv`
+    pub fn sample(&mut self, t_ms: u64, input_mv: u32) -> PortReadings {
+        let mut amps = [0.0; PORT_COUNT];
+        match self {
+            Scenario::Demo(rng) => {
+                let t_s = t_ms as f32 / 1000.0;
+                amps[1] = phone_amps(t_s);
+                amps[2] = earbuds_amps(t_s);
+                for port in [1, 2] {
+                    if amps[port] > 0.0 {
+                        amps[port] = (amps[port] + 0.01 * rng.noise()).max(0.0);
+                    }
+                }
+            }
+            Scenario::Random { rng, amps: state } => {
+                for (port, current) in state.iter_mut().enumerate() {
+                    if port == INPUT_PORT {
+                        continue;
+                    }
+                    // About one plug or unplug per port every minute
+                    if rng.next() % 600 == 0 {
+                        *current = if *current > 0.0 { 0.0 } else { 0.5 };
+                    } else if *current > 0.0 {
+                        *current = (*current + 0.05 * rng.noise()).clamp(0.02, 3.0);
+                    }
+                }
+                amps = *state;
+            }
+        }
+
+        let mut readings = [(0.0, 0.0, 0.0); PORT_COUNT];
+        let mut downstream_w = HUB_IDLE_W;
+        for (port, reading) in readings.iter_mut().enumerate() {
+            if port == INPUT_PORT {
+                continue;
+            }
+            let volts = (DOWNSTREAM_MV - DROP_MV_PER_A * amps[port]) / 1000.0;
+            *reading = (volts, amps[port], volts * amps[port]);
+            downstream_w += reading.2;
+        }
+        let input_w = downstream_w / HUB_EFFICIENCY;
+        let input_v = input_mv as f32 / 1000.0;
+        readings[INPUT_PORT] = (input_v, input_w / input_v, input_w);
+        readings
+    }
+
+    /// Current of a downstream `port` at `t_us`, for burst captures
+    pub fn burst_amps(&mut self, port: usize, t_us: u64) -> f32 {
+        match self {
+            Scenario::Demo(rng) => {
+                let t_s = t_us as f32 / 1_000_000.0;
+                let amps = match port {
+                    1 => phone_amps(t_s) + inrush_amps(t_s - PHONE_PLUG_S, 2.4, 0.004),
+                    2 => earbuds_amps(t_s) + inrush_amps(t_s - EARBUDS_PLUG_S, 0.9, 0.001),
+                    _ => 0.0,
+                };
+                if amps > 0.0 {
+                    (amps + 0.01 * rng.noise()).max(0.0)
+                } else {
+                    0.0
+                }
+            }
+            Scenario::Random { amps, .. } => amps[port],
+        }
+    }
+}
+
+/// Input capacitors charging `dt_s` after a plug-in, from `peak` with the
+/// time constant `tau_s`
+fn inrush_amps(dt_s: f32, peak: f32, tau_s: f32) -> f32 {
+    if dt_s < 0.0 {
+        0.0
+    } else {
+        peak * libm::expf(-dt_s / tau_s)
+    }
+}
+
+const PHONE_PLUG_S: f32 = 5.0;
+const EARBUDS_PLUG_S: f32 = 20.0;
+
+/// Phone plugged in at 5 s: 2 A CC for 2 minutes, then a taper with a
+/// 40 s time constant down to a 30 mA trickle that stops at 6 minutes
+fn phone_amps(t_s: f32) -> f32 {
+    const TAPER_S: f32 = 125.0;
+    if t_s < PHONE_PLUG_S {
+        0.0
+    } else if t_s < TAPER_S {
+        2.0
+    } else if t_s < 360.0 {
+        (2.0 * libm::expf(-(t_s - TAPER_S) / 40.0)).max(0.03)
+    } else {
+        0.0
+    }
+}
+
+/// Earbud case from 20 s to 4 minutes, 120 mA then topping off at 40 mA
+fn earbuds_amps(t_s: f32) -> f32 {
+    if !(EARBUDS_PLUG_S..240.0).contains(&t_s) {
+        0.0
+    } else if t_s < 90.0 {
+        0.12
+    } else {
+        0.04
+    }
+}
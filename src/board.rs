@@ -0,0 +1,174 @@
+// src/board.rs
+// The `hal` traits on this board
+//
+// The GC9D01 and its PWM backlight are the panel, wrapped because neither
+// the trait nor the driver belongs to this crate; events go to the
+// flash-backed event log, sounds to the buzzer task, counters to the
+// storage region and burst captures to the capture task.
+
+use core::convert::Infallible;
+
+use defmt::*;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::pwm::SetDutyCycle;
+use embedded_hal_async::spi::SpiDevice;
+use gc9d01::{GC9D01, Timer as Gc9d01Timer};
+
+use crate::capture::{Capture, Status, Trigger};
+use crate::display::dashboard::Error;
+use crate::event_log::ring::{Event, EventRing};
+use crate::hal::{Panel, Platform, Sound};
+use crate::shared::SETTINGS;
+use crate::storage::counters::Counters;
+use crate::storage::settings::Settings;
+use crate::{buzzer, capture, event_log, storage};
+
+/// The glass on this board, in the orientation the driver is set up for
+const PANEL_SIZE: (u16, u16) = (160, 40);
+
+/// The GC9D01 with its backlight on TIM1 CH1 (PA8)
+pub struct Gc9d01Panel<'a, BUS, DC, RST, TIMER, BL> {
+    driver: GC9D01<'a, BUS, DC, RST, TIMER>,
+    backlight: BL,
+    // (x, y, width, height) from the last `set_window`
+    window: (u16, u16, u16, u16),
+    brightness: u8,
+}
+
+impl<'a, BUS, DC, RST, TIMER, BL> Gc9d01Panel<'a, BUS, DC, RST, TIMER, BL>
+where
+    BL: SetDutyCycle,
+{
+    pub fn new(driver: GC9D01<'a, BUS, DC, RST, TIMER>, backlight: BL) -> Self {
+        Self {
+            driver,
+            backlight,
+            window: (0, 0, 0, 0),
+            brightness: 255,
+        }
+    }
+
+    fn backlight(&mut self, level: u8) -> Result<(), Error> {
+        self.backlight
+            .set_duty_cycle_fraction(level as u16, 255)
+            .map_err(|_| Error::DriverError)
+    }
+}
+
+impl<BUS, DC, RST, TIMER, BL> Panel for Gc9d01Panel<'_, BUS, DC, RST, TIMER, BL>
+where
+    BUS: SpiDevice,
+    DC: OutputPin<Error = Infallible>,
+    RST: OutputPin<Error = Infallible>,
+    TIMER: Gc9d01Timer,
+    BL: SetDutyCycle,
+{
+    fn size(&self) -> (u16, u16) {
+        PANEL_SIZE
+    }
+
+    async fn init(&mut self) -> Result<(), Error> {
+        self.driver.init().await.map_err(|_| Error::DriverError)?;
+        self.backlight(self.brightness)
+    }
+
+    async fn set_window(&mut self, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error> {
+        self.window = (x, y, width, height);
+        Ok(())
+    }
+
+    // The driver sets the window itself for every area it writes
+    async fn write_pixels(&mut self, pixels: &[Rgb565]) -> Result<(), Error> {
+        let (x, y, width, height) = self.window;
+        self.write_area(x, y, width, height, pixels).await
+    }
+
+    async fn write_area(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        pixels: &[Rgb565],
+    ) -> Result<(), Error> {
+        self.driver
+            .write_area(x, y, width, height, pixels)
+            .await
+            .map_err(|_| Error::DriverError)
+    }
+
+    async fn fill_color(&mut self, color: Rgb565) -> Result<(), Error> {
+        self.driver
+            .fill_color(color)
+            .await
+            .map_err(|_| Error::DriverError)
+    }
+
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Error> {
+        self.brightness = level;
+        self.backlight(level)
+    }
+
+    // Backlight off before sleep-in and on after sleep-out, so the blank
+    // controller is never lit; it keeps the picture for waking up
+    async fn sleep(&mut self, asleep: bool) -> Result<(), Error> {
+        if asleep {
+            self.backlight(0)?;
+            self.driver.sleep_in().await.map_err(|_| Error::DriverError)
+        } else {
+            self.driver
+                .sleep_out()
+                .await
+                .map_err(|_| Error::DriverError)?;
+            self.backlight(self.brightness)
+        }
+    }
+}
+
+pub struct Board;
+
+impl Platform for Board {
+    fn record(&mut self, event: Event) {
+        event_log::record(event);
+    }
+
+    fn next_event_seq(&self) -> u32 {
+        event_log::next_seq()
+    }
+
+    fn events(&self) -> EventRing {
+        event_log::snapshot()
+    }
+
+    fn play(&mut self, sound: Sound) {
+        buzzer::play(sound);
+    }
+
+    fn save_counters(&mut self, counters: &Counters) {
+        if let Err(e) = storage::save_counters(counters) {
+            warn!("Usage counters not saved: {}", e);
+        }
+    }
+
+    fn save_settings(&mut self, settings: &Settings) {
+        // Like `set` on the console: applied everywhere, then stored
+        SETTINGS.sender().send(*settings);
+        event_log::record(Event::SettingsChanged);
+        if let Err(e) = storage::save_settings(settings) {
+            warn!("Settings not saved: {}", e);
+        }
+    }
+
+    fn arm_capture(&mut self, trigger: Option<Trigger>) {
+        capture::arm(trigger);
+    }
+
+    fn capture_status(&self) -> Status {
+        capture::status()
+    }
+
+    fn capture(&self) -> Capture {
+        capture::snapshot()
+    }
+}
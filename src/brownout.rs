@@ -0,0 +1,43 @@
+// src/brownout.rs
+// Supply failure warning from the programmable voltage detector (PVD)
+//
+// The PVD fires while VDD falls through 2.9 V. The supply capacitors keep
+// the MCU running for a few milliseconds after that, enough to write the
+// usage counters before the brown-out reset at 2.1 V, and to leave a mark
+// so the next boot reports a brown-out instead of a power-on.
+
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::InterruptExt;
+use embassy_stm32::pac;
+use embassy_stm32::pac::pwr::vals::Pls;
+
+use crate::shared::COUNTERS;
+use crate::{storage, supervisor};
+
+// PVD output on EXTI line 16
+const PVD_LINE: usize = 16;
+
+pub fn init() {
+    pac::PWR.cr2().modify(|w| {
+        // PLS = 110: 2.9 V falling
+        w.set_pls(Pls::from_bits(0b110));
+        w.set_pvde(true);
+    });
+    // PVDO rises when VDD drops below the level
+    pac::EXTI.rtsr(0).modify(|w| w.set_line(PVD_LINE, true));
+    pac::EXTI.pr(0).write(|w| w.set_line(PVD_LINE, true));
+    pac::EXTI.imr(0).modify(|w| w.set_line(PVD_LINE, true));
+
+    interrupt::PVD_PVM.unpend();
+    unsafe { interrupt::PVD_PVM.enable() };
+}
+
+#[interrupt]
+fn PVD_PVM() {
+    pac::EXTI.pr(0).write(|w| w.set_line(PVD_LINE, true));
+    // Tells a following BOR reset apart from a cold power-on
+    supervisor::note_supply_drop();
+    if let Some(counters) = COUNTERS.try_get() {
+        let _ = storage::save_counters(&counters);
+    }
+}
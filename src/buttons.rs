@@ -0,0 +1,65 @@
+// src/buttons.rs
+// Front panel buttons, BTN2 (PB1) and BTN3 (PB2), active low
+//
+// Each button has its own task; debounced presses are queued on
+// `shared::BUTTONS` and consumed by the main loop. A press is sent when the
+// button comes up, short or long by how long it was held; held past
+// `REPEAT_DELAY_MS` it repeats instead.
+
+use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Pull;
+use embassy_stm32::{Peri, peripherals};
+use embassy_time::{Duration, Instant, Timer, with_deadline};
+
+use crate::hal::{Button, LONG_PRESS_MS, Press, REPEAT_DELAY_MS, REPEAT_PERIOD_MS};
+use crate::shared::BUTTONS;
+
+const DEBOUNCE_MS: u64 = 20;
+
+pub fn init(
+    spawner: &Spawner,
+    btn2: Peri<'static, peripherals::PB1>,
+    btn2_exti: Peri<'static, peripherals::EXTI1>,
+    btn3: Peri<'static, peripherals::PB2>,
+    btn3_exti: Peri<'static, peripherals::EXTI2>,
+) {
+    let left = ExtiInput::new(btn2, btn2_exti, Pull::Up);
+    let right = ExtiInput::new(btn3, btn3_exti, Pull::Up);
+    spawner.must_spawn(button_task(left, Button::Left));
+    spawner.must_spawn(button_task(right, Button::Right));
+}
+
+/// Wait for the button to come up, until `deadline`; true if it did
+async fn released(input: &mut ExtiInput<'static>, deadline: Instant) -> bool {
+    with_deadline(deadline, input.wait_for_high()).await.is_ok()
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn button_task(mut input: ExtiInput<'static>, button: Button) -> ! {
+    loop {
+        input.wait_for_falling_edge().await;
+        Timer::after_millis(DEBOUNCE_MS).await;
+        if input.is_low() {
+            let down = Instant::now();
+            // Presses are dropped while the queue is full
+            if released(&mut input, down + Duration::from_millis(LONG_PRESS_MS)).await {
+                let _ = BUTTONS.try_send((button, Press::Short));
+            } else if released(&mut input, down + Duration::from_millis(REPEAT_DELAY_MS)).await {
+                let _ = BUTTONS.try_send((button, Press::Long));
+            } else {
+                let mut repeats = 0u16;
+                loop {
+                    let _ = BUTTONS.try_send((button, Press::Repeat(repeats)));
+                    repeats = repeats.saturating_add(1);
+                    let next = Instant::now() + Duration::from_millis(REPEAT_PERIOD_MS);
+                    if released(&mut input, next).await {
+                        break;
+                    }
+                }
+            }
+        }
+        input.wait_for_high().await;
+        Timer::after_millis(DEBOUNCE_MS).await;
+    }
+}
@@ -0,0 +1,62 @@
+// src/buzzer.rs
+// Passive buzzer on PC6, driven by TIM3 CH1 PWM
+//
+// Sounds are queued with `play` and rendered by one task as short tone
+// sequences. The buzzer stays silent while the `mute` setting is on.
+
+use embassy_executor::Spawner;
+use embassy_stm32::gpio::OutputType;
+use embassy_stm32::time::hz;
+use embassy_stm32::timer::low_level::CountingMode;
+use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
+use embassy_stm32::{Peri, peripherals};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Timer;
+
+use crate::hal::Sound;
+use crate::shared::SETTINGS;
+
+static SOUNDS: Channel<CriticalSectionRawMutex, Sound, 4> = Channel::new();
+
+pub fn init(
+    spawner: &Spawner,
+    tim: Peri<'static, peripherals::TIM3>,
+    pin: Peri<'static, peripherals::PC6>,
+) {
+    let pwm = SimplePwm::new(
+        tim,
+        Some(PwmPin::new(pin, OutputType::PushPull)),
+        None,
+        None,
+        None,
+        hz(4000),
+        CountingMode::EdgeAlignedUp,
+    );
+    spawner.must_spawn(buzzer_task(pwm));
+}
+
+/// Queue a sound; dropped while the queue is full or the buzzer is muted
+pub fn play(sound: Sound) {
+    let muted = SETTINGS.try_get().is_some_and(|s| s.buzzer_muted);
+    if !muted {
+        let _ = SOUNDS.try_send(sound);
+    }
+}
+
+#[embassy_executor::task]
+async fn buzzer_task(mut pwm: SimplePwm<'static, peripherals::TIM3>) -> ! {
+    loop {
+        let sound = SOUNDS.receive().await;
+        for &(frequency, duration_ms) in sound.tones() {
+            if frequency > 0 {
+                pwm.set_frequency(hz(frequency));
+                let mut ch1 = pwm.ch1();
+                ch1.set_duty_cycle_percent(50);
+                ch1.enable();
+            }
+            Timer::after_millis(duration_ms).await;
+            pwm.ch1().disable();
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// src/capture.rs
+// Burst capture task: samples one INA226 at a fixed pace while armed
+//
+// The trigger logic and the window live in the core crate. Here one task
+// owns an I2C device on the shared sensor bus. Once armed, it switches the
+// chosen sensor to fast shunt conversions and reads it every
+// `SAMPLE_PERIOD_US`, between the main loop's transfers, until the window is
+// full, the capture is stopped or the trigger times out, then puts the
+// sensor's configuration back. While a capture runs, the main loop still
+// reads that port's current, but its bus voltage stands still at the last
+// value converted before the arm.
+
+use core::cell::RefCell;
+
+use defmt::*;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_executor::Spawner;
+use embassy_stm32::i2c::I2c;
+use embassy_stm32::mode;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker};
+
+use iso_usb_hub_core::capture::{
+    ARM_TIMEOUT_US, SAMPLE_PERIOD_US, read_current_ua, restore, start_fast,
+};
+pub use iso_usb_hub_core::capture::{Capture, State, Status, Trigger};
+
+use crate::SENSOR_ADDRESSES;
+use crate::shared::SETTINGS;
+
+pub type SensorI2c = I2cDevice<'static, CriticalSectionRawMutex, I2c<'static, mode::Async>>;
+
+static CAPTURE: BlockingMutex<CriticalSectionRawMutex, RefCell<Capture>> =
+    BlockingMutex::new(RefCell::new(Capture::new()));
+// The latest request wins: a trigger to arm with, or `None` to stop
+static REQUEST: Signal<CriticalSectionRawMutex, Option<Trigger>> = Signal::new();
+
+pub fn init(spawner: &Spawner, i2c: SensorI2c) {
+    spawner.must_spawn(capture_task(i2c));
+}
+
+/// Arm with `trigger`, dropping the last window, or stop with `None`
+pub fn arm(trigger: Option<Trigger>) {
+    REQUEST.signal(trigger);
+}
+
+pub fn status() -> Status {
+    CAPTURE.lock(|capture| capture.borrow().status())
+}
+
+/// Copy of the capture, 2 KiB of samples
+pub fn snapshot() -> Capture {
+    CAPTURE.lock(|capture| capture.borrow().clone())
+}
+
+#[embassy_executor::task]
+async fn capture_task(mut i2c: SensorI2c) -> ! {
+    loop {
+        let Some(trigger) = REQUEST.wait().await else {
+            CAPTURE.lock(|capture| capture.borrow_mut().stop());
+            continue;
+        };
+        let port = trigger.port;
+        let address = SENSOR_ADDRESSES[port];
+        let settings = SETTINGS.try_get().unwrap_or_default();
+        let Ok(config) = start_fast(&mut i2c, address).await else {
+            warn!("Capture: INA226 of port {} not answering", port + 1);
+            continue;
+        };
+        CAPTURE.lock(|capture| capture.borrow_mut().arm(trigger));
+        info!(
+            "Capture armed on port {} at {} mA",
+            port + 1,
+            trigger.level_ma
+        );
+
+        // A new request ends this capture; the next turn of the loop takes it
+        let mut ticker = Ticker::every(Duration::from_micros(SAMPLE_PERIOD_US));
+        while !REQUEST.signaled() {
+            let sample = read_current_ua(
+                &mut i2c,
+                address,
+                &settings.sensors[port],
+                &settings.corrections[port],
+            )
+            .await;
+            let Ok(current_ua) = sample else {
+                warn!("Capture: INA226 of port {} failed", port + 1);
+                CAPTURE.lock(|capture| capture.borrow_mut().stop());
+                break;
+            };
+            let at_us = Instant::now().as_micros();
+            let (done, status) = CAPTURE.lock(|capture| {
+                let mut capture = capture.borrow_mut();
+                let done = capture
+                    .feed(current_ua, at_us)
+                    .then(|| (capture.peak_ua(), capture.period_us()));
+                (done, capture.status())
+            });
+            if let Some((peak_ua, period_us)) = done {
+                info!(
+                    "Capture done, peak {} uA, {} us per sample",
+                    peak_ua, period_us
+                );
+                break;
+            }
+            if !status.is_running() {
+                info!(
+                    "Capture stopped, no trigger within {} s",
+                    ARM_TIMEOUT_US / 1_000_000
+                );
+                break;
+            }
+            ticker.next().await;
+        }
+        if restore(&mut i2c, address, config).await.is_err() {
+            warn!("Capture: INA226 of port {} not restored", port + 1);
+        }
+    }
+}
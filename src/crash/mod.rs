@@ -0,0 +1,103 @@
+// src/crash/mod.rs
+// Panic record that survives the reset, for hubs without a debug probe
+//
+// With the `crash-record` feature this module is the panic handler instead
+// of panic-probe: it stores the location, message and the stack words above
+// the handler's own frame in `.uninit` RAM, which a software reset leaves
+// alone, and resets. The next boot takes the record out, shows it on the
+// boot screen and keeps it for the `crash` console command until the next
+// reset.
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+use self::record::CrashRecord;
+
+pub use iso_usb_hub_core::crash::record;
+
+#[unsafe(link_section = ".uninit.CRASH")]
+static mut CRASH: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+
+// Record taken at boot
+static LAST: BlockingMutex<CriticalSectionRawMutex, Cell<Option<CrashRecord>>> =
+    BlockingMutex::new(Cell::new(None));
+
+/// Take the record left by a panic before this boot, if any
+pub fn init() -> Option<CrashRecord> {
+    let slot = (&raw mut CRASH).cast::<CrashRecord>();
+    let mut record = unsafe { slot.read_volatile() };
+    if !record.is_valid() {
+        return None;
+    }
+    record.invalidate();
+    unsafe { slot.write_volatile(record) };
+
+    defmt::error!(
+        "Crashed at {}:{}: {}",
+        record.file(),
+        record.line,
+        record.message()
+    );
+    LAST.lock(|last| last.set(Some(record)));
+    Some(record)
+}
+
+/// The crash that caused this boot
+pub fn last() -> Option<CrashRecord> {
+    LAST.lock(|last| last.get())
+}
+
+#[cfg(feature = "crash-record")]
+mod handler {
+    use core::fmt::Write;
+    use core::panic::PanicInfo;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use cortex_m::peripheral::SCB;
+    use embassy_time::Instant;
+
+    use super::CRASH;
+    use super::record::{CrashRecord, read_stack};
+
+    // Top of the stack, from cortex-m-rt's link.x
+    unsafe extern "C" {
+        static _stack_start: u32;
+    }
+
+    static PANICKING: AtomicBool = AtomicBool::new(false);
+
+    #[panic_handler]
+    fn panic(info: &PanicInfo) -> ! {
+        cortex_m::interrupt::disable();
+        // A panic while recording keeps the first record
+        if PANICKING.swap(true, Ordering::Relaxed) {
+            SCB::sys_reset();
+        }
+
+        // `msp` points at this handler's own locals. The `PanicInfo` sits in
+        // the frame of core's `panic_fmt`, which called us, so the words from
+        // there up are the panicking code's return addresses and locals.
+        let sp = cortex_m::register::msp::read() as *const u32;
+        let frame = (info as *const PanicInfo).cast::<u32>();
+        let (from, stack) = unsafe { read_stack(sp, frame, &raw const _stack_start) };
+
+        let (file, line, column) = info
+            .location()
+            .map_or(("?", 0, 0), |l| (l.file(), l.line(), l.column()));
+        let uptime_ms = Instant::now().as_millis() as u32;
+        let mut record = CrashRecord::new(file, line, column, uptime_ms, from as u32, &stack);
+        let _ = write!(record, "{}", info.message());
+        record.seal();
+        unsafe {
+            (&raw mut CRASH)
+                .cast::<CrashRecord>()
+                .write_volatile(record)
+        };
+
+        defmt::error!("{}: {}", defmt::Display2Format(&record), record.message());
+        SCB::sys_reset()
+    }
+}
@@ -1,5 +0,0 @@
-// src/display/mod.rs
-pub mod dashboard;
-pub mod font;
-
-// 其他显示相关的模块可以在这里声明
\ No newline at end of file
@@ -0,0 +1,79 @@
+// src/event_log/mod.rs
+// Timestamped log of notable events, kept in RAM and mirrored to flash
+//
+// `ring` holds the record format and the ring buffer and lives in the core
+// crate. Every event is written to its ring slot's key in the storage region
+// right away, so the log survives resets and power cuts; at boot the ring
+// is rebuilt from those keys and the boot number continues from the newest.
+
+use core::cell::RefCell;
+
+use defmt::*;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Instant;
+
+use self::ring::{CAPACITY, Event, EventRecord, EventRing, RECORD_LEN, ResetCause};
+use crate::storage::{self, key, log::KEY_COUNT};
+
+pub use iso_usb_hub_core::event_log::ring;
+
+// One storage key per ring slot
+const _: () = assert!(key::EVENTS as usize + CAPACITY <= KEY_COUNT);
+
+struct State {
+    ring: EventRing,
+    boot: u16,
+}
+
+static LOG: BlockingMutex<CriticalSectionRawMutex, RefCell<State>> =
+    BlockingMutex::new(RefCell::new(State {
+        ring: EventRing::new(),
+        boot: 0,
+    }));
+
+/// Restore the log from flash and record the boot; needs `storage::init`
+pub fn init(cause: ResetCause) {
+    let mut ring = EventRing::new();
+    let mut buf = [0u8; RECORD_LEN];
+    for slot in 0..CAPACITY {
+        if let Ok(Some(len)) = storage::read(key::EVENTS + slot as u8, &mut buf) {
+            if let Ok(record) = EventRecord::decode(&buf[..len]) {
+                ring.restore(record);
+            }
+        }
+    }
+    let boot = ring.latest().map_or(0, |r| r.boot.wrapping_add(1));
+    info!(
+        "Boot {} after {} reset, {} events in the log",
+        boot,
+        cause.name(),
+        ring.iter().count()
+    );
+
+    LOG.lock(|state| *state.borrow_mut() = State { ring, boot });
+    record(Event::Boot(cause));
+}
+
+pub fn record(event: Event) {
+    let uptime_s = Instant::now().as_secs() as u32;
+    let (slot, record) = LOG.lock(|state| {
+        let mut state = state.borrow_mut();
+        let boot = state.boot;
+        state.ring.push(boot, uptime_s, event)
+    });
+    info!("Event: {}", Display2Format(&record));
+    if let Err(e) = storage::write(key::EVENTS + slot as u8, &record.encode()) {
+        warn!("Event {} not mirrored to flash: {}", record.seq, e);
+    }
+}
+
+/// Sequence number of the next event, to notice new ones
+pub fn next_seq() -> u32 {
+    LOG.lock(|state| state.borrow().ring.next_seq())
+}
+
+/// Copy of the ring, oldest event first when iterated
+pub fn snapshot() -> EventRing {
+    LOG.lock(|state| state.borrow().ring.clone())
+}
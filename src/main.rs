@@ -4,11 +4,15 @@
 
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice as EmbassySpiDevice;
 use embassy_executor::Spawner;
-use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::{Level, Output, OutputType, Speed};
 use embassy_stm32::i2c::{self, I2c}; // Import i2c module, I2c struct
 use embassy_stm32::spi::{Config as SpiConfig, Spi as Stm32Spi};
 use embassy_stm32::time::{Hertz, khz}; // Import khz and Hertz
+use embassy_stm32::timer::low_level::CountingMode;
+use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
 use embassy_stm32::{bind_interrupts, mode, peripherals}; // Import bind_interrupts, mode, peripherals
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
 use embedded_alloc::LlffHeap as Heap;
@@ -17,23 +21,48 @@ use embedded_graphics::prelude::WebColors;
 use gc9d01::{Config as DisplayDriverConfig, GC9D01, Orientation, Timer as Gc9d01Timer};
 use static_cell::StaticCell;
 
+use core::cell::RefCell;
 use core::ptr;
-use {defmt_rtt as _, panic_probe as _};
+use defmt_rtt as _;
+#[cfg(not(feature = "crash-record"))]
+use panic_probe as _;
 
 // Add imports for INA226 and shared bus I2C device
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice as EmbassyI2cDevice; // Alias for clarity
 use ina226::INA226;
 // Removed unused imports: AsyncI2c
 
+use app::App;
+use board::{Board, Gc9d01Panel};
 use defmt::*;
-use display::dashboard::Dashboard;
-mod display;
+use event_log::ring::ResetCause;
+use hal::Panel;
+use iso_usb_hub_core::{app, attach, calibration, display, hal, history, protocol, shared};
+use selftest::Check;
+use supervisor::Task;
+mod board;
+mod brownout;
+mod buttons;
+mod buzzer;
+mod capture;
+mod crash;
+mod event_log;
+mod pd;
+mod selftest;
+mod storage;
+mod supervisor;
+#[cfg(feature = "bootloader")]
+mod update;
+mod usb;
 
 extern crate alloc;
 
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
 
+/// INA226 of ports 1-3 on I2C1
+const SENSOR_ADDRESSES: [u8; 3] = [0x40, 0x41, 0x44];
+
 // This marks the entrypoint of our application and binds interrupts.
 bind_interrupts!(
     struct Irqs {
@@ -43,7 +72,10 @@ bind_interrupts!(
 );
 
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
+    #[cfg(all(feature = "usb-dfu", not(feature = "bootloader")))]
+    usb::dfu::jump_to_bootloader_if_requested();
+
     info!("Starting GC9D01 Example");
 
     let mut config = embassy_stm32::Config::default();
@@ -64,10 +96,19 @@ async fn main(_spawner: Spawner) {
         config.rcc.mux.adc12sel = mux::Adcsel::SYS;
         config.rcc.sys = Sysclk::PLL1_R;
         config.rcc.mux.clk48sel = mux::Clk48sel::HSI48;
-        // config.enable_ucpd1_dead_battery = true;
+        // Keep the dead-battery Rd on CC until the PD task takes over
+        config.enable_ucpd1_dead_battery = true;
     }
     let p = embassy_stm32::init(config);
 
+    // Read before anything else can reset the flags
+    let mut reset_cause = supervisor::reset_cause();
+    let crash = crash::init();
+    if crash.is_some() {
+        reset_cause = ResetCause::Panic;
+    }
+    info!("Reset cause: {}", reset_cause.name());
+
     // Initialize the allocator BEFORE you use it
     {
         use core::mem::MaybeUninit;
@@ -76,6 +117,51 @@ async fn main(_spawner: Spawner) {
         unsafe { HEAP.init(ptr::addr_of_mut!(HEAP_MEM) as usize, HEAP_SIZE) }
     }
 
+    // On-chip flash, shared by the bootloader state and persistent storage
+    static FLASH_CELL: StaticCell<storage::FlashMutex> = StaticCell::new();
+    let flash = FLASH_CELL.init(BlockingMutex::new(RefCell::new(Flash::new_blocking(
+        p.FLASH,
+    ))));
+    #[cfg(feature = "bootloader")]
+    update::init(flash);
+    storage::init(flash);
+    event_log::init(reset_cause);
+
+    // Persisted user settings, the console publishes changes
+    let settings = storage::load_settings();
+    shared::SETTINGS.sender().send(settings);
+
+    // Lifetime usage counters, saved periodically and on brown-out
+    let counters = storage::load_counters();
+    let counters_sender = shared::COUNTERS.sender();
+    counters_sender.send(counters);
+    let sessions_sender = shared::SESSIONS.sender();
+    brownout::init();
+
+    // IWDG, fed while the main loop keeps checking in
+    supervisor::init(&spawner, p.IWDG);
+
+    // Composite USB device (console / telemetry / HID / DFU, per Cargo features)
+    usb::init(&spawner, p.USB, p.PA12, p.PA11);
+
+    // Front panel buttons, used to switch pages
+    buttons::init(&spawner, p.PB1, p.EXTI1, p.PB2, p.EXTI2);
+
+    // Clicks on device attach/detach, unless muted
+    buzzer::init(&spawner, p.TIM3, p.PC6);
+
+    // USB PD sink negotiating the hub input on UCPD1
+    pd::ucpd::init(
+        &spawner,
+        pd::ucpd::UcpdResources {
+            ucpd: p.UCPD1,
+            cc1: p.PB6,
+            cc2: p.PB4,
+            rx_dma: p.DMA1_CH4,
+            tx_dma: p.DMA1_CH5,
+        },
+    );
+
     // Initialize I2C1
     let i2c_scl = p.PA15; // SCL pin for I2C1
     let i2c_sda = p.PB7; // SDA pin for I2C1
@@ -100,7 +186,8 @@ async fn main(_spawner: Spawner) {
     // Create a static mutex for the I2C bus using the full I2c type
     static I2C1_BUS_CELL: StaticCell<Mutex<CriticalSectionRawMutex, I2c<'static, mode::Async>>> =
         StaticCell::new(); // Use full I2c type
-    let i2c1_bus_mutex_ref = I2C1_BUS_CELL.init(Mutex::new(i2c1));
+    // Shared, so the capture task gets a device of its own
+    let i2c1_bus_mutex_ref: &'static _ = I2C1_BUS_CELL.init(Mutex::new(i2c1));
 
     // Initialize INA226 sensors using I2cDevice for shared bus access
     // Create I2cDevice instances from the shared bus mutex with correct type parameters
@@ -108,16 +195,76 @@ async fn main(_spawner: Spawner) {
     let i2c_device_2 = EmbassyI2cDevice::new(i2c1_bus_mutex_ref);
     let i2c_device_3 = EmbassyI2cDevice::new(i2c1_bus_mutex_ref);
 
+    // Self-test results, collected as the hardware comes up
+    let mut report = selftest::Report::new();
+    if settings.self_test {
+        let mut i2c = EmbassyI2cDevice::new(i2c1_bus_mutex_ref);
+        for (port, address) in SENSOR_ADDRESSES.into_iter().enumerate() {
+            let ids = selftest::sensor_ids(&mut i2c, address).await;
+            report.push(Check::SensorId {
+                port: port as u8,
+                ids,
+            });
+        }
+    }
+
     // Initialize INA226 sensors with the I2cDevice instances
-    let mut ina226_1 = INA226::new(i2c_device_1, 0x40);
-    let mut ina226_2 = INA226::new(i2c_device_2, 0x41);
-    let mut ina226_3 = INA226::new(i2c_device_3, 0x44);
+    let [address1, address2, address3] = SENSOR_ADDRESSES;
+    let mut ina226_1 = INA226::new(i2c_device_1, address1);
+    let mut ina226_2 = INA226::new(i2c_device_2, address2);
+    let mut ina226_3 = INA226::new(i2c_device_3, address3);
+
+    // Calibrate current/power readings from the stored sensor profiles; a
+    // sensor that doesn't answer reads as failed rather than stopping the boot
+    let [profile1, profile2, profile3] = settings.sensors;
+    let calibrated = [
+        ina226_1
+            .callibrate(profile1.shunt_ohms(), profile1.max_current_amps())
+            .await
+            .is_ok(),
+        ina226_2
+            .callibrate(profile2.shunt_ohms(), profile2.max_current_amps())
+            .await
+            .is_ok(),
+        ina226_3
+            .callibrate(profile3.shunt_ohms(), profile3.max_current_amps())
+            .await
+            .is_ok(),
+    ];
+    for (port, ok) in calibrated.into_iter().enumerate() {
+        if !ok {
+            error!("INA226 of port {} not calibrated", port + 1);
+        }
+    }
 
-    // Configure INA226 sensors (optional, default config is often fine)
-    // Example: Set calibration register for current/power readings
-    ina226_1.callibrate(0.005, 4.0).await.unwrap();
-    ina226_2.callibrate(0.010, 4.0).await.unwrap();
-    ina226_3.callibrate(0.010, 4.0).await.unwrap();
+    // Burst captures, idle until armed from the panel or the console
+    capture::init(&spawner, EmbassyI2cDevice::new(i2c1_bus_mutex_ref));
+
+    if settings.self_test {
+        // Let one conversion finish with the new calibration
+        embassy_time::Timer::after_millis(10).await;
+        let currents = [
+            ina226_1.current_amps().await,
+            ina226_2.current_amps().await,
+            ina226_3.current_amps().await,
+        ];
+        for (port, current) in currents.into_iter().enumerate() {
+            let current_ma = match current {
+                Ok(Some(amps)) => {
+                    Some((settings.corrections[port].apply(amps as f32) * 1000.0) as i32)
+                }
+                _ => None,
+            };
+            report.push(Check::SensorZero {
+                port: port as u8,
+                current_ma,
+            });
+        }
+        report.push(Check::Settings(storage::check_settings()));
+        report.push(Check::UsbClock {
+            ok: selftest::usb_clock_ok(),
+        });
+    }
 
     info!("INA226 sensors initialized.");
 
@@ -183,7 +330,7 @@ async fn main(_spawner: Spawner) {
     static DISPLAY_BUFFER_CELL: StaticCell<[u8; gc9d01::BUF_SIZE]> = StaticCell::new();
     let buffer_slice: &mut [u8] = DISPLAY_BUFFER_CELL.init([0; gc9d01::BUF_SIZE]);
 
-    let mut display: GC9D01<
+    let display: GC9D01<
         '_,
         EmbassySpiDevice<
             'static,
@@ -196,82 +343,169 @@ async fn main(_spawner: Spawner) {
         EmbassyDisplayTimer,
     > = GC9D01::new(display_config, spi_device, dc_pin, rst_pin, buffer_slice);
 
+    // Backlight (BLK) on PA8, dimmed through TIM1 CH1
+    let mut backlight = SimplePwm::new(
+        p.TIM1,
+        Some(PwmPin::new(p.PA8, OutputType::PushPull)),
+        None,
+        None,
+        None,
+        khz(20),
+        CountingMode::EdgeAlignedUp,
+    )
+    .split()
+    .ch1;
+    backlight.enable();
+
+    // Everything below only sees the `Panel` side
+    let mut display = Gc9d01Panel::new(display, backlight);
+
     info!("Initializing display...");
-    match display.init().await {
-        Ok(_) => info!("Display initialized successfully!"),
-        Err(e) => error!("Display initialization failed: {:?}", e),
-    }
+    let panel_ok = match display.init().await {
+        Ok(_) => {
+            info!("Display initialized successfully!");
+            true
+        }
+        Err(_) => {
+            error!("Display initialization failed");
+            false
+        }
+    };
     info!("Display initialization complete."); // Added log
 
-    // Instantiate Dashboard
-    let mut dashboard = Dashboard::new();
-
     display.fill_color(Rgb565::CSS_BLACK).await.unwrap();
 
-    info!("Drawing test pattern.");
-    let colors = [
-        Rgb565::CSS_WHITE,
-        Rgb565::CSS_YELLOW,
-        Rgb565::CSS_CYAN,
-        Rgb565::CSS_GREEN,
-        Rgb565::CSS_MAGENTA,
-        Rgb565::CSS_RED,
-        Rgb565::CSS_BLUE,
-        Rgb565::CSS_BLACK,
-    ];
-
-    // Each stripe is 5 pixels wide and 160 pixels high
-    const STRIPE_WIDTH: u16 = 20;
-    const STRIPE_HEIGHT: u16 = 40;
-
-    // Create a buffer for one stripe's pixel data
-    let mut stripe_pixels = [Rgb565::CSS_BLACK; (STRIPE_WIDTH * STRIPE_HEIGHT) as usize];
-
-    for (i, color) in colors.iter().enumerate() {
-        let x = i as u16 * STRIPE_WIDTH;
+    if settings.self_test {
+        info!("Drawing test pattern.");
+        let pattern_ok = display::selftest::draw_pattern(&mut display).await.is_ok();
+        report.push(Check::Panel {
+            ok: panel_ok && pattern_ok,
+        });
+        embassy_time::Timer::after_millis(500).await;
 
-        // Fill the stripe buffer with the current color
-        for pixel in stripe_pixels.iter_mut() {
-            *pixel = *color;
+        for check in report.checks() {
+            info!("Self-test: {}", Display2Format(check));
         }
+        event_log::record(report.event());
+        let _ = display::selftest::draw_summary(&mut display, &report, reset_cause).await;
+        // Longer to read a failure
+        let secs = if report.failed() > 0 { 5 } else { 2 };
+        embassy_time::Timer::after_secs(secs).await;
+    }
 
-        // Write the pixel data for the current stripe
-        display
-            .write_area(x, 0, STRIPE_WIDTH, STRIPE_HEIGHT, &stripe_pixels)
+    // Where the last run crashed, on its own screen
+    if let Some(crash) = crash {
+        display.fill_color(Rgb565::CSS_BLACK).await.unwrap();
+        let mut boot_line = display::canvas::TextLine::new();
+        let mut location = heapless::String::<48>::new();
+        // "crashed at dashboard.rs:123" is too wide for one line
+        let _ = core::fmt::write(
+            &mut location,
+            format_args!("{}:{}", crash.file(), crash.line),
+        );
+        boot_line.set_text("crashed at", 4);
+        boot_line
+            .blit(&mut display, 7, Rgb565::CSS_RED, Rgb565::CSS_BLACK)
+            .await
+            .unwrap();
+        boot_line.set_text(&location, 4);
+        boot_line
+            .blit(&mut display, 20, Rgb565::CSS_RED, Rgb565::CSS_BLACK)
             .await
             .unwrap();
+        embassy_time::Timer::after_secs(5).await;
     }
 
-    // Initial delay before starting the loop
-    embassy_time::Timer::after_secs(1).await;
+    let readings_sender = shared::READINGS.sender();
+    let Ok(mut pd_trace) = shared::PD_TRACE.subscriber() else {
+        defmt::panic!("no free PD_TRACE subscriber for the display");
+    };
+    let Some(mut pd_status) = shared::PD_STATUS.receiver() else {
+        defmt::panic!("no free PD_STATUS receiver for the display");
+    };
+    let Some(mut settings_changes) = shared::SETTINGS.receiver() else {
+        defmt::panic!("no free SETTINGS receiver for the main loop");
+    };
+    let mut board = Board;
+    let mut app = App::new(settings, counters);
+
+    // Loop iterations (100 ms each) before a freshly updated image confirms
+    // itself; the bootloader rolls back if it resets before that
+    #[cfg(feature = "bootloader")]
+    let mut healthy_countdown: u32 = 300;
 
+    supervisor::main_loop_started();
     loop {
         // Read data from INA226 sensors
         // Use correct async function names and handle Option<f64> return types
-        let voltage1 = ina226_1.bus_voltage_millivolts().await.unwrap_or(0.0);
+        let voltage1 = ina226_1.bus_voltage_millivolts().await;
         let current1 = ina226_1.current_amps().await.unwrap_or(None).unwrap_or(0.0);
         let power1 = ina226_1.power_watts().await.unwrap_or(None).unwrap_or(0.0);
 
-        let voltage2 = ina226_2.bus_voltage_millivolts().await.unwrap_or(0.0);
+        let voltage2 = ina226_2.bus_voltage_millivolts().await;
         let current2 = ina226_2.current_amps().await.unwrap_or(None).unwrap_or(0.0);
         let power2 = ina226_2.power_watts().await.unwrap_or(None).unwrap_or(0.0);
 
-        let voltage3 = ina226_3.bus_voltage_millivolts().await.unwrap_or(0.0);
+        let voltage3 = ina226_3.bus_voltage_millivolts().await;
         let current3 = ina226_3.current_amps().await.unwrap_or(None).unwrap_or(0.0);
         let power3 = ina226_3.power_watts().await.unwrap_or(None).unwrap_or(0.0);
+        supervisor::check_in(Task::Sampling);
+
+        let sensor_ok = [voltage1.is_ok(), voltage2.is_ok(), voltage3.is_ok()];
 
         // Prepare data for Dashboard, converting f64 to f32
         let sensor_data = [
-            ((voltage1 / 1000.0) as f32, current1 as f32, power1 as f32),
-            ((voltage2 / 1000.0) as f32, current2 as f32, power2 as f32),
-            ((voltage3 / 1000.0) as f32, current3 as f32, power3 as f32),
+            (
+                (voltage1.unwrap_or(0.0) / 1000.0) as f32,
+                current1 as f32,
+                power1 as f32,
+            ),
+            (
+                (voltage2.unwrap_or(0.0) / 1000.0) as f32,
+                current2 as f32,
+                power2 as f32,
+            ),
+            (
+                (voltage3.unwrap_or(0.0) / 1000.0) as f32,
+                current3 as f32,
+                power3 as f32,
+            ),
         ];
 
-        // Update Dashboard data
-        dashboard.update_data(sensor_data);
+        if let Some(changed) = settings_changes.try_changed() {
+            app.set_settings(changed);
+        }
+        if let Ok(request) = shared::CALIBRATION.try_receive() {
+            app.calibrate(request);
+        }
+        app.update(&mut board, sensor_data, sensor_ok);
+
+        // Publish the corrected readings, counters and sessions to the USB
+        // functions
+        readings_sender.send(*app.readings());
+        counters_sender.send(*app.counters());
+        sessions_sender.send(*app.sessions());
 
-        // Draw Dashboard directly to the display
-        dashboard.draw(&mut display).await.unwrap();
+        while let Some(record) = pd_trace.try_next_message_pure() {
+            app.pd_trace(&record);
+        }
+        if let Some(status) = pd_status.try_changed() {
+            app.pd_status(&mut board, status);
+        }
+        while let Ok((button, press)) = shared::BUTTONS.try_receive() {
+            app.press(&mut board, button, press);
+        }
+
+        app.draw(&mut display, &board).await.unwrap();
+        supervisor::check_in(Task::Display);
+
+        #[cfg(feature = "bootloader")]
+        if healthy_countdown > 0 {
+            healthy_countdown -= 1;
+            if healthy_countdown == 0 {
+                update::confirm_healthy();
+            }
+        }
 
         // Wait for 1 second before the next update
         embassy_time::Timer::after_millis(100).await;
@@ -0,0 +1,9 @@
+// src/pd/mod.rs
+// USB Power Delivery sink on UCPD1
+//
+// The protocol, policy, decoder and trace are hardware agnostic and live in
+// the core crate; `ucpd` binds them to the peripheral.
+
+pub use iso_usb_hub_core::pd::{decode, message, policy, protocol, trace};
+
+pub mod ucpd;
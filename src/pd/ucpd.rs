@@ -0,0 +1,292 @@
+// src/pd/ucpd.rs
+// UCPD1 driver glue: Type-C attach detection and the PD protocol layer
+//
+// The peripheral only provides the PHY. GoodCRC replies, retries and the
+// policy timers are handled here; all decisions are left to `SinkPolicy`.
+// Every message in either direction is published to the PD trace.
+//
+// With the `pd-monitor` feature the sink policy is disabled and the PHY only
+// listens, so the hub can sit on a CC tap between a charger and another
+// sink. The hub's Rd stays connected and appears in parallel with the real
+// sink's; sources still detect a valid sink with the lower resistance.
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_stm32::ucpd::{self, CcPhy, CcPull, CcSel, CcVState, PdPhy, RxError, TxError, Ucpd};
+use embassy_stm32::{Peri, bind_interrupts, peripherals};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+
+use super::message::{ControlType, MAX_MESSAGE_LEN, Message};
+use super::policy::{Action, PdStatus, SinkConfig, SinkPolicy};
+use super::protocol::ProtocolLayer;
+use super::trace::{self, TraceEvent};
+use crate::shared::PD_STATUS;
+
+// tCCDebounce
+const ATTACH_DEBOUNCE_MS: u64 = 100;
+// tReceive is 0.9-1.1 ms, leave some slack for the executor
+const GOOD_CRC_TIMEOUT_US: u64 = 1_500;
+
+bind_interrupts!(
+    struct Irqs {
+        UCPD1 => ucpd::InterruptHandler<peripherals::UCPD1>;
+    }
+);
+
+pub struct UcpdResources {
+    pub ucpd: Peri<'static, peripherals::UCPD1>,
+    pub cc1: Peri<'static, peripherals::PB6>,
+    pub cc2: Peri<'static, peripherals::PB4>,
+    pub rx_dma: Peri<'static, peripherals::DMA1_CH4>,
+    pub tx_dma: Peri<'static, peripherals::DMA1_CH5>,
+}
+
+pub fn init(spawner: &Spawner, resources: UcpdResources) {
+    spawner.must_spawn(pd_task(resources, SinkConfig::default()));
+}
+
+#[embassy_executor::task]
+async fn pd_task(mut r: UcpdResources, config: SinkConfig) -> ! {
+    let status = PD_STATUS.sender();
+    let mut policy = SinkPolicy::new(config);
+    status.send(policy.status());
+
+    loop {
+        let mut ucpd = Ucpd::new(
+            r.ucpd.reborrow(),
+            Irqs,
+            r.cc1.reborrow(),
+            r.cc2.reborrow(),
+            Default::default(),
+        );
+        ucpd.cc_phy().set_pull(CcPull::Sink);
+
+        let cc_sel = wait_attached(ucpd.cc_phy()).await;
+        info!("Type-C source attached");
+
+        let (mut cc_phy, mut pd_phy) =
+            ucpd.split_pd_phy(r.rx_dma.reborrow(), r.tx_dma.reborrow(), cc_sel);
+
+        if cfg!(feature = "pd-monitor") {
+            select(wait_detached(&mut cc_phy), run_monitor(&mut pd_phy)).await;
+        } else {
+            policy.attach(now_ms());
+            status.send(policy.status());
+
+            let session = run_session(&mut pd_phy, &mut policy, |s| status.send(s.clone()));
+            select(wait_detached(&mut cc_phy), session).await;
+
+            policy.detach();
+            status.send(policy.status());
+        }
+
+        info!("Type-C source detached");
+    }
+}
+
+fn now_ms() -> u64 {
+    Instant::now().as_millis()
+}
+
+async fn wait_attached(cc_phy: &mut CcPhy<'_, peripherals::UCPD1>) -> CcSel {
+    loop {
+        let (cc1, cc2) = cc_phy.vstate();
+        if cc1 == CcVState::LOWEST && cc2 == CcVState::LOWEST {
+            cc_phy.wait_for_vstate_change().await;
+            continue;
+        }
+
+        if let Either::First(_) = select(
+            cc_phy.wait_for_vstate_change(),
+            Timer::after_millis(ATTACH_DEBOUNCE_MS),
+        )
+        .await
+        {
+            continue;
+        }
+
+        // The CC line with Rp pulled up carries the BMC signal
+        return if cc1 != CcVState::LOWEST {
+            CcSel::CC1
+        } else {
+            CcSel::CC2
+        };
+    }
+}
+
+async fn wait_detached(cc_phy: &mut CcPhy<'_, peripherals::UCPD1>) {
+    loop {
+        let (cc1, cc2) = cc_phy.vstate();
+        if cc1 == CcVState::LOWEST && cc2 == CcVState::LOWEST {
+            return;
+        }
+        cc_phy.wait_for_vstate_change().await;
+    }
+}
+
+#[derive(Format)]
+enum TransmitError {
+    /// No GoodCRC after all retries
+    NoResponse,
+    /// The partner sent a message first, ours was dropped by the PHY
+    Discarded,
+    HardReset,
+}
+
+async fn run_session(
+    pd_phy: &mut PdPhy<'_, peripherals::UCPD1>,
+    policy: &mut SinkPolicy,
+    publish: impl Fn(&PdStatus),
+) -> ! {
+    let mut protocol = ProtocolLayer::new();
+    let mut published = policy.status();
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+
+    loop {
+        let event = match policy.deadline() {
+            Some(deadline) => {
+                select(
+                    pd_phy.receive(&mut buf),
+                    Timer::at(Instant::from_millis(deadline)),
+                )
+                .await
+            }
+            None => Either::First(pd_phy.receive(&mut buf).await),
+        };
+
+        let action = match event {
+            Either::First(Ok(len)) => {
+                trace::record(TraceEvent::Received, &buf[..len]);
+                let Ok(message) = Message::parse(&buf[..len]) else {
+                    warn!("PD: malformed message ({} bytes)", len);
+                    continue;
+                };
+                // Stray GoodCRC, ours are consumed in `transmit`
+                if message.is_control(ControlType::GoodCrc) {
+                    continue;
+                }
+                if let Err(e) = send_good_crc(pd_phy, &protocol, &message).await {
+                    warn!("PD: GoodCRC not sent: {:?}", e);
+                    continue;
+                }
+                if !protocol.accept(&message) {
+                    continue;
+                }
+                policy.handle(&message, now_ms())
+            }
+            Either::First(Err(RxError::HardReset)) => {
+                warn!("PD: hard reset from source");
+                trace::record(TraceEvent::HardResetReceived, &[]);
+                protocol.reset();
+                policy.hard_reset_received(now_ms());
+                Action::None
+            }
+            Either::First(Err(_)) => continue,
+            Either::Second(()) => policy.poll(now_ms()),
+        };
+
+        match action {
+            Action::None => {}
+            Action::Send(message) => {
+                if let Err(e) = transmit(pd_phy, &mut protocol, message).await {
+                    warn!("PD: transmit failed: {:?}", e);
+                    if let TransmitError::HardReset = e {
+                        protocol.reset();
+                        policy.hard_reset_received(now_ms());
+                    }
+                }
+            }
+            Action::HardReset => {
+                warn!("PD: sending hard reset");
+                trace::record(TraceEvent::HardResetSent, &[]);
+                let _ = pd_phy.transmit_hardreset().await;
+                protocol.reset();
+            }
+        }
+
+        let status = policy.status();
+        if status != published {
+            match status.contract {
+                Some(contract) if status.contract != published.contract => info!(
+                    "PD: contract PDO {} {} mV {} mA",
+                    contract.position, contract.voltage_mv, contract.current_ma
+                ),
+                _ => {}
+            }
+            publish(&status);
+            published = status;
+        }
+    }
+}
+
+/// Passive monitor: record everything on the CC line, never acknowledge
+async fn run_monitor(pd_phy: &mut PdPhy<'_, peripherals::UCPD1>) -> ! {
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+    loop {
+        match pd_phy.receive(&mut buf).await {
+            Ok(len) => trace::record(TraceEvent::Received, &buf[..len]),
+            Err(RxError::HardReset) => trace::record(TraceEvent::HardResetReceived, &[]),
+            Err(_) => {}
+        }
+    }
+}
+
+async fn send_good_crc(
+    pd_phy: &mut PdPhy<'_, peripherals::UCPD1>,
+    protocol: &ProtocolLayer,
+    received: &Message,
+) -> Result<(), TransmitError> {
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+    let len = protocol.good_crc(received).encode(&mut buf);
+    trace::record(TraceEvent::Transmitted, &buf[..len]);
+    pd_phy
+        .transmit(&buf[..len])
+        .await
+        .map_err(TransmitError::from)
+}
+
+async fn transmit(
+    pd_phy: &mut PdPhy<'_, peripherals::UCPD1>,
+    protocol: &mut ProtocolLayer,
+    mut message: Message,
+) -> Result<(), TransmitError> {
+    protocol.prepare(&mut message);
+    let mut tx_buf = [0u8; MAX_MESSAGE_LEN];
+    let len = message.encode(&mut tx_buf);
+    let mut rx_buf = [0u8; MAX_MESSAGE_LEN];
+
+    loop {
+        trace::record(TraceEvent::Transmitted, &tx_buf[..len]);
+        pd_phy.transmit(&tx_buf[..len]).await?;
+
+        let reply = with_timeout(
+            Duration::from_micros(GOOD_CRC_TIMEOUT_US),
+            pd_phy.receive(&mut rx_buf),
+        )
+        .await;
+        match reply {
+            Ok(Ok(n)) => {
+                trace::record(TraceEvent::Received, &rx_buf[..n]);
+                if Message::parse(&rx_buf[..n]).is_ok_and(|reply| protocol.is_ack(&reply)) {
+                    protocol.transmitted();
+                    return Ok(());
+                }
+            }
+            Ok(Err(RxError::HardReset)) => return Err(TransmitError::HardReset),
+            Ok(Err(_)) | Err(_) => {}
+        }
+        if !protocol.retry() {
+            return Err(TransmitError::NoResponse);
+        }
+    }
+}
+
+impl From<TxError> for TransmitError {
+    fn from(e: TxError) -> Self {
+        match e {
+            TxError::Discarded => TransmitError::Discarded,
+            TxError::HardReset => TransmitError::HardReset,
+        }
+    }
+}
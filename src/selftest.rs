@@ -0,0 +1,35 @@
+// src/selftest.rs
+// Hardware side of the power-on self-test
+//
+// Reads what the core crate's `selftest` judges: the INA226 ID registers
+// and the USB clock here, the settings record in `storage`. The panel
+// pattern and the zero-current readings are taken in `main`, which owns
+// those drivers.
+
+use embassy_stm32::pac;
+use embedded_hal_async::i2c::I2c;
+
+pub use iso_usb_hub_core::selftest::{Check, Report, StoredSettings};
+
+const REG_MANUFACTURER_ID: u8 = 0xFE;
+const REG_DIE_ID: u8 = 0xFF;
+
+/// (manufacturer, die) ID of the INA226 at `address`, `None` if nothing
+/// answers there
+pub async fn sensor_ids<I: I2c>(i2c: &mut I, address: u8) -> Option<(u16, u16)> {
+    let mut manufacturer = [0u8; 2];
+    let mut die = [0u8; 2];
+    i2c.write_read(address, &[REG_MANUFACTURER_ID], &mut manufacturer)
+        .await
+        .ok()?;
+    i2c.write_read(address, &[REG_DIE_ID], &mut die)
+        .await
+        .ok()?;
+    Some((u16::from_be_bytes(manufacturer), u16::from_be_bytes(die)))
+}
+
+/// HSI48 is running; it clocks the USB peripheral (CLK48SEL) and the CRS
+/// trims it to the host's start-of-frame once enumerated
+pub fn usb_clock_ok() -> bool {
+    pac::RCC.crrcr().read().hsi48rdy()
+}
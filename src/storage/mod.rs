@@ -0,0 +1,161 @@
+// src/storage/mod.rs
+// Persistent data in the STORAGE flash region
+//
+// The key/value store and the stored forms of the settings and counters
+// live in the core crate. This module opens the store on the STORAGE pages,
+// which every flash layout keeps at the same address, so settings survive
+// firmware updates.
+
+use core::cell::{Cell, RefCell};
+
+use defmt::*;
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_stm32::flash::{Blocking, Flash};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+use self::counters::Counters;
+use self::log::{LogStore, MAX_VALUE_LEN};
+use self::settings::{MAX_ENCODED_LEN, Settings};
+use crate::selftest::StoredSettings;
+
+pub use iso_usb_hub_core::storage::{counters, log, settings};
+
+/// Record keys in the store, never reuse a retired one
+pub mod key {
+    pub const SETTINGS: u8 = 0;
+    pub const COUNTERS: u8 = 1;
+    /// First of the event log slots, one key per slot up to `KEY_COUNT`
+    pub const EVENTS: u8 = 8;
+}
+
+/// The on-chip flash, shared by the bootloader state and persistent storage
+pub type FlashMutex = BlockingMutex<CriticalSectionRawMutex, RefCell<Flash<'static, Blocking>>>;
+
+type StoragePartition =
+    BlockingPartition<'static, CriticalSectionRawMutex, Flash<'static, Blocking>>;
+
+static STORE: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<LogStore<StoragePartition>>>> =
+    BlockingMutex::new(RefCell::new(None));
+
+// Last counters written, to skip writes that would change nothing
+static SAVED_COUNTERS: BlockingMutex<CriticalSectionRawMutex, Cell<Option<Counters>>> =
+    BlockingMutex::new(Cell::new(None));
+
+// Provided by memory-*.x, offsets from the start of flash
+unsafe extern "C" {
+    static __storage_start: u32;
+    static __storage_end: u32;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Error {
+    /// The store could not be opened at boot
+    Unavailable,
+    Flash,
+}
+
+pub fn init(flash: &'static FlashMutex) {
+    let (start, end) = unsafe {
+        (
+            (&raw const __storage_start) as u32,
+            (&raw const __storage_end) as u32,
+        )
+    };
+    let partition = BlockingPartition::new(flash, start, end - start);
+
+    match LogStore::open(partition) {
+        Ok(store) => STORE.lock(|cell| *cell.borrow_mut() = Some(store)),
+        Err(_) => error!("Storage region unreadable, settings will not persist"),
+    }
+}
+
+/// Copy the newest value of `key` into `buf`
+pub fn read(key: u8, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+    STORE.lock(|cell| {
+        let mut cell = cell.borrow_mut();
+        let store = cell.as_mut().ok_or(Error::Unavailable)?;
+        store.read(key, buf).map_err(|_| Error::Flash)
+    })
+}
+
+/// Append a new value of `key`; blocks for a page erase now and then
+pub fn write(key: u8, value: &[u8]) -> Result<(), Error> {
+    STORE.lock(|cell| {
+        let mut cell = cell.borrow_mut();
+        let store = cell.as_mut().ok_or(Error::Unavailable)?;
+        store.write(key, value).map_err(|_| Error::Flash)
+    })
+}
+
+/// Stored settings, or the defaults when there are none or they are damaged
+pub fn load_settings() -> Settings {
+    // A record from a newer firmware may be longer than ours
+    let mut buf = [0u8; MAX_VALUE_LEN];
+    match read(key::SETTINGS, &mut buf) {
+        Ok(Some(len)) => match Settings::decode(&buf[..len]) {
+            Ok(settings) => return settings,
+            Err(_) => warn!("Stored settings are damaged, using defaults"),
+        },
+        Ok(None) => info!("No stored settings, using defaults"),
+        Err(e) => warn!("Settings not loaded: {}", e),
+    }
+    Settings::default()
+}
+
+/// State of the stored settings record, for the self-test
+pub fn check_settings() -> StoredSettings {
+    let damaged = STORE.lock(|cell| {
+        let mut cell = cell.borrow_mut();
+        cell.as_mut().map(|store| store.is_damaged(key::SETTINGS))
+    });
+    match damaged {
+        None | Some(Err(_)) => return StoredSettings::Unreadable,
+        Some(Ok(true)) => return StoredSettings::Damaged,
+        Some(Ok(false)) => {}
+    }
+    let mut buf = [0u8; MAX_VALUE_LEN];
+    match read(key::SETTINGS, &mut buf) {
+        Ok(Some(len)) if Settings::decode(&buf[..len]).is_ok() => StoredSettings::Valid,
+        Ok(Some(_)) => StoredSettings::Damaged,
+        Ok(None) => StoredSettings::Missing,
+        Err(_) => StoredSettings::Unreadable,
+    }
+}
+
+pub fn save_settings(settings: &Settings) -> Result<(), Error> {
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let len = settings.encode(&mut buf);
+    write(key::SETTINGS, &buf[..len])
+}
+
+/// Stored usage counters, or zeros when there are none
+pub fn load_counters() -> Counters {
+    let mut buf = [0u8; MAX_VALUE_LEN];
+    let counters = match read(key::COUNTERS, &mut buf) {
+        Ok(Some(len)) => match Counters::decode(&buf[..len]) {
+            Ok(counters) => counters,
+            Err(_) => {
+                warn!("Stored usage counters are damaged, starting from zero");
+                Counters::default()
+            }
+        },
+        Ok(None) => Counters::default(),
+        Err(e) => {
+            warn!("Usage counters not loaded: {}", e);
+            Counters::default()
+        }
+    };
+    SAVED_COUNTERS.lock(|saved| saved.set(Some(counters)));
+    counters
+}
+
+/// Also called from the brown-out interrupt; a no-op when nothing changed
+pub fn save_counters(counters: &Counters) -> Result<(), Error> {
+    if SAVED_COUNTERS.lock(|saved| saved.get()) == Some(*counters) {
+        return Ok(());
+    }
+    write(key::COUNTERS, &counters.encode())?;
+    SAVED_COUNTERS.lock(|saved| saved.set(Some(*counters)));
+    Ok(())
+}
@@ -0,0 +1,141 @@
+// src/supervisor.rs
+// Independent watchdog fed only while every critical task is alive
+//
+// The sampling and display halves of the main loop check in regularly. The
+// supervisor pets the IWDG once a second as long as each of them checked in
+// within `CHECK_IN_TIMEOUT_MS`; a hung I2C/SPI mutex, or any task spinning
+// without yielding to the executor, stops the petting and the IWDG resets
+// the hub. The USB device has no check-in of its own: an idle bus can't be
+// told from a stuck one, and a driver spinning in its task already stops the
+// main loop on the same executor. Once the IWDG runs it can only be stopped
+// by a reset, and the reset flags tell the next boot what happened.
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_stm32::{Peri, pac, peripherals};
+use embassy_time::{Instant, Timer};
+
+use crate::event_log::ring::ResetCause;
+
+const WATCHDOG_TIMEOUT_US: u32 = 4_000_000;
+const PET_INTERVAL_MS: u64 = 1_000;
+const CHECK_IN_TIMEOUT_MS: u32 = 3_000;
+// Display init, the sensor probes and the boot screens (12 s at most) come
+// before the main loop, which makes the first check-ins
+const BOOT_TIMEOUT_MS: u32 = 30_000;
+// From the start of the main loop
+const STARTUP_GRACE_MS: u32 = 10_000;
+
+#[derive(Clone, Copy, Debug, Format)]
+pub enum Task {
+    Sampling,
+    Display,
+}
+
+const TASKS: [Task; 2] = [Task::Sampling, Task::Display];
+
+// Uptime in ms of the last check-in, 0 = not yet
+static CHECK_INS: [AtomicU32; TASKS.len()] = [const { AtomicU32::new(0) }; TASKS.len()];
+// Uptime in ms when the main loop was entered, 0 = still booting
+static MAIN_LOOP_STARTED: AtomicU32 = AtomicU32::new(0);
+
+// Set by the brown-out handler; survives a supply dip that doesn't clear RAM
+const SUPPLY_DROP_MAGIC: u32 = 0x5EB0_0D1E;
+#[unsafe(link_section = ".uninit.SUPPLY_DROP")]
+static mut SUPPLY_DROP: MaybeUninit<u32> = MaybeUninit::uninit();
+
+fn uptime_ms() -> u32 {
+    // Wraps after 49 days, never 0 so it can't be taken for "not yet"
+    (Instant::now().as_millis() as u32).max(1)
+}
+
+pub fn check_in(task: Task) {
+    CHECK_INS[task as usize].store(uptime_ms(), Ordering::Relaxed);
+}
+
+/// The boot is over; tasks that haven't checked in yet have
+/// `STARTUP_GRACE_MS` from now
+pub fn main_loop_started() {
+    MAIN_LOOP_STARTED.store(uptime_ms(), Ordering::Relaxed);
+}
+
+pub fn init(spawner: &Spawner, iwdg: Peri<'static, peripherals::IWDG>) {
+    spawner.must_spawn(supervisor_task(iwdg));
+}
+
+#[embassy_executor::task]
+async fn supervisor_task(iwdg: Peri<'static, peripherals::IWDG>) -> ! {
+    let mut watchdog = IndependentWatchdog::new(iwdg, WATCHDOG_TIMEOUT_US);
+    watchdog.unleash();
+    let started = uptime_ms();
+
+    loop {
+        let now = uptime_ms();
+        let stalled =
+            TASKS.iter().find(
+                |&&task| match CHECK_INS[task as usize].load(Ordering::Relaxed) {
+                    0 => match MAIN_LOOP_STARTED.load(Ordering::Relaxed) {
+                        0 => now.wrapping_sub(started) > BOOT_TIMEOUT_MS,
+                        from => now.wrapping_sub(from) > STARTUP_GRACE_MS,
+                    },
+                    last => now.wrapping_sub(last) > CHECK_IN_TIMEOUT_MS,
+                },
+            );
+        match stalled {
+            None => watchdog.pet(),
+            Some(task) => {
+                error!("{} task stalled, waiting for the watchdog reset", task);
+                // Stop here for good, a recovered task doesn't undo the hang
+                loop {
+                    Timer::after_millis(PET_INTERVAL_MS).await;
+                }
+            }
+        }
+        Timer::after_millis(PET_INTERVAL_MS).await;
+    }
+}
+
+/// Called from the brown-out interrupt while VDD is falling
+pub fn note_supply_drop() {
+    unsafe {
+        (&raw mut SUPPLY_DROP)
+            .cast::<u32>()
+            .write_volatile(SUPPLY_DROP_MAGIC)
+    };
+}
+
+/// Reason for this boot; clears the flags, call once early in `main`
+pub fn reset_cause() -> ResetCause {
+    let csr = pac::RCC.csr().read();
+    pac::RCC.csr().modify(|w| w.set_rmvf(true));
+
+    let supply_dropped = unsafe {
+        let mark = (&raw mut SUPPLY_DROP).cast::<u32>();
+        let dropped = mark.read_volatile() == SUPPLY_DROP_MAGIC;
+        mark.write_volatile(0);
+        dropped
+    };
+
+    // The reset pin flag is set by every internal reset too, check it last
+    if csr.lpwrrstf() {
+        ResetCause::LowPower
+    } else if csr.iwdgrstf() || csr.wwdgrstf() {
+        ResetCause::Watchdog
+    } else if csr.sftrstf() {
+        ResetCause::Software
+    } else if csr.borrstf() && supply_dropped {
+        ResetCause::BrownOut
+    } else if csr.borrstf() {
+        ResetCause::PowerOn
+    } else if csr.oblrstf() {
+        ResetCause::OptionBytes
+    } else if csr.pinrstf() {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    }
+}
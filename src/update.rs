@@ -0,0 +1,81 @@
+// src/update.rs
+// Firmware update state shared with bootloader/ (`--features bootloader`)
+//
+// A DFU detach marks the state partition so the bootloader starts its DFU
+// mode. After a verified download the bootloader swaps the new image into
+// ACTIVE and boots it once; unless the image confirms itself with
+// `confirm_healthy`, the next reset swaps the previous image back.
+
+use core::cell::RefCell;
+
+use defmt::*;
+use embassy_boot::{AlignedBuffer, BlockingFirmwareState, State};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_stm32::flash::{Blocking, Flash, WRITE_SIZE};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use static_cell::StaticCell;
+
+use crate::storage::FlashMutex;
+
+type StatePartition = BlockingPartition<'static, CriticalSectionRawMutex, Flash<'static, Blocking>>;
+type FirmwareState = BlockingFirmwareState<'static, StatePartition>;
+
+static FIRMWARE_STATE: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<FirmwareState>>> =
+    BlockingMutex::new(RefCell::new(None));
+
+// Provided by memory-bootloader.x, offsets from the start of flash
+unsafe extern "C" {
+    static __bootloader_state_start: u32;
+    static __bootloader_state_end: u32;
+}
+
+pub fn init(flash: &'static FlashMutex) {
+    let (start, end) = unsafe {
+        (
+            (&raw const __bootloader_state_start) as u32,
+            (&raw const __bootloader_state_end) as u32,
+        )
+    };
+    let partition = BlockingPartition::new(flash, start, end - start);
+
+    static MAGIC: StaticCell<AlignedBuffer<WRITE_SIZE>> = StaticCell::new();
+    let magic = MAGIC.init(AlignedBuffer([0; WRITE_SIZE]));
+    let mut state = FirmwareState::new(partition, &mut magic.0);
+
+    match state.get_state() {
+        Ok(State::Swap) => warn!("Running a freshly swapped image, waiting for health check"),
+        Ok(State::Revert) => warn!("Previous update was rolled back"),
+        Ok(_) => {}
+        Err(_) => error!("Failed to read bootloader state"),
+    }
+
+    FIRMWARE_STATE.lock(|cell| cell.replace(Some(state)));
+}
+
+/// Keep the running image. Only writes flash right after an update.
+pub fn confirm_healthy() {
+    FIRMWARE_STATE.lock(|cell| {
+        let mut cell = cell.borrow_mut();
+        let Some(state) = cell.as_mut() else {
+            return;
+        };
+        if let Ok(State::Swap) = state.get_state() {
+            match state.mark_booted() {
+                Ok(()) => info!("New firmware confirmed healthy"),
+                Err(_) => error!("Failed to confirm new firmware"),
+            }
+        }
+    });
+}
+
+/// Ask the bootloader to stay in DFU mode on the next reset
+pub fn request_dfu() {
+    FIRMWARE_STATE.lock(|cell| {
+        if let Some(state) = cell.borrow_mut().as_mut() {
+            if state.mark_dfu().is_err() {
+                error!("Failed to request DFU mode");
+            }
+        }
+    });
+}
@@ -0,0 +1,592 @@
+// src/usb/console.rs
+// CDC-ACM text console: line based commands for bring-up and diagnostics
+
+use core::fmt::Write;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_usb::Builder;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use heapless::String;
+use static_cell::StaticCell;
+
+use super::UsbDriver;
+use crate::attach::AttachState;
+use crate::calibration::{self, Request};
+use crate::capture::{self, Capture, State as CaptureState, Trigger};
+use crate::crash::{self, record::CrashRecord};
+use crate::event_log::{self, ring::Event};
+use crate::history::Trend;
+use crate::pd::decode::{self, Analyzer};
+use crate::pd::trace::{TraceEvent, TraceRecord};
+use crate::shared::{
+    CALIBRATION, COUNTERS, INPUT_PORT, PD_TRACE, PORT_COUNT, READINGS, SESSIONS, SETTINGS,
+};
+use crate::storage;
+use crate::storage::settings::{CurrentCorrection, Settings, Theme};
+
+const MAX_PACKET_SIZE: u16 = 64;
+const MAX_LINE_LEN: usize = 64;
+
+pub type Reply = String<512>;
+
+pub fn register(spawner: &Spawner, builder: &mut Builder<'static, UsbDriver>) {
+    static STATE: StaticCell<State> = StaticCell::new();
+    let class = CdcAcmClass::new(builder, STATE.init(State::new()), MAX_PACKET_SIZE);
+    spawner.must_spawn(console_task(class));
+}
+
+#[embassy_executor::task]
+async fn console_task(mut class: CdcAcmClass<'static, UsbDriver>) -> ! {
+    loop {
+        class.wait_connection().await;
+        info!("USB console connected");
+        let _ = run_session(&mut class).await;
+        info!("USB console disconnected");
+    }
+}
+
+async fn run_session(class: &mut CdcAcmClass<'static, UsbDriver>) -> Result<(), EndpointError> {
+    let mut packet = [0u8; MAX_PACKET_SIZE as usize];
+    let mut line: String<MAX_LINE_LEN> = String::new();
+    let mut reply = Reply::new();
+
+    write_all(class, b"iso-usb-hub console, type 'help'\r\n> ").await?;
+    loop {
+        let n = class.read_packet(&mut packet).await?;
+        for &byte in &packet[..n] {
+            match byte {
+                b'\r' | b'\n' => {
+                    write_all(class, b"\r\n").await?;
+                    match line.trim() {
+                        "pd" => watch_pd(class).await?,
+                        "events" => dump_events(class).await?,
+                        "capture dump" => dump_capture(class).await?,
+                        command => {
+                            reply.clear();
+                            execute(command, &mut reply);
+                            write_all(class, reply.as_bytes()).await?;
+                        }
+                    }
+                    write_all(class, b"> ").await?;
+                    line.clear();
+                }
+                // Backspace / DEL
+                0x08 | 0x7F => {
+                    if line.pop().is_some() {
+                        write_all(class, b"\x08 \x08").await?;
+                    }
+                }
+                0x20..=0x7E => {
+                    if line.push(byte as char).is_ok() {
+                        write_all(class, &[byte]).await?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Write a buffer as a sequence of packets, terminated by a ZLP if needed
+pub async fn write_all(
+    class: &mut CdcAcmClass<'static, UsbDriver>,
+    data: &[u8],
+) -> Result<(), EndpointError> {
+    for chunk in data.chunks(MAX_PACKET_SIZE as usize) {
+        class.write_packet(chunk).await?;
+    }
+    if !data.is_empty() && data.len() % MAX_PACKET_SIZE as usize == 0 {
+        class.write_packet(&[]).await?;
+    }
+    Ok(())
+}
+
+/// Print every PD message on the CC line until a key is pressed
+async fn watch_pd(class: &mut CdcAcmClass<'static, UsbDriver>) -> Result<(), EndpointError> {
+    let Ok(mut trace) = PD_TRACE.subscriber() else {
+        return write_all(class, b"PD trace busy\r\n").await;
+    };
+    write_all(class, b"PD trace, press any key to stop\r\n").await?;
+
+    let mut packet = [0u8; MAX_PACKET_SIZE as usize];
+    let mut analyzer = Analyzer::new();
+    let mut reply = Reply::new();
+    loop {
+        let record = match select(class.read_packet(&mut packet), trace.next_message_pure()).await {
+            Either::First(result) => return result.map(|_| ()),
+            Either::Second(record) => record,
+        };
+        reply.clear();
+        format_record(&record, &mut analyzer, &mut reply);
+        write_all(class, reply.as_bytes()).await?;
+    }
+}
+
+/// Print the event log, oldest first
+async fn dump_events(class: &mut CdcAcmClass<'static, UsbDriver>) -> Result<(), EndpointError> {
+    let mut reply = Reply::new();
+    for record in event_log::snapshot().iter() {
+        reply.clear();
+        let _ = write!(reply, "{:5} {}\r\n", record.seq, record);
+        write_all(class, reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Print the last capture window as CSV, time relative to the trigger
+async fn dump_capture(class: &mut CdcAcmClass<'static, UsbDriver>) -> Result<(), EndpointError> {
+    let capture = capture::snapshot();
+    let mut reply = Reply::new();
+    format_capture(&capture, &mut reply);
+    if !capture.samples().is_empty() {
+        let _ = write!(reply, "t_us,current_mA\r\n");
+    }
+    write_all(class, reply.as_bytes()).await?;
+    let period_us = capture.period_us() as i64;
+    let trigger = capture.trigger_index() as i64;
+    for (i, &current_ua) in capture.samples().iter().enumerate() {
+        reply.clear();
+        let _ = write!(
+            reply,
+            "{},{:.3}\r\n",
+            (i as i64 - trigger) * period_us,
+            current_ua as f32 / 1000.0
+        );
+        write_all(class, reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+fn format_capture(capture: &Capture, reply: &mut Reply) {
+    let status = capture.status();
+    match status.trigger {
+        Some(trigger) => {
+            let _ = write!(
+                reply,
+                "P{} >{}mA {}",
+                trigger.port + 1,
+                trigger.level_ma,
+                status.state.name()
+            );
+        }
+        None => {
+            let _ = write!(reply, "no capture, arm with 'capture <port> <mA>'");
+        }
+    }
+    if status.state == CaptureState::Done {
+        let _ = write!(
+            reply,
+            ", peak {}mA, {} samples {}us apart",
+            capture.peak_ua() / 1000,
+            capture.samples().len(),
+            capture.period_us()
+        );
+    }
+    let _ = write!(reply, "\r\n");
+}
+
+fn format_record(record: &TraceRecord, analyzer: &mut Analyzer, reply: &mut Reply) {
+    let seconds = record.timestamp_us / 1_000_000;
+    let micros = record.timestamp_us % 1_000_000;
+    let _ = write!(reply, "[{:5}.{:06}] ", seconds, micros);
+
+    let direction = match record.event {
+        TraceEvent::Received => "RX",
+        TraceEvent::Transmitted => "TX",
+        TraceEvent::HardResetReceived => {
+            analyzer.hard_reset();
+            let _ = write!(reply, "RX Hard_Reset\r\n");
+            return;
+        }
+        TraceEvent::HardResetSent => {
+            analyzer.hard_reset();
+            let _ = write!(reply, "TX Hard_Reset\r\n");
+            return;
+        }
+    };
+
+    match decode::decode(record.bytes()) {
+        Ok(decoded) => {
+            let _ = write!(reply, "{} {}\r\n", direction, decoded);
+            if analyzer.feed(&decoded) {
+                let _ = write!(reply, "  -> {}\r\n", analyzer.summary());
+            }
+        }
+        Err(_) => {
+            let _ = write!(reply, "{} malformed", direction);
+            for byte in record.bytes() {
+                let _ = write!(reply, " {:02x}", byte);
+            }
+            let _ = write!(reply, "\r\n");
+        }
+    }
+}
+
+fn execute(line: &str, reply: &mut Reply) {
+    let mut args = line.split_whitespace();
+    let Some(command) = args.next() else {
+        return;
+    };
+
+    match command {
+        "help" => {
+            let _ = write!(reply, "help     this text\r\n");
+            let _ = write!(reply, "version  firmware version\r\n");
+            let _ = write!(reply, "read     latest port readings\r\n");
+            let _ = write!(reply, "pd       live PD message trace\r\n");
+            let _ = write!(reply, "ports    attached devices and sessions\r\n");
+            let _ = write!(reply, "counters lifetime usage per port\r\n");
+            let _ = write!(reply, "events   event log, oldest first\r\n");
+            let _ = write!(reply, "crash    panic that caused this boot\r\n");
+            let _ = write!(reply, "settings show the stored settings\r\n");
+            let _ = write!(
+                reply,
+                "set      set <name> [port] <value>, e.g. set ocp 2 1500\r\n"
+            );
+            let _ = write!(reply, "calibrate zero|<port> <mA>|clear\r\n");
+            let _ = write!(reply, "capture  [<port> <mA>|stop|dump] inrush capture\r\n");
+            let _ = write!(reply, "defaults default settings, calibration kept\r\n");
+            let _ = write!(reply, "reboot   reset the hub\r\n");
+        }
+        "version" => {
+            let _ = write!(
+                reply,
+                "{} {}\r\n",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+        "read" => match READINGS.try_get() {
+            Some(readings) => {
+                for (i, (voltage, current, power)) in readings.iter().enumerate() {
+                    let _ = write!(
+                        reply,
+                        "port{} {:.3}V {:.3}A {:.3}W\r\n",
+                        i + 1,
+                        voltage,
+                        current,
+                        power
+                    );
+                }
+            }
+            None => {
+                let _ = write!(reply, "no readings yet\r\n");
+            }
+        },
+        "ports" => match SESSIONS.try_get() {
+            Some(sessions) => {
+                for (i, session) in sessions.iter().enumerate() {
+                    if i == INPUT_PORT {
+                        continue;
+                    }
+                    let state = match session.state {
+                        AttachState::Detached => "detached",
+                        AttachState::Idle => "idle",
+                        AttachState::Charging => "charging",
+                    };
+                    let minutes = session.duration_ms / 60_000;
+                    let _ = write!(
+                        reply,
+                        "port{} {} {} session {}:{:02} {:.3}Wh",
+                        i + 1,
+                        state,
+                        session.phase.name(),
+                        minutes / 60,
+                        minutes % 60,
+                        session.energy_wh()
+                    );
+                    if let Some(seconds) = session.time_to_full_s {
+                        let _ = write!(reply, " full in {}min", seconds.div_ceil(60));
+                    }
+                    let _ = write!(reply, "\r\n");
+                }
+            }
+            None => {
+                let _ = write!(reply, "no readings yet\r\n");
+            }
+        },
+        "counters" => match COUNTERS.try_get() {
+            Some(counters) => {
+                for (i, port) in counters.ports.iter().enumerate() {
+                    let _ = write!(
+                        reply,
+                        "port{} {:.3}Wh peak {:.3}A plugs {} trips {}\r\n",
+                        i + 1,
+                        port.energy_wh(),
+                        port.peak_current_ma as f32 / 1000.0,
+                        port.plug_events,
+                        port.protection_trips
+                    );
+                }
+            }
+            None => {
+                let _ = write!(reply, "no counters yet\r\n");
+            }
+        },
+        "crash" => match crash::last() {
+            Some(crash) => format_crash(&crash, reply),
+            None => {
+                let _ = write!(reply, "no crash before this boot\r\n");
+            }
+        },
+        "settings" => format_settings(&SETTINGS.try_get().unwrap_or_default(), reply),
+        "set" => {
+            let mut settings = SETTINGS.try_get().unwrap_or_default();
+            match set(&mut settings, &mut args) {
+                Ok(()) => save(settings, reply),
+                Err(usage) => {
+                    let _ = write!(reply, "{}\r\n", usage);
+                }
+            }
+        }
+        "calibrate" => match calibrate(&mut args) {
+            Ok(Some(request)) => match CALIBRATION.try_send(request) {
+                Ok(()) => {
+                    let _ = write!(
+                        reply,
+                        "calibrating for {}s, keep the ports as they are; see 'settings'\r\n",
+                        calibration::SAMPLES / 10
+                    );
+                }
+                Err(_) => {
+                    let _ = write!(reply, "a calibration is already waiting\r\n");
+                }
+            },
+            Ok(None) => save(
+                Settings {
+                    corrections: [CurrentCorrection::NONE; PORT_COUNT],
+                    ..SETTINGS.try_get().unwrap_or_default()
+                },
+                reply,
+            ),
+            Err(usage) => {
+                let _ = write!(reply, "{}\r\n", usage);
+            }
+        },
+        "capture" => match args.next() {
+            None => format_capture(&capture::snapshot(), reply),
+            Some("stop") => {
+                capture::arm(None);
+                let _ = write!(reply, "stopped\r\n");
+            }
+            Some(port) => match capture_trigger(port, args.next()) {
+                Ok(trigger) => {
+                    capture::arm(Some(trigger));
+                    let _ = write!(reply, "armed, see 'capture', then 'capture dump'\r\n");
+                }
+                Err(usage) => {
+                    let _ = write!(reply, "{}\r\n", usage);
+                }
+            },
+        },
+        // Keeps the calibration, `calibrate clear` drops it
+        "defaults" => save(
+            Settings {
+                corrections: SETTINGS.try_get().unwrap_or_default().corrections,
+                ..Settings::default()
+            },
+            reply,
+        ),
+        "reboot" => cortex_m::peripheral::SCB::sys_reset(),
+        _ => {
+            let _ = write!(reply, "unknown command '{}'\r\n", command);
+        }
+    }
+}
+
+fn format_crash(crash: &CrashRecord, reply: &mut Reply) {
+    let _ = write!(
+        reply,
+        "{}:{} after {}.{:03}s\r\n{}\r\nsp {:08x}:",
+        crash,
+        crash.column,
+        crash.uptime_ms / 1000,
+        crash.uptime_ms % 1000,
+        crash.message(),
+        crash.sp
+    );
+    for word in crash.stack {
+        let _ = write!(reply, " {:08x}", word);
+    }
+    let _ = write!(reply, "\r\n");
+}
+
+fn format_settings(settings: &Settings, reply: &mut Reply) {
+    let theme = match settings.theme {
+        Theme::Dark => "dark",
+        Theme::Light => "light",
+    };
+    let _ = write!(
+        reply,
+        "brightness {}%  theme {}  mute {}  counter-interval {}min  rotate {}  sleep {}min  \
+         selftest {}  capture P{} >{}mA  graphs {}\r\n",
+        settings.brightness,
+        theme,
+        if settings.buzzer_muted { "on" } else { "off" },
+        settings.counter_save_minutes,
+        if settings.rotated { "on" } else { "off" },
+        settings.sleep_minutes,
+        if settings.self_test { "on" } else { "off" },
+        settings.capture_trigger.port + 1,
+        settings.capture_trigger.level_ma,
+        match settings.trend {
+            Trend::Current => "current",
+            Trend::Power => "power",
+        }
+    );
+    for (i, ((limits, sensor), correction)) in settings
+        .thresholds
+        .iter()
+        .zip(settings.sensors.iter())
+        .zip(settings.corrections.iter())
+        .enumerate()
+    {
+        let _ = write!(
+            reply,
+            "port{} ocp {}mA ovp {}mV shunt {}uOhm offset {}uA gain {:.4}\r\n",
+            i + 1,
+            limits.over_current_ma,
+            limits.over_voltage_mv,
+            sensor.shunt_micro_ohms,
+            correction.offset_ua,
+            correction.gain()
+        );
+    }
+}
+
+/// Apply `set <name> [port] <value>` to `settings`
+fn set<'a>(
+    settings: &mut Settings,
+    args: &mut impl Iterator<Item = &'a str>,
+) -> Result<(), &'static str> {
+    let usage = "usage: set brightness|theme|mute|counter-interval|rotate|sleep|selftest|graphs \
+                 <value>, set ocp|ovp|shunt|capture <port> <value>";
+    let name = args.next().ok_or(usage)?;
+    match name {
+        "brightness" => {
+            let percent: u8 = args.next().and_then(|v| v.parse().ok()).ok_or(usage)?;
+            if percent > 100 {
+                return Err("brightness is 0-100");
+            }
+            settings.brightness = percent;
+        }
+        "theme" => {
+            settings.theme = match args.next() {
+                Some("dark") => Theme::Dark,
+                Some("light") => Theme::Light,
+                _ => return Err("theme is dark or light"),
+            }
+        }
+        "mute" => {
+            settings.buzzer_muted = match args.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err("mute is on or off"),
+            }
+        }
+        "rotate" => {
+            settings.rotated = match args.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err("rotate is on or off"),
+            }
+        }
+        "graphs" => {
+            settings.trend = match args.next() {
+                Some("current") => Trend::Current,
+                Some("power") => Trend::Power,
+                _ => return Err("graphs is current or power"),
+            }
+        }
+        "selftest" => {
+            settings.self_test = match args.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err("selftest is on or off"),
+            }
+        }
+        "sleep" => {
+            settings.sleep_minutes = args.next().and_then(|v| v.parse().ok()).ok_or(usage)?;
+        }
+        "counter-interval" => {
+            settings.counter_save_minutes =
+                args.next().and_then(|v| v.parse().ok()).ok_or(usage)?;
+        }
+        // The trigger the panel arms with
+        "capture" => {
+            let port = args.next().ok_or(usage)?;
+            settings.capture_trigger = capture_trigger(port, args.next())?;
+        }
+        "ocp" | "ovp" | "shunt" => {
+            let port: usize = args.next().and_then(|v| v.parse().ok()).ok_or(usage)?;
+            let index = port
+                .checked_sub(1)
+                .filter(|&i| i < settings.thresholds.len())
+                .ok_or("no such port")?;
+            let value: u32 = args.next().and_then(|v| v.parse().ok()).ok_or(usage)?;
+            match name {
+                "ocp" => settings.thresholds[index].over_current_ma = value,
+                "ovp" => settings.thresholds[index].over_voltage_mv = value,
+                _ if value == 0 => return Err("shunt must not be zero"),
+                _ => settings.sensors[index].shunt_micro_ohms = value,
+            }
+        }
+        _ => return Err(usage),
+    }
+    Ok(())
+}
+
+/// Parse `calibrate zero|<port> <mA>|clear`; `None` for clear
+fn calibrate<'a>(
+    args: &mut impl Iterator<Item = &'a str>,
+) -> Result<Option<Request>, &'static str> {
+    let usage = "usage: calibrate zero (ports unloaded), calibrate <port> <mA> (reference \
+                 current flowing), calibrate clear";
+    match args.next().ok_or(usage)? {
+        "zero" => Ok(Some(Request::Zero)),
+        "clear" => Ok(None),
+        port => {
+            let port: usize = port.parse().map_err(|_| usage)?;
+            let port = port
+                .checked_sub(1)
+                .filter(|&i| i < PORT_COUNT)
+                .ok_or("no such port")?;
+            let reference_ma: u32 = args.next().and_then(|v| v.parse().ok()).ok_or(usage)?;
+            if reference_ma == 0 {
+                return Err("the reference current must not be zero");
+            }
+            Ok(Some(Request::Gain { port, reference_ma }))
+        }
+    }
+}
+
+fn capture_trigger(port: &str, level: Option<&str>) -> Result<Trigger, &'static str> {
+    let usage = "usage: capture <port> <mA> (arm), capture stop, capture dump";
+    let port: usize = port.parse().map_err(|_| usage)?;
+    let level_ma: u32 = level.and_then(|v| v.parse().ok()).ok_or(usage)?;
+    let trigger = Trigger {
+        port: port.checked_sub(1).ok_or("no such port")?,
+        level_ma,
+    };
+    match trigger.is_valid() {
+        true => Ok(trigger),
+        false if trigger.port >= PORT_COUNT => Err("no such port"),
+        false => Err("the trigger level must not be zero"),
+    }
+}
+
+fn save(settings: Settings, reply: &mut Reply) {
+    SETTINGS.sender().send(settings);
+    event_log::record(Event::SettingsChanged);
+    match storage::save_settings(&settings) {
+        Ok(()) => {
+            let _ = write!(reply, "saved\r\n");
+        }
+        Err(e) => {
+            let _ = write!(reply, "applied but not saved: {:?}\r\n", e);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// src/usb/dfu.rs
+// DFU runtime interface (DFU 1.1, section 4.1)
+//
+// Only DFU_DETACH is implemented. On detach the hub resets into a DFU capable
+// bootloader: bootloader/ when built with `--features bootloader` (verified
+// A/B updates, see `update`), otherwise the STM32G4 system memory bootloader.
+
+#[cfg(not(feature = "bootloader"))]
+use core::mem::MaybeUninit;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use static_cell::StaticCell;
+
+use super::UsbDriver;
+
+const CLASS_APPLICATION_SPECIFIC: u8 = 0xFE;
+const SUBCLASS_DFU: u8 = 0x01;
+const PROTOCOL_RUNTIME: u8 = 0x01;
+
+const DESCRIPTOR_DFU_FUNCTIONAL: u8 = 0x21;
+// bitWillDetach | bitCanDnload
+const DFU_ATTRIBUTES: u8 = 0x08 | 0x01;
+const DETACH_TIMEOUT_MS: u16 = 1000;
+const TRANSFER_SIZE: u16 = 64;
+const DFU_VERSION: u16 = 0x0110;
+
+const REQ_DETACH: u8 = 0x00;
+const REQ_GETSTATUS: u8 = 0x03;
+const REQ_GETSTATE: u8 = 0x05;
+
+const STATUS_OK: u8 = 0x00;
+const STATE_APP_IDLE: u8 = 0x00;
+
+// STM32G4 system memory (ROM bootloader) vector table
+#[cfg(not(feature = "bootloader"))]
+const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+#[cfg(not(feature = "bootloader"))]
+const BOOTLOADER_MAGIC: u32 = 0xB007_1DF0;
+
+// Survives the software reset, cleared on power-on by not being a valid magic
+#[cfg(not(feature = "bootloader"))]
+#[unsafe(link_section = ".uninit.BOOT_REQUEST")]
+static mut BOOT_REQUEST: MaybeUninit<u32> = MaybeUninit::uninit();
+
+static DETACH: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Must be called first thing in `main`, before clocks and peripherals are
+/// set up
+#[cfg(not(feature = "bootloader"))]
+pub fn jump_to_bootloader_if_requested() {
+    let request = (&raw mut BOOT_REQUEST).cast::<u32>();
+    unsafe {
+        if request.read_volatile() == BOOTLOADER_MAGIC {
+            request.write_volatile(0);
+            cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32);
+        }
+    }
+}
+
+struct DfuRuntime {
+    interface: InterfaceNumber,
+}
+
+impl DfuRuntime {
+    fn accepts(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == self.interface.0 as u16
+    }
+}
+
+impl Handler for DfuRuntime {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        match req.request {
+            REQ_DETACH => {
+                DETACH.signal(());
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        match req.request {
+            REQ_GETSTATUS => {
+                // bStatus, bwPollTimeout (3 bytes), bState, iString
+                buf[..6].copy_from_slice(&[STATUS_OK, 0, 0, 0, STATE_APP_IDLE, 0]);
+                Some(InResponse::Accepted(&buf[..6]))
+            }
+            REQ_GETSTATE => {
+                buf[0] = STATE_APP_IDLE;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => Some(InResponse::Rejected),
+        }
+    }
+}
+
+pub fn register(spawner: &Spawner, builder: &mut Builder<'static, UsbDriver>) {
+    let interface = {
+        let mut function =
+            builder.function(CLASS_APPLICATION_SPECIFIC, SUBCLASS_DFU, PROTOCOL_RUNTIME);
+        let mut interface = function.interface();
+        let number = interface.interface_number();
+        defmt::assert_eq!(number.0, super::DFU_INTERFACE);
+        let mut alt = interface.alt_setting(
+            CLASS_APPLICATION_SPECIFIC,
+            SUBCLASS_DFU,
+            PROTOCOL_RUNTIME,
+            None,
+        );
+        let timeout = DETACH_TIMEOUT_MS.to_le_bytes();
+        let transfer = TRANSFER_SIZE.to_le_bytes();
+        let version = DFU_VERSION.to_le_bytes();
+        alt.descriptor(
+            DESCRIPTOR_DFU_FUNCTIONAL,
+            &[
+                DFU_ATTRIBUTES,
+                timeout[0],
+                timeout[1],
+                transfer[0],
+                transfer[1],
+                version[0],
+                version[1],
+            ],
+        );
+        number
+    };
+
+    static HANDLER: StaticCell<DfuRuntime> = StaticCell::new();
+    builder.handler(HANDLER.init(DfuRuntime { interface }));
+    spawner.must_spawn(detach_task());
+}
+
+#[embassy_executor::task]
+async fn detach_task() {
+    DETACH.wait().await;
+    info!("DFU detach requested, rebooting into bootloader");
+    // Let the control transfer status stage complete before resetting
+    embassy_time::Timer::after_millis(50).await;
+    #[cfg(feature = "bootloader")]
+    crate::update::request_dfu();
+    #[cfg(not(feature = "bootloader"))]
+    unsafe {
+        (&raw mut BOOT_REQUEST)
+            .cast::<u32>()
+            .write_volatile(BOOTLOADER_MAGIC);
+    }
+    cortex_m::peripheral::SCB::sys_reset();
+}
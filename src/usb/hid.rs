@@ -0,0 +1,69 @@
+// src/usb/hid.rs
+// Vendor-defined HID interface: driverless access to the readings frames
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_time::Instant;
+use embassy_usb::Builder;
+use embassy_usb::class::hid::{Config, HidWriter, State};
+use embassy_usb::driver::EndpointError;
+use static_cell::StaticCell;
+
+use super::UsbDriver;
+use crate::protocol::{self, MAX_FRAME_LEN};
+use crate::shared::READINGS;
+
+const REPORT_LEN: usize = MAX_FRAME_LEN;
+
+// One 64-byte input report on vendor page 0xFF00 carrying a protocol frame
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xFF, // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01,       // Usage (0x01)
+    0xA1, 0x01,       // Collection (Application)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08,       //   Report Size (8)
+    0x95, 0x40,       //   Report Count (64)
+    0x09, 0x01,       //   Usage (0x01)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0xC0,             // End Collection
+];
+
+pub fn register(spawner: &Spawner, builder: &mut Builder<'static, UsbDriver>) {
+    static STATE: StaticCell<State> = StaticCell::new();
+    let config = Config {
+        report_descriptor: REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: 100,
+        max_packet_size: REPORT_LEN as u16,
+    };
+    let writer = HidWriter::<_, REPORT_LEN>::new(builder, STATE.init(State::new()), config);
+    spawner.must_spawn(hid_task(writer));
+}
+
+#[embassy_executor::task]
+async fn hid_task(mut writer: HidWriter<'static, UsbDriver, REPORT_LEN>) -> ! {
+    let Some(mut readings) = READINGS.receiver() else {
+        defmt::panic!("no free READINGS receiver for HID");
+    };
+    let mut seq: u8 = 0;
+
+    loop {
+        writer.ready().await;
+        info!("USB HID ready");
+
+        loop {
+            let latest = readings.changed().await;
+            let uptime_ms = Instant::now().as_millis() as u32;
+            let frame = protocol::encode_readings(seq, uptime_ms, &latest);
+            seq = seq.wrapping_add(1);
+
+            match writer.write(&protocol::encode_report(&frame)).await {
+                Ok(()) => {}
+                Err(EndpointError::Disabled) => break,
+                Err(EndpointError::BufferOverflow) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// src/usb/mod.rs
+// Composite USB device
+//
+// Every USB function is enabled by its own Cargo feature and registered on a
+// single `embassy_usb::Builder`, in the order below. Interface numbers are
+// stable whatever the feature set: a disabled function keeps its numbers as
+// empty vendor-specific interfaces.
+//
+//   0-1 console   (CDC-ACM, 2 interfaces, IAD)  `usb-console`
+//   2   telemetry (vendor bulk IN)              `usb-telemetry`
+//   3   hid       (vendor-defined HID reports)  `usb-hid`
+//   4   dfu       (DFU runtime, detach only)    `usb-dfu`
+
+use embassy_executor::Spawner;
+use embassy_stm32::usb::{Driver, InterruptHandler};
+use embassy_stm32::{Peri, bind_interrupts, peripherals};
+use embassy_usb::{Builder, UsbDevice};
+use static_cell::StaticCell;
+
+#[cfg(feature = "usb-console")]
+pub mod console;
+#[cfg(feature = "usb-dfu")]
+pub mod dfu;
+#[cfg(feature = "usb-hid")]
+pub mod hid;
+#[cfg(feature = "usb-telemetry")]
+pub mod telemetry;
+
+pub type UsbDriver = Driver<'static, peripherals::USB>;
+
+// pid.codes test VID/PID, replace before shipping devices
+const USB_VID: u16 = 0x1209;
+const USB_PID: u16 = 0x0001;
+
+// First interface number of every function, checked as they are registered
+const CONSOLE_INTERFACE: u8 = 0;
+const TELEMETRY_INTERFACE: u8 = CONSOLE_INTERFACE + 2;
+const HID_INTERFACE: u8 = TELEMETRY_INTERFACE + 1;
+const DFU_INTERFACE: u8 = HID_INTERFACE + 1;
+
+// Vendor class with no subclass or protocol, for the reserved interfaces
+const CLASS_VENDOR: u8 = 0xFF;
+
+bind_interrupts!(
+    struct Irqs {
+        USB_LP => InterruptHandler<peripherals::USB>;
+    }
+);
+
+/// Build the composite device and spawn one task per enabled function
+pub fn init(
+    spawner: &Spawner,
+    usb: Peri<'static, peripherals::USB>,
+    dp: Peri<'static, peripherals::PA12>,
+    dm: Peri<'static, peripherals::PA11>,
+) {
+    let driver = Driver::new(usb, Irqs, dp, dm);
+
+    let mut config = embassy_usb::Config::new(USB_VID, USB_PID);
+    config.manufacturer = Some("Ivan Li");
+    config.product = Some("Isolated USB Hub");
+    // 96-bit factory UID rendered as 24 hex digits, unique per chip
+    config.serial_number = Some(embassy_stm32::uid::uid_hex());
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Required for composite devices using Interface Association Descriptors
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static MSOS_DESCRIPTOR: StaticCell<[u8; 0]> = StaticCell::new();
+    static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+
+    #[allow(unused_mut)]
+    let mut builder = Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.init([0; 256]),
+        BOS_DESCRIPTOR.init([0; 256]),
+        MSOS_DESCRIPTOR.init([0; 0]),
+        CONTROL_BUF.init([0; 64]),
+    );
+
+    #[cfg(feature = "usb-console")]
+    console::register(spawner, &mut builder);
+    if cfg!(not(feature = "usb-console")) {
+        reserve(&mut builder, CONSOLE_INTERFACE, 2);
+    }
+    #[cfg(feature = "usb-telemetry")]
+    telemetry::register(spawner, &mut builder);
+    if cfg!(not(feature = "usb-telemetry")) {
+        reserve(&mut builder, TELEMETRY_INTERFACE, 1);
+    }
+    #[cfg(feature = "usb-hid")]
+    hid::register(spawner, &mut builder);
+    if cfg!(not(feature = "usb-hid")) {
+        reserve(&mut builder, HID_INTERFACE, 1);
+    }
+    // Also shows the functions before it took as many interfaces as planned
+    #[cfg(feature = "usb-dfu")]
+    dfu::register(spawner, &mut builder);
+    if cfg!(not(feature = "usb-dfu")) {
+        reserve(&mut builder, DFU_INTERFACE, 1);
+    }
+
+    let device = builder.build();
+    spawner.must_spawn(usb_task(device));
+}
+
+/// Empty interfaces in place of a disabled function, so the functions after
+/// it keep their numbers
+fn reserve(builder: &mut Builder<'static, UsbDriver>, first: u8, count: u8) {
+    let mut function = builder.function(CLASS_VENDOR, 0, 0);
+    for number in first..first + count {
+        let mut interface = function.interface();
+        defmt::assert_eq!(interface.interface_number().0, number);
+        interface.alt_setting(CLASS_VENDOR, 0, 0, None);
+    }
+}
+
+#[embassy_executor::task]
+async fn usb_task(mut device: UsbDevice<'static, UsbDriver>) -> ! {
+    device.run().await
+}
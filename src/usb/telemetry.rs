@@ -0,0 +1,69 @@
+// src/usb/telemetry.rs
+// Vendor-specific bulk interface streaming binary frames (see `protocol`):
+// port readings and every PD message on the CC line
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_time::Instant;
+use embassy_usb::Builder;
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn};
+
+use super::UsbDriver;
+use crate::protocol;
+use crate::shared::{PD_TRACE, READINGS};
+
+const MAX_PACKET_SIZE: u16 = 64;
+
+// Vendor class, subclass and protocol
+const CLASS_VENDOR: u8 = 0xFF;
+const SUBCLASS_TELEMETRY: u8 = 0x01;
+const PROTOCOL_FRAMES_V1: u8 = 0x01;
+
+type BulkIn = <UsbDriver as Driver<'static>>::EndpointIn;
+
+pub fn register(spawner: &Spawner, builder: &mut Builder<'static, UsbDriver>) {
+    let mut function = builder.function(CLASS_VENDOR, SUBCLASS_TELEMETRY, PROTOCOL_FRAMES_V1);
+    let mut interface = function.interface();
+    defmt::assert_eq!(interface.interface_number().0, super::TELEMETRY_INTERFACE);
+    let mut alt = interface.alt_setting(CLASS_VENDOR, SUBCLASS_TELEMETRY, PROTOCOL_FRAMES_V1, None);
+    let ep_in = alt.endpoint_bulk_in(MAX_PACKET_SIZE);
+
+    spawner.must_spawn(telemetry_task(ep_in));
+}
+
+#[embassy_executor::task]
+async fn telemetry_task(mut ep_in: BulkIn) -> ! {
+    let Some(mut readings) = READINGS.receiver() else {
+        defmt::panic!("no free READINGS receiver for telemetry");
+    };
+    let Ok(mut pd_trace) = PD_TRACE.subscriber() else {
+        defmt::panic!("no free PD_TRACE subscriber for telemetry");
+    };
+    let mut seq: u8 = 0;
+
+    loop {
+        ep_in.wait_enabled().await;
+        info!("USB telemetry enabled");
+
+        loop {
+            let frame = match select(readings.changed(), pd_trace.next_message_pure()).await {
+                Either::First(latest) => {
+                    let uptime_ms = Instant::now().as_millis() as u32;
+                    protocol::encode_readings(seq, uptime_ms, &latest)
+                }
+                Either::Second(record) => protocol::encode_pd_message(seq, &record),
+            };
+            seq = seq.wrapping_add(1);
+
+            match ep_in.write(&frame).await {
+                Ok(()) => {}
+                Err(EndpointError::Disabled) => break,
+                // Host is not reading fast enough, drop the frame
+                Err(EndpointError::BufferOverflow) => {}
+            }
+        }
+
+        info!("USB telemetry disabled");
+    }
+}